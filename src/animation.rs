@@ -0,0 +1,522 @@
+//! animation state machine of player characters. The state (idle, walk, run, jump, crouch)
+//! follows the replicated velocity and controller state of the player. State changes cross
+//! fade between the clips, so the skeleton is posed here by sampling the clips directly
+//! instead of through the AnimationPlayer, which plays one clip at a time.
+//!
+//! On top of the clips the spine and head bones are turned by the view pitch of the player, so
+//! others see where they aim. Each bone takes its share of the pitch.
+//!
+//! clips and aim bones are configured in the `animations` section of `character.ron`:
+//!
+//!     animations: (
+//!         idle: Some("models/character.glb#Animation0"),
+//!         walk: Some("models/character.glb#Animation1"),
+//!         run_threshold: 8.0,
+//!         blend_time: 0.2,
+//!         aim_bones: [("Spine", 0.3), ("Spine2", 0.3), ("Head", 0.4)],
+//!     ),
+
+use std::collections::{HashMap, VecDeque};
+
+use bevy::{
+    animation::{EntityPath, Keyframes, VariableCurve},
+    math::Vec3Swizzles,
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::character::CharacterModel;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnimationState {
+    Idle,
+    Walk,
+    Run,
+    /// in the air
+    Jump,
+    Crouch,
+}
+
+impl AnimationState {
+    /// the clip plays once and holds its last frame
+    fn holds(self) -> bool {
+        self == AnimationState::Jump
+    }
+}
+
+/// replicated state of a player the state machine follows
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Locomotion {
+    pub velocity: Vec3,
+    pub grounded: bool,
+    pub crouching: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CharacterAnimations {
+    /// asset paths of the clips, states without a clip (the default) keep the bind pose
+    pub idle: Option<String>,
+    pub walk: Option<String>,
+    pub run: Option<String>,
+    pub jump: Option<String>,
+    pub crouch: Option<String>,
+    /// lateral speed below which the player stands
+    pub idle_threshold: f32,
+    /// lateral speed from which the player runs instead of walking
+    pub run_threshold: f32,
+    /// lateral speed of the walk and run clips as authored. The clips are played faster or
+    /// slower to match the speed of the player, so the feet don't slide.
+    pub walk_clip_speed: f32,
+    pub run_clip_speed: f32,
+    /// length of the cross fade between two states (seconds)
+    pub blend_time: f32,
+    /// bones (by name) turned by the view pitch and their share of it
+    pub aim_bones: Vec<(String, f32)>,
+}
+
+impl Default for CharacterAnimations {
+    fn default() -> Self {
+        Self {
+            idle: None,
+            walk: None,
+            run: None,
+            jump: None,
+            crouch: None,
+            idle_threshold: 0.5,
+            run_threshold: 8.0,
+            walk_clip_speed: 4.0,
+            run_clip_speed: 10.0,
+            blend_time: 0.2,
+            aim_bones: vec![
+                ("Spine".to_string(), 0.2),
+                ("Spine1".to_string(), 0.2),
+                ("Spine2".to_string(), 0.2),
+                ("Neck".to_string(), 0.2),
+                ("Head".to_string(), 0.2),
+            ],
+        }
+    }
+}
+
+impl CharacterAnimations {
+    pub fn select(&self, locomotion: &Locomotion) -> AnimationState {
+        let speed = locomotion.velocity.xz().length();
+        if !locomotion.grounded {
+            AnimationState::Jump
+        } else if locomotion.crouching {
+            AnimationState::Crouch
+        } else if speed < self.idle_threshold {
+            AnimationState::Idle
+        } else if speed < self.run_threshold {
+            AnimationState::Walk
+        } else {
+            AnimationState::Run
+        }
+    }
+
+    /// playback speed of the clip of `state` at `locomotion`
+    fn rate(&self, state: AnimationState, locomotion: &Locomotion) -> f32 {
+        let speed = locomotion.velocity.xz().length();
+        match state {
+            AnimationState::Walk if self.walk_clip_speed > 0.0 => speed / self.walk_clip_speed,
+            AnimationState::Run if self.run_clip_speed > 0.0 => speed / self.run_clip_speed,
+            _ => 1.0,
+        }
+    }
+
+    fn clip(&self, state: AnimationState) -> Option<&String> {
+        match state {
+            AnimationState::Idle => self.idle.as_ref(),
+            AnimationState::Walk => self.walk.as_ref(),
+            AnimationState::Run => self.run.as_ref(),
+            AnimationState::Jump => self.jump.as_ref(),
+            AnimationState::Crouch => self.crouch.as_ref(),
+        }
+    }
+}
+
+/// the loaded clips of all states
+#[derive(Default)]
+pub struct AnimationClips(HashMap<AnimationState, Handle<AnimationClip>>);
+
+pub fn load_animation_clips_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    model: Res<CharacterModel>,
+) {
+    let mut clips = AnimationClips::default();
+    // the capsule has nothing to animate
+    if model.model.is_none() {
+        commands.insert_resource(clips);
+        return;
+    }
+    for state in [
+        AnimationState::Idle,
+        AnimationState::Walk,
+        AnimationState::Run,
+        AnimationState::Jump,
+        AnimationState::Crouch,
+    ] {
+        if let Some(path) = model.animations.clip(state) {
+            clips.0.insert(state, asset_server.load(path.as_str()));
+        }
+    }
+    commands.insert_resource(clips);
+}
+
+/// bone turned by the view pitch
+#[derive(Debug, Clone, Copy)]
+struct AimBone {
+    entity: Entity,
+    /// share of the pitch
+    weight: f32,
+    /// rotation added on top of the animated pose, taken back before the next frame
+    applied: Quat,
+}
+
+/// animation state of a character scene, `locomotion` and the view are set by the client
+/// every frame
+#[derive(Debug, Component)]
+pub struct CharacterAnimator {
+    pub locomotion: Locomotion,
+    pub yaw: f32,
+    pub pitch: f32,
+    state: AnimationState,
+    time: f32,
+    /// state being faded out and its time
+    previous: Option<(AnimationState, f32)>,
+    /// progress of the cross fade, 0..1
+    blend: f32,
+    /// bones of the scene by their animation path
+    bones: HashMap<EntityPath, Entity>,
+    /// None until the scene is spawned
+    aim_bones: Option<Vec<AimBone>>,
+}
+
+impl Default for CharacterAnimator {
+    fn default() -> Self {
+        Self {
+            locomotion: Locomotion {
+                grounded: true,
+                ..default()
+            },
+            yaw: 0.0,
+            pitch: 0.0,
+            state: AnimationState::Idle,
+            time: 0.0,
+            previous: None,
+            blend: 1.0,
+            bones: HashMap::new(),
+            aim_bones: None,
+        }
+    }
+}
+
+impl CharacterAnimator {
+    pub fn state(&self) -> AnimationState {
+        self.state
+    }
+
+    /// advance the state machine by `dt`
+    fn update(&mut self, animations: &CharacterAnimations, dt: f32) {
+        let state = animations.select(&self.locomotion);
+        if state != self.state {
+            self.previous = Some((self.state, self.time));
+            self.state = state;
+            self.time = 0.0;
+            self.blend = 0.0;
+        }
+        self.time += dt * animations.rate(self.state, &self.locomotion);
+        if let Some((previous, time)) = &mut self.previous {
+            *time += dt * animations.rate(*previous, &self.locomotion);
+            self.blend += dt / animations.blend_time.max(0.001);
+            if self.blend >= 1.0 {
+                self.previous = None;
+                self.blend = 1.0;
+            }
+        }
+    }
+
+    /// the bone at `path` below the animation root `root`
+    fn bone(
+        &mut self,
+        root: Entity,
+        path: &EntityPath,
+        children: &Query<&Children>,
+        names: &Query<&Name>,
+    ) -> Option<Entity> {
+        if let Some(bone) = self.bones.get(path) {
+            return Some(*bone);
+        }
+        let mut bone = root;
+        for part in path.parts.iter().skip(1) {
+            bone = *children
+                .get(bone)
+                .ok()?
+                .iter()
+                .find(|child| names.get(**child).map_or(false, |name| name == part))?;
+        }
+        self.bones.insert(path.clone(), bone);
+        Some(bone)
+    }
+}
+
+/// clip time for playing `state` for `time` seconds: looped, or held at the end
+fn clip_time(clip: &AnimationClip, state: AnimationState, time: f32) -> f32 {
+    let duration = clip.duration();
+    if duration <= 0.0 {
+        0.0
+    } else if state.holds() {
+        time.min(duration)
+    } else {
+        time.rem_euclid(duration)
+    }
+}
+
+/// apply the value of `curve` at `time` to `transform`
+fn sample_curve(curve: &VariableCurve, time: f32, transform: &mut Transform) {
+    let timestamps = &curve.keyframe_timestamps;
+    let last = match timestamps.len() {
+        0 => return,
+        len => len - 1,
+    };
+    let (from, to, t) = match timestamps.iter().position(|timestamp| *timestamp > time) {
+        Some(0) => (0, 0, 0.0),
+        Some(next) => {
+            let start = timestamps[next - 1];
+            let t = (time - start) / (timestamps[next] - start);
+            (next - 1, next, t)
+        }
+        None => (last, last, 0.0),
+    };
+    match &curve.keyframes {
+        Keyframes::Rotation(keyframes) => {
+            transform.rotation = keyframes[from].slerp(keyframes[to], t);
+        }
+        Keyframes::Translation(keyframes) => {
+            transform.translation = keyframes[from].lerp(keyframes[to], t);
+        }
+        Keyframes::Scale(keyframes) => {
+            transform.scale = keyframes[from].lerp(keyframes[to], t);
+        }
+    }
+}
+
+/// the pose of the bones animated by `clip`, starting from their current transforms
+fn sample_clip(
+    animator: &mut CharacterAnimator,
+    root: Entity,
+    clip: &AnimationClip,
+    time: f32,
+    children: &Query<&Children>,
+    names: &Query<&Name>,
+    bones: &Query<&mut Transform, Without<CharacterAnimator>>,
+) -> HashMap<Entity, Transform> {
+    let mut pose = HashMap::new();
+    for (path, curves) in clip.curves() {
+        let bone = match animator.bone(root, path, children, names) {
+            Some(bone) => bone,
+            None => continue,
+        };
+        let mut transform = match bones.get(bone) {
+            Ok(transform) => *transform,
+            Err(_) => continue,
+        };
+        for curve in curves {
+            sample_curve(curve, time, &mut transform);
+        }
+        pose.insert(bone, transform);
+    }
+    pose
+}
+
+/// run the state machines and pose the skeletons, cross fading between the last and the
+/// current state, then turn the upper bodies by the view pitch
+#[allow(clippy::too_many_arguments)]
+pub fn animate_characters_system(
+    time: Res<Time>,
+    model: Res<CharacterModel>,
+    clips: Option<Res<AnimationClips>>,
+    clip_assets: Res<Assets<AnimationClip>>,
+    animation_roots: Query<Entity, With<AnimationPlayer>>,
+    children: Query<&Children>,
+    names: Query<&Name>,
+    globals: Query<&GlobalTransform>,
+    mut animators: Query<(Entity, &mut CharacterAnimator)>,
+    mut bones: Query<&mut Transform, Without<CharacterAnimator>>,
+) {
+    let clips = match clips {
+        Some(clips) => clips,
+        None => return,
+    };
+    let dt = time.delta_seconds();
+    for (entity, mut animator) in &mut animators {
+        animator.update(&model.animations, dt);
+        if animator.aim_bones.is_none() && children.contains(entity) {
+            animator.aim_bones = Some(find_aim_bones(
+                entity,
+                &model.animations.aim_bones,
+                &children,
+                &names,
+            ));
+        }
+        // bones without curves keep their transform, take back the aim of the last frame
+        for aim_bone in animator.aim_bones.iter_mut().flatten() {
+            if let Ok(mut transform) = bones.get_mut(aim_bone.entity) {
+                transform.rotation *= aim_bone.applied.inverse();
+            }
+            aim_bone.applied = Quat::IDENTITY;
+        }
+        // the glTF loader puts an AnimationPlayer on the root of the animated nodes, the
+        // paths of the clips start there
+        if let Some(root) = find_descendant(entity, &children, &|entity| {
+            animation_roots.contains(entity)
+        }) {
+            pose_skeleton(
+                &mut animator,
+                root,
+                &clips,
+                &clip_assets,
+                &children,
+                &names,
+                &mut bones,
+            );
+        }
+        aim(&mut animator, &globals, &mut bones);
+    }
+}
+
+/// the bones named in `aim_bones` below `entity`
+fn find_aim_bones(
+    entity: Entity,
+    aim_bones: &[(String, f32)],
+    children: &Query<&Children>,
+    names: &Query<&Name>,
+) -> Vec<AimBone> {
+    aim_bones
+        .iter()
+        .filter_map(|(name, weight)| {
+            let bone = find_descendant(entity, children, &|bone| {
+                names
+                    .get(bone)
+                    .map_or(false, |bone_name| bone_name.as_str() == name)
+            });
+            if bone.is_none() {
+                debug!("character has no aim bone {:?}", name);
+            }
+            Some(AimBone {
+                entity: bone?,
+                weight: *weight,
+                applied: Quat::IDENTITY,
+            })
+        })
+        .collect()
+}
+
+/// turn the aim bones around the right axis of the player by their share of the pitch
+fn aim(
+    animator: &mut CharacterAnimator,
+    globals: &Query<&GlobalTransform>,
+    bones: &mut Query<&mut Transform, Without<CharacterAnimator>>,
+) {
+    let right = Quat::from_rotation_y(animator.yaw) * Vec3::X;
+    let pitch = animator.pitch;
+    for aim_bone in animator.aim_bones.iter_mut().flatten() {
+        let (global, mut transform) =
+            match (globals.get(aim_bone.entity), bones.get_mut(aim_bone.entity)) {
+                (Ok(global), Ok(transform)) => (global, transform),
+                _ => continue,
+            };
+        // the world axis in the space of the bone. The global transform is the one of the
+        // last frame, close enough for the direction of the axis.
+        let (_, rotation, _) = global.to_scale_rotation_translation();
+        let axis = (rotation.inverse() * right).normalize_or_zero();
+        if axis == Vec3::ZERO {
+            continue;
+        }
+        aim_bone.applied = Quat::from_axis_angle(axis, pitch * aim_bone.weight);
+        transform.rotation *= aim_bone.applied;
+    }
+}
+
+/// sample the clips of the current and the fading state into the skeleton below `root`
+fn pose_skeleton(
+    animator: &mut CharacterAnimator,
+    root: Entity,
+    clips: &AnimationClips,
+    clip_assets: &Assets<AnimationClip>,
+    children: &Query<&Children>,
+    names: &Query<&Name>,
+    bones: &mut Query<&mut Transform, Without<CharacterAnimator>>,
+) {
+    let clip = |state| {
+        clips
+            .0
+            .get(&state)
+            .and_then(|handle| clip_assets.get(handle))
+    };
+    let current = match clip(animator.state) {
+        Some(clip) => {
+            let time = clip_time(clip, animator.state, animator.time);
+            sample_clip(animator, root, clip, time, children, names, bones)
+        }
+        None => HashMap::new(),
+    };
+    let previous = match animator
+        .previous
+        .and_then(|(state, time)| Some((state, time, clip(state)?)))
+    {
+        Some((state, time, clip)) => {
+            let time = clip_time(clip, state, time);
+            sample_clip(animator, root, clip, time, children, names, bones)
+        }
+        None => HashMap::new(),
+    };
+    // smoothstep, so the fade starts and ends softly
+    let weight = animator.blend * animator.blend * (3.0 - 2.0 * animator.blend);
+    for (bone, to) in &current {
+        if let Ok(mut transform) = bones.get_mut(*bone) {
+            *transform = match previous.get(bone) {
+                Some(from) => blend(from, to, weight),
+                None => blend(&transform, to, weight),
+            };
+        }
+    }
+    for (bone, from) in &previous {
+        if current.contains_key(bone) {
+            continue;
+        }
+        if let Ok(mut transform) = bones.get_mut(*bone) {
+            *transform = blend(from, &transform, weight);
+        }
+    }
+}
+
+fn blend(from: &Transform, to: &Transform, weight: f32) -> Transform {
+    Transform {
+        translation: from.translation.lerp(to.translation, weight),
+        rotation: from.rotation.slerp(to.rotation, weight),
+        scale: from.scale.lerp(to.scale, weight),
+    }
+}
+
+/// first entity below `entity` (breadth first) matching `predicate`
+pub fn find_descendant(
+    entity: Entity,
+    children: &Query<&Children>,
+    predicate: &dyn Fn(Entity) -> bool,
+) -> Option<Entity> {
+    let mut queue = VecDeque::from([entity]);
+    while let Some(entity) = queue.pop_front() {
+        if let Ok(entity_children) = children.get(entity) {
+            for child in entity_children.iter() {
+                if predicate(*child) {
+                    return Some(*child);
+                }
+                queue.push_back(*child);
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,330 @@
+//! positional sound effects: footsteps, jumps, gunshots, explosions and projectiles whizzing
+//! by, played where the replicated (or predicted) events happen. Bevy plays sounds without
+//! position, so the volume is attenuated by the distance to the active camera here.
+//!
+//! settings in audio.toml. Volumes are factors, 0 mutes:
+//!     master_volume = 1
+//!     effects_volume = 1
+//!     footsteps_volume = 0.6
+//!     reference_distance = 2
+//!     max_distance = 60
+//!
+//! Sounds are loaded from assets/sounds/<name>.ogg, missing files stay silent.
+
+use std::{collections::HashMap, path::Path};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::ViewModelCamera,
+    controller::{MovementEvent, MovementEventKind},
+    pool::Free,
+};
+
+pub const AUDIO_CONFIG_FILE: &str = "audio.toml";
+
+/// the asset folder of the AssetServer, sound files are looked up there before loading
+const ASSETS_DIR: &str = "assets";
+
+/// projectiles passing the listener closer than this whizz (m)
+pub const WHIZZ_RADIUS: f32 = 2.5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    pub master_volume: f32,
+    /// weapons, explosions, jumps and landings
+    pub effects_volume: f32,
+    pub footsteps_volume: f32,
+    /// sounds closer than this play at full volume (m)
+    pub reference_distance: f32,
+    /// sounds further away than this are not played (m)
+    pub max_distance: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master_volume: 1.0,
+            effects_volume: 1.0,
+            footsteps_volume: 0.6,
+            reference_distance: 2.0,
+            max_distance: 60.0,
+        }
+    }
+}
+
+impl AudioSettings {
+    /// volume of a sound at `distance` from the listener: full up to the reference distance,
+    /// then falling with the inverse distance and faded out towards the max distance
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        if distance >= self.max_distance {
+            return 0.0;
+        }
+        let reference = self.reference_distance.max(0.01);
+        let fade = 1.0 - distance / self.max_distance;
+        reference / distance.max(reference) * fade
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SoundKind {
+    Footstep,
+    Jump,
+    Land,
+    Slide,
+    Gunshot,
+    RocketLaunch,
+    GrenadeThrow,
+    Explosion,
+    Whizz,
+    Hurt,
+}
+
+impl SoundKind {
+    const ALL: [SoundKind; 10] = [
+        SoundKind::Footstep,
+        SoundKind::Jump,
+        SoundKind::Land,
+        SoundKind::Slide,
+        SoundKind::Gunshot,
+        SoundKind::RocketLaunch,
+        SoundKind::GrenadeThrow,
+        SoundKind::Explosion,
+        SoundKind::Whizz,
+        SoundKind::Hurt,
+    ];
+
+    fn file(self) -> &'static str {
+        match self {
+            SoundKind::Footstep => "sounds/footstep.ogg",
+            SoundKind::Jump => "sounds/jump.ogg",
+            SoundKind::Land => "sounds/land.ogg",
+            SoundKind::Slide => "sounds/slide.ogg",
+            SoundKind::Gunshot => "sounds/gunshot.ogg",
+            SoundKind::RocketLaunch => "sounds/rocket_launch.ogg",
+            SoundKind::GrenadeThrow => "sounds/grenade_throw.ogg",
+            SoundKind::Explosion => "sounds/explosion.ogg",
+            SoundKind::Whizz => "sounds/whizz.ogg",
+            SoundKind::Hurt => "sounds/hurt.ogg",
+        }
+    }
+
+    /// volume before the settings and the attenuation
+    fn volume(self) -> f32 {
+        match self {
+            SoundKind::Footstep => 0.5,
+            SoundKind::Jump | SoundKind::Slide | SoundKind::GrenadeThrow => 0.6,
+            SoundKind::Land => 0.7,
+            SoundKind::Whizz => 0.8,
+            SoundKind::Gunshot
+            | SoundKind::RocketLaunch
+            | SoundKind::Explosion
+            | SoundKind::Hurt => 1.0,
+        }
+    }
+
+    /// explosions and shots carry further than steps
+    fn range(self) -> f32 {
+        match self {
+            SoundKind::Explosion => 3.0,
+            SoundKind::Gunshot | SoundKind::RocketLaunch => 2.0,
+            _ => 1.0,
+        }
+    }
+}
+
+/// play `kind` at `position`, or without attenuation for None (the own weapon)
+#[derive(Debug, Clone, Copy)]
+pub struct SoundEvent {
+    pub kind: SoundKind,
+    pub position: Option<Vec3>,
+}
+
+impl SoundEvent {
+    pub fn at(kind: SoundKind, position: Vec3) -> Self {
+        Self {
+            kind,
+            position: Some(position),
+        }
+    }
+
+    pub fn local(kind: SoundKind) -> Self {
+        Self {
+            kind,
+            position: None,
+        }
+    }
+}
+
+/// a hitscan shot from `origin` to `end` fired by someone else, whizzes if it passes close
+#[derive(Debug, Clone, Copy)]
+pub struct BulletPassEvent {
+    pub origin: Vec3,
+    pub end: Vec3,
+}
+
+/// handles of the sounds with a file
+pub struct SoundAssets(HashMap<SoundKind, Handle<AudioSource>>);
+
+/// load the sounds that have a file, the others are skipped instead of failing to load
+pub fn load_sounds_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let (present, missing): (Vec<SoundKind>, Vec<SoundKind>) = SoundKind::ALL
+        .into_iter()
+        .partition(|kind| Path::new(ASSETS_DIR).join(kind.file()).is_file());
+    if !missing.is_empty() {
+        info!(
+            "{} of {} sound files missing, they stay silent",
+            missing.len(),
+            SoundKind::ALL.len()
+        );
+    }
+    let sounds = present
+        .into_iter()
+        .map(|kind| (kind, asset_server.load(kind.file())))
+        .collect();
+    commands.insert_resource(SoundAssets(sounds));
+}
+
+/// position of the camera looking at the world, sounds are heard there
+fn listener_position(
+    cameras: &Query<(&Camera, &GlobalTransform), (With<Camera3d>, Without<ViewModelCamera>)>,
+) -> Option<Vec3> {
+    cameras
+        .iter()
+        .filter(|(camera, _)| camera.is_active)
+        .max_by_key(|(camera, _)| camera.priority)
+        .map(|(_, transform)| transform.translation())
+}
+
+/// play the requested sounds, attenuated by their distance to the listener
+#[allow(clippy::type_complexity)]
+pub fn play_sounds_system(
+    audio: Res<Audio>,
+    settings: Res<AudioSettings>,
+    sounds: Option<Res<SoundAssets>>,
+    sources: Res<Assets<AudioSource>>,
+    mut events: EventReader<SoundEvent>,
+    cameras: Query<(&Camera, &GlobalTransform), (With<Camera3d>, Without<ViewModelCamera>)>,
+) {
+    let sounds = match sounds {
+        Some(sounds) => sounds,
+        None => return,
+    };
+    let listener = listener_position(&cameras);
+    for event in events.iter() {
+        let handle = match sounds.0.get(&event.kind) {
+            Some(handle) => handle,
+            None => continue,
+        };
+        // queued sounds would wait for their asset and block the queue
+        if sources.get(handle).is_none() {
+            continue;
+        }
+        let category = match event.kind {
+            SoundKind::Footstep => settings.footsteps_volume,
+            _ => settings.effects_volume,
+        };
+        let attenuation = match (event.position, listener) {
+            (Some(position), Some(listener)) => {
+                settings.attenuation(position.distance(listener) / event.kind.range())
+            }
+            (Some(_), None) => 0.0,
+            (None, _) => 1.0,
+        };
+        let volume = settings.master_volume * category * event.kind.volume() * attenuation;
+        if volume > 0.01 {
+            audio.play_with_settings(handle.clone(), PlaybackSettings::ONCE.with_volume(volume));
+        }
+    }
+}
+
+/// steps, jumps and landings of all players, simulated or replicated
+pub fn movement_sounds_system(
+    mut movement_events: EventReader<MovementEvent>,
+    mut sounds: EventWriter<SoundEvent>,
+) {
+    for event in movement_events.iter() {
+        let kind = match event.kind {
+            MovementEventKind::Footstep => SoundKind::Footstep,
+            MovementEventKind::Jump | MovementEventKind::Vault => SoundKind::Jump,
+            MovementEventKind::Land { .. } => SoundKind::Land,
+            MovementEventKind::SlideStart => SoundKind::Slide,
+            MovementEventKind::SlideStop => continue,
+        };
+        sounds.send(SoundEvent::at(kind, event.position));
+    }
+}
+
+/// closest approach of the segment `origin`..`end` to `listener`, if the shot passes within
+/// WHIZZ_RADIUS without ending there
+fn passing_point(origin: Vec3, end: Vec3, listener: Vec3) -> Option<Vec3> {
+    let line = end - origin;
+    let length_squared = line.length_squared();
+    if length_squared <= f32::EPSILON || end.distance(listener) < WHIZZ_RADIUS {
+        return None;
+    }
+    let t = ((listener - origin).dot(line) / length_squared).clamp(0.0, 1.0);
+    let point = origin + line * t;
+    (point.distance(listener) < WHIZZ_RADIUS).then_some(point)
+}
+
+/// whizz for hitscan shots passing close by
+#[allow(clippy::type_complexity)]
+pub fn bullet_whizz_system(
+    mut passes: EventReader<BulletPassEvent>,
+    mut sounds: EventWriter<SoundEvent>,
+    cameras: Query<(&Camera, &GlobalTransform), (With<Camera3d>, Without<ViewModelCamera>)>,
+) {
+    let listener = match listener_position(&cameras) {
+        Some(listener) => listener,
+        None => return,
+    };
+    for pass in passes.iter() {
+        if let Some(point) = passing_point(pass.origin, pass.end, listener) {
+            sounds.send(SoundEvent::at(SoundKind::Whizz, point));
+        }
+    }
+}
+
+/// projectiles of other players, whizz once at their closest approach to the listener
+#[derive(Debug, Component)]
+pub struct Whizz {
+    last_distance: f32,
+    played: bool,
+}
+
+impl Default for Whizz {
+    fn default() -> Self {
+        Self {
+            last_distance: f32::MAX,
+            played: false,
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn projectile_whizz_system(
+    mut sounds: EventWriter<SoundEvent>,
+    cameras: Query<(&Camera, &GlobalTransform), (With<Camera3d>, Without<ViewModelCamera>)>,
+    // free ones wait in their pool
+    mut projectiles: Query<(&GlobalTransform, &mut Whizz), Without<Free>>,
+) {
+    let listener = match listener_position(&cameras) {
+        Some(listener) => listener,
+        None => return,
+    };
+    for (transform, mut whizz) in &mut projectiles {
+        if whizz.played {
+            continue;
+        }
+        let position = transform.translation();
+        let distance = position.distance(listener);
+        if distance > whizz.last_distance && whizz.last_distance < WHIZZ_RADIUS {
+            sounds.send(SoundEvent::at(SoundKind::Whizz, position));
+            whizz.played = true;
+        }
+        whizz.last_distance = distance;
+    }
+}
@@ -0,0 +1,436 @@
+//! headless load test: connect simulated clients to a running server, send randomized inputs
+//! like a player holding keys and moving the mouse, consume the network frames and report
+//! bandwidth and latency.
+//!
+//!     bot_client [--server=127.0.0.1:5000] [--clients=64] [--rate=60] [--duration=60]
+//!         [--report=5] [--name=LoadBot]
+//!
+//! `--rate` is the input rate per client (Hz), `--duration` the test length in seconds (0 runs
+//! until interrupted), `--report` the seconds between intermediate reports.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use bevy::prelude::Vec3;
+use bevy_renet::renet::{ClientAuthentication, RenetClient};
+use rand::Rng;
+use renet_test::{
+    client_connection_config, controller::FpsControllerInput, frame::NetworkFrame,
+    names::name_to_user_data, ClientChannel, PlayerInput, ServerChannel, PROTOCOL_ID,
+};
+
+/// inputs older than this without an acknowledgement count as lost
+const MAX_INPUT_AGE: Duration = Duration::from_secs(2);
+
+fn arg_value(name: &str) -> Option<String> {
+    let prefix = format!("--{}=", name);
+    std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.strip_prefix(&prefix).map(str::to_string))
+}
+
+fn parsed_arg<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match arg_value(name) {
+        Some(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid --{}: {}", name, value)),
+        None => default,
+    }
+}
+
+/// what a player does with the keys held until the next change
+#[derive(Debug, Default, Clone, Copy)]
+struct Intent {
+    movement: Vec3,
+    sprint: bool,
+    crouch: bool,
+    /// yaw change per second
+    turn: f32,
+    /// seconds left
+    left: f32,
+}
+
+impl Intent {
+    fn random(rng: &mut impl Rng) -> Self {
+        let movement = Vec3::new(
+            rng.gen_range(-1..=1) as f32,
+            0.0,
+            rng.gen_range(-1..=1) as f32,
+        );
+        Self {
+            movement,
+            sprint: rng.gen_bool(0.3),
+            crouch: rng.gen_bool(0.1),
+            turn: rng.gen_range(-2.0..2.0),
+            left: rng.gen_range(0.3..2.0),
+        }
+    }
+}
+
+/// counters of one reporting interval, or of the whole run
+#[derive(Debug, Default, Clone)]
+struct Stats {
+    inputs_sent: u64,
+    frames: u64,
+    /// ticks skipped between consecutive frames
+    frames_missed: u64,
+    frame_bytes: u64,
+    message_bytes: u64,
+    /// seconds from sending an input to the first frame acknowledging it
+    input_latencies: Vec<f64>,
+    inputs_lost: u64,
+    /// rtt reported by the connections (ms)
+    rtts: Vec<f64>,
+    /// renet rates and loss sampled at the end of the interval, summed over the clients
+    sent_kbps: f64,
+    received_kbps: f64,
+    packet_loss: f64,
+}
+
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        self.inputs_sent += other.inputs_sent;
+        self.frames += other.frames;
+        self.frames_missed += other.frames_missed;
+        self.frame_bytes += other.frame_bytes;
+        self.message_bytes += other.message_bytes;
+        self.input_latencies
+            .extend_from_slice(&other.input_latencies);
+        self.inputs_lost += other.inputs_lost;
+        self.rtts.extend_from_slice(&other.rtts);
+    }
+}
+
+/// Stats of all clients over `seconds`
+struct Report<'a> {
+    stats: &'a Stats,
+    seconds: f64,
+    connected: usize,
+    clients: usize,
+}
+
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    let index = ((sorted.len() - 1) as f64 * fraction).round() as usize;
+    sorted[index]
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = self.stats;
+        let seconds = self.seconds.max(f64::EPSILON);
+        writeln!(
+            f,
+            "{:.1}s, {}/{} clients connected",
+            self.seconds, self.connected, self.clients
+        )?;
+        let connected = self.connected.max(1) as f64;
+        writeln!(
+            f,
+            "  frames: {:.1}/s per client, {} missed, {:.0} bytes avg",
+            stats.frames as f64 / seconds / connected,
+            stats.frames_missed,
+            stats.frame_bytes as f64 / stats.frames.max(1) as f64
+        )?;
+        writeln!(
+            f,
+            "  received: {:.1} kB/s frames + {:.1} kB/s messages total, renet {:.1} kbps in / \
+             {:.1} kbps out per client, {:.1}% loss",
+            stats.frame_bytes as f64 / seconds / 1000.0,
+            stats.message_bytes as f64 / seconds / 1000.0,
+            stats.received_kbps / connected,
+            stats.sent_kbps / connected,
+            stats.packet_loss / connected * 100.0
+        )?;
+        let mut rtts = stats.rtts.clone();
+        rtts.sort_by(f64::total_cmp);
+        if !rtts.is_empty() {
+            writeln!(
+                f,
+                "  rtt: avg {:.1} ms, p95 {:.1} ms, max {:.1} ms",
+                rtts.iter().sum::<f64>() / rtts.len() as f64,
+                percentile(&rtts, 0.95),
+                percentile(&rtts, 1.0)
+            )?;
+        }
+        let mut latencies = stats.input_latencies.clone();
+        latencies.sort_by(f64::total_cmp);
+        if !latencies.is_empty() {
+            writeln!(
+                f,
+                "  input to ack: avg {:.1} ms, p50 {:.1} ms, p95 {:.1} ms, max {:.1} ms, \
+                 {} of {} inputs unacknowledged",
+                latencies.iter().sum::<f64>() / latencies.len() as f64 * 1000.0,
+                percentile(&latencies, 0.5) * 1000.0,
+                percentile(&latencies, 0.95) * 1000.0,
+                percentile(&latencies, 1.0) * 1000.0,
+                stats.inputs_lost,
+                stats.inputs_sent
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct SimulatedClient {
+    id: u64,
+    client: RenetClient,
+    serial: u32,
+    /// newest tick received
+    tick: Option<u32>,
+    /// newest input serial the server acknowledged
+    acked: u32,
+    /// sent, not yet acknowledged inputs with their send time
+    pending: VecDeque<(u32, Instant)>,
+    yaw: f32,
+    pitch: f32,
+    intent: Intent,
+    stats: Stats,
+}
+
+impl SimulatedClient {
+    fn connect(server_addr: SocketAddr, client_id: u64, name: &str) -> Self {
+        let bind_addr = if server_addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind_addr).expect("failed to bind socket");
+        let current_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let authentication = ClientAuthentication::Unsecure {
+            client_id,
+            protocol_id: PROTOCOL_ID,
+            server_addr,
+            user_data: Some(name_to_user_data(name)),
+        };
+        let client = RenetClient::new(
+            current_time,
+            socket,
+            client_id,
+            client_connection_config(),
+            authentication,
+        )
+        .expect("failed to create client");
+        Self {
+            id: client_id,
+            client,
+            serial: 0,
+            tick: None,
+            acked: 0,
+            pending: VecDeque::new(),
+            yaw: 0.0,
+            pitch: 0.0,
+            intent: Intent::default(),
+            stats: Stats::default(),
+        }
+    }
+
+    fn send_input(&mut self, rng: &mut impl Rng, dt: f32, now: Instant, since_start: f64) {
+        self.intent.left -= dt;
+        if self.intent.left <= 0.0 {
+            self.intent = Intent::random(rng);
+        }
+        self.yaw += self.intent.turn * dt;
+        self.pitch = (self.pitch + rng.gen_range(-0.5..0.5) * dt).clamp(-0.5, 0.5);
+        self.serial += 1;
+
+        let player_input = PlayerInput {
+            most_recent_tick: self.tick,
+            serial: self.serial,
+            ..Default::default()
+        };
+        self.client.send_message(
+            ClientChannel::Input.id(),
+            bincode::serialize(&player_input).unwrap(),
+        );
+        let input = FpsControllerInput {
+            serial: self.serial,
+            sprint: self.intent.sprint,
+            jump: rng.gen_bool(0.02),
+            crouch: self.intent.crouch,
+            sent_at: since_start,
+            pitch: self.pitch,
+            yaw: self.yaw,
+            movement: self.intent.movement,
+            ..Default::default()
+        };
+        self.client.send_message(
+            ClientChannel::FcInput.id(),
+            bincode::serialize(&input).unwrap(),
+        );
+        self.pending.push_back((self.serial, now));
+        self.stats.inputs_sent += 1;
+    }
+
+    fn receive(&mut self, now: Instant) {
+        while let Some(message) = self
+            .client
+            .receive_message(ServerChannel::ServerMessages.id())
+        {
+            self.stats.message_bytes += message.len() as u64;
+        }
+        while let Some(message) = self
+            .client
+            .receive_message(ServerChannel::NetworkFrame.id())
+        {
+            self.stats.frame_bytes += message.len() as u64;
+            let frame: NetworkFrame = match bincode::deserialize(&message) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    eprintln!("invalid network frame: {}", e);
+                    continue;
+                }
+            };
+            self.stats.frames += 1;
+            match self.tick {
+                Some(tick) if frame.tick <= tick => continue,
+                Some(tick) => self.stats.frames_missed += (frame.tick - tick - 1) as u64,
+                None => (),
+            }
+            self.tick = Some(frame.tick);
+            self.acked = self.acked.max(frame.last_player_input);
+        }
+        while let Some(&(serial, sent_at)) = self.pending.front() {
+            if serial <= self.acked {
+                self.stats
+                    .input_latencies
+                    .push((now - sent_at).as_secs_f64());
+            } else if now - sent_at > MAX_INPUT_AGE {
+                self.stats.inputs_lost += 1;
+            } else {
+                break;
+            }
+            self.pending.pop_front();
+        }
+    }
+
+    /// hand over the stats of the interval, with a sample of the connection state
+    fn take_stats(&mut self) -> Stats {
+        let info = self.client.network_info();
+        let mut stats = std::mem::take(&mut self.stats);
+        stats.rtts.push(info.rtt as f64);
+        stats.sent_kbps = info.sent_kbps as f64;
+        stats.received_kbps = info.received_kbps as f64;
+        stats.packet_loss = info.packet_loss as f64;
+        stats
+    }
+}
+
+fn main() {
+    let server_addr: SocketAddr = arg_value("server")
+        .unwrap_or_else(|| "127.0.0.1:5000".to_string())
+        .parse()
+        .expect("invalid server address");
+    let count: usize = parsed_arg("clients", 64);
+    let rate: f32 = parsed_arg("rate", 60.0);
+    let duration: f64 = parsed_arg("duration", 60.0);
+    let report_interval: f64 = parsed_arg("report", 5.0);
+    let name = arg_value("name").unwrap_or_else(|| "LoadBot".to_string());
+
+    // ids next to the ones real clients pick (milliseconds since the epoch), unlikely to collide
+    let first_id = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    println!(
+        "connecting {} clients to {}, {} inputs/s each",
+        count, server_addr, rate
+    );
+    let mut clients: Vec<SimulatedClient> = (0..count)
+        .map(|index| {
+            SimulatedClient::connect(
+                server_addr,
+                first_id + index as u64,
+                &format!("{}{}", name, index + 1),
+            )
+        })
+        .collect();
+
+    let mut rng = rand::thread_rng();
+    let step = Duration::from_secs_f32(1.0 / rate);
+    let start = Instant::now();
+    let mut last_update = start;
+    let mut next_step = start;
+    let mut last_report = start;
+    let mut interval = Stats::default();
+    let mut total = Stats::default();
+    loop {
+        let now = Instant::now();
+        let since_start = (now - start).as_secs_f64();
+        let dt = now - last_update;
+        last_update = now;
+        for simulated in &mut clients {
+            if let Err(e) = simulated.client.update(dt) {
+                eprintln!("client {}: {}", simulated.id, e);
+            }
+            if simulated.client.is_connected() {
+                simulated.send_input(&mut rng, dt.as_secs_f32(), now, since_start);
+                simulated.receive(now);
+            }
+            if let Err(e) = simulated.client.send_packets() {
+                eprintln!("client {}: {}", simulated.id, e);
+            }
+        }
+
+        let finished = duration > 0.0 && since_start >= duration;
+        if (now - last_report).as_secs_f64() >= report_interval || finished {
+            for simulated in &mut clients {
+                let stats = simulated.take_stats();
+                interval.sent_kbps += stats.sent_kbps;
+                interval.received_kbps += stats.received_kbps;
+                interval.packet_loss += stats.packet_loss;
+                interval.add(&stats);
+            }
+            let connected = clients
+                .iter()
+                .filter(|simulated| simulated.client.is_connected())
+                .count();
+            print!(
+                "{}",
+                Report {
+                    stats: &interval,
+                    seconds: (now - last_report).as_secs_f64(),
+                    connected,
+                    clients: count,
+                }
+            );
+            total.add(&interval);
+            total.sent_kbps = interval.sent_kbps;
+            total.received_kbps = interval.received_kbps;
+            total.packet_loss = interval.packet_loss;
+            interval = Stats::default();
+            last_report = now;
+            if finished {
+                println!("total:");
+                print!(
+                    "{}",
+                    Report {
+                        stats: &total,
+                        seconds: since_start,
+                        connected,
+                        clients: count,
+                    }
+                );
+                break;
+            }
+        }
+
+        next_step += step;
+        let now = Instant::now();
+        if next_step > now {
+            thread::sleep(next_step - now);
+        } else {
+            // fell behind, don't try to catch up with a burst of inputs
+            next_step = now;
+        }
+    }
+    for simulated in &mut clients {
+        simulated.client.disconnect();
+    }
+}
@@ -1,24 +1,68 @@
 use std::{
-    collections::{HashMap, VecDeque},
-    net::UdpSocket,
-    time::SystemTime,
+    collections::{HashMap, HashSet, VecDeque},
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, SystemTime},
 };
 
-use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
-use bevy_egui::{EguiContext, EguiPlugin};
+use bevy::{
+    app::AppExit,
+    diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    input::InputSystem,
+    prelude::*,
+};
+use bevy_egui::{egui, EguiContext, EguiPlugin};
 use bevy_rapier3d::prelude::*;
 use bevy_renet::{
     renet::{ClientAuthentication, RenetClient, RenetError},
     run_if_client_connected, RenetClientPlugin,
 };
 use renet_test::{
-    client_connection_config,
+    animation::{self, CharacterAnimator, Locomotion},
+    audio::{
+        self, AudioSettings, BulletPassEvent, SoundEvent, SoundKind, Whizz, AUDIO_CONFIG_FILE,
+    },
+    camera::{CameraMode, MAX_FOV, MIN_FOV},
+    character::{self, CharacterModel, CharacterVisual, CHARACTER_CONFIG_FILE},
+    client_connection_config, config,
+    control::{self, ControlPoint, ControlPointState},
     controller::{self, FpsControllerPhysicsBundle},
-    exit_on_esc_system,
-    frame::NetworkFrame,
-    predict::VelocityExtrapolate,
-    setup_level, ClientChannel, ObjectType, PlayerCommand, PlayerInput, ServerChannel,
-    ServerMessages, PLAYER_MOVE_SPEED, PROTOCOL_ID,
+    crosshair::{CrosshairSettings, CROSSHAIR_CONFIG_FILE},
+    effects::{self, EffectEvent, EffectKind},
+    flag::{self, Flag, FlagState},
+    frame::{NetworkFrame, OwnPlayerState},
+    gamemode::{GameModeKind, MatchPhase, MatchSummary, Score, Winner},
+    hazard::{self, HazardZone},
+    identity::{PlayerIdentity, IDENTITY_FILE},
+    input::{
+        self, Action, ActionInput, ActionToggles, Binding, InputMap, WheelInput, INPUT_CONFIG_FILE,
+    },
+    killcam::{KillCam, ViewHistory, ViewSample},
+    killfeed::KillFeed,
+    level::{CurrentLevel, JumpPadEvent, LevelEvent, LevelKind, SurfaceMaterial, TeleportEvent},
+    mapfile::{self, MapManifest},
+    minimap::{self, GunfirePings, MinimapOutline, MINIMAP_RANGE, PING_TIME},
+    mover::{Mover, MovingPlatform},
+    names::{name_to_user_data, DEFAULT_NAME},
+    netcheck,
+    pickup::{self, Pickup},
+    pool::{EntityPools, Free, PoolKind, Pooled},
+    practice::{PracticeRuns, RunRecording, RunSample, MAX_RUN_TIME},
+    predict::{ExtrapolationLimits, VelocityExtrapolate, NETWORK_CONFIG_FILE},
+    prefab::{PrefabRegistry, PREFABS_FILE},
+    projectile::{Ballistic, ProjectileKind, ProjectileKinds},
+    ragdoll, server,
+    spectator::{self, Spectating, SpectatorView},
+    status,
+    targets::PLAYER_CENTER_OFFSET,
+    team::Team,
+    usable::{self, Usable},
+    video::{self, DisplayMode, VideoSettings, VIDEO_CONFIG_FILE},
+    view::{self, CameraShake, ViewEffects, VIEW_CONFIG_FILE},
+    viewmodel::{self, ViewModelEvent},
+    vote::{VoteKind, VoteState},
+    weapon::{self, Inventory, SurfaceImpact, WeaponKind, WeaponSpread},
+    ClientChannel, PlayerCommand, PlayerInput, ServerChannel, ServerInfo, ServerMessages,
+    CRATE_COLOR, CRATE_HALF_SIZE, PLAYER_MOVE_SPEED, PROTOCOL_ID,
 };
 use renet_visualizer::{RenetClientVisualizer, RenetVisualizerStyle};
 use smooth_bevy_cameras::LookTransformPlugin;
@@ -30,6 +74,8 @@ struct NetworkMapping(HashMap<Entity, Entity>);
 struct PlayerInfo {
     client_entity: Entity,
     server_entity: Entity,
+    name: String,
+    spectating: bool,
 }
 
 #[derive(Debug, Default)]
@@ -43,6 +89,27 @@ struct MostRecentTick {
     predicted: u32,
 }
 
+/// joining state. While the server is full we may wait in its join queue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum JoinStatus {
+    Connecting,
+    /// position in the join queue, 1 = next
+    Queued(usize),
+    /// rejected, the server disconnects us
+    ServerFull,
+    Joined,
+    /// the map of the server is missing or differs locally, we disconnected
+    MapMismatch(String),
+    /// left the server from the pause menu
+    Disconnected,
+}
+
+impl Default for JoinStatus {
+    fn default() -> Self {
+        JoinStatus::Connecting
+    }
+}
+
 #[derive(Component, Default)]
 struct PlayerInputQueue {
     queue: VecDeque<PlayerInput>,
@@ -52,20 +119,248 @@ struct PlayerInputQueue {
 #[derive(Component, Default, Debug)]
 struct TransformFromServer(Transform);
 
-fn new_renet_client() -> RenetClient {
-    let server_addr = "127.0.0.1:5000".parse().unwrap();
+/// replicated gameplay state of a remote player, used for visuals only
+#[derive(Component, Default, Debug)]
+struct RemotePlayerState {
+    yaw: f32,
+    pitch: f32,
+    blocking: bool,
+    aiming: bool,
+    shield: f32,
+    damaged: bool,
+    grounded: bool,
+    crouching: bool,
+}
+
+/// shield model attached to remote players, visible while they block
+#[derive(Component)]
+struct ShieldPose;
+
+/// weapon model of remote players: held low at the hip, raised to the eyes while they aim
+/// down sights
+#[derive(Component)]
+struct WeaponPose;
+
+/// how long the hitmarker stays on the crosshair (seconds)
+const HITMARKER_TIME: f64 = 0.25;
+
+/// how long a kill is confirmed below the crosshair (seconds)
+const KILL_CONFIRM_TIME: f64 = 1.0;
+
+/// how long damage numbers float above the victim (seconds)
+const DAMAGE_NUMBER_TIME: f64 = 1.0;
+
+/// how far damage numbers rise while they are shown (m)
+const DAMAGE_NUMBER_RISE: f32 = 0.6;
+
+/// damage numbers start this high above the victim's feet
+const DAMAGE_NUMBER_HEIGHT: f32 = 2.2;
+
+/// hit of the local player confirmed by the server
+#[derive(Debug, Clone, Copy)]
+struct ConfirmedHit {
+    /// local time the confirmation arrived
+    time: f64,
+    amount: f32,
+    headshot: bool,
+    kill: bool,
+    /// feet of the victim
+    position: Vec3,
+}
+
+/// recent confirmed hits: the latest drives the hitmarker, all of them are shown as floating
+/// damage numbers
+#[derive(Default)]
+struct HitFeedback {
+    hits: Vec<ConfirmedHit>,
+}
+
+/// how long a damage direction is shown around the crosshair (seconds)
+const DAMAGE_INDICATOR_TIME: f64 = 1.5;
+
+/// how long the red screen edge of a hit takes to fade (seconds)
+const DAMAGE_VIGNETTE_TIME: f64 = 0.5;
+
+/// damage for the strongest vignette
+const DAMAGE_VIGNETTE_FULL: f32 = 50.0;
+
+/// damage the local player took, reported by the server
+#[derive(Debug, Clone, Copy)]
+struct TakenDamage {
+    /// local time the report arrived
+    time: f64,
+    amount: f32,
+    source: Option<Vec3>,
+}
+
+/// recent damage taken, drives the damage indicators and the vignette
+#[derive(Default)]
+struct DamageFeedback {
+    hits: Vec<TakenDamage>,
+}
+
+/// replicated state of the match
+#[derive(Debug, Default)]
+struct MatchInfo {
+    /// None until the server sent the match state
+    mode: Option<GameModeKind>,
+    phase: Option<MatchPhase>,
+    frag_limit: u32,
+    /// counted down locally between server updates
+    time_left: Option<f32>,
+    /// best first
+    scores: Vec<(u64, Score)>,
+    /// red first
+    team_scores: Vec<(Team, u32)>,
+    /// the match that just ended, shown until the local time `summary_until`
+    summary: Option<MatchSummary>,
+    summary_until: f64,
+    /// map of the server, None until the server sent it
+    map: Option<LevelKind>,
+    /// counts the reloads of the map by the server
+    map_reloads: u32,
+    /// options of the running map vote, empty without a vote
+    map_vote: Vec<LevelKind>,
+    /// votes per option
+    map_votes: Vec<u32>,
+    own_vote: Option<usize>,
+}
+
+/// replicated state of the running player vote
+#[derive(Debug, Default)]
+struct VotePrompt {
+    /// counted down locally between server updates
+    state: Option<VoteState>,
+    /// false for the subject of a vote
+    can_vote: bool,
+    own_vote: Option<bool>,
+    /// outcome of the last vote or why ours was turned down, shown until the local time
+    message: Option<(String, f64)>,
+}
+
+impl VotePrompt {
+    /// F1 and F2 answer the vote until we did
+    fn takes_vote_keys(&self) -> bool {
+        self.state.is_some() && self.can_vote && self.own_vote.is_none()
+    }
+}
+
+/// seconds the outcome of a vote stays on screen
+const VOTE_MESSAGE_TIME: f64 = 5.0;
+
+/// the last message of the server admin, shown until the local time
+#[derive(Debug, Default)]
+struct AdminMessage(Option<(String, f64)>);
+
+/// seconds an admin message stays on screen
+const ADMIN_MESSAGE_TIME: f64 = 8.0;
+
+/// what we connected to, shown on joining until the local time
+#[derive(Debug, Default)]
+struct JoinSplash(Option<(ServerInfo, f64)>);
+
+/// seconds the join splash stays on screen
+const JOIN_SPLASH_TIME: f64 = 10.0;
+
+/// replicated availability of the pickup at `position`
+struct PickupChanged {
+    position: Vec3,
+    available: bool,
+}
+
+/// replicated state of the flags
+struct FlagsChanged(Vec<(Team, FlagState)>);
+
+/// replicated state of the control points by position
+struct ControlPointsChanged(Vec<(Vec3, ControlPointState)>);
+
+/// replicated state of the usable at `position`
+struct UsableChanged {
+    position: Vec3,
+    active: bool,
+}
+
+/// hit effect shell around remote players, visible while they were recently damaged: sparks
+/// while blocking, blood otherwise
+#[derive(Component)]
+struct DamageOverlay;
+
+/// requested player name from `--name=<name>`. The server may change it.
+fn player_name_from_args() -> String {
+    std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.strip_prefix("--name=").map(str::to_string))
+        .unwrap_or_else(|| DEFAULT_NAME.to_string())
+}
+
+/// server address from `--server=<ip:port>`
+fn server_addr_from_args() -> SocketAddr {
+    std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.strip_prefix("--server=").map(str::to_string))
+        .unwrap_or_else(|| server::DEFAULT_SERVER_ADDR.to_string())
+        .parse()
+        .expect("invalid server address")
+}
+
+/// `--offline`: start a server inside the client and connect to it, otherwise the server from
+/// the command line. None if the local server failed to start.
+fn server_addr_or_local_server() -> Option<SocketAddr> {
+    if !std::env::args().any(|arg| arg == "--offline") {
+        return Some(server_addr_from_args());
+    }
+    match server::spawn_local_server() {
+        Ok(addr) => {
+            info!("offline, local server listening on {}", addr);
+            Some(addr)
+        }
+        Err(e) => {
+            error!("failed to start local server: {}", e);
+            None
+        }
+    }
+}
+
+/// `--netcheck`: probe the server for a few seconds, print the results and write them to a
+/// file that can be attached to bug reports
+fn run_netcheck(server_addr: SocketAddr) {
+    println!("checking connection to {} ...", server_addr);
+    match netcheck::run_netcheck(
+        server_addr,
+        Duration::from_secs(5),
+        Duration::from_millis(50),
+    ) {
+        Ok(report) => {
+            print!("{}", report);
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            let path = format!("netcheck-{}.txt", timestamp);
+            match report.write_to(&path) {
+                Ok(()) => println!("results written to {}", path),
+                Err(e) => println!("failed to write {}: {}", path, e),
+            }
+        }
+        Err(e) => println!("network check failed: {}", e),
+    }
+}
+
+fn new_renet_client(server_addr: SocketAddr) -> RenetClient {
     let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
     let connection_config = client_connection_config();
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
     let client_id = current_time.as_millis() as u64;
-    info!("client id 1: {}", client_id);
+    info!(client_id, "client id");
+    let mut user_data = name_to_user_data(&player_name_from_args());
+    PlayerIdentity::load_or_create(IDENTITY_FILE).write_user_data(&mut user_data);
     let authentication = ClientAuthentication::Unsecure {
         client_id,
         protocol_id: PROTOCOL_ID,
         server_addr,
-        user_data: None,
+        user_data: Some(user_data),
     };
 
     RenetClient::new(
@@ -79,8 +374,21 @@ fn new_renet_client() -> RenetClient {
 }
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--netcheck") {
+        run_netcheck(server_addr_from_args());
+        return;
+    }
+
     let mut app = App::new();
+    let video = config::load_or_default::<VideoSettings, _>(VIDEO_CONFIG_FILE);
+    app.insert_resource(video.window_descriptor());
+    app.insert_resource(video);
     app.add_plugins(DefaultPlugins);
+    // after the log plugin, so the local server logs too
+    let server_addr = match server_addr_or_local_server() {
+        Some(server_addr) => server_addr,
+        None => return,
+    };
     app.add_plugin(RenetClientPlugin);
     app.add_plugin(LookTransformPlugin);
     app.add_plugin(FrameTimeDiagnosticsPlugin::default());
@@ -91,23 +399,122 @@ fn main() {
         .add_plugin(RapierDebugRenderPlugin::default());
     app.add_event::<PlayerCommand>();
     app.add_event::<controller::FpsControllerInput>();
+    app.add_event::<PredictedExplosion>();
+    app.add_event::<PickupChanged>();
+    app.add_event::<UsableChanged>();
+    app.add_event::<FlagsChanged>();
+    app.add_event::<ControlPointsChanged>();
+    app.add_event::<ViewModelEvent>();
+    app.add_event::<EffectEvent>();
+    app.add_event::<SoundEvent>();
+    app.add_event::<BulletPassEvent>();
 
     app.insert_resource(ClientLobby::default());
+    app.init_resource::<OwnPlayerState>();
+    app.init_resource::<JoinStatus>();
+    app.insert_resource(PrefabRegistry::load_or_default(PREFABS_FILE));
+    app.insert_resource(CharacterModel::load_or_default(CHARACTER_CONFIG_FILE));
+    app.init_resource::<EntityPools>();
+    app.insert_resource(config::load_or_default::<ExtrapolationLimits, _>(
+        NETWORK_CONFIG_FILE,
+    ));
+    app.init_resource::<ProjectileKinds>();
     app.insert_resource(PlayerInput::default());
-    app.init_resource::<controller::FpsControllerConfig>();
+    app.insert_resource(config::load_or_default::<InputMap, _>(INPUT_CONFIG_FILE));
+    app.init_resource::<ControlsWindow>();
+    app.init_resource::<SettingsMenu>();
+    app.init_resource::<PauseMenu>();
     app.init_resource::<controller::FpsControllerSerial>();
+    app.init_resource::<controller::MouseLook>();
+    app.init_resource::<ActionToggles>();
+    app.init_resource::<WheelInput>();
+    app.insert_resource(Spectating::from_args());
+    app.insert_resource(config::load_or_default::<ViewEffects, _>(VIEW_CONFIG_FILE));
+    app.insert_resource(config::load_or_default::<AudioSettings, _>(
+        AUDIO_CONFIG_FILE,
+    ));
+    app.insert_resource(config::load_or_default::<CrosshairSettings, _>(
+        CROSSHAIR_CONFIG_FILE,
+    ));
+    app.init_resource::<CameraShake>();
+    app.init_resource::<PredictedExplosions>();
+    app.init_resource::<WeaponSpread>();
+    app.init_resource::<HitFeedback>();
+    app.init_resource::<DamageFeedback>();
+    app.init_resource::<MinimapOutline>();
+    app.init_resource::<GunfirePings>();
+    app.init_resource::<Inventory>();
+    app.init_resource::<KillFeed>();
+    app.init_resource::<MatchInfo>();
+    app.init_resource::<VotePrompt>();
+    app.init_resource::<AdminMessage>();
+    app.init_resource::<JoinSplash>();
+    app.init_resource::<ViewHistory>();
+    app.init_resource::<KillCam>();
 
-    app.insert_resource(new_renet_client());
+    app.insert_resource(new_renet_client(server_addr));
     app.insert_resource(NetworkMapping::default());
-    // app.insert_resource(controller::FpsControllerConfig::default());
     // app.insert_resource(PlayerInputQueue::default());
 
-    app.add_system(controller::fps_controller_input);
+    app.add_system_to_stage(
+        CoreStage::PreUpdate,
+        input::update_wheel_input_system.after(InputSystem),
+    );
+    app.add_system_to_stage(
+        CoreStage::PreUpdate,
+        input::update_action_toggles_system.after(input::update_wheel_input_system),
+    );
+    app.add_system(
+        controller::fps_controller_input.with_run_criteria(spectator::run_if_not_spectating),
+    );
     app.add_system(controller::fps_controller_move.after(controller::fps_controller_input));
 
     app.add_system(player_input);
     app.add_system(renet_test::camera::camera_follow);
     app.add_system(renet_test::camera::update_target_system);
+    app.init_resource::<CameraMode>();
+    app.add_system(renet_test::camera::switch_camera_mode_system);
+    app.add_system(
+        renet_test::camera::apply_camera_mode_system
+            .after(renet_test::camera::switch_camera_mode_system),
+    );
+    app.add_system(
+        renet_test::camera::third_person_camera_system.after(controller::fps_controller_render),
+    );
+    app.add_system(view::view_effects_system.after(controller::fps_controller_render));
+    app.init_resource::<renet_test::camera::FovModifiers>();
+    app.add_system(
+        renet_test::camera::first_person_projection_system.after(view::view_effects_system),
+    );
+    app.add_system(view::landing_shake_system.after(controller::fps_controller_move));
+    app.add_system(viewmodel::view_model_camera_system);
+    app.add_system(viewmodel::view_model_reload_system.after(client_sync_players));
+    app.add_system(
+        viewmodel::view_model_system
+            .after(player_input)
+            .after(viewmodel::view_model_reload_system),
+    );
+    app.add_system(
+        view::camera_shake_system
+            .after(view::view_effects_system)
+            .after(renet_test::camera::third_person_camera_system),
+    );
+    app.add_system(spectator_input_system);
+    app.add_system(spectator::free_cam_system.after(spectator_input_system));
+    app.add_system(
+        spectator_camera_system
+            .after(spectator::free_cam_system)
+            .after(renet_test::camera::third_person_camera_system),
+    );
+    app.add_system(update_spectator_visibility_system);
+    app.add_system(record_view_history_system.after(client_sync_players));
+    app.add_system(
+        kill_cam_system
+            .after(record_view_history_system)
+            .after(spectator_camera_system)
+            .after(view::camera_shake_system),
+    );
+    app.add_system(send_spectate_system.with_run_criteria(run_if_client_connected));
     app.add_system(client_send_input.with_run_criteria(run_if_client_connected));
     app.add_system(client_send_player_commands.with_run_criteria(run_if_client_connected));
     app.add_system(client_sync_players.with_run_criteria(run_if_client_connected));
@@ -117,35 +524,178 @@ fn main() {
     //         .after(player_input)
     //         .after(client_sync_players),
     // )
+    app.add_system(
+        predict_projectiles
+            .with_run_criteria(run_if_client_connected)
+            .after(client_sync_players)
+            .before(predict_entities),
+    );
     app.add_system(
         predict_entities
             .with_run_criteria(run_if_client_connected)
             .after(client_sync_players),
     );
+    app.add_system(predicted_explosion_system.after(predict_projectiles));
 
-    app.add_system(exit_on_esc_system);
+    app.add_system(
+        cursor_grab_system
+            .after(player_input)
+            .before(vote_prompt_system),
+    );
+    app.add_startup_system(grab_cursor_on_startup);
 
     app.insert_resource(RenetClientVisualizer::<200>::new(
         RenetVisualizerStyle::default(),
     ));
-    app.add_system(update_visulizer_system);
+    app.init_resource::<PredictionStats>();
+    app.add_system(
+        prediction_stats_system
+            .after(controller::fps_controller_move)
+            .after(client_sync_players),
+    );
+    app.add_system(update_visulizer_system.before(vote_prompt_system));
+    app.add_system(perf_overlay_system.before(vote_prompt_system));
+    app.add_system(controls_window_system.after(settings_menu_system));
+    app.add_system(settings_menu_system.after(pause_menu_system));
+    app.add_system(
+        pause_menu_system
+            .after(player_input)
+            .before(controls_window_system),
+    );
+    app.add_system(hazard::hazard_lifetime_system);
+    app.add_system(hazard_warning_system);
+    app.add_system(update_shield_pose_system);
+    app.add_system(update_weapon_pose_system);
+    app.add_system(update_character_pose_system);
+    app.add_system(animation::animate_characters_system.after(update_character_pose_system));
+    app.add_system(character::character_fallback_system);
+    app.add_system(character::tint_character_system);
+    app.add_system(update_damage_overlay_system);
+    app.add_system(crosshair_system);
+    app.add_system(apply_pickup_changes_system.after(client_sync_players));
+    app.add_system(pickup::pickup_visual_system.after(apply_pickup_changes_system));
+    app.add_system(apply_usable_changes_system.after(client_sync_players));
+    app.add_system(
+        mover_platform_velocity_system
+            .after(client_sync_players)
+            .before(controller::fps_controller_move),
+    );
+    app.add_system(usable::usable_pose_system.after(apply_usable_changes_system));
+    app.add_system(damage_numbers_system.after(crosshair_system));
+    app.add_system(damage_indicator_system.after(client_sync_players));
+    app.init_resource::<PracticeRuns>();
+    app.add_system(renet_test::practice::record_practice_run_system);
+    app.add_system(
+        practice_run_overlay_system.after(renet_test::practice::record_practice_run_system),
+    );
+    app.add_system(player_hud_system);
+    app.add_system(minimap::build_outline_system);
+    app.add_system(minimap_system.after(client_sync_players));
+    app.add_system(kill_feed_system);
+    app.add_system(match_hud_system);
+    app.add_system(match_summary_system);
+    app.add_system(map_vote_input_system);
+    app.add_system(vote_prompt_system.after(client_sync_players));
+    app.add_system(admin_message_system.after(client_sync_players));
+    app.add_system(join_splash_system.after(client_sync_players));
+    app.add_system(player_list_system);
+    app.add_system(apply_map_change_system.after(client_sync_players));
+    app.add_system(apply_flag_changes_system.after(client_sync_players));
+    app.add_system(flag_visual_system.after(apply_flag_changes_system));
+    app.add_system(apply_control_point_changes_system.after(client_sync_players));
+    app.add_system(control_point_color_system.after(apply_control_point_changes_system));
+    app.add_system(apply_own_status_system.after(client_sync_players));
+    app.add_system(apply_match_freeze_system.after(client_sync_players));
+    app.add_system(predicted_level_effect_system);
+    app.add_system(renet_test::despawn_lifetime_system);
+    app.add_system(debris_system);
+    app.add_system(decal_system);
+    app.add_system(
+        effects::spawn_effects_system
+            .after(client_sync_players)
+            .after(predicted_explosion_system),
+    );
+    app.add_system(effects::particle_system);
+    app.add_system(audio::movement_sounds_system);
+    app.add_system(audio::bullet_whizz_system.after(client_sync_players));
+    app.add_system(audio::projectile_whizz_system);
+    app.add_system(
+        audio::play_sounds_system
+            .after(audio::movement_sounds_system)
+            .after(audio::bullet_whizz_system)
+            .after(audio::projectile_whizz_system)
+            .after(player_input)
+            .after(client_sync_players)
+            .after(predicted_explosion_system),
+    );
 
-    app.add_startup_system(setup_level);
+    LevelKind::from_args().add_setup_system(&mut app);
     app.add_startup_system(renet_test::camera::setup_camera);
+    app.add_startup_system(effects::setup_effects);
+    app.add_startup_system(setup_shot_effects);
+    app.add_startup_system(audio::load_sounds_system);
+    app.add_startup_system(renet_test::camera::setup_first_person_camera);
+    app.add_startup_system_to_stage(StartupStage::PostStartup, viewmodel::setup_view_model);
     app.add_startup_system(renet_test::camera::setup_target);
     app.add_startup_system(setup_fps_controller);
+    app.add_startup_system(animation::load_animation_clips_system);
     app.add_system(panic_on_error_system);
+    app.add_system(join_status_window_system);
 
     app.run();
 }
 
-// If any error is found we just panic
-fn panic_on_error_system(mut renet_error: EventReader<RenetError>) {
+// If any error is found we just panic. Being turned away by a full server or leaving because of
+// a missing map is expected, that is only logged.
+fn panic_on_error_system(
+    mut renet_error: EventReader<RenetError>,
+    join_status: Res<JoinStatus>,
+    mut logged: Local<bool>,
+) {
     for e in renet_error.iter() {
-        panic!("{}", e);
+        let reason = match &*join_status {
+            JoinStatus::ServerFull => "server full",
+            JoinStatus::MapMismatch(_) => "map mismatch",
+            JoinStatus::Disconnected => "left the server",
+            _ => panic!("{}", e),
+        };
+        if !*logged {
+            error!("disconnected, {}: {}", reason, e);
+            *logged = true;
+        }
     }
 }
 
+/// waiting room message while the server is full, or why we could not join
+fn join_status_window_system(
+    mut egui_context: ResMut<EguiContext>,
+    join_status: Res<JoinStatus>,
+    mut app_exit_events: EventWriter<AppExit>,
+) {
+    let text = match &*join_status {
+        JoinStatus::Queued(position) => {
+            format!(
+                "Server full, waiting for a free slot.\nPosition in queue: {}",
+                position
+            )
+        }
+        JoinStatus::ServerFull => "Server full, try again later.".to_string(),
+        JoinStatus::MapMismatch(error) => format!("Cannot join: {}", error),
+        JoinStatus::Disconnected => "Disconnected.".to_string(),
+        JoinStatus::Connecting | JoinStatus::Joined => return,
+    };
+    egui::Window::new("join")
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(text);
+            if *join_status == JoinStatus::Disconnected && ui.button("quit").clicked() {
+                app_exit_events.send(AppExit);
+            }
+        });
+}
+
 fn setup_fps_controller(mut commands: Commands) {
     commands
         .spawn_bundle(FpsControllerPhysicsBundle::default())
@@ -168,318 +718,3368 @@ fn setup_fps_controller(mut commands: Commands) {
                                                             // }
         )
         .insert(controller::FpsController { ..default() })
+        .insert(controller::LogicalPlayer(0))
+        .insert(ragdoll::player_collision_groups())
         .insert(Transform::from_xyz(0.0, 3.0, 0.0));
 }
 
+/// frames shown in the prediction graphs, as many as in the network visualizer
+const PREDICTION_GRAPH_SAMPLES: usize = 200;
+
+/// own positions remembered until the server applied their input, about two seconds
+const PREDICTION_HISTORY: usize = 128;
+
+/// server updates where the own player is further off than this count as misprediction (m)
+const MISPREDICTION_DISTANCE: f32 = 0.05;
+
+/// prediction quality for the F1 overlay. The own player is simulated locally, the error is the
+/// distance between the server position and the local position after the same input serial.
+#[derive(Default)]
+struct PredictionStats {
+    /// position of the logical player by last applied input serial
+    predicted: VecDeque<(u32, Vec3)>,
+    /// local times of the mispredictions within the last second
+    mispredictions: VecDeque<f64>,
+    /// error of each server update (m)
+    errors: VecDeque<f32>,
+    /// per frame: mispredictions per second, inputs not applied by the server yet and the
+    /// oldest extrapolation of a remote entity (ticks)
+    misprediction_rate: VecDeque<f32>,
+    input_queue: VecDeque<f32>,
+    extrapolation_age: VecDeque<f32>,
+}
+
+fn push_graph_sample(samples: &mut VecDeque<f32>, value: f32) {
+    if samples.len() >= PREDICTION_GRAPH_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(value);
+}
+
+/// compare the own predicted positions against the server updates and sample the graphs
+#[allow(clippy::type_complexity)]
+fn prediction_stats_system(
+    time: Res<Time>,
+    most_recent_tick: Option<Res<MostRecentTick>>,
+    mut stats: ResMut<PredictionStats>,
+    logical_query: Query<(&Transform, &controller::FpsController), With<controller::LogicalPlayer>>,
+    controlled_query: Query<
+        (
+            ChangeTrackers<TransformFromServer>,
+            &TransformFromServer,
+            &PlayerInputQueue,
+        ),
+        With<renet_test::ControlledPlayer>,
+    >,
+    extrapolated: Query<&VelocityExtrapolate, Without<renet_test::ControlledPlayer>>,
+) {
+    let now = time.seconds_since_startup();
+    let stats = &mut *stats;
+    let mut applied_serial = None;
+    if let Ok((transform, controller)) = logical_query.get_single() {
+        let serial = controller.last_applied_serial;
+        applied_serial = Some(serial);
+        if stats
+            .predicted
+            .back()
+            .map_or(true, |(last, _)| *last != serial)
+        {
+            if stats.predicted.len() >= PREDICTION_HISTORY {
+                stats.predicted.pop_front();
+            }
+            stats.predicted.push_back((serial, transform.translation));
+        }
+    }
+
+    let mut queue_depth = 0;
+    if let Ok((changes, from_server, input_queue)) = controlled_query.get_single() {
+        let server_serial = input_queue.last_server_serial;
+        if let Some(applied_serial) = applied_serial {
+            queue_depth = applied_serial.saturating_sub(server_serial);
+        }
+        if changes.is_changed() {
+            while stats
+                .predicted
+                .front()
+                .map_or(false, |(serial, _)| *serial < server_serial)
+            {
+                stats.predicted.pop_front();
+            }
+            let predicted = stats
+                .predicted
+                .front()
+                .copied()
+                .filter(|(serial, _)| *serial == server_serial);
+            if let Some((_, position)) = predicted {
+                let error = position.distance(from_server.0.translation);
+                push_graph_sample(&mut stats.errors, error);
+                if error > MISPREDICTION_DISTANCE {
+                    debug!(serial = server_serial, error, "misprediction");
+                    stats.mispredictions.push_back(now);
+                }
+            }
+        }
+    }
+    while stats
+        .mispredictions
+        .front()
+        .map_or(false, |time| now - time > 1.0)
+    {
+        stats.mispredictions.pop_front();
+    }
+
+    let extrapolation_age = most_recent_tick.map_or(0, |tick| {
+        extrapolated
+            .iter()
+            .map(|extrapolate| tick.predicted.saturating_sub(extrapolate.base_tick))
+            .max()
+            .unwrap_or(0)
+    });
+    let misprediction_rate = stats.mispredictions.len() as f32;
+    push_graph_sample(&mut stats.misprediction_rate, misprediction_rate);
+    push_graph_sample(&mut stats.input_queue, queue_depth as f32);
+    push_graph_sample(&mut stats.extrapolation_age, extrapolation_age as f32);
+}
+
+/// F1: network visualizer and prediction graphs
 fn update_visulizer_system(
     mut egui_context: ResMut<EguiContext>,
     mut visualizer: ResMut<RenetClientVisualizer<200>>,
     client: Res<RenetClient>,
     mut show_visualizer: Local<bool>,
     keyboard_input: Res<Input<KeyCode>>,
+    vote_prompt: Res<VotePrompt>,
+    stats: Res<PredictionStats>,
 ) {
     visualizer.add_network_info(client.network_info());
-    if keyboard_input.just_pressed(KeyCode::F1) {
+    if keyboard_input.just_pressed(KeyCode::F1) && !vote_prompt.takes_vote_keys() {
         *show_visualizer = !*show_visualizer;
     }
-    if *show_visualizer {
-        visualizer.show_window(egui_context.ctx_mut());
+    if !*show_visualizer {
+        return;
     }
+    visualizer.show_window(egui_context.ctx_mut());
+    egui::Window::new("prediction").show(egui_context.ctx_mut(), |ui| {
+        let graphs = [
+            ("error (m)", &stats.errors),
+            ("mispredictions / s", &stats.misprediction_rate),
+            ("inputs in flight", &stats.input_queue),
+            ("extrapolation (ticks)", &stats.extrapolation_age),
+        ];
+        for (name, samples) in graphs {
+            ui.label(format!(
+                "{}: {:.2}",
+                name,
+                samples.back().copied().unwrap_or_default()
+            ));
+            let line = egui::plot::Line::new(egui::plot::Values::from_values_iter(
+                samples
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| egui::plot::Value::new(i as f64, *value)),
+            ));
+            egui::plot::Plot::new(name)
+                .height(60.0)
+                .width(300.0)
+                .include_y(0.0)
+                .show(ui, |plot_ui| plot_ui.line(line));
+        }
+    });
 }
 
-/// read input into PlayerInput resource and enqueue PlayerCommand::BasicAttack
-// #[allow(clippy::too_many_arguments)]
-fn player_input(
+/// lock and hide the cursor during gameplay, free it for menus
+fn set_cursor_grab(window: &mut Window, grab: bool) {
+    window.set_cursor_lock_mode(grab);
+    window.set_cursor_visibility(!grab);
+}
+
+fn grab_cursor_on_startup(mut windows: ResMut<Windows>) {
+    if let Some(window) = windows.get_primary_mut() {
+        set_cursor_grab(window, true);
+    }
+}
+
+/// opening a menu (F1, F3, F4, F8) releases the cursor, clicking into the game grabs it again
+/// unless the pause menu is open. Runs after player_input so the grabbing click does not attack.
+fn cursor_grab_system(
+    mut windows: ResMut<Windows>,
+    mut egui_context: ResMut<EguiContext>,
     keyboard_input: Res<Input<KeyCode>>,
-    mut player_input: ResMut<PlayerInput>,
     mouse_button_input: Res<Input<MouseButton>>,
-    target_query: Query<&Transform, With<renet_test::WorldSpacePointer>>,
-    mut player_commands: EventWriter<PlayerCommand>,
-    most_recent_tick: Option<Res<MostRecentTick>>,
+    vote_prompt: Res<VotePrompt>,
+    pause_menu: Res<PauseMenu>,
 ) {
-    debug!("player_input");
-    player_input.serial += 1;
-    player_input.left = keyboard_input.pressed(KeyCode::A) || keyboard_input.pressed(KeyCode::Left);
-    player_input.right =
-        keyboard_input.pressed(KeyCode::D) || keyboard_input.pressed(KeyCode::Right);
-    player_input.up = keyboard_input.pressed(KeyCode::W) || keyboard_input.pressed(KeyCode::Up);
-    player_input.down = keyboard_input.pressed(KeyCode::S) || keyboard_input.pressed(KeyCode::Down);
-    player_input.most_recent_tick = most_recent_tick.as_ref().map(|tick| tick.from_server);
-
-    if mouse_button_input.just_pressed(MouseButton::Left) {
-        let target_transform = target_query.single();
-        player_commands.send(PlayerCommand::BasicAttack {
-            cast_at: target_transform.translation,
-        });
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    if window.cursor_locked() {
+        // F1 answers a running vote instead of opening the visualizer
+        let menu_key = keyboard_input.any_just_pressed([KeyCode::F3, KeyCode::F4, KeyCode::F8])
+            || (keyboard_input.just_pressed(KeyCode::F1) && !vote_prompt.takes_vote_keys());
+        if menu_key {
+            set_cursor_grab(window, false);
+        }
+    } else if window.is_focused()
+        && !pause_menu.open
+        && mouse_button_input.just_pressed(MouseButton::Left)
+        && !egui_context.ctx_mut().wants_pointer_input()
+    {
+        set_cursor_grab(window, true);
     }
-    // info!("most recent tick: {:?}", most_recent_tick);
 }
 
-/// serialize and send PlayerInput to server on ClientChannel::Input
-fn client_send_input(
-    player_input: Res<PlayerInput>,
-    mut client: ResMut<RenetClient>,
-    mut player_input_queue: Query<&mut PlayerInputQueue, With<renet_test::ControlledPlayer>>,
-    mut event_reader: EventReader<controller::FpsControllerInput>,
+/// the pause menu is open, gameplay input is off meanwhile
+#[derive(Default)]
+struct PauseMenu {
+    open: bool,
+}
+
+/// Escape: pause menu to resume, change settings, leave the server or quit. The game keeps
+/// running underneath, it is multiplayer.
+#[allow(clippy::too_many_arguments)]
+fn pause_menu_system(
+    mut egui_context: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut windows: ResMut<Windows>,
+    mut pause_menu: ResMut<PauseMenu>,
+    mut settings_menu: ResMut<SettingsMenu>,
+    input_map: Res<InputMap>,
+    (mut client, mut join_status): (ResMut<RenetClient>, ResMut<JoinStatus>),
+    mut app_exit_events: EventWriter<AppExit>,
 ) {
-    if let Ok(mut player_input_queue) = player_input_queue.get_single_mut() {
-        player_input_queue.queue.push_back(*player_input);
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    let mut resume = false;
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        if pause_menu.open {
+            resume = true;
+        } else if input_map.enable_input {
+            // not while rebinding a key, Escape cancels that
+            pause_menu.open = true;
+            set_cursor_grab(window, false);
+        }
     }
-    {
-        let input_message = bincode::serialize(&*player_input).unwrap();
-        client.send_message(ClientChannel::Input.id(), input_message);
+    if pause_menu.open {
+        let connected = !matches!(
+            *join_status,
+            JoinStatus::Disconnected | JoinStatus::MapMismatch(_)
+        );
+        egui::Window::new("paused")
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .collapsible(false)
+            .resizable(false)
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.vertical_centered_justified(|ui| {
+                    if ui.button("resume").clicked() {
+                        resume = true;
+                    }
+                    if ui.button("settings").clicked() {
+                        settings_menu.open = true;
+                    }
+                    if ui
+                        .add_enabled(connected, egui::Button::new("disconnect"))
+                        .clicked()
+                    {
+                        client.disconnect();
+                        *join_status = JoinStatus::Disconnected;
+                        resume = true;
+                    }
+                    if ui.button("quit").clicked() {
+                        client.disconnect();
+                        app_exit_events.send(AppExit);
+                    }
+                });
+            });
     }
-    for input in event_reader.iter() {
-        let input_message = bincode::serialize(input).unwrap();
-        client.send_message(ClientChannel::FcInput.id(), input_message);
+    if resume {
+        pause_menu.open = false;
+        if *join_status != JoinStatus::Disconnected {
+            set_cursor_grab(window, true);
+        }
     }
-    // let input_message = bincode::serialize(&*player_input).unwrap();
-    // client.send_message(ClientChannel::Input.id(), input_message);
 }
 
-/// serialize and send PlayerCommand to server on ClientChannel::Command
-fn client_send_player_commands(
-    mut player_commands: EventReader<PlayerCommand>,
-    mut client: ResMut<RenetClient>,
+/// F2: frame time and entity pool statistics
+fn perf_overlay_system(
+    mut egui_context: ResMut<EguiContext>,
+    diagnostics: Res<Diagnostics>,
+    pools: Res<EntityPools>,
+    mut show_overlay: Local<bool>,
+    keyboard_input: Res<Input<KeyCode>>,
+    vote_prompt: Res<VotePrompt>,
 ) {
-    for command in player_commands.iter() {
-        let command_message = bincode::serialize(command).unwrap();
-        client.send_message(ClientChannel::Command.id(), command_message);
+    if keyboard_input.just_pressed(KeyCode::F2) && !vote_prompt.takes_vote_keys() {
+        *show_overlay = !*show_overlay;
     }
+    if !*show_overlay {
+        return;
+    }
+    egui::Window::new("perf").show(egui_context.ctx_mut(), |ui| {
+        if let Some(fps) = diagnostics
+            .get(FrameTimeDiagnosticsPlugin::FPS)
+            .and_then(|fps| fps.average())
+        {
+            ui.label(format!("fps: {:.1}", fps));
+        }
+        for (kind, stats) in pools.stats() {
+            ui.label(format!(
+                "{:?}: {} in use, {} free, {} spawned, {} reused",
+                kind, stats.in_use, stats.free, stats.spawned, stats.reused
+            ));
+        }
+    });
 }
 
-/// receive ServerChannel::ServerMessage:
-/// - PlayerCreate
-/// - PlayerRemove
-/// - SpawnProjectile (directly spawn entity)
-/// - DespawnProjectile (directly de-spawn entity)
-///
-/// receive ServerChannel::NetworkFrame
-/// - update most_recent_tick
-/// - deserialize & apply transformation updates to entities
-///
-
-#[allow(clippy::too_many_arguments)]
-fn client_sync_players(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut client: ResMut<RenetClient>,
-    mut lobby: ResMut<ClientLobby>,
-    mut network_mapping: ResMut<NetworkMapping>,
-    mut most_recent_tick: Option<ResMut<MostRecentTick>>,
-    mut transform_query: Query<&mut Transform>,
-    mut controlled_player: Query<
-        (&mut PlayerInputQueue, &mut TransformFromServer),
-        With<renet_test::ControlledPlayer>,
-    >,
-    mut extrapolate: Query<
-        (&mut TransformFromServer, &mut VelocityExtrapolate),
-        Without<renet_test::ControlledPlayer>,
-    >,
+/// F3: show and rebind the controls. Changes are saved to the input config file.
+fn controls_window_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut input_map: ResMut<InputMap>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    wheel_input: Res<WheelInput>,
+    gamepad_button_input: Res<Input<GamepadButton>>,
+    mut controls_window: ResMut<ControlsWindow>,
+    mut rebinding: Local<Option<Action>>,
+    pause_menu: Res<PauseMenu>,
 ) {
-    let client_id = client.client_id();
-    while let Some(message) = client.receive_message(ServerChannel::ServerMessages.id()) {
-        let server_message = bincode::deserialize(&message).unwrap();
-        match server_message {
-            ServerMessages::PlayerCreate {
-                id,
-                translation,
-                entity,
-            } => {
-                info!("Player {} connected. {}", id, client_id);
-                let mut client_entity = commands.spawn_bundle(PbrBundle {
-                    mesh: meshes.add(Mesh::from(shape::Capsule::default())),
-                    material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
-                    transform: Transform::from_xyz(translation[0], translation[1], translation[2]),
-                    ..Default::default()
-                });
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        controls_window.0 = !controls_window.0;
+    }
+    if !controls_window.0 {
+        *rebinding = None;
+    }
+
+    // wait for the next key or button press while rebinding
+    if let Some(action) = *rebinding {
+        match input::any_just_pressed(
+            &keyboard_input,
+            &mouse_button_input,
+            &wheel_input,
+            &gamepad_button_input,
+        ) {
+            Some(Binding::Key(KeyCode::Escape)) => *rebinding = None,
+            Some(binding) => {
+                input_map.bind(action, binding);
+                *rebinding = None;
+                if let Err(e) = config::save(&*input_map, INPUT_CONFIG_FILE) {
+                    warn!("failed to save {}: {}", INPUT_CONFIG_FILE, e);
+                }
+            }
+            None => (),
+        }
+    }
+    // no gameplay input while rebinding or paused
+    let enable_input = rebinding.is_none() && !pause_menu.open;
+    if input_map.enable_input != enable_input {
+        input_map.enable_input = enable_input;
+    }
+    if !controls_window.0 {
+        return;
+    }
+
+    egui::Window::new("controls").show(egui_context.ctx_mut(), |ui| {
+        egui::Grid::new("bindings").show(ui, |ui| {
+            for action in Action::ALL {
+                ui.label(format!("{:?}", action));
+                let bindings: Vec<String> = input_map
+                    .bindings(action)
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                ui.label(bindings.join(", "));
+                if *rebinding == Some(action) {
+                    ui.label("press a key (Esc to cancel)");
+                } else if ui.button("add").clicked() {
+                    *rebinding = Some(action);
+                }
+                if ui.button("clear").clicked() {
+                    input_map.clear(action);
+                    if let Err(e) = config::save(&*input_map, INPUT_CONFIG_FILE) {
+                        warn!("failed to save {}: {}", INPUT_CONFIG_FILE, e);
+                    }
+                }
+                let mut toggle = input_map.is_toggle(action);
+                if ui.checkbox(&mut toggle, "toggle").changed() {
+                    input_map.set_toggle(action, toggle);
+                    if let Err(e) = config::save(&*input_map, INPUT_CONFIG_FILE) {
+                        warn!("failed to save {}: {}", INPUT_CONFIG_FILE, e);
+                    }
+                }
+                ui.end_row();
+            }
+        });
+    });
+}
+
+/// the key bindings window is open (F3 or from the settings menu)
+#[derive(Default)]
+struct ControlsWindow(bool);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SettingsTab {
+    Video,
+    Audio,
+    Input,
+    Network,
+}
+
+/// state of the settings menu. Changes apply right away, the changed config files are written
+/// when the menu closes.
+struct SettingsMenu {
+    open: bool,
+    tab: SettingsTab,
+    unsaved: HashSet<&'static str>,
+}
+
+impl Default for SettingsMenu {
+    fn default() -> Self {
+        Self {
+            open: false,
+            tab: SettingsTab::Video,
+            unsaved: HashSet::new(),
+        }
+    }
+}
+
+/// F8: settings menu for the video, audio, input and network config files
+#[allow(clippy::too_many_arguments)]
+fn settings_menu_system(
+    mut egui_context: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut menu: ResMut<SettingsMenu>,
+    mut controls_window: ResMut<ControlsWindow>,
+    mut windows: ResMut<Windows>,
+    (mut video, mut effects, mut audio_settings, mut input_map, mut limits): (
+        ResMut<VideoSettings>,
+        ResMut<ViewEffects>,
+        ResMut<AudioSettings>,
+        ResMut<InputMap>,
+        ResMut<ExtrapolationLimits>,
+    ),
+) {
+    if keyboard_input.just_pressed(KeyCode::F8) {
+        menu.open = !menu.open;
+    }
+    if menu.open {
+        // edit copies, so the resources only change (and trigger change detection) on edits
+        let mut video_edit = video.clone();
+        let (mut fov, mut view_model_fov) = (effects.fov, effects.view_model_fov);
+        let mut audio_edit = audio_settings.clone();
+        let mut mouse = input_map.mouse;
+        let mut limits_edit = limits.clone();
+        let mut open = true;
+        let mut tab = menu.tab;
+        egui::Window::new("settings")
+            .open(&mut open)
+            .collapsible(false)
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut tab, SettingsTab::Video, "video");
+                    ui.selectable_value(&mut tab, SettingsTab::Audio, "audio");
+                    ui.selectable_value(&mut tab, SettingsTab::Input, "input");
+                    ui.selectable_value(&mut tab, SettingsTab::Network, "network");
+                });
+                ui.separator();
+                match tab {
+                    SettingsTab::Video => {
+                        egui::ComboBox::from_label("resolution")
+                            .selected_text(format!("{}x{}", video_edit.width, video_edit.height))
+                            .show_ui(ui, |ui| {
+                                for (width, height) in video::RESOLUTIONS {
+                                    let selected =
+                                        video_edit.width == width && video_edit.height == height;
+                                    if ui
+                                        .selectable_label(selected, format!("{}x{}", width, height))
+                                        .clicked()
+                                    {
+                                        video_edit.width = width;
+                                        video_edit.height = height;
+                                    }
+                                }
+                            });
+                        egui::ComboBox::from_label("window mode")
+                            .selected_text(video_edit.mode.to_string())
+                            .show_ui(ui, |ui| {
+                                for mode in DisplayMode::ALL {
+                                    ui.selectable_value(
+                                        &mut video_edit.mode,
+                                        mode,
+                                        mode.to_string(),
+                                    );
+                                }
+                            });
+                        ui.checkbox(&mut video_edit.vsync, "vsync");
+                        ui.add(egui::Slider::new(&mut fov, MIN_FOV..=MAX_FOV).text("vertical fov"));
+                        ui.add(
+                            egui::Slider::new(&mut view_model_fov, MIN_FOV..=MAX_FOV)
+                                .text("weapon fov"),
+                        );
+                    }
+                    SettingsTab::Audio => {
+                        ui.add(
+                            egui::Slider::new(&mut audio_edit.master_volume, 0.0..=1.0)
+                                .text("master volume"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut audio_edit.effects_volume, 0.0..=1.0)
+                                .text("effects volume"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut audio_edit.footsteps_volume, 0.0..=1.0)
+                                .text("footsteps volume"),
+                        );
+                    }
+                    SettingsTab::Input => {
+                        ui.add(
+                            egui::Slider::new(&mut mouse.sensitivity, 0.0001..=0.01)
+                                .logarithmic(true)
+                                .text("mouse sensitivity"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut mouse.ads_multiplier, 0.1..=1.0)
+                                .text("aiming sensitivity"),
+                        );
+                        ui.checkbox(&mut mouse.invert_y, "invert y");
+                        if ui.button("key bindings").clicked() {
+                            controls_window.0 = true;
+                        }
+                    }
+                    SettingsTab::Network => {
+                        ui.label("extrapolation of other players and projectiles");
+                        ui.add(
+                            egui::Slider::new(&mut limits_edit.max_ticks_erratic, 0..=30)
+                                .text("max ticks (players)"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut limits_edit.max_ticks_steady, 0..=120)
+                                .text("max ticks (projectiles)"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut limits_edit.max_distance, 0.0..=10.0)
+                                .text("max distance"),
+                        );
+                    }
+                }
+            });
+        menu.open = open;
+        if menu.tab != tab {
+            menu.tab = tab;
+        }
+        if *video != video_edit {
+            if let Some(window) = windows.get_primary_mut() {
+                video_edit.apply(window);
+            }
+            *video = video_edit;
+            menu.unsaved.insert(VIDEO_CONFIG_FILE);
+        }
+        if effects.fov != fov || effects.view_model_fov != view_model_fov {
+            effects.fov = fov;
+            effects.view_model_fov = view_model_fov;
+            menu.unsaved.insert(VIEW_CONFIG_FILE);
+        }
+        if *audio_settings != audio_edit {
+            *audio_settings = audio_edit;
+            menu.unsaved.insert(AUDIO_CONFIG_FILE);
+        }
+        if input_map.mouse != mouse {
+            input_map.mouse = mouse;
+            menu.unsaved.insert(INPUT_CONFIG_FILE);
+        }
+        if *limits != limits_edit {
+            *limits = limits_edit;
+            menu.unsaved.insert(NETWORK_CONFIG_FILE);
+        }
+    }
+    if menu.open || menu.unsaved.is_empty() {
+        return;
+    }
+    for file in std::mem::take(&mut menu.unsaved) {
+        let result = match file {
+            VIDEO_CONFIG_FILE => config::save(&*video, file),
+            VIEW_CONFIG_FILE => config::save(&*effects, file),
+            AUDIO_CONFIG_FILE => config::save(&*audio_settings, file),
+            INPUT_CONFIG_FILE => config::save(&*input_map, file),
+            NETWORK_CONFIG_FILE => config::save(&*limits, file),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            warn!("failed to save {}: {}", file, e);
+        }
+    }
+}
+
+/// show a HUD warning while the local player stands inside a hazard zone
+fn hazard_warning_system(
+    mut egui_context: ResMut<EguiContext>,
+    player_query: Query<&Transform, With<controller::FpsController>>,
+    zone_query: Query<(&Transform, &HazardZone)>,
+) {
+    let player_transform = match player_query.get_single() {
+        Ok(transform) => transform,
+        Err(_) => return,
+    };
+    let zone = zone_query.iter().find(|(transform, zone)| {
+        zone.contains(transform.translation, player_transform.translation)
+    });
+
+    if let Some((_, zone)) = zone {
+        egui::Area::new("hazard_warning")
+            .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 40.0))
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.colored_label(
+                    egui::Color32::from_rgb(255, 96, 0),
+                    format!("Warning: you are standing in {}!", zone.kind.label()),
+                );
+            });
+    }
+}
+
+/// read input into PlayerInput resource and enqueue PlayerCommand::BasicAttack,
+/// PlayerCommand::Hitscan, PlayerCommand::Grenade, PlayerCommand::Rocket,
+/// PlayerCommand::Melee and PlayerCommand::Use. These go along the view in the player views, from the eyes to the
+/// pointer in the top down view.
+#[allow(clippy::too_many_arguments)]
+fn player_input(
+    actions: ActionInput,
+    windows: Res<Windows>,
+    mut player_input: ResMut<PlayerInput>,
+    target_query: Query<&Transform, With<renet_test::WorldSpacePointer>>,
+    mut player_commands: EventWriter<PlayerCommand>,
+    most_recent_tick: Option<Res<MostRecentTick>>,
+    spectating: Res<Spectating>,
+    camera_mode: Res<CameraMode>,
+    view_query: Query<&GlobalTransform, (With<controller::RenderPlayer>, With<Camera>)>,
+    logical_query: Query<&Transform, With<controller::LogicalPlayer>>,
+    (time, client, mut weapon_spread, mut mouse_look, own_state, inventory, match_info): (
+        Res<Time>,
+        Res<RenetClient>,
+        ResMut<WeaponSpread>,
+        ResMut<controller::MouseLook>,
+        Res<OwnPlayerState>,
+        Res<Inventory>,
+        Res<MatchInfo>,
+    ),
+    (mut view_model_events, mut sound_events): (
+        EventWriter<ViewModelEvent>,
+        EventWriter<SoundEvent>,
+    ),
+) {
+    debug!("player_input");
+    player_input.serial += 1;
+    player_input.left = actions.pressed(Action::MoveLeft);
+    player_input.right = actions.pressed(Action::MoveRight);
+    player_input.up = actions.pressed(Action::MoveForward);
+    player_input.down = actions.pressed(Action::MoveBack);
+    player_input.most_recent_tick = most_recent_tick.as_ref().map(|tick| tick.from_server);
+
+    let cursor_grabbed = windows
+        .get_primary()
+        .map_or(false, |window| window.cursor_locked());
+    if !cursor_grabbed || spectating.is_spectating() {
+        return;
+    }
+    // the server drops attacks during the countdown
+    let frozen = match_info.phase.map_or(false, |phase| phase.is_frozen());
+    let now = time.seconds_since_startup();
+    if actions.just_pressed(Action::Attack) && !frozen && weapon_spread.try_fireball(now, 0.0) {
+        let target_transform = target_query.single();
+        player_commands.send(PlayerCommand::BasicAttack {
+            cast_at: target_transform.translation,
+        });
+    }
+    // the server drops commands without ammo or within the fire interval, don't show recoil
+    // for them
+    let shoot = actions.just_pressed(Action::Shoot)
+        && !frozen
+        && weapon_spread.ready(WeaponKind::Hitscan, now, 0.0);
+    let ammo = own_state.ammo;
+    let throw_grenade = actions.just_pressed(Action::Grenade)
+        && !frozen
+        && ammo.map_or(true, |ammo| ammo.grenades > 0);
+    let fire_rocket = actions.just_pressed(Action::Rocket)
+        && !frozen
+        && inventory.has(WeaponKind::Rocket)
+        && ammo.map_or(true, |ammo| ammo.rockets > 0)
+        && weapon_spread.ready(WeaponKind::Rocket, now, 0.0);
+    let melee = actions.just_pressed(Action::Melee) && !frozen;
+    let use_key = actions.just_pressed(Action::Use);
+    if !shoot && !throw_grenade && !fire_rocket && !melee && !use_key {
+        return;
+    }
+    let view = if camera_mode.is_player_view() {
+        view_query
+            .get_single()
+            .ok()
+            .map(|view| (view.translation(), view.forward()))
+    } else {
+        logical_query.get_single().ok().map(|player| {
+            let eye = player.translation + Vec3::Y * controller::EYE_HEIGHT;
+            (eye, target_query.single().translation - eye)
+        })
+    };
+    let (origin, direction) = match view {
+        Some(view) => view,
+        None => return,
+    };
+    // mirror the server's spread and apply the recoil of the pattern
+    let mut fire = |weapon: WeaponKind| {
+        let shot = weapon_spread.next_shot();
+        weapon_spread.fire(weapon, now);
+        mouse_look.add_kick(weapon::recoil_kick(weapon, client.client_id(), shot));
+        view_model_events.send(ViewModelEvent::Fire(weapon));
+        sound_events.send(SoundEvent::local(match weapon {
+            WeaponKind::Hitscan => SoundKind::Gunshot,
+            WeaponKind::Rocket => SoundKind::RocketLaunch,
+        }));
+        shot
+    };
+    if shoot {
+        player_commands.send(PlayerCommand::Hitscan {
+            origin,
+            direction,
+            tick: most_recent_tick.as_ref().map(|tick| tick.from_server),
+            shot: fire(WeaponKind::Hitscan),
+        });
+    }
+    if throw_grenade {
+        sound_events.send(SoundEvent::local(SoundKind::GrenadeThrow));
+        player_commands.send(PlayerCommand::Grenade { origin, direction });
+    }
+    if fire_rocket {
+        player_commands.send(PlayerCommand::Rocket {
+            origin,
+            direction,
+            shot: fire(WeaponKind::Rocket),
+        });
+    }
+    if melee {
+        player_commands.send(PlayerCommand::Melee { origin, direction });
+    }
+    if use_key {
+        player_commands.send(PlayerCommand::Use { origin, direction });
+    }
+    // info!("most recent tick: {:?}", most_recent_tick);
+}
+
+/// serialize and send PlayerInput to server on ClientChannel::Input
+fn client_send_input(
+    player_input: Res<PlayerInput>,
+    mut client: ResMut<RenetClient>,
+    mut player_input_queue: Query<&mut PlayerInputQueue, With<renet_test::ControlledPlayer>>,
+    mut event_reader: EventReader<controller::FpsControllerInput>,
+) {
+    if let Ok(mut player_input_queue) = player_input_queue.get_single_mut() {
+        player_input_queue.queue.push_back(*player_input);
+    }
+    {
+        let input_message = bincode::serialize(&*player_input).unwrap();
+        client.send_message(ClientChannel::Input.id(), input_message);
+    }
+    for input in event_reader.iter() {
+        let input_message = bincode::serialize(input).unwrap();
+        client.send_message(ClientChannel::FcInput.id(), input_message);
+    }
+    // let input_message = bincode::serialize(&*player_input).unwrap();
+    // client.send_message(ClientChannel::Input.id(), input_message);
+}
+
+/// serialize and send PlayerCommand to server on ClientChannel::Command
+fn client_send_player_commands(
+    mut player_commands: EventReader<PlayerCommand>,
+    mut client: ResMut<RenetClient>,
+) {
+    for command in player_commands.iter() {
+        let command_message = bincode::serialize(command).unwrap();
+        client.send_message(ClientChannel::Command.id(), command_message);
+    }
+}
+
+/// receive ServerChannel::ServerMessage:
+/// - PlayerCreate
+/// - PlayerRemove
+/// - SpawnProjectile (directly spawn entity)
+/// - DespawnProjectile (directly de-spawn entity)
+///
+/// receive ServerChannel::NetworkFrame
+/// - update most_recent_tick
+/// - deserialize & apply transformation updates to entities
+///
+
+#[allow(clippy::too_many_arguments)]
+fn client_sync_players(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut client: ResMut<RenetClient>,
+    mut lobby: ResMut<ClientLobby>,
+    mut network_mapping: ResMut<NetworkMapping>,
+    mut most_recent_tick: Option<ResMut<MostRecentTick>>,
+    mut transform_query: Query<&mut Transform>,
+    mut controlled_player: Query<
+        (&mut PlayerInputQueue, &mut TransformFromServer),
+        With<renet_test::ControlledPlayer>,
+    >,
+    mut extrapolate: Query<
+        (&mut TransformFromServer, &mut VelocityExtrapolate),
+        Without<renet_test::ControlledPlayer>,
+    >,
+    mut player_states: Query<&mut RemotePlayerState>,
+    (
+        mut own_state,
+        mut join_status,
+        mut camera_shake,
+        mut projectile_settings,
+        mut predicted,
+        mut hit_feedback,
+        time,
+        mut pickup_changes,
+        mut inventory,
+        mut usable_changes,
+        movers,
+        mut kill_feed,
+        mut match_info,
+        mut flag_changes,
+        mut control_point_changes,
+        (
+            mut vote_prompt,
+            mut kill_cam,
+            prefabs,
+            character_model,
+            asset_server,
+            teams,
+            mut effect_events,
+            mut shot_effects,
+            mut sound_events,
+            mut bullet_passes,
+            mut damage_feedback,
+            mut gunfire_pings,
+            mut admin_message,
+            mut join_splash,
+        ),
+    ): (
+        ResMut<OwnPlayerState>,
+        ResMut<JoinStatus>,
+        ResMut<CameraShake>,
+        ResMut<ProjectileKinds>,
+        ResMut<PredictedExplosions>,
+        ResMut<HitFeedback>,
+        Res<Time>,
+        EventWriter<PickupChanged>,
+        ResMut<Inventory>,
+        EventWriter<UsableChanged>,
+        Query<(Entity, &Mover)>,
+        ResMut<KillFeed>,
+        ResMut<MatchInfo>,
+        EventWriter<FlagsChanged>,
+        EventWriter<ControlPointsChanged>,
+        (
+            ResMut<VotePrompt>,
+            ResMut<KillCam>,
+            Res<PrefabRegistry>,
+            Res<CharacterModel>,
+            Res<AssetServer>,
+            Query<&Team>,
+            EventWriter<EffectEvent>,
+            ResMut<ShotEffects>,
+            EventWriter<SoundEvent>,
+            EventWriter<BulletPassEvent>,
+            ResMut<DamageFeedback>,
+            ResMut<GunfirePings>,
+            ResMut<AdminMessage>,
+            ResMut<JoinSplash>,
+        ),
+    ),
+    mut fps_controllers: Query<(Entity, &mut controller::FpsController)>,
+    mut pools: ResMut<EntityPools>,
+    pooled: Query<&Pooled>,
+    mut movement_events: EventWriter<controller::MovementEvent>,
+) {
+    let client_id = client.client_id();
+    while let Some(message) = client.receive_message(ServerChannel::ServerMessages.id()) {
+        let server_message: ServerMessages = bincode::deserialize(&message).unwrap();
+        // broadcasts reach us while waiting in the join queue, the catch-up messages after
+        // JoinAccepted cover them
+        let join_message = matches!(
+            server_message,
+            ServerMessages::JoinAccepted
+                | ServerMessages::QueuePosition { .. }
+                | ServerMessages::ServerFull
+        );
+        if *join_status != JoinStatus::Joined && !join_message {
+            continue;
+        }
+        match server_message {
+            ServerMessages::JoinAccepted => {
+                info!("joined");
+                *join_status = JoinStatus::Joined;
+            }
+            ServerMessages::QueuePosition { position } => {
+                info!("server full, position in join queue: {}", position);
+                *join_status = JoinStatus::Queued(position);
+            }
+            ServerMessages::ServerFull => {
+                warn!("server full");
+                *join_status = JoinStatus::ServerFull;
+            }
+            ServerMessages::PlayerCreate {
+                id,
+                name,
+                translation,
+                entity,
+                team,
+            } => {
+                match team {
+                    Some(team) => info!(
+                        player_id = id,
+                        name = %name,
+                        team = team.label(),
+                        "player connected"
+                    ),
+                    None => info!(player_id = id, name = %name, "player connected"),
+                }
+                let mut client_entity = commands.spawn_bundle(SpatialBundle {
+                    transform: Transform::from_xyz(translation[0], translation[1], translation[2]),
+                    ..Default::default()
+                });
+                client_entity.with_children(|parent| {
+                    character_model.spawn(
+                        parent,
+                        &asset_server,
+                        &mut meshes,
+                        &mut materials,
+                        team.map(|team| team.color()),
+                    );
+                });
 
                 if client_id == id {
-                    info!("controlled player");
+                    info!(client_id, name = %name, "controlled player joined");
                     client_entity
                         .insert(renet_test::ControlledPlayer)
                         .insert(PlayerInputQueue::default());
                 } else {
-                    client_entity.insert(VelocityExtrapolate::default());
+                    client_entity
+                        .insert(VelocityExtrapolate::default())
+                        .insert(RemotePlayerState::default())
+                        .with_children(|parent| {
+                            parent
+                                .spawn_bundle(PbrBundle {
+                                    mesh: meshes.add(Mesh::from(shape::Box::new(0.8, 1.0, 0.05))),
+                                    material: materials.add(Color::rgb(0.4, 0.6, 0.9).into()),
+                                    visibility: Visibility { is_visible: false },
+                                    ..Default::default()
+                                })
+                                .insert(ShieldPose);
+                            parent
+                                .spawn_bundle(PbrBundle {
+                                    mesh: meshes.add(Mesh::from(shape::Box::new(0.08, 0.08, 0.5))),
+                                    material: materials.add(Color::rgb(0.2, 0.2, 0.2).into()),
+                                    ..Default::default()
+                                })
+                                .insert(WeaponPose);
+                            parent
+                                .spawn_bundle(PbrBundle {
+                                    mesh: meshes.add(Mesh::from(shape::Capsule {
+                                        radius: 0.55,
+                                        ..default()
+                                    })),
+                                    material: materials.add(StandardMaterial {
+                                        base_color: Color::rgba(0.8, 0.0, 0.0, 0.5),
+                                        alpha_mode: AlphaMode::Blend,
+                                        unlit: true,
+                                        ..default()
+                                    }),
+                                    visibility: Visibility { is_visible: false },
+                                    ..Default::default()
+                                })
+                                .insert(DamageOverlay);
+                        });
                 }
 
                 client_entity.insert(TransformFromServer::default());
+                if let Some(team) = team {
+                    client_entity.insert(team);
+                }
                 let player_info = PlayerInfo {
                     server_entity: entity,
                     client_entity: client_entity.id(),
+                    name,
+                    spectating: false,
                 };
                 lobby.players.insert(id, player_info);
                 network_mapping.0.insert(entity, client_entity.id());
             }
-            ServerMessages::PlayerRemove { id } => {
-                println!("Player {} disconnected.", id);
-                if let Some(PlayerInfo {
-                    server_entity,
-                    client_entity,
-                }) = lobby.players.remove(&id)
-                {
-                    commands.entity(client_entity).despawn();
-                    network_mapping.0.remove(&server_entity);
+            ServerMessages::PlayerRemove { id } => {
+                println!("Player {} disconnected.", id);
+                if let Some(PlayerInfo {
+                    server_entity,
+                    client_entity,
+                    ..
+                }) = lobby.players.remove(&id)
+                {
+                    commands.entity(client_entity).despawn_recursive();
+                    network_mapping.0.remove(&server_entity);
+                }
+            }
+            ServerMessages::SpawnProjectile {
+                entity,
+                translation,
+                prefab: id,
+                velocity,
+                tick,
+                owner,
+            } => {
+                let prefab = prefabs.get(&id);
+                let kind = match prefab.projectile {
+                    Some(kind) => kind,
+                    None => {
+                        // physics props, moved by the network frames
+                        info!("spawn {}", id);
+                        let bundle = prefab.pbr_bundle(
+                            &mut meshes,
+                            &mut materials,
+                            Transform::from_translation(translation),
+                        );
+                        let mut prop = commands.spawn_bundle(bundle);
+                        prop.insert(TransformFromServer(Transform::from_translation(
+                            translation,
+                        )));
+                        if prefab.replication.extrapolate {
+                            prop.insert(VelocityExtrapolate {
+                                velocity,
+                                base_tick: tick,
+                                ..default()
+                            });
+                        }
+                        network_mapping.0.insert(entity, prop.id());
+                        continue;
+                    }
+                };
+                // grenades are thrown, our own shots are shown by the view model and were
+                // heard when fired
+                let own_projectile = owner == Some(client_id);
+                if kind != ProjectileKind::Grenade && !own_projectile {
+                    effect_events.send(EffectEvent::new(
+                        EffectKind::MuzzleFlash,
+                        translation,
+                        velocity,
+                    ));
+                }
+                if !own_projectile {
+                    let sound = match kind {
+                        ProjectileKind::Grenade => SoundKind::GrenadeThrow,
+                        ProjectileKind::Fireball | ProjectileKind::Rocket => {
+                            SoundKind::RocketLaunch
+                        }
+                    };
+                    sound_events.send(SoundEvent::at(sound, translation));
+                    gunfire_pings.push(translation, time.seconds_since_startup());
+                }
+                let settings = projectile_settings.get(kind).clone();
+                let pool_kind = match kind {
+                    ProjectileKind::Fireball => PoolKind::Projectile,
+                    ProjectileKind::Grenade => PoolKind::Grenade,
+                    ProjectileKind::Rocket => PoolKind::Rocket,
+                };
+                // our own rockets explode in the prediction, ignoring our own collider
+                let own_rocket = kind == ProjectileKind::Rocket && owner == Some(client_id);
+                let own_player = fps_controllers
+                    .iter()
+                    .next()
+                    .map(|(player, _)| player)
+                    .filter(|_| own_rocket);
+                let projectile_entity = pools.acquire(
+                    &mut commands,
+                    pool_kind,
+                    Transform::from_translation(translation),
+                    || {
+                        prefab.pbr_bundle(
+                            &mut meshes,
+                            &mut materials,
+                            Transform::from_translation(translation),
+                        )
+                    },
+                );
+                commands
+                    .entity(projectile_entity)
+                    .insert(TransformFromServer(Transform::from_translation(
+                        translation,
+                    )))
+                    .insert(VelocityExtrapolate {
+                        velocity,
+                        base_tick: tick,
+                        ..default()
+                    })
+                    .insert(Ballistic::new(&settings, velocity, own_player));
+                if own_projectile {
+                    commands.entity(projectile_entity).remove::<Whizz>();
+                } else {
+                    commands.entity(projectile_entity).insert(Whizz::default());
+                }
+                if own_rocket {
+                    commands.entity(projectile_entity).insert(OwnRocket {
+                        server_entity: entity,
+                        exploded: false,
+                    });
+                } else {
+                    commands.entity(projectile_entity).remove::<OwnRocket>();
+                }
+                network_mapping.0.insert(entity, projectile_entity);
+            }
+            ServerMessages::DespawnProjectile { entity } => {
+                predicted.0.remove(&entity);
+                if let Some(entity) = network_mapping.0.remove(&entity) {
+                    match pooled.get(entity) {
+                        Ok(pooled) => pools.release(&mut commands, entity, pooled.0),
+                        Err(_) => commands.entity(entity).despawn(),
+                    }
+                }
+            }
+            ServerMessages::PropDestroyed { entity, position } => {
+                if let Some(entity) = network_mapping.0.remove(&entity) {
+                    commands.entity(entity).despawn();
+                }
+                spawn_debris(&mut commands, &mut meshes, &mut materials, position);
+            }
+            ServerMessages::MovementSettings { settings } => {
+                info!("movement settings: {:?}", settings);
+                for (_, mut fps_controller) in &mut fps_controllers {
+                    settings.apply(&mut fps_controller);
+                }
+            }
+            ServerMessages::HitConfirm {
+                amount,
+                headshot,
+                kill,
+                position,
+            } => {
+                hit_feedback.hits.push(ConfirmedHit {
+                    time: time.seconds_since_startup(),
+                    amount,
+                    headshot,
+                    kill,
+                    position,
+                });
+            }
+            ServerMessages::DamageTaken { amount, source } => {
+                damage_feedback.hits.push(TakenDamage {
+                    time: time.seconds_since_startup(),
+                    amount,
+                    source,
+                });
+                sound_events.send(SoundEvent::local(SoundKind::Hurt));
+            }
+            ServerMessages::MeleeSwing {
+                attacker: _,
+                origin,
+                direction,
+                hit_player,
+            } => {
+                spawn_melee_swing(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    origin,
+                    direction,
+                    hit_player,
+                );
+                if hit_player {
+                    effect_events.send(EffectEvent::new(
+                        EffectKind::BloodPuff,
+                        origin + direction.normalize_or_zero() * weapon::MELEE_RANGE * 0.6,
+                        direction,
+                    ));
+                }
+            }
+            ServerMessages::Explosion {
+                position,
+                radius,
+                projectile,
+            } => {
+                // already shown by the prediction
+                if predicted.0.contains(&projectile) {
+                    continue;
+                }
+                let distance = fps_controllers.iter().next().and_then(|(player, _)| {
+                    let player = transform_query.get(player).ok()?.translation;
+                    Some(player.distance(position))
+                });
+                if let Some(distance) = distance {
+                    camera_shake.add_explosion(distance);
+                }
+                spawn_explosion_effect(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    &mut pools,
+                    position,
+                    radius,
+                );
+                effect_events.send(
+                    EffectEvent::new(EffectKind::Fireball, position, Vec3::ZERO)
+                        .with_scale(radius * 0.5),
+                );
+                sound_events.send(SoundEvent::at(SoundKind::Explosion, position));
+            }
+            ServerMessages::ProjectileSettings { settings } => {
+                info!("projectile settings: {:?}", settings);
+                *projectile_settings = settings;
+            }
+            ServerMessages::Movers {
+                movers: server_movers,
+            } => {
+                for (server_entity, position) in server_movers {
+                    let entity = match movers.iter().find(|(_, mover)| mover.matches(position)) {
+                        Some((entity, _)) => entity,
+                        None => {
+                            warn!("no mover at {:?}", position);
+                            continue;
+                        }
+                    };
+                    commands
+                        .entity(entity)
+                        .insert(TransformFromServer(Transform::from_translation(position)))
+                        .insert(VelocityExtrapolate::default());
+                    network_mapping.0.insert(server_entity, entity);
+                }
+            }
+            ServerMessages::LevelEvent {
+                event: LevelEvent::Use { position, active },
+                player: _,
+            } => {
+                usable_changes.send(UsableChanged { position, active });
+            }
+            ServerMessages::LevelEvent { event, player } => {
+                // our own activations were predicted and already played
+                if player != Some(client_id) {
+                    spawn_level_effect(
+                        &mut commands,
+                        &mut meshes,
+                        &mut materials,
+                        &mut pools,
+                        event,
+                    );
+                }
+            }
+            ServerMessages::MovementEvents { events } => {
+                for (id, kind, position) in events {
+                    if id == client_id {
+                        continue;
+                    }
+                    if let Some(player) = lobby.players.get(&id) {
+                        movement_events.send(controller::MovementEvent {
+                            entity: player.client_entity,
+                            kind,
+                            position,
+                        });
+                    }
+                }
+            }
+            ServerMessages::HitscanShot {
+                shooter,
+                origin,
+                end,
+                hit_player,
+                impacts,
+            } => {
+                let direction = end - origin;
+                if shooter != client_id {
+                    effect_events.send(EffectEvent::new(
+                        EffectKind::MuzzleFlash,
+                        origin,
+                        direction,
+                    ));
+                    sound_events.send(SoundEvent::at(SoundKind::Gunshot, origin));
+                    bullet_passes.send(BulletPassEvent { origin, end });
+                    gunfire_pings.push(origin, time.seconds_since_startup());
+                }
+                if hit_player {
+                    effect_events.send(EffectEvent::new(EffectKind::BloodPuff, end, direction));
+                }
+                for impact in impacts.iter() {
+                    effect_events.send(EffectEvent::new(
+                        EffectKind::ImpactSparks,
+                        impact.position,
+                        impact.normal,
+                    ));
+                }
+                spawn_hitscan_tracer(
+                    &mut commands,
+                    &mut pools,
+                    &shot_effects,
+                    origin,
+                    end,
+                    hit_player,
+                );
+                for impact in impacts {
+                    spawn_impact_decal(
+                        &mut commands,
+                        &mut materials,
+                        &mut pools,
+                        &mut shot_effects,
+                        time.seconds_since_startup(),
+                        &impact,
+                    );
+                }
+            }
+            ServerMessages::PickupTaken {
+                position,
+                kind,
+                player,
+            } => {
+                if player == Some(client_id) {
+                    info!("picked up {}", kind.label());
+                }
+                pickup_changes.send(PickupChanged {
+                    position,
+                    available: false,
+                });
+            }
+            ServerMessages::InventoryChanged {
+                id,
+                inventory: changed,
+            } => {
+                if id == client_id {
+                    *inventory = changed;
+                }
+            }
+            ServerMessages::PickupRespawned { position } => {
+                pickup_changes.send(PickupChanged {
+                    position,
+                    available: true,
+                });
+            }
+            ServerMessages::PlayerSpectate { id, spectating } => {
+                info!("Player {} spectating: {}", id, spectating);
+                if let Some(player) = lobby.players.get_mut(&id) {
+                    player.spectating = spectating;
+                }
+            }
+            ServerMessages::MatchState {
+                mode,
+                phase,
+                time_left,
+                frag_limit,
+            } => {
+                info!("{} match: {:?}", mode.label(), phase);
+                match_info.mode = Some(mode);
+                match_info.phase = Some(phase);
+                match_info.time_left = time_left;
+                match_info.frag_limit = frag_limit;
+            }
+            ServerMessages::ChangeMap { manifest } => {
+                info!("Server map: {}", manifest.map.name());
+                if let Err(e) = check_map_files(&manifest) {
+                    *join_status = JoinStatus::MapMismatch(e);
+                    client.disconnect();
+                    return;
+                }
+                match_info.map = Some(manifest.map);
+            }
+            ServerMessages::ReloadMap { manifest } => {
+                info!("Server reloaded map {}", manifest.map.name());
+                if let Err(e) = check_map_files(&manifest) {
+                    *join_status = JoinStatus::MapMismatch(e);
+                    client.disconnect();
+                    return;
+                }
+                match_info.map_reloads += 1;
+            }
+            ServerMessages::MapVote { options } => {
+                match_info.map_votes = vec![0; options.len()];
+                match_info.map_vote = options;
+                match_info.own_vote = None;
+            }
+            ServerMessages::MapVoteTally { votes } => {
+                match_info.map_votes = votes;
+            }
+            ServerMessages::Vote { state } => {
+                let new_vote = vote_prompt.state.as_ref().map_or(true, |running| {
+                    running.kind != state.kind || running.initiator != state.initiator
+                });
+                if new_vote {
+                    vote_prompt.can_vote = match state.kind {
+                        VoteKind::Kick { player } => player != client_id,
+                    };
+                    // the caller votes yes with the call
+                    vote_prompt.own_vote = if state.initiator == client_id {
+                        Some(true)
+                    } else {
+                        None
+                    };
+                }
+                vote_prompt.state = Some(state);
+            }
+            ServerMessages::VoteEnded { kind, passed } => {
+                vote_prompt.state = None;
+                let question = kind.question(|id| {
+                    lobby
+                        .players
+                        .get(&id)
+                        .map_or_else(|| format!("player {}", id), |info| info.name.clone())
+                });
+                let outcome = if passed { "passed" } else { "failed" };
+                vote_prompt.message = Some((
+                    format!("vote {} {}", question, outcome),
+                    time.seconds_since_startup() + VOTE_MESSAGE_TIME,
+                ));
+            }
+            ServerMessages::VoteRejected { reason } => {
+                vote_prompt.message = Some((
+                    format!("vote not called: {}", reason),
+                    time.seconds_since_startup() + VOTE_MESSAGE_TIME,
+                ));
+            }
+            ServerMessages::ServerInfo { info } => {
+                info!(
+                    server = %info.name,
+                    map = info.map.name(),
+                    mode = info.mode.label(),
+                    "joined server"
+                );
+                join_splash.0 = Some((info, time.seconds_since_startup() + JOIN_SPLASH_TIME));
+            }
+            ServerMessages::AdminMessage { text } => {
+                info!("admin: {}", text);
+                admin_message.0 = Some((text, time.seconds_since_startup() + ADMIN_MESSAGE_TIME));
+            }
+            ServerMessages::MatchSummary { summary, duration } => {
+                match_info.summary = Some(summary);
+                match_info.summary_until = time.seconds_since_startup() + duration as f64;
+            }
+            ServerMessages::Scores {
+                scores,
+                team_scores,
+            } => {
+                match_info.scores = scores;
+                match_info.team_scores = team_scores;
+            }
+            ServerMessages::Flags { flags } => {
+                flag_changes.send(FlagsChanged(flags));
+            }
+            ServerMessages::ControlPoints { points } => {
+                control_point_changes.send(ControlPointsChanged(points));
+            }
+            ServerMessages::KillFeed { event } => {
+                match event.attacker {
+                    Some(attacker) if event.victim == client_id && attacker != client_id => {
+                        kill_cam.start(attacker, time.seconds_since_startup());
+                    }
+                    _ => (),
+                }
+                kill_feed.push(time.seconds_since_startup(), event);
+            }
+            ServerMessages::PlayerDied {
+                id,
+                translation,
+                yaw,
+                velocity,
+            } => {
+                let team = lobby
+                    .players
+                    .get(&id)
+                    .and_then(|player| teams.get(player.client_entity).ok());
+                ragdoll::spawn_ragdoll(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    translation,
+                    yaw,
+                    velocity,
+                    team.map_or(Color::rgb(0.8, 0.7, 0.6), |team| team.color()),
+                );
+            }
+            ServerMessages::PlayerRespawn {
+                id,
+                translation,
+                cause,
+            } => {
+                match cause {
+                    Some(cause) => info!("Player {} {}, respawned.", id, cause.label()),
+                    None => info!("Player {} respawned for the new match.", id),
+                }
+                // a new match starts without replays of the last one
+                if id == client_id && cause.is_none() {
+                    kill_cam.stop();
+                }
+                // the local controller is only simulated here, move it ourselves
+                if id == client_id {
+                    for (entity, mut fps_controller) in &mut fps_controllers {
+                        fps_controller.velocity = Vec3::ZERO;
+                        fps_controller.impulse = Vec3::ZERO;
+                        if let Ok(mut transform) = transform_query.get_mut(entity) {
+                            transform.translation = translation;
+                        }
+                    }
+                }
+            }
+            ServerMessages::PlayerImpulse { impulse, source } => {
+                if matches!(source, Some(source) if predicted.0.contains(&source)) {
+                    continue;
+                }
+                for (_, mut fps_controller) in &mut fps_controllers {
+                    fps_controller.add_impulse(impulse);
+                }
+            }
+            ServerMessages::SpawnHazardZone {
+                translation,
+                kind,
+                radius,
+                lifetime,
+            } => {
+                hazard::spawn_hazard_zone(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    translation,
+                    HazardZone::new(kind, radius, lifetime),
+                );
+            }
+        }
+    }
+
+    while let Some(message) = client.receive_message(ServerChannel::NetworkFrame.id()) {
+        let frame: NetworkFrame = bincode::deserialize(&message).unwrap();
+        // info!("network frame");
+        match most_recent_tick {
+            None => {
+                commands.insert_resource(MostRecentTick {
+                    from_server: frame.tick,
+                    predicted: frame.tick,
+                });
+            }
+            Some(ref mut tick) if tick.from_server < frame.tick => {
+                tick.from_server = frame.tick;
+                tick.predicted = frame.tick;
+                //  = Some(MostRecentTick {
+                //     from_server: frame.tick,
+                //     predicted: frame.tick,
+                // })
+            }
+            _ => continue,
+        }
+        let _frame_span = debug_span!(
+            "frame",
+            client_id,
+            tick = frame.tick,
+            serial = frame.last_player_input
+        )
+        .entered();
+        if let (Some(old_health), Some(health)) = (own_state.health, frame.own.health) {
+            camera_shake.add_trauma((old_health - health) * view::DAMAGE_TRAUMA);
+        }
+        *own_state = frame.own;
+
+        for i in 0..frame.entities.entities.len() {
+            trace!(
+                index = i,
+                server_entity = ?frame.entities.entities[i],
+                entity = ?network_mapping.0.get(&frame.entities.entities[i]),
+                "entity update"
+            );
+
+            if let Some(entity) = network_mapping.0.get(&frame.entities.entities[i]) {
+                let translation = frame.entities.translations[i];
+                // let rotation = frame.entities.rotations[i];
+                let transform = Transform {
+                    translation,
+                    // rotation,
+                    ..Default::default()
+                };
+
+                if let Ok(old_transform) = transform_query.get(*entity) {
+                    trace!(
+                        ?entity,
+                        from = ?old_transform.translation,
+                        to = ?transform.translation,
+                        "apply transform"
+                    );
+                }
+
+                if let Ok((mut player_input_queue, mut transform_from_server)) =
+                    controlled_player.get_mut(*entity)
+                {
+                    debug!(translation = ?transform.translation, "own player update");
+                    *transform_from_server = TransformFromServer(transform);
+                    player_input_queue.last_server_serial = frame.last_player_input;
+                }
+                if let Ok(mut ent_transform) = transform_query.get_mut(*entity) {
+                    *ent_transform = transform;
+                }
+                if let Ok((mut transform_from_server, mut extrapolate)) =
+                    extrapolate.get_mut(*entity)
+                {
+                    *transform_from_server = TransformFromServer(transform);
+                    extrapolate.update(frame.entities.velocities[i], frame.tick);
+                }
+            }
+        }
+        for i in 0..frame.with_rotation.entities.len() {
+            trace!(
+                index = i,
+                server_entity = ?frame.with_rotation.entities[i],
+                entity = ?network_mapping.0.get(&frame.with_rotation.entities[i]),
+                "entity update"
+            );
+
+            if let Some(entity) = network_mapping.0.get(&frame.with_rotation.entities[i]) {
+                let translation = frame.with_rotation.translations[i];
+                let rotation = frame.with_rotation.rotations[i];
+                let transform = Transform {
+                    translation,
+                    rotation,
+                    ..Default::default()
+                };
+
+                if let Ok(old_transform) = transform_query.get(*entity) {
+                    trace!(
+                        ?entity,
+                        from = ?old_transform.translation,
+                        to = ?transform.translation,
+                        "apply transform"
+                    );
+                }
+
+                if let Ok((mut player_input_queue, mut transform_from_server)) =
+                    controlled_player.get_mut(*entity)
+                {
+                    *transform_from_server = TransformFromServer(transform);
+                    player_input_queue.last_server_serial = frame.last_player_input;
+                }
+                if let Ok(mut ent_transform) = transform_query.get_mut(*entity) {
+                    *ent_transform = transform;
+                }
+                if let Ok((mut transform_from_server, mut extrapolate)) =
+                    extrapolate.get_mut(*entity)
+                {
+                    *transform_from_server = TransformFromServer(transform);
+                    extrapolate.update(frame.with_rotation.velocities[i], frame.tick);
+                }
+            }
+        }
+        for i in 0..frame.players.entities.len() {
+            if let Some(entity) = network_mapping.0.get(&frame.players.entities[i]) {
+                if let Ok(mut state) = player_states.get_mut(*entity) {
+                    state.yaw = frame.players.yaws[i];
+                    state.pitch = frame.players.pitches[i];
+                    state.blocking = frame.players.blocking[i];
+                    state.aiming = frame.players.aiming[i];
+                    state.shield = frame.players.shields[i];
+                    state.damaged = frame.players.damaged[i];
+                    state.grounded = frame.players.grounded[i];
+                    state.crouching = frame.players.crouching[i];
+                }
+            }
+        }
+    }
+}
+
+/// client ids of players that can be followed, sorted for a stable cycling order
+fn follow_targets(lobby: &ClientLobby, client_id: u64) -> Vec<u64> {
+    let mut targets: Vec<u64> = lobby
+        .players
+        .iter()
+        .filter(|(id, player)| **id != client_id && !player.spectating)
+        .map(|(id, _)| *id)
+        .collect();
+    targets.sort_unstable();
+    targets
+}
+
+/// F7: start/stop spectating. While spectating Attack follows the next player, Block the
+/// previous one and Jump returns to the free camera.
+fn spectator_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    actions: ActionInput,
+    windows: Res<Windows>,
+    client: Res<RenetClient>,
+    lobby: Res<ClientLobby>,
+    mut spectating: ResMut<Spectating>,
+    camera_query: Query<&GlobalTransform, (With<controller::RenderPlayer>, With<Camera>)>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F7) {
+        if spectating.is_spectating() {
+            spectating.view = None;
+        } else {
+            // the free camera starts at the current player view
+            if let Ok(transform) = camera_query.get_single() {
+                spectating.free_cam.set_from(transform);
+            }
+            spectating.view = Some(SpectatorView::FreeCam);
+        }
+    }
+    if !spectating.is_spectating() {
+        return;
+    }
+    let targets = follow_targets(&lobby, client.client_id());
+    // the followed player left or started spectating itself
+    if let Some(followed) = spectating.followed() {
+        if !targets.contains(&followed) {
+            spectating.cycle(&targets, true);
+        }
+    }
+    let cursor_grabbed = windows
+        .get_primary()
+        .map_or(false, |window| window.cursor_locked());
+    if !cursor_grabbed {
+        return;
+    }
+    if actions.just_pressed(Action::Attack) {
+        spectating.cycle(&targets, true);
+    } else if actions.just_pressed(Action::Block) {
+        spectating.cycle(&targets, false);
+    } else if spectating.followed().is_some() && actions.just_pressed(Action::Jump) {
+        // continue flying from the followed view
+        if let Ok(transform) = camera_query.get_single() {
+            spectating.free_cam.set_from(transform);
+        }
+        spectating.view = Some(SpectatorView::FreeCam);
+    }
+}
+
+/// tell the server when spectating starts or stops, also on connect for `--spectate`
+fn send_spectate_system(
+    spectating: Res<Spectating>,
+    mut player_commands: EventWriter<PlayerCommand>,
+    mut sent: Local<bool>,
+) {
+    if spectating.is_spectating() != *sent {
+        *sent = spectating.is_spectating();
+        player_commands.send(PlayerCommand::Spectate { spectate: *sent });
+    }
+}
+
+/// put the player view camera at the free camera or the eyes of the followed player
+fn spectator_camera_system(
+    spectating: Res<Spectating>,
+    lobby: Res<ClientLobby>,
+    player_query: Query<(&Transform, &RemotePlayerState), Without<Camera>>,
+    mut camera_query: Query<&mut Transform, (With<controller::RenderPlayer>, With<Camera>)>,
+) {
+    let transform = match spectating.view {
+        None => return,
+        Some(SpectatorView::FreeCam) => spectating.free_cam.transform(),
+        Some(SpectatorView::Follow(id)) => {
+            let (player_transform, state) = match lobby
+                .players
+                .get(&id)
+                .and_then(|player| player_query.get(player.client_entity).ok())
+            {
+                Some(player) => player,
+                None => return,
+            };
+            Transform::from_translation(
+                player_transform.translation + Vec3::Y * controller::EYE_HEIGHT,
+            )
+            .with_rotation(controller::look_quat(state.pitch, state.yaw))
+        }
+    };
+    for mut camera_transform in &mut camera_query {
+        *camera_transform = transform;
+    }
+}
+
+/// hide the models of spectating players, and of the followed player or killer whose eyes we
+/// look through
+fn update_spectator_visibility_system(
+    spectating: Res<Spectating>,
+    kill_cam: Res<KillCam>,
+    lobby: Res<ClientLobby>,
+    mut player_query: Query<&mut Visibility, With<RemotePlayerState>>,
+) {
+    for (id, player) in lobby.players.iter() {
+        if let Ok(mut visibility) = player_query.get_mut(player.client_entity) {
+            let visible = !player.spectating
+                && spectating.followed() != Some(*id)
+                && kill_cam.killer != Some(*id);
+            if visibility.is_visible != visible {
+                visibility.is_visible = visible;
+            }
+        }
+    }
+}
+
+/// remember where the remote players were and where they looked, for the kill cam
+fn record_view_history_system(
+    time: Res<Time>,
+    lobby: Res<ClientLobby>,
+    player_query: Query<(&Transform, &RemotePlayerState)>,
+    mut history: ResMut<ViewHistory>,
+) {
+    let now = time.seconds_since_startup();
+    for (id, player) in lobby.players.iter() {
+        if let Ok((transform, state)) = player_query.get(player.client_entity) {
+            history.record(
+                *id,
+                ViewSample {
+                    time: now,
+                    position: transform.translation,
+                    yaw: state.yaw,
+                    pitch: state.pitch,
+                },
+            );
+        }
+    }
+    history.retain_players(|id| lobby.players.contains_key(&id));
+}
+
+/// after a death replay the view of the killer, Jump skips the replay. Overrides the player
+/// view and spectator cameras while it runs.
+fn kill_cam_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    actions: ActionInput,
+    lobby: Res<ClientLobby>,
+    history: Res<ViewHistory>,
+    mut kill_cam: ResMut<KillCam>,
+    mut camera_query: Query<&mut Transform, (With<controller::RenderPlayer>, With<Camera>)>,
+) {
+    let killer = match kill_cam.killer {
+        Some(killer) => killer,
+        None => return,
+    };
+    let now = time.seconds_since_startup();
+    let eye = match history.eye_at(killer, kill_cam.replay_time(now)) {
+        Some(eye) if now <= kill_cam.until && !actions.just_pressed(Action::Jump) => eye,
+        // done, skipped, or nothing recorded of the killer
+        _ => {
+            kill_cam.stop();
+            return;
+        }
+    };
+    for mut camera_transform in &mut camera_query {
+        *camera_transform = eye;
+    }
+    let name = lobby
+        .players
+        .get(&killer)
+        .map_or_else(|| format!("player {}", killer), |info| info.name.clone());
+    egui::Area::new("kill_cam")
+        .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0.0, -80.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.heading(format!("killed by {}", name));
+            ui.label("Jump to skip");
+        });
+}
+
+/// shared meshes and materials of the hitscan effects, and the bullet holes on the walls
+struct ShotEffects {
+    tracer_mesh: Handle<Mesh>,
+    tracer_material: Handle<StandardMaterial>,
+    flash_mesh: Handle<Mesh>,
+    player_flash_material: Handle<StandardMaterial>,
+    world_flash_material: Handle<StandardMaterial>,
+    decal_mesh: Handle<Mesh>,
+    decal_materials: HashMap<SurfaceMaterial, Handle<StandardMaterial>>,
+    /// live decals with the time they expire, oldest first
+    decals: VecDeque<(Entity, f64)>,
+}
+
+fn setup_shot_effects(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut unlit = |color: Color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            unlit: true,
+            ..default()
+        })
+    };
+    commands.insert_resource(ShotEffects {
+        // unit length along z, scaled to the shot
+        tracer_mesh: meshes.add(Mesh::from(shape::Box::new(0.02, 0.02, 1.0))),
+        tracer_material: unlit(Color::rgb(1.0, 1.0, 0.7)),
+        flash_mesh: meshes.add(Mesh::from(shape::Icosphere {
+            radius: 0.08,
+            subdivisions: 2,
+        })),
+        player_flash_material: unlit(Color::rgb(1.0, 0.1, 0.1)),
+        world_flash_material: unlit(Color::rgb(1.0, 0.8, 0.2)),
+        // unit size, scaled by the hole size
+        decal_mesh: meshes.add(Mesh::from(shape::Box::new(1.0, 1.0, 0.002))),
+        decal_materials: HashMap::new(),
+        decals: VecDeque::new(),
+    });
+}
+
+/// short lived line from the muzzle to the impact, with an impact flash: red on players,
+/// yellow on the world
+fn spawn_hitscan_tracer(
+    commands: &mut Commands,
+    pools: &mut EntityPools,
+    effects: &ShotEffects,
+    origin: Vec3,
+    end: Vec3,
+    hit_player: bool,
+) {
+    let length = origin.distance(end);
+    if length <= f32::EPSILON {
+        return;
+    }
+    let transform = Transform::from_translation(origin.lerp(end, 0.5))
+        .looking_at(end, Vec3::Y)
+        .with_scale(Vec3::new(1.0, 1.0, length));
+    let tracer = pools.acquire(commands, PoolKind::Tracer, transform, || PbrBundle {
+        mesh: effects.tracer_mesh.clone(),
+        material: effects.tracer_material.clone(),
+        ..default()
+    });
+    commands
+        .entity(tracer)
+        .insert(renet_test::Lifetime(Timer::from_seconds(
+            weapon::TRACER_LIFETIME,
+            false,
+        )));
+    let flash_material = if hit_player {
+        effects.player_flash_material.clone()
+    } else {
+        effects.world_flash_material.clone()
+    };
+    let transform = Transform::from_translation(end);
+    let flash = pools.acquire(commands, PoolKind::ImpactFlash, transform, || PbrBundle {
+        mesh: effects.flash_mesh.clone(),
+        material: flash_material.clone(),
+        ..default()
+    });
+    commands
+        .entity(flash)
+        .insert(flash_material)
+        .insert(renet_test::Lifetime(Timer::from_seconds(
+            weapon::TRACER_LIFETIME * 2.0,
+            false,
+        )));
+}
+
+/// bullet hole on a surface, exit holes are larger. Beyond weapon::MAX_DECALS the oldest hole
+/// makes room.
+fn spawn_impact_decal(
+    commands: &mut Commands,
+    materials: &mut Assets<StandardMaterial>,
+    pools: &mut EntityPools,
+    effects: &mut ShotEffects,
+    now: f64,
+    impact: &SurfaceImpact,
+) {
+    let size = if impact.exit { 0.12 } else { 0.08 };
+    let up = if impact.normal.y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    };
+    // slightly off the surface to avoid z-fighting, the thin side along the normal
+    let position = impact.position + impact.normal * 0.005;
+    let transform = Transform::from_translation(position)
+        .looking_at(position + impact.normal, up)
+        .with_scale(Vec3::new(size, size, 1.0));
+    let material = effects
+        .decal_materials
+        .entry(impact.material)
+        .or_insert_with(|| {
+            materials.add(StandardMaterial {
+                base_color: impact.material.decal_color(),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            })
+        })
+        .clone();
+    if effects.decals.len() >= weapon::MAX_DECALS {
+        if let Some((oldest, _)) = effects.decals.pop_front() {
+            pools.release(commands, oldest, PoolKind::Decal);
+        }
+    }
+    let mesh = effects.decal_mesh.clone();
+    let decal = pools.acquire(commands, PoolKind::Decal, transform, || PbrBundle {
+        mesh,
+        material: material.clone(),
+        ..default()
+    });
+    commands.entity(decal).insert(material);
+    effects
+        .decals
+        .push_back((decal, now + weapon::DECAL_LIFETIME as f64));
+}
+
+/// return expired bullet holes to their pool
+fn decal_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pools: ResMut<EntityPools>,
+    mut effects: ResMut<ShotEffects>,
+) {
+    let now = time.seconds_since_startup();
+    while let Some(&(decal, expires)) = effects.decals.front() {
+        if expires > now {
+            break;
+        }
+        effects.decals.pop_front();
+        pools.release(&mut commands, decal, PoolKind::Decal);
+    }
+}
+
+/// short flat slab across the reach of a melee swing, red if it hit a player
+fn spawn_melee_swing(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    origin: Vec3,
+    direction: Vec3,
+    hit_player: bool,
+) {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return;
+    }
+    let color = if hit_player {
+        Color::rgba(1.0, 0.2, 0.2, 0.5)
+    } else {
+        Color::rgba(1.0, 1.0, 1.0, 0.3)
+    };
+    let center = origin + direction * weapon::MELEE_RANGE * 0.6;
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(
+                weapon::MELEE_RADIUS * 3.0,
+                0.02,
+                weapon::MELEE_RANGE * 0.5,
+            ))),
+            material: materials.add(StandardMaterial {
+                base_color: color,
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            }),
+            transform: Transform::from_translation(center).looking_at(center + direction, Vec3::Y),
+            ..default()
+        })
+        .insert(renet_test::Lifetime(Timer::from_seconds(
+            weapon::MELEE_SWING_TIME,
+            false,
+        )));
+}
+
+/// debris chunk flying off a destroyed prop, local only
+#[derive(Component)]
+struct Debris {
+    velocity: Vec3,
+}
+
+const DEBRIS_COUNT: usize = 8;
+const DEBRIS_LIFETIME: f32 = 1.5;
+
+/// burst of small chunks where a prop was destroyed
+fn spawn_debris(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    position: Vec3,
+) {
+    let mesh = meshes.add(Mesh::from(shape::Cube::new(CRATE_HALF_SIZE * 0.4)));
+    let material = materials.add(CRATE_COLOR.into());
+    for _ in 0..DEBRIS_COUNT {
+        let direction = Vec3::new(
+            rand::random::<f32>() - 0.5,
+            rand::random::<f32>(),
+            rand::random::<f32>() - 0.5,
+        )
+        .normalize_or_zero();
+        let offset = direction * CRATE_HALF_SIZE * 0.5;
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: mesh.clone(),
+                material: material.clone(),
+                transform: Transform::from_translation(position + offset),
+                ..default()
+            })
+            .insert(Debris {
+                velocity: direction * (2.0 + rand::random::<f32>() * 3.0),
+            })
+            .insert(renet_test::Lifetime(Timer::from_seconds(
+                DEBRIS_LIFETIME,
+                false,
+            )));
+    }
+}
+
+/// let debris fly and tumble, ignoring collisions
+fn debris_system(time: Res<Time>, mut debris: Query<(&mut Debris, &mut Transform)>) {
+    let dt = time.delta_seconds();
+    for (mut debris, mut transform) in &mut debris {
+        debris.velocity.y -= 9.81 * dt;
+        transform.translation += debris.velocity * dt;
+        transform.rotate_x(6.0 * dt);
+    }
+}
+
+/// expanding flash at an explosion, sized by the explosion radius
+fn spawn_explosion_effect(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    pools: &mut EntityPools,
+    position: Vec3,
+    radius: f32,
+) {
+    let transform = Transform::from_translation(position).with_scale(Vec3::splat(radius * 0.5));
+    let entity = pools.acquire(commands, PoolKind::ExplosionEffect, transform, || {
+        PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Icosphere {
+                radius: 1.0,
+                subdivisions: 3,
+            })),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgba(1.0, 0.6, 0.1, 0.6),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            }),
+            ..default()
+        }
+    });
+    commands
+        .entity(entity)
+        .insert(renet_test::Lifetime(Timer::from_seconds(0.25, false)));
+}
+
+/// short ring flash at activated level devices
+fn spawn_level_effect(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    pools: &mut EntityPools,
+    event: LevelEvent,
+) {
+    let (kind, color, positions) = match event {
+        LevelEvent::JumpPad { position } => (
+            PoolKind::JumpPadEffect,
+            Color::rgb(0.6, 1.0, 1.0),
+            vec![position],
+        ),
+        LevelEvent::Teleport { from, to } => (
+            PoolKind::TeleportEffect,
+            Color::rgb(0.9, 0.6, 1.0),
+            vec![from, to],
+        ),
+        // usables show their state themselves
+        LevelEvent::Use { .. } => return,
+    };
+    for position in positions {
+        let transform = Transform::from_translation(position + Vec3::Y * 0.2);
+        let entity = pools.acquire(commands, kind, transform, || PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Torus {
+                radius: 0.5,
+                ring_radius: 0.05,
+                ..default()
+            })),
+            material: materials.add(StandardMaterial {
+                base_color: color,
+                unlit: true,
+                ..default()
+            }),
+            ..default()
+        });
+        commands
+            .entity(entity)
+            .insert(renet_test::Lifetime(Timer::from_seconds(0.3, false)));
+    }
+}
+
+/// effects for level devices activated by the locally predicted player
+fn predicted_level_effect_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut jump_pad_events: EventReader<JumpPadEvent>,
+    mut teleport_events: EventReader<TeleportEvent>,
+    pads: Query<&Transform>,
+    mut pools: ResMut<EntityPools>,
+) {
+    for event in jump_pad_events.iter() {
+        let position = pads
+            .get(event.pad)
+            .map_or(event.position, |transform| transform.translation);
+        let event = LevelEvent::JumpPad { position };
+        spawn_level_effect(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &mut pools,
+            event,
+        );
+    }
+    for event in teleport_events.iter() {
+        let event = LevelEvent::Teleport {
+            from: event.from,
+            to: event.to,
+        };
+        spawn_level_effect(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &mut pools,
+            event,
+        );
+    }
+}
+
+/// show the shield in front of remote players while they are blocking
+fn update_shield_pose_system(
+    player_query: Query<&RemotePlayerState>,
+    mut shield_query: Query<(&Parent, &mut Visibility, &mut Transform), With<ShieldPose>>,
+) {
+    for (parent, mut visibility, mut transform) in &mut shield_query {
+        if let Ok(state) = player_query.get(parent.get()) {
+            visibility.is_visible = state.blocking;
+            let rotation = Quat::from_rotation_y(state.yaw);
+            transform.rotation = rotation;
+            transform.translation = rotation * Vec3::new(0.0, 0.0, -0.6);
+        }
+    }
+}
+
+/// hold the weapon of remote players along their view, shouldered while aiming down sights
+fn update_weapon_pose_system(
+    player_query: Query<&RemotePlayerState>,
+    mut weapon_query: Query<(&Parent, &mut Transform), With<WeaponPose>>,
+) {
+    for (parent, mut transform) in &mut weapon_query {
+        if let Ok(state) = player_query.get(parent.get()) {
+            let yaw = Quat::from_rotation_y(state.yaw);
+            let (offset, pitch) = if state.aiming {
+                (Vec3::new(0.0, 0.45, -0.5), state.pitch)
+            } else {
+                (Vec3::new(0.3, 0.0, -0.4), state.pitch * 0.5)
+            };
+            transform.rotation = yaw * Quat::from_rotation_x(pitch);
+            transform.translation = yaw * offset;
+        }
+    }
+}
+
+/// turn the player characters with the view and feed their animation state machines and aim
+/// pitch. Our own character is hidden while we look through its eyes.
+#[allow(clippy::type_complexity)]
+fn update_character_pose_system(
+    camera_mode: Res<CameraMode>,
+    player_query: Query<(&RemotePlayerState, &VelocityExtrapolate)>,
+    own_player: Query<Entity, With<renet_test::ControlledPlayer>>,
+    controller_query: Query<&controller::FpsController, With<controller::LogicalPlayer>>,
+    mut character_query: Query<(
+        &Parent,
+        &CharacterVisual,
+        &mut Transform,
+        &mut Visibility,
+        Option<&mut CharacterAnimator>,
+    )>,
+) {
+    for (parent, visual, mut transform, mut visibility, animator) in &mut character_query {
+        let (yaw, pitch, locomotion) =
+            if let Ok((state, extrapolate)) = player_query.get(parent.get()) {
+                let locomotion = Locomotion {
+                    velocity: extrapolate.velocity,
+                    grounded: state.grounded,
+                    crouching: state.crouching,
+                };
+                (state.yaw, state.pitch, locomotion)
+            } else if own_player.contains(parent.get()) {
+                let visible = *camera_mode != CameraMode::FirstPerson;
+                if visibility.is_visible != visible {
+                    visibility.is_visible = visible;
+                }
+                let controller = match controller_query.get_single() {
+                    Ok(controller) => controller,
+                    Err(_) => continue,
+                };
+                let locomotion = Locomotion {
+                    velocity: controller.velocity,
+                    grounded: controller.ground_tick >= 1,
+                    crouching: controller.crouched,
+                };
+                (controller.yaw, controller.pitch, locomotion)
+            } else {
+                continue;
+            };
+        *transform = visual.pose(yaw);
+        if let Some(mut animator) = animator {
+            animator.locomotion = locomotion;
+            animator.yaw = yaw;
+            animator.pitch = pitch;
+        }
+    }
+}
+
+fn update_damage_overlay_system(
+    player_query: Query<&RemotePlayerState>,
+    mut overlay_query: Query<
+        (&Parent, &mut Visibility, &Handle<StandardMaterial>),
+        With<DamageOverlay>,
+    >,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (parent, mut visibility, material) in &mut overlay_query {
+        let state = match player_query.get(parent.get()) {
+            Ok(state) => state,
+            Err(_) => continue,
+        };
+        if visibility.is_visible != state.damaged {
+            visibility.is_visible = state.damaged;
+        }
+        if !state.damaged {
+            continue;
+        }
+        let color = if state.blocking {
+            Color::rgba(1.0, 0.8, 0.2, 0.5)
+        } else {
+            Color::rgba(0.8, 0.0, 0.0, 0.5)
+        };
+        if let Some(material) = materials.get(material) {
+            if material.base_color == color {
+                continue;
+            }
+        }
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color = color;
+        }
+    }
+}
+
+/// first and third person crosshair (crosshair.toml), the gap follows the hitscan spread cone:
+/// movement spread of the local player plus bloom from firing
+fn crosshair_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    camera_mode: Res<CameraMode>,
+    settings: Res<CrosshairSettings>,
+    player_query: Query<&controller::FpsController>,
+    weapon_spread: Res<WeaponSpread>,
+    hit_feedback: Res<HitFeedback>,
+) {
+    if !camera_mode.is_player_view() {
+        return;
+    }
+    let controller = match player_query.get_single() {
+        Ok(controller) => controller,
+        Err(_) => return,
+    };
+    let spread = weapon::aimed_cone(
+        controller.movement_spread()
+            + weapon_spread.cone(WeaponKind::Hitscan, time.seconds_since_startup()),
+        controller.aiming,
+    );
+    let ctx = egui_context.ctx_mut();
+    let center = ctx.input().screen_rect().center();
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Foreground,
+        egui::Id::new("crosshair"),
+    ));
+    settings.paint(&painter, center, spread);
+
+    let now = time.seconds_since_startup();
+    if let Some(hit) = hit_feedback.hits.last() {
+        let age = now - hit.time;
+        if age <= HITMARKER_TIME {
+            let fade = (1.0 - age / HITMARKER_TIME) as f32;
+            settings.paint_hitmarker(&painter, center, fade, hit.headshot, hit.kill);
+        }
+    }
+    // kill confirmation below the crosshair
+    let kill = hit_feedback.hits.iter().rev().find(|hit| hit.kill);
+    if let Some(kill) = kill {
+        let age = now - kill.time;
+        if settings.hitmarkers && age <= KILL_CONFIRM_TIME {
+            let [r, g, b, _] = settings.kill_color.to_srgba_unmultiplied();
+            let alpha = (255.0 * (1.0 - age / KILL_CONFIRM_TIME)) as u8;
+            painter.text(
+                center + egui::vec2(0.0, 40.0),
+                egui::Align2::CENTER_CENTER,
+                if kill.headshot {
+                    "HEADSHOT KILL"
+                } else {
+                    "KILL"
+                },
+                egui::FontId::proportional(18.0),
+                egui::Color32::from_rgba_unmultiplied(r, g, b, alpha),
+            );
+        }
+    }
+}
+
+/// arcs around the crosshair pointing to where recent damage came from, and a red screen edge
+/// growing with the damage
+fn damage_indicator_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    mut damage_feedback: ResMut<DamageFeedback>,
+    player_query: Query<(&Transform, &controller::FpsController), With<controller::LogicalPlayer>>,
+) {
+    let now = time.seconds_since_startup();
+    let max_age = DAMAGE_INDICATOR_TIME.max(DAMAGE_VIGNETTE_TIME);
+    damage_feedback.hits.retain(|hit| now - hit.time <= max_age);
+    if damage_feedback.hits.is_empty() {
+        return;
+    }
+    let ctx = egui_context.ctx_mut();
+    let screen = ctx.input().screen_rect();
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("damage_indicator"),
+    ));
+
+    let vignette = damage_feedback
+        .hits
+        .iter()
+        .map(|hit| {
+            let fade = (1.0 - (now - hit.time) / DAMAGE_VIGNETTE_TIME).max(0.0) as f32;
+            hit.amount / DAMAGE_VIGNETTE_FULL * fade
+        })
+        .sum::<f32>()
+        .min(1.0);
+    if vignette > 0.0 {
+        // red at the screen edge, fading to transparent towards the inside
+        let width = screen.width().min(screen.height()) * 0.25;
+        let inner = screen.shrink(width);
+        let outer_color =
+            egui::Color32::from_rgba_unmultiplied(200, 0, 0, (vignette * 160.0) as u8);
+        let mut mesh = egui::Mesh::default();
+        for corner in [
+            screen.left_top(),
+            screen.right_top(),
+            screen.right_bottom(),
+            screen.left_bottom(),
+        ] {
+            mesh.colored_vertex(corner, outer_color);
+        }
+        for corner in [
+            inner.left_top(),
+            inner.right_top(),
+            inner.right_bottom(),
+            inner.left_bottom(),
+        ] {
+            mesh.colored_vertex(corner, egui::Color32::TRANSPARENT);
+        }
+        for side in 0..4 {
+            let next = (side + 1) % 4;
+            mesh.add_triangle(side, next, 4 + side);
+            mesh.add_triangle(next, 4 + next, 4 + side);
+        }
+        painter.add(egui::Shape::mesh(mesh));
+    }
+
+    let (transform, controller) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let center = screen.center();
+    let radius = 80.0;
+    for hit in &damage_feedback.hits {
+        let age = now - hit.time;
+        let source = match hit.source {
+            Some(source) if age <= DAMAGE_INDICATOR_TIME => source,
+            _ => continue,
+        };
+        // direction to the source in the view: forward is up on the screen
+        let local = Quat::from_rotation_y(-controller.yaw) * (source - transform.translation);
+        if local.x == 0.0 && local.z == 0.0 {
+            continue;
+        }
+        let angle = local.x.atan2(-local.z);
+        let alpha = (255.0 * (1.0 - age / DAMAGE_INDICATOR_TIME)) as u8;
+        let stroke = egui::Stroke::new(
+            4.0,
+            egui::Color32::from_rgba_unmultiplied(255, 40, 40, alpha),
+        );
+        let points = (0..=8)
+            .map(|i| {
+                let a = angle + (i as f32 / 8.0 - 0.5) * 0.6;
+                center + egui::vec2(a.sin(), -a.cos()) * radius
+            })
+            .collect::<Vec<_>>();
+        painter.add(egui::Shape::line(points, stroke));
+    }
+}
+
+/// damage numbers rising above the victims of confirmed hits (view.toml `damage_numbers`)
+fn damage_numbers_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    effects: Res<ViewEffects>,
+    mut hit_feedback: ResMut<HitFeedback>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<controller::RenderPlayer>>,
+) {
+    let now = time.seconds_since_startup();
+    let max_age = HITMARKER_TIME
+        .max(DAMAGE_NUMBER_TIME)
+        .max(KILL_CONFIRM_TIME);
+    hit_feedback.hits.retain(|hit| now - hit.time <= max_age);
+    if !effects.damage_numbers || hit_feedback.hits.is_empty() {
+        return;
+    }
+    let (camera, camera_transform) = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let ctx = egui_context.ctx_mut();
+    let screen_height = ctx.input().screen_rect().height();
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Foreground,
+        egui::Id::new("damage_numbers"),
+    ));
+    for hit in &hit_feedback.hits {
+        let t = ((now - hit.time) / DAMAGE_NUMBER_TIME) as f32;
+        if t > 1.0 {
+            continue;
+        }
+        let position = hit.position + Vec3::Y * (DAMAGE_NUMBER_HEIGHT + DAMAGE_NUMBER_RISE * t);
+        // viewport coordinates start at the bottom left, egui at the top left
+        let screen = match camera.world_to_viewport(camera_transform, position) {
+            Some(screen) => egui::pos2(screen.x, screen_height - screen.y),
+            None => continue,
+        };
+        let alpha = (255.0 * (1.0 - t)) as u8;
+        let color = if hit.headshot {
+            egui::Color32::from_rgba_unmultiplied(255, 200, 40, alpha)
+        } else {
+            egui::Color32::from_rgba_unmultiplied(255, 255, 255, alpha)
+        };
+        painter.text(
+            screen,
+            egui::Align2::CENTER_CENTER,
+            format!("{:.0}", hit.amount),
+            egui::FontId::proportional(if hit.kill { 22.0 } else { 16.0 }),
+            color,
+        );
+    }
+}
+
+/// F6: start/stop a practice run. While recording, speed and height of the run are plotted
+/// against the previous run (ghost).
+fn practice_run_overlay_system(
+    mut egui_context: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut runs: ResMut<PracticeRuns>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F6) {
+        if runs.is_recording() {
+            runs.stop();
+        } else {
+            runs.start();
+        }
+    }
+    let current = match &runs.current {
+        Some(current) => current,
+        None => return,
+    };
+    let time = current.duration();
+    let line = |run: &RunRecording, value: fn(&RunSample) -> f32| {
+        egui::plot::Line::new(egui::plot::Values::from_values_iter(
+            run.samples
+                .iter()
+                .map(|sample| egui::plot::Value::new(sample.time, value(sample))),
+        ))
+    };
+    egui::Window::new("practice run")
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(format!("{:.1} / {:.0} s", time, MAX_RUN_TIME));
+            let ghost_sample = runs.ghost.as_ref().and_then(|ghost| ghost.sample_at(time));
+            if let (Some(sample), Some(ghost_sample)) = (current.samples.last(), ghost_sample) {
+                ui.label(format!(
+                    "speed {:+.1}  height {:+.2} vs. ghost",
+                    sample.speed - ghost_sample.speed,
+                    sample.height - ghost_sample.height
+                ));
+            }
+            let plots: [(&str, fn(&RunSample) -> f32); 2] = [
+                ("speed", |sample| sample.speed),
+                ("height", |sample| sample.height),
+            ];
+            for (name, value) in plots {
+                egui::plot::Plot::new(name)
+                    .height(100.0)
+                    .width(300.0)
+                    .show(ui, |plot_ui| {
+                        if let Some(ghost) = &runs.ghost {
+                            plot_ui
+                                .line(line(ghost, value).color(egui::Color32::GRAY).name("ghost"));
+                        }
+                        plot_ui.line(line(current, value).name(name));
+                    });
+            }
+        });
+}
+
+fn apply_pickup_changes_system(
+    mut pickup_changes: EventReader<PickupChanged>,
+    mut pickups: Query<(&Transform, &mut Pickup)>,
+) {
+    for change in pickup_changes.iter() {
+        pickup::set_pickup_available(&mut pickups, change.position, change.available);
+    }
+}
+
+/// movers carry the predicted player with their replicated velocity
+fn mover_platform_velocity_system(
+    mut movers: Query<(&VelocityExtrapolate, &mut MovingPlatform), Changed<VelocityExtrapolate>>,
+) {
+    for (extrapolate, mut platform) in &mut movers {
+        platform.velocity = extrapolate.velocity;
+    }
+}
+
+fn apply_usable_changes_system(
+    mut usable_changes: EventReader<UsableChanged>,
+    mut usables: Query<&mut Usable>,
+) {
+    for change in usable_changes.iter() {
+        usable::set_usable_active(&mut usables, change.position, change.active);
+    }
+}
+
+/// spawn flags the first time the server replicates them, update their state after that
+fn apply_flag_changes_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut flag_changes: EventReader<FlagsChanged>,
+    mut flags: Query<&mut Flag>,
+) {
+    // every message has all flags, the latest one wins. Spawns only happen at the end of the
+    // frame, applying several would spawn a flag more than once.
+    if let Some(FlagsChanged(changes)) = flag_changes.iter().last() {
+        for (team, state) in changes {
+            if !flag::set_flag_state(&mut flags, *team, *state) {
+                let flag = Flag::new(*team).with_state(*state);
+                flag::spawn_flag(&mut commands, &mut meshes, &mut materials, flag);
+            }
+        }
+    }
+}
+
+/// put flags where they rest, carried ones over their carrier
+fn flag_visual_system(
+    lobby: Res<ClientLobby>,
+    mut flags: Query<(&Flag, &mut Transform)>,
+    carriers: Query<&Transform, Without<Flag>>,
+) {
+    for (flag, mut transform) in &mut flags {
+        let position = match flag.state {
+            FlagState::Carried { carrier } => {
+                let carrier = lobby
+                    .players
+                    .get(&carrier)
+                    .and_then(|info| carriers.get(info.client_entity).ok());
+                match carrier {
+                    Some(carrier) => carrier.translation + flag::FLAG_CARRY_OFFSET,
+                    None => continue,
+                }
+            }
+            _ => match flag.resting_position() {
+                Some(position) => position,
+                None => continue,
+            },
+        };
+        if transform.translation != position {
+            transform.translation = position;
+        }
+    }
+}
+
+/// spawn control points the first time the server replicates them, update their state after
+/// that
+fn apply_control_point_changes_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut control_point_changes: EventReader<ControlPointsChanged>,
+    mut control_points: Query<(&mut ControlPoint, &Transform)>,
+) {
+    // every message has all control points, the latest one wins, see
+    // apply_flag_changes_system
+    if let Some(ControlPointsChanged(changes)) = control_point_changes.iter().last() {
+        for (position, state) in changes {
+            let existing = control_points
+                .iter_mut()
+                .find(|(_, transform)| ControlPoint::matches(transform.translation, *position));
+            match existing {
+                Some((mut control_point, _)) => {
+                    if control_point.state != *state {
+                        control_point.state = *state;
+                    }
+                }
+                None => {
+                    control::spawn_control_point(
+                        &mut commands,
+                        &mut meshes,
+                        &mut materials,
+                        *position,
+                        ControlPoint { state: *state },
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// color control points by their owner
+fn control_point_color_system(
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    control_points: Query<(&ControlPoint, &Handle<StandardMaterial>), Changed<ControlPoint>>,
+) {
+    for (control_point, material) in control_points.iter() {
+        if let Some(material) = materials.get_mut(material) {
+            let color = control::owner_color(control_point.state.owner);
+            material.base_color = color;
+            material.emissive = color * 0.3;
+        }
+    }
+}
+
+/// match clock and own frags (team scores in team modes) at the top, results while the match
+/// is over
+fn match_hud_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    client: Res<RenetClient>,
+    lobby: Res<ClientLobby>,
+    mut match_info: ResMut<MatchInfo>,
+    control_points: Query<&ControlPoint>,
+) {
+    let (mode, phase) = match (match_info.mode, match_info.phase) {
+        (Some(mode), Some(phase)) => (mode, phase),
+        _ => return,
+    };
+    if let Some(time_left) = &mut match_info.time_left {
+        *time_left = (*time_left - time.delta_seconds()).max(0.0);
+    }
+    let name = |id: u64| {
+        lobby
+            .players
+            .get(&id)
+            .map_or_else(|| format!("player {}", id), |info| info.name.clone())
+    };
+    let client_id = client.client_id();
+    let frags = match_info
+        .scores
+        .iter()
+        .find(|(id, _)| *id == client_id)
+        .map_or(0, |(_, score)| score.frags);
+    let clock = match (phase, match_info.time_left) {
+        (MatchPhase::Overtime, _) => "overtime".to_string(),
+        (_, Some(time_left)) => {
+            let seconds = time_left.ceil() as u32;
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        }
+        (_, None) => String::new(),
+    };
+    egui::Area::new("match_hud")
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 10.0))
+        .show(egui_context.ctx_mut(), |ui| match phase {
+            MatchPhase::Warmup => {
+                ui.label(format!("{}  warmup, waiting for players", mode.label()));
+            }
+            MatchPhase::Countdown => {
+                let seconds = match_info
+                    .time_left
+                    .map_or(0, |time_left| time_left.ceil() as u32);
+                ui.heading(format!("{} starts in {}", mode.label(), seconds));
+            }
+            MatchPhase::Running | MatchPhase::Overtime => {
+                if mode.team_based() {
+                    let team_score = |team: Team| {
+                        match_info
+                            .team_scores
+                            .iter()
+                            .find(|(other, _)| *other == team)
+                            .map_or(0, |(_, score)| *score)
+                    };
+                    ui.label(format!(
+                        "{}  {}  red {} : {} blue",
+                        mode.label(),
+                        clock,
+                        team_score(Team::Red),
+                        team_score(Team::Blue)
+                    ));
+                    for control_point in control_points.iter() {
+                        let state = &control_point.state;
+                        let owner = state.owner.map_or("neutral", |team| team.label());
+                        let progress = match state.capturing {
+                            _ if state.contested => "  contested".to_string(),
+                            Some(team) => {
+                                format!("  {} {:.0}%", team.label(), state.progress * 100.0)
+                            }
+                            None => String::new(),
+                        };
+                        ui.label(format!("hill: {}{}", owner, progress));
+                    }
+                    return;
                 }
+                let limit = if match_info.frag_limit > 0 {
+                    format!(" / {}", match_info.frag_limit)
+                } else {
+                    String::new()
+                };
+                ui.label(format!(
+                    "{}  {}  frags {}{}",
+                    mode.label(),
+                    clock,
+                    frags,
+                    limit
+                ));
             }
-            ServerMessages::SpawnProjectile {
-                entity,
-                translation,
-                object_type: ObjectType::Projectile,
-            } => {
-                let mut projectile_entity = commands.spawn_bundle(PbrBundle {
-                    mesh: meshes.add(Mesh::from(shape::Icosphere {
-                        radius: 0.1,
-                        subdivisions: 5,
-                    })),
-                    material: materials.add(Color::rgb(1.0, 0.0, 0.0).into()),
-                    transform: Transform::from_translation(translation),
-                    ..Default::default()
-                });
-                projectile_entity
-                    .insert(TransformFromServer::default())
-                    .insert(VelocityExtrapolate::default());
-                network_mapping.0.insert(entity, projectile_entity.id());
+            MatchPhase::Ended { winner } => {
+                let result = match winner {
+                    Some(Winner::Player(id)) => format!("{} wins!", name(id)),
+                    Some(Winner::Team(team)) => format!("{} team wins!", team.label()),
+                    None => "draw".to_string(),
+                };
+                ui.heading(result);
+                ui.label(format!("next match in {}", clock));
             }
-            ServerMessages::SpawnProjectile {
-                entity,
-                translation,
-                object_type: ObjectType::Box,
-            } => {
-                info!("spawn box");
-                let mut bundle = ObjectType::Box.representation_bundle(&mut meshes, &mut materials);
-                bundle.transform = Transform::from_translation(translation);
+        });
+}
 
-                let mut projectile_entity = commands.spawn_bundle(bundle);
-                projectile_entity
-                    .insert(TransformFromServer::default())
-                    .insert(VelocityExtrapolate::default());
-                network_mapping.0.insert(entity, projectile_entity.id());
+/// final scores and awards of the last match and the map vote, for the time the server asked
+/// for
+fn match_summary_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    lobby: Res<ClientLobby>,
+    mut match_info: ResMut<MatchInfo>,
+    mut player_commands: EventWriter<PlayerCommand>,
+) {
+    if match_info.summary.is_some() && time.seconds_since_startup() > match_info.summary_until {
+        match_info.summary = None;
+        match_info.map_vote.clear();
+    }
+    let summary = match &match_info.summary {
+        Some(summary) => summary,
+        None => return,
+    };
+    let name = |id: u64| {
+        lobby
+            .players
+            .get(&id)
+            .map_or_else(|| format!("player {}", id), |info| info.name.clone())
+    };
+    let mut vote = None;
+    egui::Window::new("match summary")
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(summary.mode.label());
+            if !summary.team_scores.is_empty() {
+                let teams: Vec<String> = summary
+                    .team_scores
+                    .iter()
+                    .map(|(team, score)| format!("{} {}", team.label(), score))
+                    .collect();
+                ui.heading(teams.join(" : "));
             }
-            ServerMessages::DespawnProjectile { entity } => {
-                if let Some(entity) = network_mapping.0.remove(&entity) {
-                    commands.entity(entity).despawn();
+            egui::Grid::new("summary_scores").show(ui, |ui| {
+                ui.label("player");
+                ui.label("frags");
+                ui.label("deaths");
+                if summary.mode.has_flags() {
+                    ui.label("captures");
                 }
+                ui.end_row();
+                for (id, score) in &summary.scores {
+                    ui.label(name(*id));
+                    ui.label(score.frags.to_string());
+                    ui.label(score.deaths.to_string());
+                    if summary.mode.has_flags() {
+                        ui.label(score.captures.to_string());
+                    }
+                    ui.end_row();
+                }
+            });
+            for award in &summary.awards {
+                ui.label(format!(
+                    "{}: {} ({})",
+                    award.kind.label(),
+                    name(award.player),
+                    award.value
+                ));
             }
-        }
-    }
-
-    while let Some(message) = client.receive_message(ServerChannel::NetworkFrame.id()) {
-        let frame: NetworkFrame = bincode::deserialize(&message).unwrap();
-        // info!("network frame");
-        match most_recent_tick {
-            None => {
-                commands.insert_resource(MostRecentTick {
-                    from_server: frame.tick,
-                    predicted: frame.tick,
-                });
+            if match_info.map_vote.is_empty() {
+                return;
             }
-            Some(ref mut tick) if tick.from_server < frame.tick => {
-                tick.from_server = frame.tick;
-                tick.predicted = frame.tick;
-                //  = Some(MostRecentTick {
-                //     from_server: frame.tick,
-                //     predicted: frame.tick,
-                // })
+            ui.separator();
+            ui.label("vote for the next map (number keys)");
+            for (option, map) in match_info.map_vote.iter().enumerate() {
+                let votes = match_info.map_votes.get(option).copied().unwrap_or(0);
+                let text = format!("{}: {} ({})", option + 1, map.name(), votes);
+                let selected = match_info.own_vote == Some(option);
+                if ui.selectable_label(selected, text).clicked() {
+                    vote = Some(option);
+                }
             }
-            _ => continue,
-        }
+        });
+    if let Some(option) = vote {
+        cast_map_vote(&mut match_info, &mut player_commands, option);
+    }
+}
 
-        for i in 0..frame.entities.entities.len() {
-            info!(
-                "entity {} {:?} -> {:?}",
-                i,
-                frame.entities.entities[i],
-                network_mapping.0.get(&frame.entities.entities[i])
-            );
+/// number keys vote for the options of the running map vote
+fn map_vote_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut match_info: ResMut<MatchInfo>,
+    mut player_commands: EventWriter<PlayerCommand>,
+) {
+    const KEYS: [KeyCode; 9] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    let option = KEYS
+        .iter()
+        .take(match_info.map_vote.len())
+        .position(|key| keyboard_input.just_pressed(*key));
+    if let Some(option) = option {
+        cast_map_vote(&mut match_info, &mut player_commands, option);
+    }
+}
 
-            if let Some(entity) = network_mapping.0.get(&frame.entities.entities[i]) {
-                let translation = frame.entities.translations[i];
-                // let rotation = frame.entities.rotations[i];
-                let transform = Transform {
-                    translation,
-                    // rotation,
-                    ..Default::default()
-                };
+fn cast_map_vote(
+    match_info: &mut MatchInfo,
+    player_commands: &mut EventWriter<PlayerCommand>,
+    option: usize,
+) {
+    if match_info.own_vote == Some(option) {
+        return;
+    }
+    match_info.own_vote = Some(option);
+    player_commands.send(PlayerCommand::Vote { option });
+}
 
-                if let Ok(old_transform) = transform_query.get(*entity) {
-                    debug!(
-                        "apply transform {} {:?} -> {:?} {:?}",
-                        frame.last_player_input,
-                        entity,
-                        transform.translation,
-                        old_transform.translation
-                    );
+/// the running player vote at the left edge, F1 votes yes and F2 no. Below it the outcome of the
+/// last vote.
+fn vote_prompt_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    lobby: Res<ClientLobby>,
+    mut vote_prompt: ResMut<VotePrompt>,
+    mut player_commands: EventWriter<PlayerCommand>,
+) {
+    let now = time.seconds_since_startup();
+    if matches!(vote_prompt.message, Some((_, until)) if now > until) {
+        vote_prompt.message = None;
+    }
+    if vote_prompt.can_vote && vote_prompt.state.is_some() {
+        let yes = if keyboard_input.just_pressed(KeyCode::F1) {
+            Some(true)
+        } else if keyboard_input.just_pressed(KeyCode::F2) {
+            Some(false)
+        } else {
+            None
+        };
+        if let Some(yes) = yes {
+            if vote_prompt.own_vote != Some(yes) {
+                vote_prompt.own_vote = Some(yes);
+                player_commands.send(PlayerCommand::CastVote { yes });
+            }
+        }
+    }
+    if let Some(state) = &mut vote_prompt.state {
+        state.time_left = (state.time_left - time.delta_seconds()).max(0.0);
+    }
+    if vote_prompt.state.is_none() && vote_prompt.message.is_none() {
+        return;
+    }
+    let name = |id: u64| {
+        lobby
+            .players
+            .get(&id)
+            .map_or_else(|| format!("player {}", id), |info| info.name.clone())
+    };
+    egui::Area::new("vote_prompt")
+        .anchor(egui::Align2::LEFT_CENTER, egui::vec2(10.0, 0.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            if let Some(state) = &vote_prompt.state {
+                ui.heading(state.kind.question(&name));
+                ui.label(format!("called by {}", name(state.initiator)));
+                ui.label(format!(
+                    "yes {} / no {}, {} of {} needed, {:.0} s left",
+                    state.yes, state.no, state.needed, state.voters, state.time_left
+                ));
+                match (vote_prompt.can_vote, vote_prompt.own_vote) {
+                    (false, _) => (),
+                    (true, None) => {
+                        ui.label("F1: yes  F2: no");
+                    }
+                    (true, Some(yes)) => {
+                        ui.label(format!(
+                            "you voted {} (F1/F2 to change)",
+                            if yes { "yes" } else { "no" }
+                        ));
+                    }
                 }
+            }
+            if let Some((message, _)) = &vote_prompt.message {
+                ui.label(message);
+            }
+        });
+}
 
-                if let Ok((mut player_input_queue, mut transform_from_server)) =
-                    controlled_player.get_mut(*entity)
-                {
-                    info!("player transform update: {:?}", transform);
-                    *transform_from_server = TransformFromServer(transform);
-                    player_input_queue.last_server_serial = frame.last_player_input;
+/// the last admin message at the top of the screen
+fn admin_message_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    mut admin_message: ResMut<AdminMessage>,
+) {
+    let text = match &admin_message.0 {
+        Some((_, until)) if time.seconds_since_startup() > *until => {
+            admin_message.0 = None;
+            return;
+        }
+        Some((text, _)) => text.clone(),
+        None => return,
+    };
+    egui::Area::new("admin_message")
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 90.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.heading(format!("admin: {}", text));
+        });
+}
+
+/// the server name, message of the day and rules after joining
+fn join_splash_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    mut join_splash: ResMut<JoinSplash>,
+) {
+    let info = match &join_splash.0 {
+        Some((_, until)) if time.seconds_since_startup() > *until => {
+            join_splash.0 = None;
+            return;
+        }
+        Some((info, _)) => info,
+        None => return,
+    };
+    egui::Area::new("join_splash")
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 140.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.vertical_centered(|ui| {
+                ui.heading(&info.name);
+                if !info.motd.is_empty() {
+                    ui.label(&info.motd);
                 }
-                if let Ok(mut ent_transform) = transform_query.get_mut(*entity) {
-                    *ent_transform = transform;
+                ui.label(format!(
+                    "{} on {}, {} players max, {} Hz",
+                    info.mode.label(),
+                    info.map.name(),
+                    info.max_players,
+                    info.tick_rate
+                ));
+                for rule in &info.rules {
+                    ui.label(rule);
                 }
-                if let Ok((mut transform_from_server, mut extrapolate)) =
-                    extrapolate.get_mut(*entity)
-                {
-                    *transform_from_server = TransformFromServer(transform);
-                    extrapolate.base_tick = frame.tick;
-                    extrapolate.velocity = frame.entities.velocities[i];
+            });
+        });
+}
+
+/// F4: the players on the server, with a button to call a vote to kick them
+fn player_list_system(
+    mut egui_context: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    client: Res<RenetClient>,
+    lobby: Res<ClientLobby>,
+    mut show_window: Local<bool>,
+    mut player_commands: EventWriter<PlayerCommand>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F4) {
+        *show_window = !*show_window;
+    }
+    if !*show_window {
+        return;
+    }
+    let client_id = client.client_id();
+    let mut players: Vec<(&u64, &PlayerInfo)> = lobby.players.iter().collect();
+    players.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+    egui::Window::new("players").show(egui_context.ctx_mut(), |ui| {
+        egui::Grid::new("player_list").show(ui, |ui| {
+            for (id, info) in players {
+                ui.label(&info.name);
+                if *id != client_id && ui.button("vote kick").clicked() {
+                    player_commands.send(PlayerCommand::CallVote {
+                        kind: VoteKind::Kick { player: *id },
+                    });
                 }
+                ui.end_row();
             }
+        });
+    });
+}
+
+/// the local files of the map of the server have to match its manifest, maps we lack are
+/// fetched if possible
+fn check_map_files(manifest: &MapManifest) -> Result<(), String> {
+    let e = match manifest.verify() {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    warn!("{}", e);
+    mapfile::fetch_map(manifest).map_err(|fetch_error| {
+        error!("{}: {}", e, fetch_error);
+        e
+    })
+}
+
+/// follow the map of the server, and its reloads
+fn apply_map_change_system(
+    match_info: Res<MatchInfo>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut reloads: Local<u32>,
+) {
+    if let Some(map) = &match_info.map {
+        if current_level.map != *map {
+            current_level.map = map.clone();
         }
-        for i in 0..frame.with_rotation.entities.len() {
-            info!(
-                "entity {} {:?} -> {:?}",
-                i,
-                frame.with_rotation.entities[i],
-                network_mapping.0.get(&frame.with_rotation.entities[i])
-            );
+    }
+    if *reloads != match_info.map_reloads {
+        *reloads = match_info.map_reloads;
+        current_level.reload();
+    }
+}
 
-            if let Some(entity) = network_mapping.0.get(&frame.with_rotation.entities[i]) {
-                let translation = frame.with_rotation.translations[i];
-                let rotation = frame.with_rotation.rotations[i];
-                let transform = Transform {
-                    translation,
-                    rotation,
-                    ..Default::default()
+/// latest kills in the top right corner, fading out
+fn kill_feed_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    lobby: Res<ClientLobby>,
+    mut kill_feed: ResMut<KillFeed>,
+) {
+    let now = time.seconds_since_startup();
+    kill_feed.expire(now);
+    let name = |id: u64| {
+        lobby
+            .players
+            .get(&id)
+            .map_or_else(|| format!("player {}", id), |info| info.name.clone())
+    };
+    egui::Area::new("kill_feed")
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            for (event, alpha) in kill_feed.visible(now) {
+                let alpha = (255.0 * alpha) as u8;
+                let color = if event.headshot {
+                    egui::Color32::from_rgba_unmultiplied(255, 200, 40, alpha)
+                } else {
+                    egui::Color32::from_rgba_unmultiplied(255, 255, 255, alpha)
                 };
+                ui.colored_label(color, event.text(name));
+            }
+        });
+}
 
-                if let Ok(old_transform) = transform_query.get(*entity) {
-                    debug!(
-                        "apply transform {} {:?} -> {:?} {:?}",
-                        frame.last_player_input,
-                        entity,
-                        transform.translation,
-                        old_transform.translation
+/// status bars of the local player
+fn egui_color(color: Color) -> egui::Color32 {
+    let [r, g, b, _] = color.as_rgba_f32();
+    egui::Color32::from_rgb((r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8)
+}
+
+/// north-up minimap around the local player in the top right corner: the level outline,
+/// teammates and recent gunfire of others
+#[allow(clippy::too_many_arguments)]
+fn minimap_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    lobby: Res<ClientLobby>,
+    outline: Res<MinimapOutline>,
+    mut pings: ResMut<GunfirePings>,
+    logical_query: Query<(&Transform, &controller::FpsController), With<controller::LogicalPlayer>>,
+    own_team: Query<(Entity, &Team), With<renet_test::ControlledPlayer>>,
+    players: Query<(&Transform, &Team)>,
+) {
+    let now = time.seconds_since_startup();
+    pings
+        .0
+        .retain(|(_, ping_time)| now - ping_time <= PING_TIME);
+    let (transform, controller) = match logical_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let size = 180.0;
+    let ctx = egui_context.ctx_mut();
+    let screen = ctx.input().screen_rect();
+    let rect = egui::Rect::from_min_size(
+        screen.right_top() + egui::vec2(-size - 10.0, 10.0),
+        egui::vec2(size, size),
+    );
+    let painter = ctx
+        .layer_painter(egui::LayerId::new(
+            egui::Order::Background,
+            egui::Id::new("minimap"),
+        ))
+        .with_clip_rect(rect);
+    let center = rect.center();
+    let scale = size * 0.5 / MINIMAP_RANGE;
+    let own = transform.translation;
+    // -z (north) is up
+    let to_map =
+        |position: Vec3| center + egui::vec2(position.x - own.x, position.z - own.z) * scale;
+
+    painter.rect_filled(rect, 4.0, egui::Color32::from_black_alpha(160));
+    for outline_rect in &outline.rects {
+        let min = to_map(Vec3::new(outline_rect.min.x, 0.0, outline_rect.min.y));
+        let max = to_map(Vec3::new(outline_rect.max.x, 0.0, outline_rect.max.y));
+        let color = if outline_rect.wall {
+            egui::Color32::from_gray(150)
+        } else {
+            egui::Color32::from_gray(60)
+        };
+        painter.rect_filled(egui::Rect::from_min_max(min, max), 0.0, color);
+    }
+
+    for (position, ping_time) in &pings.0 {
+        let fade = 1.0 - ((now - ping_time) / PING_TIME) as f32;
+        let color = egui::Color32::from_rgba_unmultiplied(255, 200, 40, (255.0 * fade) as u8);
+        painter.circle_stroke(
+            to_map(*position),
+            3.0 + 6.0 * (1.0 - fade),
+            egui::Stroke::new(1.5, color),
+        );
+    }
+
+    if let Ok((own_entity, own_team)) = own_team.get_single() {
+        for player in lobby.players.values() {
+            if player.client_entity == own_entity || player.spectating {
+                continue;
+            }
+            match players.get(player.client_entity) {
+                Ok((transform, team)) if team == own_team => {
+                    painter.circle_filled(
+                        to_map(transform.translation),
+                        3.5,
+                        egui_color(team.color()),
                     );
                 }
+                _ => (),
+            }
+        }
+    }
 
-                if let Ok((mut player_input_queue, mut transform_from_server)) =
-                    controlled_player.get_mut(*entity)
-                {
-                    *transform_from_server = TransformFromServer(transform);
-                    player_input_queue.last_server_serial = frame.last_player_input;
-                }
-                if let Ok(mut ent_transform) = transform_query.get_mut(*entity) {
-                    *ent_transform = transform;
-                }
-                if let Ok((mut transform_from_server, mut extrapolate)) =
-                    extrapolate.get_mut(*entity)
-                {
-                    *transform_from_server = TransformFromServer(transform);
-                    extrapolate.base_tick = frame.tick;
-                    extrapolate.velocity = frame.with_rotation.velocities[i];
-                }
+    // the local player: an arrow along the view
+    let forward = egui::vec2(-controller.yaw.sin(), -controller.yaw.cos());
+    let side = egui::vec2(-forward.y, forward.x);
+    painter.add(egui::Shape::convex_polygon(
+        vec![
+            center + forward * 7.0,
+            center - forward * 4.0 + side * 4.5,
+            center - forward * 4.0 - side * 4.5,
+        ],
+        egui::Color32::WHITE,
+        egui::Stroke::none(),
+    ));
+}
+
+fn player_hud_system(
+    mut egui_context: ResMut<EguiContext>,
+    player_query: Query<&controller::FpsController>,
+    own_state: Res<OwnPlayerState>,
+    inventory: Res<Inventory>,
+) {
+    if player_query.get_single().is_err() {
+        return;
+    }
+    egui::Area::new("player_hud")
+        .anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(10.0, -10.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.set_width(200.0);
+            ui.add(
+                egui::ProgressBar::new(own_state.shield)
+                    .text(format!("shield {:.0}%", own_state.shield * 100.0)),
+            );
+            if let Some(stamina) = own_state.stamina {
+                let text = if own_state.exhausted {
+                    "exhausted".to_string()
+                } else {
+                    format!("stamina {:.0}%", stamina * 100.0)
+                };
+                ui.add(egui::ProgressBar::new(stamina).text(text));
+            }
+            if let Some(armor) = own_state.armor {
+                ui.add(egui::ProgressBar::new(armor).text(format!("armor {:.0}%", armor * 100.0)));
             }
+            if let Some(ammo) = own_state.ammo {
+                ui.label(format!(
+                    "grenades {}  rockets {}",
+                    ammo.grenades, ammo.rockets
+                ));
+            }
+            let weapons: Vec<_> = inventory
+                .weapons
+                .iter()
+                .map(|weapon| weapon.label())
+                .collect();
+            ui.label(weapons.join(", "));
+            ui.horizontal(|ui| {
+                for effect in &own_state.status {
+                    let [r, g, b, _] = effect.kind.color().as_rgba_f32();
+                    let color = egui::Color32::from_rgb(
+                        (r * 255.0) as u8,
+                        (g * 255.0) as u8,
+                        (b * 255.0) as u8,
+                    );
+                    let text = if !effect.kind.timed() {
+                        effect.kind.label().to_string()
+                    } else if effect.stacks > 1 {
+                        format!(
+                            "{} x{} {:.0}s",
+                            effect.kind.label(),
+                            effect.stacks,
+                            effect.remaining
+                        )
+                    } else {
+                        format!("{} {:.0}s", effect.kind.label(), effect.remaining)
+                    };
+                    ui.colored_label(color, text);
+                }
+            });
+        });
+}
+
+/// mirror the freeze time of the server, so the own player stays put during the countdown
+fn apply_match_freeze_system(
+    match_info: Res<MatchInfo>,
+    mut player_query: Query<&mut controller::FpsController, With<renet_test::ControlledPlayer>>,
+) {
+    let frozen = match_info.phase.map_or(false, |phase| phase.is_frozen());
+    for mut controller in &mut player_query {
+        if controller.frozen != frozen {
+            controller.frozen = frozen;
         }
     }
 }
 
+/// predict the speed modifiers of the own status effects the server applies, and take over
+/// the shield meter, blocked damage drains it on the server only
+fn apply_own_status_system(
+    own_state: Res<OwnPlayerState>,
+    mut player_query: Query<&mut controller::FpsController, With<renet_test::ControlledPlayer>>,
+) {
+    if !own_state.is_changed() {
+        return;
+    }
+    let factor = status::speed_factor(&own_state.status);
+    for mut controller in &mut player_query {
+        controller.status_speed_factor = factor;
+        controller.shield = own_state.shield * controller.max_shield;
+    }
+}
+
 fn _client_predict_input(
     mut transform_query: Query<
         (&mut Transform, &TransformFromServer, &mut PlayerInputQueue),
@@ -533,14 +4133,176 @@ fn _client_predict_input(
     }
 }
 
+/// rocket fired by the local player
+#[derive(Component)]
+struct OwnRocket {
+    server_entity: Entity,
+    /// the prediction reached the impact
+    exploded: bool,
+}
+
+/// the prediction of an own rocket reached its impact
+struct PredictedExplosion {
+    rocket: Entity,
+    server_entity: Entity,
+    position: Vec3,
+}
+
+/// server entities of own rockets whose explosion was predicted. The impulse and effects the
+/// server sends for them were applied already.
+#[derive(Default)]
+struct PredictedExplosions(HashSet<Entity>);
+
+/// run the projectile simulation from the last server state up to the predicted tick. Impacts
+/// only stop the projectile, exploding is up to the server, except for the own rockets.
+#[allow(clippy::type_complexity)]
+fn predict_projectiles(
+    most_recent_tick: Option<Res<MostRecentTick>>,
+    limits: Res<ExtrapolationLimits>,
+    physics_context: Res<RapierContext>,
+    mut projectiles: Query<
+        (
+            Entity,
+            &mut Transform,
+            &TransformFromServer,
+            &VelocityExtrapolate,
+            &Ballistic,
+            Option<&mut OwnRocket>,
+        ),
+        Without<Free>,
+    >,
+    mut explosions: EventWriter<PredictedExplosion>,
+) {
+    let tick = match most_recent_tick {
+        Some(tick) => tick.predicted,
+        None => return,
+    };
+    for (entity, mut transform, transform_from_server, extrapolate, ballistic, own_rocket) in
+        &mut projectiles
+    {
+        let ticks = tick
+            .saturating_sub(extrapolate.base_tick)
+            .min(limits.max_ticks_steady);
+        let mut ballistic = Ballistic {
+            velocity: extrapolate.velocity,
+            ..ballistic.clone()
+        };
+        let mut translation = transform_from_server.0.translation;
+        let mut impact = false;
+        for _ in 0..ticks {
+            if ballistic
+                .step(&physics_context, &mut translation, 1.0 / 60.0)
+                .is_some()
+            {
+                impact = true;
+                break;
+            }
+        }
+        transform.translation = translation;
+        if let Some(mut own_rocket) = own_rocket {
+            if impact && !own_rocket.exploded {
+                own_rocket.exploded = true;
+                explosions.send(PredictedExplosion {
+                    rocket: entity,
+                    server_entity: own_rocket.server_entity,
+                    position: translation,
+                });
+            }
+        }
+    }
+}
+
+/// push the own player away from a predicted rocket explosion right away, so rocket jumps
+/// don't wait for the server
+#[allow(clippy::too_many_arguments)]
+fn predicted_explosion_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut pools: ResMut<EntityPools>,
+    mut explosions: EventReader<PredictedExplosion>,
+    mut predicted: ResMut<PredictedExplosions>,
+    mut camera_shake: ResMut<CameraShake>,
+    mut effect_events: EventWriter<EffectEvent>,
+    mut sound_events: EventWriter<SoundEvent>,
+    mut players: Query<
+        (&Transform, &mut controller::FpsController),
+        With<controller::LogicalPlayer>,
+    >,
+) {
+    let explosion = weapon::ROCKET_EXPLOSION;
+    for event in explosions.iter() {
+        predicted.0.insert(event.server_entity);
+        commands
+            .entity(event.rocket)
+            .insert(Visibility { is_visible: false });
+        if let Ok((transform, mut controller)) = players.get_single_mut() {
+            if let Some(impulse) = controller::explosion_impulse(
+                event.position,
+                explosion.radius,
+                explosion.strength,
+                transform.translation + PLAYER_CENTER_OFFSET,
+            ) {
+                controller.add_impulse(impulse);
+            }
+            camera_shake.add_explosion(transform.translation.distance(event.position));
+        }
+        spawn_explosion_effect(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &mut pools,
+            event.position,
+            explosion.radius,
+        );
+        effect_events.send(
+            EffectEvent::new(EffectKind::Fireball, event.position, Vec3::ZERO)
+                .with_scale(explosion.radius * 0.5),
+        );
+        sound_events.send(SoundEvent::at(SoundKind::Explosion, event.position));
+    }
+}
+
+/// extrapolate replicated entities, fading out the ones that ran into the extrapolation limits
+#[allow(clippy::type_complexity)]
 fn predict_entities(
     most_recent_tick: Option<ResMut<MostRecentTick>>,
-    mut transform_query: Query<(&mut Transform, &TransformFromServer, &VelocityExtrapolate)>,
+    limits: Res<ExtrapolationLimits>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut transform_query: Query<
+        (
+            &mut Transform,
+            &TransformFromServer,
+            &mut VelocityExtrapolate,
+            Option<&Handle<StandardMaterial>>,
+        ),
+        Without<Ballistic>,
+    >,
 ) {
     if let Some(mut tick) = most_recent_tick {
-        for (mut transform, transform_from_server, extrapolate) in &mut transform_query {
-            transform.translation =
-                extrapolate.apply(tick.predicted, transform_from_server.0.translation);
+        for (mut transform, transform_from_server, mut extrapolate, material) in
+            &mut transform_query
+        {
+            let (translation, stale) =
+                extrapolate.apply(tick.predicted, transform_from_server.0.translation, &limits);
+            transform.translation = translation;
+            extrapolate.stale = stale;
+            // compare against the material, pooled entities may come back still faded
+            if let Some(handle) = material {
+                let shown_stale = materials
+                    .get(handle)
+                    .map_or(stale, |material| material.alpha_mode == AlphaMode::Blend);
+                if shown_stale != stale {
+                    if let Some(material) = materials.get_mut(handle) {
+                        material.base_color.set_a(if stale { 0.4 } else { 1.0 });
+                        material.alpha_mode = if stale {
+                            AlphaMode::Blend
+                        } else {
+                            AlphaMode::Opaque
+                        };
+                    }
+                }
+            }
             debug!(
                 "predict: {:?} {:?} {:?}",
                 transform.translation, transform_from_server, extrapolate
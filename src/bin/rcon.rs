@@ -0,0 +1,74 @@
+//! remote admin tool for dedicated servers with rcon enabled, see `rcon` in the library.
+//!
+//!     rcon [--server=127.0.0.1:5010] [--password=secret] [command...]
+//!
+//! Runs the command given on the command line, or reads commands from stdin, one per line.
+//! Without `--password` the password is taken from the RCON_PASSWORD environment variable.
+
+use std::io::{self, BufRead, Write};
+
+use renet_test::rcon::RconClient;
+
+const DEFAULT_SERVER: &str = "127.0.0.1:5010";
+
+fn arg_value(name: &str) -> Option<String> {
+    let prefix = format!("--{}=", name);
+    std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.strip_prefix(&prefix).map(str::to_string))
+}
+
+fn main() {
+    let server = arg_value("server").unwrap_or_else(|| DEFAULT_SERVER.to_string());
+    let password = arg_value("password")
+        .or_else(|| std::env::var("RCON_PASSWORD").ok())
+        .unwrap_or_else(|| {
+            eprintln!("no password, use --password=<password> or RCON_PASSWORD");
+            std::process::exit(1);
+        });
+    let command: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+
+    let mut client = RconClient::connect(&server, &password).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if !command.is_empty() {
+        match client.send(&command.join(" ")) {
+            Ok(reply) => println!("{}", reply),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    println!("connected to {}, help lists the commands", server);
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match client.send(&line) {
+            Ok(reply) => println!("{}", reply),
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
+        }
+    }
+}
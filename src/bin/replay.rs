@@ -0,0 +1,162 @@
+//! replay recorded sessions through the movement simulation and compare the results.
+//!
+//! run a recording and write the trace of this build:
+//!     replay --session=session.bin --trace=new.trace
+//! run a recording and compare against the trace of another build:
+//!     replay --session=session.bin --baseline=old.trace [--diff=diff.csv] [--threshold=0.001]
+//! compare two existing traces:
+//!     replay --compare old.trace new.trace [--diff=diff.csv] [--threshold=0.001]
+//!
+//! exits with status 1 if the traces differ.
+//!
+//! Recordings hold the movement inputs only: all players start at PLAYER_SPAWN_POINT, and
+//! respawns, teleports and knockback of the recorded session are not reproduced. A trace shows
+//! how a build simulates the inputs, compare it to the trace of another build, not to the
+//! session.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufWriter,
+    time::{Duration, Instant},
+};
+
+use bevy::{asset::AssetPlugin, prelude::*, time::TimeSystem};
+use bevy_rapier3d::prelude::*;
+use renet_test::{
+    controller::{
+        self, FpsController, FpsControllerInputQueue, FpsControllerPhysicsBundle, LandedEvent,
+        MovementEvent,
+    },
+    level::{JumpPadEvent, TeleportEvent},
+    replay::{diff_traces, SessionRecording, SimTrace, TraceSample},
+    PLAYER_SPAWN_POINT,
+};
+
+/// clock driven by the recorded frame times instead of wall time
+struct ReplayClock(Instant);
+
+fn replay_time_system(mut time: ResMut<Time>, clock: Res<ReplayClock>) {
+    time.update_with_instant(clock.0);
+}
+
+fn arg_value(name: &str) -> Option<String> {
+    let prefix = format!("--{}=", name);
+    std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.strip_prefix(&prefix).map(str::to_string))
+}
+
+fn run_session(recording: &SessionRecording) -> SimTrace {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(AssetPlugin)
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>()
+        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default());
+
+    app.add_event::<JumpPadEvent>()
+        .add_event::<TeleportEvent>()
+        .add_event::<LandedEvent>()
+        .add_event::<MovementEvent>();
+    app.insert_resource(ReplayClock(Instant::now()));
+    app.add_system_to_stage(CoreStage::First, replay_time_system.after(TimeSystem));
+    app.add_system(controller::fps_controller_move);
+    recording.level.clone().add_setup_system(&mut app);
+
+    let mut players = HashMap::new();
+    for client in recording.clients() {
+        let mut fps_controller = FpsController::default();
+        recording.settings.apply(&mut fps_controller);
+        let entity = app
+            .world
+            .spawn()
+            .insert_bundle(TransformBundle::from_transform(
+                Transform::from_translation(PLAYER_SPAWN_POINT),
+            ))
+            .insert_bundle(FpsControllerPhysicsBundle::default())
+            .insert(FpsControllerInputQueue::default())
+            .insert(fps_controller)
+            .id();
+        players.insert(client, entity);
+    }
+
+    let mut trace = SimTrace::default();
+    for (frame_index, frame) in recording.frames.iter().enumerate() {
+        for (client, input) in &frame.inputs {
+            if let Some(mut queue) = players
+                .get(client)
+                .and_then(|entity| app.world.get_mut::<FpsControllerInputQueue>(*entity))
+            {
+                queue.queue.push_back(input.clone());
+            }
+        }
+        app.world.resource_mut::<ReplayClock>().0 += Duration::from_secs_f32(frame.dt);
+        app.update();
+
+        for (client, entity) in &players {
+            let position = app.world.get::<Transform>(*entity).unwrap().translation;
+            let velocity = app.world.get::<Velocity>(*entity).unwrap().linvel;
+            trace.samples.push(TraceSample {
+                frame: frame_index as u32,
+                client: *client,
+                position,
+                velocity,
+            });
+        }
+    }
+    trace
+}
+
+fn compare(baseline: &SimTrace, trace: &SimTrace) -> bool {
+    let threshold = arg_value("threshold")
+        .map(|threshold| threshold.parse().expect("invalid threshold"))
+        .unwrap_or(0.001);
+    let diff = diff_traces(baseline, trace, threshold);
+    print!("{}", diff);
+    if let Some(path) = arg_value("diff") {
+        let file = File::create(&path).expect("failed to create diff file");
+        diff.write_csv(BufWriter::new(file))
+            .expect("failed to write diff file");
+        println!("diff written to {}", path);
+    }
+    diff.is_identical()
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let identical = if let Some(index) = args.iter().position(|arg| arg == "--compare") {
+        let (a, b) = match (args.get(index + 1), args.get(index + 2)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => panic!("--compare needs two trace files"),
+        };
+        let a = SimTrace::load(a).expect("failed to load trace");
+        let b = SimTrace::load(b).expect("failed to load trace");
+        compare(&a, &b)
+    } else {
+        let session = arg_value("session").expect("missing --session=<file>");
+        let recording = SessionRecording::load(&session).expect("failed to load session");
+        println!(
+            "replaying {} frames of {} clients",
+            recording.frames.len(),
+            recording.clients().len()
+        );
+        let trace = run_session(&recording);
+        if let Some(path) = arg_value("trace") {
+            trace.save(&path).expect("failed to write trace");
+            println!("trace written to {}", path);
+        }
+        match arg_value("baseline") {
+            Some(baseline) => {
+                let baseline = SimTrace::load(baseline).expect("failed to load baseline");
+                compare(&baseline, &trace)
+            }
+            None => true,
+        }
+    };
+    if !identical {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,606 @@
+//! bots: server side players without a client. They take a lobby slot under a reserved client
+//! id and play through the same paths as humans: every frame they produce an
+//! FpsControllerInput for their input queue, their shots go through the regular PlayerCommand
+//! handling. Bots find their way on a navigation grid built from the level colliders. They
+//! wander around, chase enemies they saw and shoot at enemies in sight. Bots far from all human
+//! players only look around and pick their state every few frames, see `lod`.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ServerSettings,
+    controller::{FpsController, FpsControllerInput, FpsControllerInputQueue, EYE_HEIGHT},
+    health::Health,
+    level::{CurrentLevel, LevelEntity, LevelKind},
+    lod::SimulationLod,
+    targets::{PlayerTargets, PLAYER_CENTER_OFFSET},
+    team::Team,
+    Player, PlayerCommand,
+};
+
+/// client ids from here on belong to bots. Client ids of humans are connection times in ms.
+pub const BOT_ID_BASE: u64 = u64::MAX - 0xffff;
+
+/// size of the navigation grid cells
+const NAV_CELL_SIZE: f32 = 1.0;
+
+/// highest step between neighboring cells bots walk (or jump) up
+const NAV_MAX_STEP: f32 = 0.6;
+
+/// deepest drop between neighboring cells bots walk down
+const NAV_MAX_DROP: f32 = 3.0;
+
+/// surfaces steeper than this (cosine of the slope) are not walkable
+const NAV_MIN_NORMAL_Y: f32 = 0.7;
+
+/// free space needed above walkable surfaces
+const NAV_CLEARANCE: f32 = 1.8;
+
+/// vertical step between the surfaces found in one grid column
+const NAV_LAYER_STEP: f32 = 0.25;
+
+/// bots see enemies up to this far
+const SIGHT_RANGE: f32 = 30.0;
+
+/// bots shoot at enemies in sight up to this far
+const ATTACK_RANGE: f32 = 20.0;
+
+/// seconds between the shots of a bot
+const FIRE_INTERVAL: f64 = 0.4;
+
+/// seconds between path updates towards a moving goal
+const REPATH_INTERVAL: f32 = 1.0;
+
+/// waypoints closer than this (horizontally) count as reached
+const WAYPOINT_RADIUS: f32 = 0.5;
+
+/// seconds a chase goes on after losing sight of the enemy
+const CHASE_TIME: f32 = 8.0;
+
+/// a bot moving slower than this for STUCK_TIME looks for another way
+const STUCK_SPEED: f32 = 0.3;
+
+const STUCK_TIME: f32 = 1.5;
+
+pub fn is_bot(id: u64) -> bool {
+    id >= BOT_ID_BASE
+}
+
+/// client id of the bot with `index`
+pub fn bot_id(index: usize) -> u64 {
+    BOT_ID_BASE + index as u64
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BotSettings {
+    /// bots playing along, they leave when humans need their slots
+    pub count: usize,
+    /// bots name themselves after this
+    pub name: String,
+    /// aim error of bots (radians), 0 for perfect aim
+    pub aim_error: f32,
+}
+
+impl Default for BotSettings {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            name: "Bot".to_string(),
+            aim_error: 0.06,
+        }
+    }
+}
+
+struct NavNode {
+    /// walkable surface point
+    position: Vec3,
+    neighbors: Vec<usize>,
+}
+
+/// server: walkable surfaces of the level on a grid, with links between neighboring cells
+/// players can walk between. A grid column holds a node for every walkable surface in it, so
+/// floors above each other are kept apart.
+#[derive(Default)]
+pub struct NavMesh {
+    /// map the grid was built for
+    pub map: Option<LevelKind>,
+    /// CurrentLevel::generation of the level the grid was built for
+    generation: u32,
+    nodes: Vec<NavNode>,
+    /// node indices by grid cell
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+fn cell_of(position: Vec3) -> (i32, i32) {
+    (
+        (position.x / NAV_CELL_SIZE).floor() as i32,
+        (position.z / NAV_CELL_SIZE).floor() as i32,
+    )
+}
+
+impl NavMesh {
+    /// sample the static colliders between `min` and `max`
+    pub fn build(physics_context: &RapierContext, min: Vec3, max: Vec3, map: LevelKind) -> Self {
+        let filter = QueryFilter::only_fixed().exclude_sensors();
+        let mut nav_mesh = NavMesh {
+            map: Some(map),
+            ..default()
+        };
+        let (min_cell, max_cell) = (cell_of(min), cell_of(max));
+        for x in min_cell.0..=max_cell.0 {
+            for z in min_cell.1..=max_cell.1 {
+                let column = Vec3::new(
+                    (x as f32 + 0.5) * NAV_CELL_SIZE,
+                    0.0,
+                    (z as f32 + 0.5) * NAV_CELL_SIZE,
+                );
+                // walk down the column from surface to surface
+                let mut top = max.y + 1.0;
+                while top > min.y {
+                    let origin = Vec3::new(column.x, top, column.z);
+                    let (_, hit) = match physics_context.cast_ray_and_get_normal(
+                        origin,
+                        -Vec3::Y,
+                        top - min.y,
+                        true,
+                        filter,
+                    ) {
+                        Some(hit) => hit,
+                        None => break,
+                    };
+                    // started inside of something
+                    if hit.toi <= 0.0 {
+                        top -= NAV_LAYER_STEP;
+                        continue;
+                    }
+                    let surface = hit.point;
+                    let headroom = physics_context
+                        .cast_ray(
+                            surface + Vec3::Y * 0.05,
+                            Vec3::Y,
+                            NAV_CLEARANCE,
+                            true,
+                            filter,
+                        )
+                        .is_none();
+                    if hit.normal.y >= NAV_MIN_NORMAL_Y && headroom {
+                        nav_mesh
+                            .cells
+                            .entry((x, z))
+                            .or_default()
+                            .push(nav_mesh.nodes.len());
+                        nav_mesh.nodes.push(NavNode {
+                            position: surface,
+                            neighbors: Vec::new(),
+                        });
+                    }
+                    top = surface.y - NAV_LAYER_STEP;
+                }
+            }
+        }
+
+        // link nodes of neighboring cells if nothing is in the way
+        for index in 0..nav_mesh.nodes.len() {
+            let position = nav_mesh.nodes[index].position;
+            let cell = cell_of(position);
+            let mut neighbors = Vec::new();
+            for (dx, dz) in [
+                (-1, -1),
+                (-1, 0),
+                (-1, 1),
+                (0, -1),
+                (0, 1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+            ] {
+                let others = match nav_mesh.cells.get(&(cell.0 + dx, cell.1 + dz)) {
+                    Some(others) => others,
+                    None => continue,
+                };
+                for other in others {
+                    let other_position = nav_mesh.nodes[*other].position;
+                    let rise = other_position.y - position.y;
+                    if rise > NAV_MAX_STEP || rise < -NAV_MAX_DROP {
+                        continue;
+                    }
+                    // look for walls above step height, at the height of the higher node
+                    let height = position.y.max(other_position.y) + NAV_MAX_STEP + 0.1;
+                    let from = Vec3::new(position.x, height, position.z);
+                    let to = Vec3::new(other_position.x, height, other_position.z);
+                    let distance = from.distance(to);
+                    let blocked = physics_context
+                        .cast_ray(from, (to - from) / distance, distance, true, filter)
+                        .is_some();
+                    if !blocked {
+                        neighbors.push(*other);
+                    }
+                }
+            }
+            nav_mesh.nodes[index].neighbors = neighbors;
+        }
+        info!(
+            "Built the navigation grid for {}: {} nodes.",
+            map.name(),
+            nav_mesh.nodes.len()
+        );
+        nav_mesh
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// the node closest to a player standing at `position`, on the surface below its feet
+    fn nearest(&self, position: Vec3) -> Option<usize> {
+        let cell = cell_of(position);
+        let mut nearest: Option<(usize, f32)> = None;
+        for dx in -1..=1 {
+            for dz in -1..=1 {
+                let nodes = match self.cells.get(&(cell.0 + dx, cell.1 + dz)) {
+                    Some(nodes) => nodes,
+                    None => continue,
+                };
+                for node in nodes {
+                    let node_position = self.nodes[*node].position;
+                    if node_position.y > position.y + NAV_MAX_STEP {
+                        continue;
+                    }
+                    let distance = node_position.distance(position);
+                    if nearest.map_or(true, |(_, best)| distance < best) {
+                        nearest = Some((*node, distance));
+                    }
+                }
+            }
+        }
+        nearest.map(|(node, _)| node)
+    }
+
+    /// waypoints from `from` to `to` (A*), without the start. None if there is no way.
+    pub fn path(&self, from: Vec3, to: Vec3) -> Option<Vec<Vec3>> {
+        let (start, goal) = (self.nearest(from)?, self.nearest(to)?);
+        let goal_position = self.nodes[goal].position;
+        let mut open = BinaryHeap::new();
+        let mut costs: HashMap<usize, f32> = HashMap::from([(start, 0.0)]);
+        let mut came_from: HashMap<usize, usize> = HashMap::new();
+        open.push(Reverse((0, start)));
+        while let Some(Reverse((_, current))) = open.pop() {
+            if current == goal {
+                let mut path = vec![goal_position];
+                let mut node = goal;
+                while let Some(previous) = came_from.get(&node) {
+                    if *previous != start {
+                        path.push(self.nodes[*previous].position);
+                    }
+                    node = *previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let position = self.nodes[current].position;
+            let cost = costs[&current];
+            for next in &self.nodes[current].neighbors {
+                let next_position = self.nodes[*next].position;
+                let next_cost = cost + position.distance(next_position);
+                if costs.get(next).map_or(false, |known| *known <= next_cost) {
+                    continue;
+                }
+                costs.insert(*next, next_cost);
+                came_from.insert(*next, current);
+                // integer priorities in mm, f32 is not Ord
+                let estimate = next_cost + next_position.distance(goal_position);
+                open.push(Reverse(((estimate * 1000.0) as u32, *next)));
+            }
+        }
+        None
+    }
+
+    /// a random walkable position, to wander to
+    pub fn random_position(&self, rng: &mut impl Rng) -> Option<Vec3> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        Some(self.nodes[rng.gen_range(0..self.nodes.len())].position)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BotState {
+    Wander,
+    /// going where `target` was last seen
+    Chase {
+        target: Entity,
+        last_seen: Vec3,
+    },
+    /// shooting at `target`
+    Attack {
+        target: Entity,
+    },
+}
+
+impl BotState {
+    pub fn label(&self) -> &'static str {
+        match self {
+            BotState::Wander => "wander",
+            BotState::Chase { .. } => "chase",
+            BotState::Attack { .. } => "attack",
+        }
+    }
+}
+
+#[derive(Debug, Component)]
+pub struct Bot {
+    pub state: BotState,
+    /// waypoints still ahead, next first
+    path: Vec<Vec3>,
+    repath: Timer,
+    /// runs while the chased enemy is out of sight
+    chase: Timer,
+    stuck: Timer,
+    next_shot: f64,
+    /// serial of the next input
+    serial: u32,
+    /// spread pattern number of the next shot
+    shot: u32,
+}
+
+impl Default for Bot {
+    fn default() -> Self {
+        Self {
+            state: BotState::Wander,
+            path: Vec::new(),
+            repath: Timer::from_seconds(REPATH_INTERVAL, true),
+            chase: Timer::from_seconds(CHASE_TIME, false),
+            stuck: Timer::from_seconds(STUCK_TIME, false),
+            next_shot: 0.0,
+            serial: 1,
+            shot: 0,
+        }
+    }
+}
+
+/// server: commands of the bots for this frame, handled together with the commands of the
+/// clients
+#[derive(Debug, Default)]
+pub struct BotCommands(pub Vec<(u64, PlayerCommand)>);
+
+/// server: build the navigation grid for a newly (re)loaded level. Colliders reach the physics world
+/// one frame after the level was spawned, so this waits a frame.
+pub fn build_nav_mesh_system(
+    physics_context: Res<RapierContext>,
+    current_level: Res<CurrentLevel>,
+    mut nav_mesh: ResMut<NavMesh>,
+    mut pending: Local<Option<u32>>,
+    colliders: Query<(&Collider, &GlobalTransform), With<LevelEntity>>,
+) {
+    let generation = current_level.generation();
+    let map = match current_level.loaded() {
+        Some(map) if nav_mesh.generation != generation => map,
+        _ => return,
+    };
+    if *pending != Some(generation) {
+        *pending = Some(generation);
+        return;
+    }
+    let mut min = Vec3::splat(f32::MAX);
+    let mut max = Vec3::splat(f32::MIN);
+    for (collider, transform) in colliders.iter() {
+        let aabb = collider.raw.compute_local_aabb();
+        let reach = aabb.center().coords.norm() + aabb.half_extents().norm();
+        min = min.min(transform.translation() - Vec3::splat(reach));
+        max = max.max(transform.translation() + Vec3::splat(reach));
+    }
+    *nav_mesh = if min.x <= max.x {
+        NavMesh::build(&physics_context, min, max, map.clone())
+    } else {
+        NavMesh {
+            map: Some(map.clone()),
+            ..default()
+        }
+    };
+    nav_mesh.generation = generation;
+}
+
+/// yaw and pitch looking along `direction`
+fn look_angles(direction: Vec3) -> (f32, f32) {
+    let yaw = f32::atan2(-direction.x, -direction.z);
+    let pitch = f32::atan2(direction.y, Vec2::new(direction.x, direction.z).length());
+    (yaw, pitch)
+}
+
+/// server: let the bots look around, pick their state, walk their paths and shoot. Their
+/// movement goes into their input queues, shots into BotCommands.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn bot_system(
+    time: Res<Time>,
+    nav_mesh: Res<NavMesh>,
+    settings: Res<ServerSettings>,
+    mut bot_commands: ResMut<BotCommands>,
+    mut frame: Local<u32>,
+    mut bots: Query<(
+        Entity,
+        &Player,
+        &Transform,
+        &Health,
+        &FpsController,
+        &mut Bot,
+        &mut FpsControllerInputQueue,
+        Option<&Team>,
+        Option<&SimulationLod>,
+    )>,
+    targets: PlayerTargets,
+    healths: Query<&Health>,
+) {
+    let now = time.seconds_since_startup();
+    let mut rng = rand::thread_rng();
+    *frame = frame.wrapping_add(1);
+    for (entity, player, transform, health, controller, mut bot, mut input_queue, team, lod) in
+        &mut bots
+    {
+        let position = transform.translation;
+        let mut input = FpsControllerInput {
+            serial: bot.serial,
+            sent_at: now,
+            pitch: controller.pitch,
+            yaw: controller.yaw - controller.yaw_offset,
+            ..default()
+        };
+        bot.serial += 1;
+        // already applied inputs are only kept for clients to replay
+        input_queue
+            .queue
+            .retain(|input| input.serial > controller.last_applied_serial);
+
+        if health.is_dead() {
+            bot.state = BotState::Wander;
+            bot.path.clear();
+            input_queue.queue.push_back(input);
+            continue;
+        }
+
+        // the think step: find the closest enemy in sight, pick the state and a path for it.
+        // Without it the bot keeps its state and path and aims at its target.
+        let think = lod.map_or(true, |lod| lod.should_think(&settings.lod, *frame));
+        // where a player stands
+        let feet = |target: Entity| {
+            targets
+                .position(target)
+                .map(|center| center - PLAYER_CENTER_OFFSET)
+        };
+        let eye = position + Vec3::Y * EYE_HEIGHT;
+        let enemy = if think {
+            targets
+                .enemies_of(team.copied())
+                .filter(|(other, center)| {
+                    *other != entity
+                        && healths.get(*other).map_or(false, |other| !other.is_dead())
+                        && center.distance(eye) < SIGHT_RANGE
+                        && targets.visible_from(eye, *other, Some(entity))
+                })
+                .map(|(other, center)| (other, center - PLAYER_CENTER_OFFSET))
+                .min_by(|(_, a), (_, b)| a.distance(position).total_cmp(&b.distance(position)))
+        } else {
+            match bot.state {
+                BotState::Attack { target } => feet(target).map(|feet| (target, feet)),
+                _ => None,
+            }
+        };
+
+        if think {
+            let previous = bot.state;
+            bot.state = match (enemy, previous) {
+                (Some((target, target_position)), _)
+                    if target_position.distance(position) < ATTACK_RANGE =>
+                {
+                    BotState::Attack { target }
+                }
+                (Some((target, last_seen)), _) => BotState::Chase { target, last_seen },
+                // lost sight of it, go where it was seen last
+                (None, BotState::Attack { target }) => match feet(target) {
+                    Some(last_seen) => BotState::Chase { target, last_seen },
+                    None => BotState::Wander,
+                },
+                (None, BotState::Chase { .. }) if !bot.chase.finished() => previous,
+                (None, _) => BotState::Wander,
+            };
+            if enemy.is_some() {
+                bot.chase.reset();
+            } else {
+                bot.chase.tick(time.delta());
+            }
+
+            // pick a path for the state
+            let repath = bot.repath.tick(time.delta()).just_finished();
+            let goal = match bot.state {
+                BotState::Wander if bot.path.is_empty() => nav_mesh.random_position(&mut rng),
+                BotState::Wander => None,
+                BotState::Chase { last_seen, .. } if repath || bot.path.is_empty() => {
+                    Some(last_seen)
+                }
+                BotState::Chase { .. } => None,
+                BotState::Attack { target } if repath => feet(target),
+                BotState::Attack { .. } => None,
+            };
+            if let Some(goal) = goal {
+                bot.path = nav_mesh.path(position, goal).unwrap_or_default();
+            }
+            if previous.label() != bot.state.label() {
+                debug!("Bot {} {}.", player.id, bot.state.label());
+            }
+        }
+
+        // walk the path
+        while bot.path.first().map_or(false, |waypoint| {
+            Vec2::new(waypoint.x - position.x, waypoint.z - position.z).length() < WAYPOINT_RADIUS
+        }) {
+            bot.path.remove(0);
+        }
+        let walk = bot.path.first().map(|waypoint| {
+            let offset = *waypoint - position;
+            (
+                Vec3::new(offset.x, 0.0, offset.z).normalize_or_zero(),
+                offset.y,
+            )
+        });
+        let (mut yaw, mut pitch) = (input.yaw, 0.0);
+        if let Some((direction, rise)) = walk {
+            (yaw, _) = look_angles(direction);
+            input.jump = rise > 0.3;
+        }
+
+        // face and shoot the enemy
+        if let (BotState::Attack { .. }, Some((_, target_position))) = (bot.state, enemy) {
+            let aim = (target_position + PLAYER_CENTER_OFFSET - eye).normalize_or_zero();
+            (yaw, pitch) = look_angles(aim);
+            if now >= bot.next_shot {
+                bot.next_shot = now + FIRE_INTERVAL;
+                let error = settings.bots.aim_error;
+                let direction = Quat::from_euler(
+                    EulerRot::YXZ,
+                    rng.gen_range(-error..=error),
+                    rng.gen_range(-error..=error),
+                    0.0,
+                ) * aim;
+                bot_commands.0.push((
+                    player.id,
+                    PlayerCommand::Hitscan {
+                        origin: eye,
+                        direction,
+                        tick: None,
+                        shot: bot.shot,
+                    },
+                ));
+                bot.shot += 1;
+            }
+        }
+        input.yaw = yaw - controller.yaw_offset;
+        input.pitch = pitch;
+        // move along the path relative to where the bot looks
+        if let Some((direction, _)) = walk {
+            let forward = Vec3::new(-yaw.sin(), 0.0, -yaw.cos());
+            let right = Vec3::new(yaw.cos(), 0.0, -yaw.sin());
+            input.movement = Vec3::new(direction.dot(right), 0.0, direction.dot(forward));
+
+            // not getting anywhere, try another way
+            if controller.velocity.length() < STUCK_SPEED {
+                if bot.stuck.tick(time.delta()).finished() {
+                    bot.path.clear();
+                    bot.stuck.reset();
+                    input.jump = true;
+                }
+            } else {
+                bot.stuck.reset();
+            }
+        }
+        input_queue.queue.push_back(input);
+    }
+}
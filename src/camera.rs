@@ -1,19 +1,123 @@
-use bevy::prelude::*;
+use bevy::{
+    core_pipeline::{clear_color::ClearColorConfig, core_3d::Camera3dDepthLoadOp},
+    prelude::*,
+    render::{camera::Projection, view::RenderLayers},
+};
+use bevy_rapier3d::prelude::*;
 use smooth_bevy_cameras::{LookTransform, LookTransformBundle, Smoother};
 
-use crate::{ControlledPlayer, Ray3d, WorldSpacePointer};
+use crate::{
+    controller::{LogicalPlayer, RenderPlayer},
+    killcam::KillCam,
+    spectator::Spectating,
+    view::ViewEffects,
+    ControlledPlayer, Ray3d, WorldSpacePointer,
+};
 
-/// update camera tracking
+/// distance of the third person camera behind the eyes
+pub const THIRD_PERSON_DISTANCE: f32 = 4.0;
+
+/// radius of the sphere cast keeping the third person camera out of walls
+const THIRD_PERSON_PROBE_RADIUS: f32 = 0.2;
+
+/// render layer of the first person view model, only drawn by the ViewModelCamera
+pub const VIEW_MODEL_LAYER: u8 = 1;
+
+/// default vertical field of view of the view model camera (degrees)
+pub const VIEW_MODEL_FOV: f32 = 50.0;
+
+/// limits of the vertical field of view settings (degrees)
+pub const MIN_FOV: f32 = 30.0;
+pub const MAX_FOV: f32 = 90.0;
+
+/// a field of view setting within the limits (degrees)
+pub fn clamp_fov(fov: f32) -> f32 {
+    fov.clamp(MIN_FOV, MAX_FOV)
+}
+
+/// changes of the world field of view on top of the setting, filled by the view effects
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FovModifiers {
+    /// widening at high speed (degrees)
+    pub kick: f32,
+    /// multiplier while aiming down sights
+    pub zoom: f32,
+}
+
+impl Default for FovModifiers {
+    fn default() -> Self {
+        Self {
+            kick: 0.0,
+            zoom: 1.0,
+        }
+    }
+}
+
+/// draws the view model on top of the world, so it never clips into walls. Child of the
+/// RenderPlayer camera.
+#[derive(Component)]
+pub struct ViewModelCamera;
+
+/// which camera renders the game, cycled with F5
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// smooth camera following the player from above
+    TopDown,
+    /// camera at the eyes of the locally simulated player (RenderPlayer)
+    FirstPerson,
+    /// RenderPlayer camera pulled back behind the player, same pitch and yaw as first person
+    ThirdPerson,
+}
+
+impl Default for CameraMode {
+    fn default() -> Self {
+        CameraMode::TopDown
+    }
+}
+
+impl CameraMode {
+    pub fn next(self) -> Self {
+        match self {
+            CameraMode::TopDown => CameraMode::FirstPerson,
+            CameraMode::FirstPerson => CameraMode::ThirdPerson,
+            CameraMode::ThirdPerson => CameraMode::TopDown,
+        }
+    }
+
+    /// view follows the pitch and yaw of the FpsController
+    pub fn is_player_view(self) -> bool {
+        self != CameraMode::TopDown
+    }
+}
+
+/// update camera tracking. The top down camera aims at the mouse cursor, the player view
+/// cameras along the view direction.
 pub fn update_target_system(
     windows: Res<Windows>,
+    mode: Res<CameraMode>,
     mut target_query: Query<&mut Transform, With<WorldSpacePointer>>,
     camera_query: Query<(&Camera, &GlobalTransform)>,
 ) {
-    let (camera, camera_transform) = camera_query.single();
+    let (camera, camera_transform) = match camera_query.iter().find(|(camera, _)| camera.is_active)
+    {
+        Some(camera) => camera,
+        None => return,
+    };
     let mut target_transform = target_query.single_mut();
-    if let Some(ray) = Ray3d::from_screenspace(&windows, camera, camera_transform) {
+    let ray = if mode.is_player_view() {
+        Some(Ray3d {
+            origin: camera_transform.translation(),
+            direction: camera_transform.forward(),
+        })
+    } else {
+        Ray3d::from_screenspace(&windows, camera, camera_transform)
+    };
+    if let Some(ray) = ray {
         if let Some(pos) = ray.intersect_y_plane(1.0) {
             target_transform.translation = pos;
+        } else if mode.is_player_view() {
+            // looking above the horizon
+            target_transform.translation = ray.origin + ray.direction * 20.0;
         }
     }
 }
@@ -34,6 +138,147 @@ pub fn setup_camera(mut commands: Commands) {
         });
 }
 
+/// first person camera, inactive until switched to. Positioned by fps_controller_render.
+/// The view model camera follows it as child and renders after it into the same image,
+/// keeping the colors but clearing the depth.
+pub fn setup_first_person_camera(mut commands: Commands, effects: Res<ViewEffects>) {
+    commands
+        .spawn_bundle(Camera3dBundle {
+            camera: Camera {
+                is_active: false,
+                ..default()
+            },
+            projection: Projection::Perspective(PerspectiveProjection {
+                fov: clamp_fov(effects.fov).to_radians(),
+                ..default()
+            }),
+            ..default()
+        })
+        .insert(RenderPlayer(0))
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(Camera3dBundle {
+                    camera: Camera {
+                        is_active: false,
+                        priority: 1,
+                        ..default()
+                    },
+                    camera_3d: Camera3d {
+                        clear_color: ClearColorConfig::None,
+                        depth_load_op: Camera3dDepthLoadOp::Clear(0.0),
+                    },
+                    projection: Projection::Perspective(PerspectiveProjection {
+                        fov: clamp_fov(effects.view_model_fov).to_radians(),
+                        near: 0.01,
+                        ..default()
+                    }),
+                    ..default()
+                })
+                .insert(RenderLayers::layer(VIEW_MODEL_LAYER))
+                .insert(ViewModelCamera);
+        });
+}
+
+/// field of view of the first person cameras: the world uses the FOV setting with the view
+/// effect modifiers, the view model its own setting so the weapon keeps its size.
+#[allow(clippy::type_complexity)]
+pub fn first_person_projection_system(
+    effects: Res<ViewEffects>,
+    modifiers: Res<FovModifiers>,
+    mut world_cameras: Query<&mut Projection, (With<RenderPlayer>, With<Camera>)>,
+    mut view_model_cameras: Query<&mut Projection, (With<ViewModelCamera>, Without<RenderPlayer>)>,
+) {
+    let world_fov = ((clamp_fov(effects.fov) + modifiers.kick) * modifiers.zoom).to_radians();
+    let view_model_fov = clamp_fov(effects.view_model_fov).to_radians();
+    let cameras = world_cameras
+        .iter_mut()
+        .map(|projection| (projection, world_fov))
+        .chain(
+            view_model_cameras
+                .iter_mut()
+                .map(|projection| (projection, view_model_fov)),
+        );
+    for (mut projection, fov) in cameras {
+        if let Projection::Perspective(perspective) = &mut *projection {
+            if perspective.fov != fov {
+                perspective.fov = fov;
+            }
+        }
+    }
+}
+
+pub fn switch_camera_mode_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut mode: ResMut<CameraMode>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        *mode = mode.next();
+    }
+}
+
+/// pull the RenderPlayer camera back from the eyes in third person. A sphere cast along the
+/// boom shortens it in front of walls, so the camera does not clip through them.
+pub fn third_person_camera_system(
+    mode: Res<CameraMode>,
+    physics_context: Res<RapierContext>,
+    logical_query: Query<Entity, With<LogicalPlayer>>,
+    mut camera_query: Query<&mut Transform, (With<RenderPlayer>, With<Camera>)>,
+) {
+    if *mode != CameraMode::ThirdPerson {
+        return;
+    }
+    let mut filter = QueryFilter::default();
+    if let Ok(player) = logical_query.get_single() {
+        filter = filter.exclude_rigid_body(player);
+    }
+    for mut transform in &mut camera_query {
+        let eye = transform.translation;
+        let back = transform.back();
+        let distance = match physics_context.cast_shape(
+            eye,
+            Quat::IDENTITY,
+            back,
+            &Collider::ball(THIRD_PERSON_PROBE_RADIUS),
+            THIRD_PERSON_DISTANCE,
+            filter,
+        ) {
+            Some((_, hit)) => hit.toi,
+            None => THIRD_PERSON_DISTANCE,
+        };
+        transform.translation = eye + back * distance;
+    }
+}
+
+/// activate the camera of the current mode. The own player model is hidden in first person,
+/// it would block the view. Spectating always uses the player view camera and hides the own
+/// model, so does the kill cam.
+pub fn apply_camera_mode_system(
+    mode: Res<CameraMode>,
+    spectating: Res<Spectating>,
+    kill_cam: Res<KillCam>,
+    mut top_down_cameras: Query<&mut Camera, With<LookTransform>>,
+    mut first_person_cameras: Query<&mut Camera, (With<RenderPlayer>, Without<LookTransform>)>,
+    mut player_query: Query<&mut Visibility, With<ControlledPlayer>>,
+) {
+    let player_view = mode.is_player_view() || spectating.is_spectating() || kill_cam.is_active();
+    let hide_player = *mode == CameraMode::FirstPerson || spectating.is_spectating();
+    for mut camera in &mut top_down_cameras {
+        if camera.is_active == player_view {
+            camera.is_active = !player_view;
+        }
+    }
+    for mut camera in &mut first_person_cameras {
+        if camera.is_active != player_view {
+            camera.is_active = player_view;
+        }
+    }
+    for mut visibility in &mut player_query {
+        if visibility.is_visible == hide_player {
+            visibility.is_visible = !hide_player;
+        }
+    }
+}
+
 pub fn setup_target(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -0,0 +1,226 @@
+//! client side player character: a glTF scene (skinned mesh) loaded through the AssetServer,
+//! attached as child to the replicated player entity. The model is placed by `character.ron`
+//! so it lines up with the capsule collider, whose origin is at the feet:
+//!
+//!     (
+//!         model: Some("models/character.glb#Scene0"),
+//!         scale: 1.0,
+//!         offset: (0.0, 0.0, 0.0),
+//!         yaw: 180.0,
+//!         team_tint: true,
+//!     )
+//!
+//! Players are drawn as capsules if the model is None (the default) or fails to load.
+
+use std::{fs, path::Path};
+
+use bevy::{asset::LoadState, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::{CharacterAnimations, CharacterAnimator},
+    targets::PLAYER_CENTER_OFFSET,
+};
+
+pub const CHARACTER_CONFIG_FILE: &str = "character.ron";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CharacterModel {
+    /// asset path of the glTF scene, relative to the assets folder
+    pub model: Option<String>,
+    /// uniform scale of the scene
+    pub scale: f32,
+    /// from the player origin (feet) to the origin of the scene, before the scale
+    pub offset: Vec3,
+    /// turns the scene to look along -Z like the player (degrees). glTF characters usually
+    /// face +Z.
+    pub yaw: f32,
+    /// multiply the colors of the model with the team color
+    pub team_tint: bool,
+    /// clips of the animation states, see `animation`
+    pub animations: CharacterAnimations,
+}
+
+impl Default for CharacterModel {
+    fn default() -> Self {
+        Self {
+            model: None,
+            scale: 1.0,
+            offset: Vec3::ZERO,
+            yaw: 180.0,
+            team_tint: true,
+            animations: CharacterAnimations::default(),
+        }
+    }
+}
+
+impl CharacterModel {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        ron::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// load the file if there is one, the defaults otherwise
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Self {
+        if !path.as_ref().exists() {
+            return Self::default();
+        }
+        Self::load(path).unwrap_or_else(|e| {
+            warn!("failed to load character model: {}", e);
+            Self::default()
+        })
+    }
+
+    /// spawn the visible character as child of the player entity `parent`. `tint` is the
+    /// team color, the untinted model or the neutral capsule color without a team.
+    pub fn spawn(
+        &self,
+        parent: &mut ChildBuilder,
+        asset_server: &AssetServer,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        tint: Option<Color>,
+    ) -> Entity {
+        let tint = if self.team_tint { tint } else { None };
+        match &self.model {
+            Some(model) => {
+                let visual = CharacterVisual {
+                    offset: self.offset,
+                    scale: self.scale,
+                    yaw: self.yaw.to_radians(),
+                    tint,
+                };
+                parent
+                    .spawn_bundle(SceneBundle {
+                        scene: asset_server.load(model.as_str()),
+                        transform: visual.pose(0.0),
+                        ..default()
+                    })
+                    .insert(visual)
+                    .insert(CharacterAnimator::default())
+                    .id()
+            }
+            None => spawn_capsule(parent, meshes, materials, tint),
+        }
+    }
+}
+
+/// the visible character of a player. Rotated with the view yaw of the player, the player
+/// entity itself is not rotated.
+#[derive(Debug, Clone, Component)]
+pub struct CharacterVisual {
+    pub offset: Vec3,
+    pub scale: f32,
+    /// turn of the model relative to the player (radians)
+    pub yaw: f32,
+    pub tint: Option<Color>,
+}
+
+impl CharacterVisual {
+    fn capsule(tint: Option<Color>) -> Self {
+        Self {
+            offset: PLAYER_CENTER_OFFSET,
+            scale: 1.0,
+            yaw: 0.0,
+            tint,
+        }
+    }
+
+    /// local transform of the model while the player looks along `yaw`
+    pub fn pose(&self, yaw: f32) -> Transform {
+        let rotation = Quat::from_rotation_y(yaw);
+        Transform {
+            translation: rotation * self.offset * self.scale,
+            rotation: rotation * Quat::from_rotation_y(self.yaw),
+            scale: Vec3::splat(self.scale),
+        }
+    }
+}
+
+fn capsule_color(tint: Option<Color>) -> Color {
+    tint.unwrap_or_else(|| Color::rgb(0.8, 0.7, 0.6))
+}
+
+fn spawn_capsule(
+    parent: &mut ChildBuilder,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    tint: Option<Color>,
+) -> Entity {
+    let visual = CharacterVisual::capsule(tint);
+    parent
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Capsule::default())),
+            material: materials.add(capsule_color(tint).into()),
+            transform: visual.pose(0.0),
+            ..default()
+        })
+        .insert(visual)
+        .id()
+}
+
+/// replace characters whose model failed to load by capsules
+pub fn character_fallback_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut characters: Query<(Entity, &mut CharacterVisual, &Handle<Scene>)>,
+) {
+    for (entity, mut visual, scene) in &mut characters {
+        if asset_server.get_load_state(scene) != LoadState::Failed {
+            continue;
+        }
+        warn!("failed to load character model, using a capsule");
+        *visual = CharacterVisual::capsule(visual.tint);
+        commands
+            .entity(entity)
+            .remove::<Handle<Scene>>()
+            .remove::<CharacterAnimator>()
+            .insert(meshes.add(Mesh::from(shape::Capsule::default())))
+            .insert(materials.add(capsule_color(visual.tint).into()));
+    }
+}
+
+/// give the materials of freshly spawned character scenes the team color. Scene instances
+/// share their materials, so tinted copies are made.
+pub fn tint_character_system(
+    characters: Query<&CharacterVisual>,
+    parents: Query<&Parent>,
+    mut added: Query<(Entity, &mut Handle<StandardMaterial>), Added<Handle<StandardMaterial>>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, mut handle) in &mut added {
+        // the capsule is colored directly
+        if characters.contains(entity) {
+            continue;
+        }
+        let mut ancestor = entity;
+        let tint = loop {
+            match parents.get(ancestor) {
+                Ok(parent) => ancestor = parent.get(),
+                Err(_) => break None,
+            }
+            if let Ok(visual) = characters.get(ancestor) {
+                break visual.tint;
+            }
+        };
+        let tint = match tint {
+            Some(tint) => tint,
+            None => continue,
+        };
+        if let Some(material) = materials.get(&handle) {
+            let mut material = material.clone();
+            let color = material.base_color;
+            material.base_color = Color::rgba(
+                color.r() * tint.r(),
+                color.g() * tint.g(),
+                color.b() * tint.b(),
+                color.a(),
+            );
+            *handle = materials.add(material);
+        }
+    }
+}
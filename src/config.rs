@@ -0,0 +1,334 @@
+//! layered server configuration.
+//!
+//! `server.toml` is read first. Files listed in its `include` array are loaded before it, so
+//! the including file overrides them. Then `maps/<map>.toml` next to `server.toml` overrides
+//! settings for the selected map. Missing keys keep their defaults, a missing `server.toml`
+//! runs with defaults only.
+//!
+//!     include = ["presets/quake.toml"]
+//!     server_name = "friday frags"
+//!     motd = "welcome, be nice"
+//!     rules = ["no spawn camping"]
+//!     map = "movement_test"
+//!     watch_map = true
+//!     tick_rate = 20.0
+//!     metrics_addr = "0.0.0.0:9100"
+//!     rcon_addr = "127.0.0.1:5010"
+//!     rcon_password = "secret"
+//!     ban_list = "bans.txt"
+//!     stats_db = "stats.sqlite"
+//!
+//!     [movement]
+//!     air_control_style = "Quake"
+//!     gravity = 18.0
+//!
+//!     [physics]
+//!     kill_z = -50.0
+//!
+//!     [fireball]
+//!     gravity = 9.81
+//!     max_bounces = 2
+//!
+//!     [rotation]
+//!     maps = ["default", "movement_test"]
+//!     vote = true
+//!
+//!     [bots]
+//!     count = 4
+//!
+//!     [votes]
+//!     pass_fraction = 0.6
+//!     cooldown = 60.0
+//!
+//!     [game]
+//!     frag_limit = 30
+//!     time_limit = 900.0
+//!
+//! The client settings files (input, view, audio, ...) are TOML too, read and written with
+//! `load_or_default` and `save`.
+
+use std::{fs, io, path::Path};
+
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use toml::Value;
+
+use crate::{
+    bot::BotSettings,
+    controller::MovementSettings,
+    gamemode::GameModeSettings,
+    level::LevelKind,
+    lod::LodSettings,
+    mapvote::MapRotation,
+    names::NameRules,
+    projectile::{ProjectileKinds, ProjectileSettings},
+    team::TeamSettings,
+    vote::VoteSettings,
+    ServerInfo,
+};
+
+pub const SERVER_CONFIG_FILE: &str = "server.toml";
+
+/// nesting limit for includes, guards against include cycles
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PhysicsConfig {
+    /// long server frames are split into physics steps of at most this length (seconds)
+    pub max_substep_dt: f32,
+    pub max_substeps: usize,
+    /// players falling below this height die, props are removed
+    pub kill_z: f32,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        Self {
+            max_substep_dt: 1.0 / 60.0,
+            max_substeps: 8,
+            kill_z: -20.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    /// shown to joining players with `motd` and `rules`
+    pub server_name: String,
+    /// message of the day
+    pub motd: String,
+    /// rules of the server admin, listed after the match rules
+    pub rules: Vec<String>,
+    pub map: LevelKind,
+    /// reload the map when its files change, for working on maps with the game running
+    pub watch_map: bool,
+    /// maps played after `map`, and the vote on them
+    pub rotation: MapRotation,
+    /// player slots, only read at startup
+    pub max_players: usize,
+    /// let clients wait for a free slot while the server is full instead of turning them away
+    pub join_queue: bool,
+    /// network frames sent per second
+    pub tick_rate: f32,
+    /// address of the Prometheus metrics endpoint, off if unset. Only read at startup.
+    pub metrics_addr: Option<String>,
+    /// address of the remote admin listener, see `rcon`. Off unless `rcon_password` is set
+    /// too. Only read at startup.
+    pub rcon_addr: Option<String>,
+    pub rcon_password: Option<String>,
+    /// file with one banned player name per line, rewritten by the rcon ban and unban commands
+    pub ban_list: Option<String>,
+    /// sqlite file of the persistent player statistics, see `stats`. Off if unset, only read
+    /// at startup.
+    pub stats_db: Option<String>,
+    /// seconds between spawned physics cubes
+    pub cube_spawn_interval: f32,
+    pub movement: MovementSettings,
+    pub physics: PhysicsConfig,
+    /// speed, gravity, lifetime and bounces of fireballs
+    pub fireball: ProjectileSettings,
+    /// grenade ballistics, `lifetime` is the fuse
+    pub grenade: ProjectileSettings,
+    pub rocket: ProjectileSettings,
+    pub lod: LodSettings,
+    pub names: NameRules,
+    /// file with one forbidden word per line, replaces `names.profanity`
+    pub profanity_list: Option<String>,
+    pub teams: TeamSettings,
+    /// game mode and match rules
+    pub game: GameModeSettings,
+    /// rules for votes called by players
+    pub votes: VoteSettings,
+    /// server side players
+    pub bots: BotSettings,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            server_name: "shooter server".to_string(),
+            motd: String::new(),
+            rules: Vec::new(),
+            map: LevelKind::default(),
+            watch_map: false,
+            rotation: MapRotation::default(),
+            max_players: 64,
+            join_queue: true,
+            tick_rate: 12.0,
+            metrics_addr: None,
+            rcon_addr: None,
+            rcon_password: None,
+            ban_list: None,
+            stats_db: None,
+            cube_spawn_interval: 1.0,
+            movement: MovementSettings::default(),
+            physics: PhysicsConfig::default(),
+            fireball: ProjectileSettings::default(),
+            grenade: ProjectileSettings::grenade(),
+            rocket: ProjectileSettings::rocket(),
+            lod: LodSettings::default(),
+            names: NameRules::default(),
+            profanity_list: None,
+            teams: TeamSettings::default(),
+            game: GameModeSettings::default(),
+            votes: VoteSettings::default(),
+            bots: BotSettings::default(),
+        }
+    }
+}
+
+/// load a settings file. Keys missing in the file keep their defaults if the type is
+/// `#[serde(default)]`.
+pub fn load<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> io::Result<T> {
+    let text = fs::read_to_string(path)?;
+    toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// load a settings file, falling back to the defaults if it is missing or broken
+pub fn load_or_default<T: DeserializeOwned + Default, P: AsRef<Path>>(path: P) -> T {
+    match load(&path) {
+        Ok(value) => value,
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("failed to load {}: {}", path.as_ref().display(), e);
+            }
+            T::default()
+        }
+    }
+}
+
+pub fn save<T: Serialize, P: AsRef<Path>>(value: &T, path: P) -> io::Result<()> {
+    // through Value, which writes plain keys before tables regardless of the field order
+    let text = Value::try_from(value)
+        .and_then(|value| toml::to_string(&value))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(path, text)
+}
+
+/// merge `overlay` into `base`, tables are merged key by key, everything else is replaced
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// load a config file with its includes (relative to the file)
+fn load_layered(path: &Path, depth: usize) -> Result<Value, String> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("{}: includes nested too deep", path.display()));
+    }
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut value: Value =
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let includes = match value
+        .as_table_mut()
+        .and_then(|table| table.remove("include"))
+    {
+        None => Vec::new(),
+        Some(Value::String(include)) => vec![include],
+        Some(Value::Array(includes)) => includes
+            .into_iter()
+            .map(|include| match include {
+                Value::String(include) => Ok(include),
+                _ => Err(format!("{}: include must be a file name", path.display())),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err(format!("{}: invalid include", path.display())),
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut merged = Value::Table(Default::default());
+    for include in includes {
+        merge(&mut merged, load_layered(&dir.join(include), depth + 1)?);
+    }
+    merge(&mut merged, value);
+    Ok(merged)
+}
+
+impl ServerSettings {
+    /// what joining players see, `game` are the match rules in effect
+    pub fn info(&self, game: &GameModeSettings) -> ServerInfo {
+        let mut rules = game.rules();
+        rules.extend(self.rules.iter().cloned());
+        ServerInfo {
+            name: self.server_name.clone(),
+            motd: self.motd.clone(),
+            map: self.map.clone(),
+            mode: game.mode,
+            tick_rate: self.tick_rate,
+            max_players: self.max_players,
+            rules,
+        }
+    }
+
+    pub fn projectiles(&self) -> ProjectileKinds {
+        ProjectileKinds {
+            fireball: self.fireball.clone(),
+            grenade: self.grenade.clone(),
+            rocket: self.rocket.clone(),
+        }
+    }
+
+    /// load `path` and the overrides of the map. `map` replaces the map set in the files.
+    pub fn load<P: AsRef<Path>>(path: P, map: Option<LevelKind>) -> Result<Self, String> {
+        let path = path.as_ref();
+        // start from the defaults, so missing keys of sections with non-default values (e.g.
+        // `grenade`) keep the defaults of that section
+        let mut value = Value::try_from(ServerSettings::default()).map_err(|e| e.to_string())?;
+        if path.exists() {
+            merge(&mut value, load_layered(path, 0)?);
+        }
+        let map = match map {
+            Some(map) => map,
+            None => match value.get("map").and_then(Value::as_str) {
+                Some(map) => map.parse()?,
+                None => LevelKind::default(),
+            },
+        };
+        let map_file = path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("maps")
+            .join(format!("{}.toml", map.name()));
+        if map_file.exists() {
+            merge(&mut value, load_layered(&map_file, 0)?);
+        }
+        let mut settings: ServerSettings = value
+            .try_into()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        settings.map = map;
+        Ok(settings)
+    }
+
+    /// a copy with the setting at the dotted `key` (e.g. `game.frag_limit`) changed to
+    /// `value`, given in TOML syntax. Values that are not valid TOML are taken as strings.
+    pub fn with_setting(&self, key: &str, value: &str) -> Result<Self, String> {
+        let mut root = Value::try_from(self).map_err(|e| e.to_string())?;
+        let mut setting = &mut root;
+        for part in key.split('.') {
+            setting = setting
+                .get_mut(part)
+                .ok_or_else(|| format!("unknown setting: {}", key))?;
+        }
+        if setting.is_table() {
+            return Err(format!("{} is a section, set its keys one by one", key));
+        }
+        *setting = match format!("value = {}", value).parse::<Value>() {
+            Ok(Value::Table(mut table)) => table.remove("value").unwrap(),
+            _ => Value::String(value.to_string()),
+        };
+        root.try_into()
+            .map_err(|e| format!("invalid value for {}: {}", key, e))
+    }
+}
@@ -0,0 +1,229 @@
+//! control points: zones teams take over by standing in them. A team alone in a zone
+//! progresses towards owning it, the owner's team resets the progress of attackers, both teams
+//! together contest it and nothing moves. Owned zones earn their team points, see
+//! `gamemode::score_control_points_system`. The server runs the zones and replicates their
+//! state, clients only show them.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gamemode::{GameModeSettings, MatchEvent},
+    health::Health,
+    spectator::Spectator,
+    team::Team,
+    Player, PLAYER_SPAWN_POINT,
+};
+
+/// players closer than this to the center of a zone (horizontally) stand in it
+pub const CONTROL_POINT_RADIUS: f32 = 1.2;
+
+/// players with their feet up to this far above the zone stand in it
+pub const CONTROL_POINT_HEIGHT: f32 = 2.0;
+
+/// seconds a single player takes to take over a zone
+pub const CAPTURE_TIME: f32 = 5.0;
+
+/// every further player in a zone speeds the capture up by this much, up to MAX_CAPTURE_RATE
+const CAPTURE_RATE_PER_PLAYER: f32 = 0.5;
+
+const MAX_CAPTURE_RATE: f32 = 2.0;
+
+/// an empty zone loses progress at this fraction of the single player capture rate
+const DECAY_RATE: f32 = 0.5;
+
+/// seconds between progress updates sent to the clients, ownership changes go out at once
+pub const CONTROL_POINT_SYNC_INTERVAL: f32 = 0.2;
+
+/// replicated positions closer than this belong to the same zone
+const CONTROL_POINT_MATCH_DISTANCE: f32 = 0.1;
+
+/// zones of the default map: the hill on the floor in its middle, between the team spawns
+pub fn control_point_positions() -> Vec<Vec3> {
+    vec![PLAYER_SPAWN_POINT + Vec3::new(0.0, -1.0, 0.8)]
+}
+
+/// replicated state of a zone
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ControlPointState {
+    pub owner: Option<Team>,
+    /// team taking the zone over
+    pub capturing: Option<Team>,
+    /// progress of the capturing team (0..1)
+    pub progress: f32,
+    /// both teams stand in the zone
+    pub contested: bool,
+}
+
+impl ControlPointState {
+    /// same owner and the same teams at work, progress aside
+    pub fn same_phase(&self, other: &ControlPointState) -> bool {
+        self.owner == other.owner
+            && self.capturing == other.capturing
+            && self.contested == other.contested
+    }
+
+    /// advance by `dt` seconds with `red` and `blue` players standing in the zone. Returns the
+    /// team that just took the zone over.
+    fn advance(&mut self, red: usize, blue: usize, dt: f32) -> Option<Team> {
+        self.contested = red > 0 && blue > 0;
+        if self.contested {
+            return None;
+        }
+        let (team, players) = match (red, blue) {
+            (0, 0) => {
+                self.regress(DECAY_RATE / CAPTURE_TIME * dt);
+                return None;
+            }
+            (red, 0) => (Team::Red, red),
+            (_, blue) => (Team::Blue, blue),
+        };
+        let rate = (1.0 + CAPTURE_RATE_PER_PLAYER * (players - 1) as f32).min(MAX_CAPTURE_RATE)
+            / CAPTURE_TIME;
+        // owners and teams undoing an enemy capture first push the progress back
+        if self.owner == Some(team) || self.capturing.map_or(false, |other| other != team) {
+            self.regress(rate * dt);
+            return None;
+        }
+        self.capturing = Some(team);
+        self.progress += rate * dt;
+        if self.progress < 1.0 {
+            return None;
+        }
+        self.owner = Some(team);
+        self.capturing = None;
+        self.progress = 0.0;
+        Some(team)
+    }
+
+    fn regress(&mut self, amount: f32) {
+        self.progress = (self.progress - amount).max(0.0);
+        if self.progress == 0.0 {
+            self.capturing = None;
+        }
+    }
+}
+
+#[derive(Debug, Default, Component)]
+pub struct ControlPoint {
+    pub state: ControlPointState,
+}
+
+impl ControlPoint {
+    /// true if the replicated `position` refers to the zone at `translation`
+    pub fn matches(translation: Vec3, position: Vec3) -> bool {
+        translation.distance(position) < CONTROL_POINT_MATCH_DISTANCE
+    }
+}
+
+/// color of a zone owned by `owner`
+pub fn owner_color(owner: Option<Team>) -> Color {
+    owner.map_or(Color::rgb(0.7, 0.7, 0.7), |team| team.color())
+}
+
+/// spawn a zone marker, a ring on the ground at `position` in the owner's color
+pub fn spawn_control_point(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    position: Vec3,
+    control_point: ControlPoint,
+) -> Entity {
+    let color = owner_color(control_point.state.owner);
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Torus {
+                radius: CONTROL_POINT_RADIUS,
+                ring_radius: 0.05,
+                subdivisions_segments: 32,
+                subdivisions_sides: 8,
+            })),
+            material: materials.add(StandardMaterial {
+                base_color: color,
+                emissive: color * 0.3,
+                ..default()
+            }),
+            transform: Transform::from_translation(position),
+            ..default()
+        })
+        .insert(control_point)
+        .id()
+}
+
+/// server: something happened to the zone at `position`
+#[derive(Debug, Clone)]
+pub struct ControlPointEvent {
+    pub position: Vec3,
+    /// team that took the zone over
+    pub team: Team,
+}
+
+/// server: put up the zones in modes that have them
+pub fn setup_control_points_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<GameModeSettings>,
+) {
+    if !settings.mode.has_control_points() {
+        return;
+    }
+    for position in control_point_positions() {
+        spawn_control_point(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            position,
+            ControlPoint::default(),
+        );
+    }
+}
+
+/// server: count the players of both teams in every zone and move its state on. A new match
+/// starts with neutral zones.
+#[allow(clippy::type_complexity)]
+pub fn control_point_system(
+    time: Res<Time>,
+    mut match_events: EventReader<MatchEvent>,
+    mut control_points: Query<(&mut ControlPoint, &Transform)>,
+    players: Query<(&Transform, &Health, &Team), (With<Player>, Without<Spectator>)>,
+    mut control_point_events: EventWriter<ControlPointEvent>,
+) {
+    let restart = match_events
+        .iter()
+        .filter(|event| matches!(event, MatchEvent::Started))
+        .count()
+        > 0;
+    for (mut control_point, transform) in &mut control_points {
+        let center = transform.translation;
+        let (mut red, mut blue) = (0, 0);
+        for (player_transform, health, team) in players.iter() {
+            let offset = player_transform.translation - center;
+            let inside = Vec2::new(offset.x, offset.z).length() < CONTROL_POINT_RADIUS
+                && (-0.5..CONTROL_POINT_HEIGHT).contains(&offset.y);
+            if !inside || health.is_dead() {
+                continue;
+            }
+            match team {
+                Team::Red => red += 1,
+                Team::Blue => blue += 1,
+            }
+        }
+        let mut state = if restart {
+            ControlPointState::default()
+        } else {
+            control_point.state
+        };
+        let taken = state.advance(red, blue, time.delta_seconds());
+        // only touch the zone on changes, changes trigger a broadcast
+        if state != control_point.state {
+            control_point.state = state;
+        }
+        if let Some(team) = taken {
+            control_point_events.send(ControlPointEvent {
+                position: center,
+                team,
+            });
+        }
+    }
+}
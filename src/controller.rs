@@ -8,10 +8,21 @@ use bevy::{math::Vec3Swizzles, prelude::*};
 use bevy_rapier3d::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::input::{Action, ActionInput};
+use crate::level::{
+    self, GravityZone, JumpPad, JumpPadEvent, TeleportEvent, Teleporter, TeleporterExit,
+};
+use crate::mover::MovingPlatform;
+use crate::ragdoll;
+
 pub struct FpsControllerPlugin;
 
 impl Plugin for FpsControllerPlugin {
     fn build(&self, app: &mut App) {
+        app.add_event::<JumpPadEvent>()
+            .add_event::<TeleportEvent>()
+            .add_event::<LandedEvent>()
+            .add_event::<MovementEvent>();
         // TODO: these need to be sequential (exclusive system set)
         app.add_system(fps_controller_input)
             // .add_system(fps_controller_look)
@@ -20,11 +31,118 @@ impl Plugin for FpsControllerPlugin {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MoveMode {
     Noclip,
     Ground,
 }
 
+/// How much control the player has over their velocity while airborne
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AirControlStyle {
+    /// Quake style air acceleration: wish speed capped, no limit on total air speed
+    /// (unbounded strafe gain)
+    Quake,
+    /// CS/Source style: like Quake, but lateral air speed is clamped to max_air_speed
+    Source,
+    /// No air control, momentum is kept from the moment the ground was left
+    None,
+}
+
+impl Default for AirControlStyle {
+    fn default() -> Self {
+        AirControlStyle::Source
+    }
+}
+
+impl std::str::FromStr for AirControlStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "quake" => Ok(AirControlStyle::Quake),
+            "source" => Ok(AirControlStyle::Source),
+            "none" => Ok(AirControlStyle::None),
+            _ => Err(format!("unknown air control style: {}", s)),
+        }
+    }
+}
+
+/// Server controlled movement settings. Replicated to clients on connect, so that client
+/// prediction runs the same simulation as the server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MovementSettings {
+    pub air_control_style: AirControlStyle,
+    /// Gravity outside of gravity zones
+    pub gravity: f32,
+    /// Enables sprint/jump stamina, game modes may turn it off
+    pub stamina: bool,
+    /// Keep jumping while jump is held, otherwise every jump needs a new press
+    pub auto_bhop: bool,
+    /// Cap lateral speed on landing to this fraction of run_speed
+    pub bhop_speed_cap: Option<f32>,
+    /// Skip ground friction on the first tick after landing (allows b-hopping without
+    /// losing speed)
+    pub bhop_friction_grace: bool,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            air_control_style: AirControlStyle::default(),
+            gravity: 23.0,
+            stamina: true,
+            auto_bhop: true,
+            bhop_speed_cap: None,
+            bhop_friction_grace: true,
+        }
+    }
+}
+
+impl MovementSettings {
+    pub fn apply(&self, controller: &mut FpsController) {
+        controller.air_control_style = self.air_control_style;
+        controller.gravity = self.gravity;
+        controller.auto_bhop = self.auto_bhop;
+        controller.bhop_speed_cap = self.bhop_speed_cap;
+        controller.bhop_friction_grace = self.bhop_friction_grace;
+        match (self.stamina, controller.stamina.is_some()) {
+            (true, false) => controller.stamina = Some(Stamina::default()),
+            (false, true) => controller.stamina = None,
+            _ => (),
+        }
+    }
+}
+
+/// Sent when a player touches the ground again after being airborne
+#[derive(Debug, Clone)]
+pub struct LandedEvent {
+    pub entity: Entity,
+    /// Downward speed at the moment of ground contact
+    pub impact_speed: f32,
+    pub position: Vec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MovementEventKind {
+    Footstep,
+    Jump,
+    Land { impact_speed: f32 },
+    SlideStart,
+    SlideStop,
+    Vault,
+}
+
+/// Movement happenings for audio and animation. Emitted by the movement simulation for
+/// simulated players and re-emitted by the client for replicated remote players.
+#[derive(Debug, Clone)]
+pub struct MovementEvent {
+    pub entity: Entity,
+    pub kind: MovementEventKind,
+    pub position: Vec3,
+}
+
 #[derive(Component)]
 pub struct LogicalPlayer(pub u8);
 
@@ -41,51 +159,34 @@ pub struct FpsControllerInput {
     pub sprint: bool,
     pub jump: bool,
     pub crouch: bool,
+    pub block: bool,
+    /// aim down sights
+    pub aim: bool,
+    /// client time when the input was sampled (seconds since startup), for latency statistics
+    pub sent_at: f64,
     pub pitch: f32,
     pub yaw: f32,
     pub movement: Vec3,
 }
 
-#[derive(Component, Default)]
-pub struct FpsControllerInputQueue {
-    pub queue: VecDeque<FpsControllerInput>,
-}
-
-// #[derive(Component)]
-pub struct FpsControllerConfig {
-    pub sensitivity: f32,
-    pub enable_input: bool,
-    pub key_forward: KeyCode,
-    pub key_back: KeyCode,
-    pub key_left: KeyCode,
-    pub key_right: KeyCode,
-    pub key_up: KeyCode,
-    pub key_down: KeyCode,
-    pub key_sprint: KeyCode,
-    pub key_jump: KeyCode,
-    pub key_fly: KeyCode,
-    pub key_crouch: KeyCode,
-}
-
-impl Default for FpsControllerConfig {
-    fn default() -> Self {
+impl FpsControllerInput {
+    /// the same input with only looking around left, for frozen players
+    pub fn look_only(&self) -> Self {
         Self {
-            enable_input: true,
-            key_forward: KeyCode::W,
-            key_back: KeyCode::S,
-            key_left: KeyCode::A,
-            key_right: KeyCode::D,
-            key_up: KeyCode::Q,
-            key_down: KeyCode::E,
-            key_sprint: KeyCode::LShift,
-            key_jump: KeyCode::Space,
-            key_fly: KeyCode::F,
-            key_crouch: KeyCode::LControl,
-            sensitivity: 0.001,
+            serial: self.serial,
+            sent_at: self.sent_at,
+            pitch: self.pitch,
+            yaw: self.yaw,
+            ..default()
         }
     }
 }
 
+#[derive(Component, Default)]
+pub struct FpsControllerInputQueue {
+    pub queue: VecDeque<FpsControllerInput>,
+}
+
 #[derive(Component)]
 pub struct FpsController {
     pub last_applied_serial: u32,
@@ -107,9 +208,179 @@ pub struct FpsController {
     pub fly_friction: f32,
     pub pitch: f32,
     pub yaw: f32,
+    /// Added to the input yaw, used to turn the view when going through teleporters
+    pub yaw_offset: f32,
     pub velocity: Vec3,
     pub ground_tick: u8,
     pub stop_speed: f32,
+    /// Ground with a normal whose y component is below this is too steep to walk on.
+    /// The player slides (surfs) along it instead.
+    pub traction_normal_cutoff: f32,
+    /// Grace period (seconds) after leaving the ground during which a jump is still accepted
+    pub coyote_time: f32,
+    /// How long (seconds) a jump press is remembered before touching the ground
+    pub jump_buffer_time: f32,
+    /// Remaining coyote time, refilled while grounded
+    pub coyote_timer: f32,
+    /// Remaining time of a buffered jump press
+    pub jump_buffer_timer: f32,
+    /// Shield meter, drained while blocking and by blocked damage
+    pub shield: f32,
+    pub max_shield: f32,
+    /// Shield drain per second while blocking
+    pub shield_drain: f32,
+    /// Shield regeneration per second while not blocking
+    pub shield_regen: f32,
+    /// Fraction of max_shield required to raise the shield again after it was depleted
+    pub shield_raise_threshold: f32,
+    /// Movement speed multiplier while blocking
+    pub block_speed_factor: f32,
+    /// Damage multiplier for hits from the facing hemisphere while blocking
+    pub block_damage_factor: f32,
+    pub blocking: bool,
+    /// Movement speed multiplier while aiming down sights
+    pub aim_speed_factor: f32,
+    /// Aiming down sights: slower movement, tighter spread and a zoomed view
+    pub aiming: bool,
+    /// Movement speed multiplier of active status effects (speed boost, slow)
+    pub status_speed_factor: f32,
+    /// Movement input is ignored, looking around still works (freeze time before a match)
+    pub frozen: bool,
+    /// Sprint stamina, None disables stamina and allows unlimited sprinting
+    pub stamina: Option<Stamina>,
+    pub air_control_style: AirControlStyle,
+    pub auto_bhop: bool,
+    pub bhop_speed_cap: Option<f32>,
+    pub bhop_friction_grace: bool,
+    /// Jump input of the previous tick, to detect new presses
+    pub jump_held: bool,
+    /// Remaining time during which jump pads are ignored after a launch
+    pub jump_pad_lockout: f32,
+    /// External velocity change (knockback, explosions) applied on the next move
+    pub impulse: Vec3,
+    /// Distance walked on the ground between two footstep events
+    pub step_distance: f32,
+    /// Ground distance walked since the last footstep
+    pub step_progress: f32,
+    /// Sliding along a surface too steep to stand on
+    pub sliding: bool,
+    /// Top of the capsule segment while standing and while crouched
+    pub stand_height: f32,
+    pub crouch_height: f32,
+    /// Ground speed multiplier while crouched
+    pub crouch_speed_factor: f32,
+    pub crouched: bool,
+    /// Highest ledge (above the feet) a vault can climb onto
+    pub max_vault_height: f32,
+    /// Time a vault takes to move onto the ledge (seconds)
+    pub vault_duration: f32,
+    pub vault: Option<Vault>,
+}
+
+/// Movement onto a ledge: first up along the wall, then forward onto the ledge
+#[derive(Clone, Copy, Debug)]
+pub struct Vault {
+    pub start: Vec3,
+    pub target: Vec3,
+    pub elapsed: f32,
+}
+
+impl Vault {
+    /// Position at `t` in [0, 1]
+    fn position_at(&self, t: f32) -> Vec3 {
+        let rise = (t * 2.0).min(1.0);
+        let across = (t * 2.0 - 1.0).clamp(0.0, 1.0);
+        Vec3::new(
+            self.start.x + (self.target.x - self.start.x) * across,
+            self.start.y + (self.target.y - self.start.y) * rise,
+            self.start.z + (self.target.z - self.start.z) * across,
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Stamina {
+    pub current: f32,
+    pub max: f32,
+    /// Drain per second while sprinting
+    pub sprint_drain: f32,
+    /// Stamina consumed by a jump
+    pub jump_cost: f32,
+    /// Full regeneration per second while not sprinting
+    pub regen: f32,
+    /// Seconds after the last sprint/jump before regeneration starts
+    pub regen_delay: f32,
+    /// Seconds it takes for regeneration to ramp up from zero to the full rate
+    pub regen_ramp: f32,
+    /// Fraction of max stamina needed to recover from exhaustion
+    pub recover_threshold: f32,
+    /// Jump speed multiplier while exhausted
+    pub exhausted_jump_factor: f32,
+    /// Movement speed multiplier while exhausted
+    pub exhausted_speed_factor: f32,
+    pub exhausted: bool,
+    /// Time since stamina was last consumed
+    pub rest_timer: f32,
+}
+
+impl Default for Stamina {
+    fn default() -> Self {
+        Self {
+            current: 100.0,
+            max: 100.0,
+            sprint_drain: 25.0,
+            jump_cost: 10.0,
+            regen: 20.0,
+            regen_delay: 0.5,
+            regen_ramp: 1.0,
+            recover_threshold: 0.3,
+            exhausted_jump_factor: 0.6,
+            exhausted_speed_factor: 0.8,
+            exhausted: false,
+            rest_timer: 0.0,
+        }
+    }
+}
+
+impl Stamina {
+    pub fn fraction(&self) -> f32 {
+        self.current / self.max
+    }
+
+    /// Current regeneration rate: zero during regen_delay, then ramping up quadratically
+    /// to the full rate over regen_ramp
+    pub fn regen_rate(&self) -> f32 {
+        let t = self.rest_timer - self.regen_delay;
+        if t <= 0.0 {
+            return 0.0;
+        }
+        let ramp = if self.regen_ramp > 0.0 {
+            (t / self.regen_ramp).min(1.0)
+        } else {
+            1.0
+        };
+        self.regen * ramp * ramp
+    }
+
+    fn consume(&mut self, amount: f32) {
+        self.current = (self.current - amount).max(0.0);
+        self.rest_timer = 0.0;
+        if self.current <= 0.0 {
+            self.exhausted = true;
+        }
+    }
+
+    fn update(&mut self, sprinting: bool, dt: f32) {
+        if sprinting {
+            self.consume(self.sprint_drain * dt);
+        } else {
+            self.rest_timer += dt;
+            self.current = (self.current + self.regen_rate() * dt).min(self.max);
+            if self.exhausted && self.fraction() >= self.recover_threshold {
+                self.exhausted = false;
+            }
+        }
+    }
 }
 
 impl Default for FpsController {
@@ -133,10 +404,118 @@ impl Default for FpsController {
             fly_friction: 0.5,
             pitch: 0.0,
             yaw: 0.0,
+            yaw_offset: 0.0,
             velocity: Vec3::ZERO,
             ground_tick: 0,
             stop_speed: 1.0,
             jump_speed: 8.5,
+            traction_normal_cutoff: 0.7,
+            coyote_time: 0.1,
+            jump_buffer_time: 0.1,
+            coyote_timer: 0.0,
+            jump_buffer_timer: 0.0,
+            shield: 100.0,
+            max_shield: 100.0,
+            shield_drain: 10.0,
+            shield_regen: 20.0,
+            shield_raise_threshold: 0.2,
+            block_speed_factor: 0.5,
+            block_damage_factor: 0.25,
+            blocking: false,
+            aim_speed_factor: 0.6,
+            aiming: false,
+            status_speed_factor: 1.0,
+            frozen: false,
+            stamina: Some(Stamina::default()),
+            air_control_style: AirControlStyle::default(),
+            auto_bhop: true,
+            bhop_speed_cap: None,
+            bhop_friction_grace: true,
+            jump_held: false,
+            jump_pad_lockout: 0.0,
+            impulse: Vec3::ZERO,
+            step_distance: 2.0,
+            step_progress: 0.0,
+            sliding: false,
+            stand_height: 1.5,
+            crouch_height: 0.9,
+            crouch_speed_factor: 0.5,
+            crouched: false,
+            max_vault_height: 1.3,
+            vault_duration: 0.35,
+            vault: None,
+        }
+    }
+}
+
+impl FpsController {
+    /// Damage multiplier for a hit at `position` coming from `source`: reduced while blocking
+    /// and the source lies in the facing hemisphere.
+    pub fn block_factor(&self, position: Vec3, source: Vec3) -> f32 {
+        if !self.blocking {
+            return 1.0;
+        }
+        let facing = look_quat(0.0, self.yaw) * -Vec3::Z;
+        let to_source = (source - position) * Vec3::new(1.0, 0.0, 1.0);
+        if facing.dot(to_source) > 0.0 {
+            self.block_damage_factor
+        } else {
+            1.0
+        }
+    }
+
+    /// Velocity of the first jump pad the player at `position` overlaps, unless a previous
+    /// launch is still locking out jump pads
+    fn jump_pad_launch(
+        &mut self,
+        jump_pads: &Query<(Entity, &Transform, &JumpPad), Without<FpsController>>,
+        position: Vec3,
+        dt: f32,
+    ) -> Option<(Entity, Vec3)> {
+        if self.jump_pad_lockout > 0.0 {
+            self.jump_pad_lockout = (self.jump_pad_lockout - dt).max(0.0);
+            return None;
+        }
+        let (pad_entity, _, pad) = jump_pads
+            .iter()
+            .find(|(_, transform, pad)| pad.contains(transform.translation, position))?;
+        self.jump_pad_lockout = level::JUMP_PAD_LOCKOUT;
+        Some((pad_entity, pad.launch_velocity))
+    }
+
+    /// Aim inaccuracy caused by movement, grows with speed and while airborne, shrinks while
+    /// crouched. Drives the crosshair size.
+    pub fn movement_spread(&self) -> f32 {
+        let mut spread = 0.01 + self.velocity.xz().length() * 0.002;
+        if self.ground_tick == 0 {
+            spread += 0.03;
+        }
+        if self.crouched {
+            spread *= 0.6;
+        }
+        spread
+    }
+
+    /// Queue an external velocity change, accumulated until the next move
+    pub fn add_impulse(&mut self, impulse: Vec3) {
+        self.impulse += impulse;
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.stamina
+            .as_ref()
+            .map_or(false, |stamina| stamina.exhausted)
+    }
+
+    fn update_block(&mut self, input: &FpsControllerInput, dt: f32) {
+        // Once depleted the shield has to recharge a bit before it can be raised again
+        let can_raise =
+            self.blocking || self.shield >= self.max_shield * self.shield_raise_threshold;
+        self.blocking = input.block && self.shield > 0.0 && can_raise;
+        if self.blocking {
+            self.shield = (self.shield - self.shield_drain * dt).max(0.0);
+        } else {
+            self.shield = (self.shield + self.shield_regen * dt).min(self.max_shield);
         }
     }
 }
@@ -148,45 +527,96 @@ impl Default for FpsController {
 // ███████╗╚██████╔╝╚██████╔╝██║╚██████╗
 // ╚══════╝ ╚═════╝  ╚═════╝ ╚═╝ ╚═════╝
 
-const ANGLE_EPSILON: f32 = 0.001953125;
+pub const ANGLE_EPSILON: f32 = 0.001953125;
+
+/// eye height above the feet of a standing player, where fps_controller_render puts the camera
+pub const EYE_HEIGHT: f32 = 1.875;
+
+/// Longest time step the movement simulation integrates at once. Longer frames (hitches) are
+/// clamped, matching the physics step limit on the server.
+pub const MAX_MOVE_DT: f32 = 8.0 / 60.0;
+
+/// mouse motion collected between input samples
+#[derive(Default)]
+pub struct MouseLook {
+    /// raw counts after the sensitivity curve, not yet sampled
+    pending: Vec2,
+    /// filtered look rate (counts per second), used when smoothing is enabled
+    smoothed_rate: Vec2,
+    /// view kick (pitch, yaw in radians) applied with the next input, e.g. weapon recoil
+    kick: Vec2,
+}
+
+impl MouseLook {
+    pub fn add_kick(&mut self, kick: Vec2) {
+        self.kick += kick;
+    }
+
+    /// take the accumulated motion, optionally smoothed over time
+    fn sample(&mut self, smoothing: f32, dt: f32) -> Vec2 {
+        let counts = std::mem::take(&mut self.pending);
+        if smoothing <= 0.0 || dt <= 0.0 {
+            self.smoothed_rate = Vec2::ZERO;
+            return counts;
+        }
+        let keep = smoothing.min(0.99).powf(dt * 60.0);
+        self.smoothed_rate = (counts / dt).lerp(self.smoothed_rate, keep);
+        self.smoothed_rate * dt
+    }
+}
 
+#[allow(clippy::too_many_arguments)]
 pub fn fps_controller_input(
-    key_input: Res<Input<KeyCode>>,
-    controller: Res<FpsControllerConfig>,
+    time: Res<Time>,
+    actions: ActionInput,
     mut serial: ResMut<FpsControllerSerial>,
     mut windows: ResMut<Windows>,
     mut mouse_events: EventReader<MouseMotion>,
+    mut mouse_look: ResMut<MouseLook>,
     mut query: Query<&mut FpsControllerInputQueue>,
     mut event_writer: EventWriter<FpsControllerInput>,
 ) {
-    if !controller.enable_input {
+    // the curve is applied per raw event, independent of how many events fall into a frame
+    let mouse = &actions.map.mouse;
+    for mouse_event in mouse_events.iter() {
+        mouse_look.pending += mouse.curve(mouse_event.delta);
+    }
+    let window = windows.get_primary_mut().unwrap();
+    // no mouse look while the cursor is free for menus
+    let look = window.is_focused() && window.cursor_locked();
+    if !actions.map.enable_input || !look {
+        *mouse_look = MouseLook::default();
+    }
+    if !actions.map.enable_input {
         return;
     }
 
     let mut input = FpsControllerInput::default();
-    let window = windows.get_primary_mut().unwrap();
-    if window.is_focused() {
-        let mut mouse_delta = Vec2::ZERO;
-        for mouse_event in mouse_events.iter() {
-            mouse_delta += mouse_event.delta;
-        }
-        mouse_delta *= controller.sensitivity;
+    if look {
+        let counts = mouse_look.sample(mouse.smoothing, time.delta_seconds());
+        let mouse_delta = mouse.scale(counts, actions.pressed(Action::Aim));
+
+        let kick = std::mem::take(&mut mouse_look.kick);
 
-        input.pitch = (input.pitch - mouse_delta.y)
+        input.pitch = (input.pitch - mouse_delta.y + kick.x)
             .clamp(-FRAC_PI_2 + ANGLE_EPSILON, FRAC_PI_2 - ANGLE_EPSILON);
-        input.yaw -= mouse_delta.x;
+        input.yaw -= mouse_delta.x - kick.y;
     }
 
+    let stick = actions.left_stick();
     input.movement = Vec3::new(
-        get_axis(&key_input, controller.key_right, controller.key_left),
-        get_axis(&key_input, controller.key_up, controller.key_down),
-        get_axis(&key_input, controller.key_forward, controller.key_back),
+        (actions.axis(Action::MoveRight, Action::MoveLeft) + stick.x).clamp(-1.0, 1.0),
+        actions.axis(Action::MoveUp, Action::MoveDown),
+        (actions.axis(Action::MoveForward, Action::MoveBack) + stick.y).clamp(-1.0, 1.0),
     );
-    input.sprint = key_input.pressed(controller.key_sprint);
-    input.jump = key_input.pressed(controller.key_jump);
-    input.fly = key_input.just_pressed(controller.key_fly);
-    input.crouch = key_input.pressed(controller.key_crouch);
+    input.sprint = actions.pressed(Action::Sprint);
+    input.jump = actions.pressed(Action::Jump);
+    input.fly = actions.just_pressed(Action::Fly);
+    input.crouch = actions.pressed(Action::Crouch);
+    input.block = actions.pressed(Action::Block);
+    input.aim = actions.pressed(Action::Aim);
     input.serial = serial.0;
+    input.sent_at = time.seconds_since_startup();
     serial.0 += 1;
 
     for mut input_queue in query.iter_mut() {
@@ -202,6 +632,7 @@ pub fn fps_controller_input(
 //     }
 // }
 
+#[allow(clippy::too_many_arguments)]
 pub fn fps_controller_move(
     time: Res<Time>,
     physics_context: Res<RapierContext>,
@@ -209,14 +640,24 @@ pub fn fps_controller_move(
         Entity,
         &FpsControllerInputQueue,
         &mut FpsController,
-        &Collider,
+        &mut Collider,
         &mut Transform,
         &mut Velocity,
     )>,
+    gravity_zones: Query<(&Transform, &GravityZone), Without<FpsController>>,
+    jump_pads: Query<(Entity, &Transform, &JumpPad), Without<FpsController>>,
+    mut jump_pad_events: EventWriter<JumpPadEvent>,
+    teleporters: Query<(Entity, &Transform, &Teleporter), Without<FpsController>>,
+    teleporter_exits: Query<&Transform, (With<TeleporterExit>, Without<FpsController>)>,
+    mut teleport_events: EventWriter<TeleportEvent>,
+    mut landed_events: EventWriter<LandedEvent>,
+    mut movement_events: EventWriter<MovementEvent>,
+    platforms: Query<&MovingPlatform, Without<FpsController>>,
 ) {
-    let dt = time.delta_seconds();
+    let dt = time.delta_seconds().min(MAX_MOVE_DT);
 
-    for (entity, input_queue, mut controller, collider, transform, mut velocity) in query.iter_mut()
+    for (entity, input_queue, mut controller, mut collider, mut transform, mut velocity) in
+        query.iter_mut()
     {
         // info!("queue: {}", input_queue.queue.len());
         for input in &input_queue.queue {
@@ -224,6 +665,13 @@ pub fn fps_controller_move(
                 // info!("skip: {}", input.serial);
                 continue;
             }
+            let frozen_input;
+            let input = if controller.frozen {
+                frozen_input = input.look_only();
+                &frozen_input
+            } else {
+                input
+            };
 
             if input.fly {
                 controller.move_mode = match controller.move_mode {
@@ -232,12 +680,109 @@ pub fn fps_controller_move(
                 }
             }
 
-            let orientation = look_quat(input.pitch, input.yaw);
+            // Teleporters run as part of the movement simulation, so the client predicts them
+            // for the same input as the server
+            if let Some(destination) =
+                level::teleport_destination(&teleporters, &teleporter_exits, transform.translation)
+            {
+                let from = transform.translation;
+                transform.translation = destination.translation;
+                controller.yaw_offset += destination.yaw_delta;
+                // Noclip velocity is relative to the view and turns with it
+                if controller.move_mode == MoveMode::Ground {
+                    controller.velocity = destination.rotate(controller.velocity);
+                }
+                velocity.linvel = destination.rotate(velocity.linvel);
+                teleport_events.send(TeleportEvent {
+                    entity,
+                    teleporter: destination.teleporter,
+                    from,
+                    to: destination.translation,
+                });
+            }
+
+            controller.pitch = input.pitch;
+            controller.yaw = input.yaw + controller.yaw_offset;
+            controller.update_block(input, dt);
+            // the shield covers the sights
+            controller.aiming = input.aim && !controller.blocking;
+
+            // A vault takes over movement until the player stands on the ledge
+            if let Some(mut vault) = controller.vault {
+                vault.elapsed += dt;
+                let t = (vault.elapsed / controller.vault_duration).min(1.0);
+                if dt > 0.0 {
+                    velocity.linvel = (vault.position_at(t) - transform.translation) / dt;
+                }
+                if t >= 1.0 {
+                    controller.vault = None;
+                    controller.velocity = Vec3::ZERO;
+                } else {
+                    controller.vault = Some(vault);
+                }
+                controller.last_applied_serial = input.serial;
+                continue;
+            }
+
+            let orientation = look_quat(controller.pitch, controller.yaw);
             let right = orientation * Vec3::X;
             let forward = orientation * -Vec3::Z;
+
+            // Gravity zones override the global gravity, zero-g switches to flying
+            let gravity =
+                level::gravity_at(&gravity_zones, transform.translation, controller.gravity);
+            let move_mode = if gravity.abs() < 1e-3 {
+                MoveMode::Noclip
+            } else {
+                controller.move_mode
+            };
+
+            // Crouching shortens the capsule. In the air the feet are pulled up instead of
+            // lowering the head, so crouch jumps reach higher ledges. Standing up needs room.
+            let wants_crouch = input.crouch && move_mode == MoveMode::Ground;
+            if wants_crouch != controller.crouched {
+                if let Some(capsule) = collider.as_capsule() {
+                    let bottom = capsule.segment().a();
+                    let height = if wants_crouch {
+                        controller.crouch_height
+                    } else {
+                        controller.stand_height
+                    };
+                    let resized = Collider::capsule(
+                        bottom,
+                        Vec3::new(bottom.x, height, bottom.z),
+                        capsule.radius(),
+                    );
+                    let shift = if controller.ground_tick == 0 {
+                        let amount = controller.stand_height - controller.crouch_height;
+                        Vec3::Y * if wants_crouch { amount } else { -amount }
+                    } else {
+                        Vec3::ZERO
+                    };
+                    let blocked = !wants_crouch
+                        && physics_context
+                            .intersection_with_shape(
+                                // lifted a bit, touching the ground does not count
+                                transform.translation + shift + Vec3::Y * 0.05,
+                                transform.rotation,
+                                &resized,
+                                ragdoll::ignore_ragdolls(
+                                    QueryFilter::default().exclude_rigid_body(entity),
+                                ),
+                            )
+                            .is_some();
+                    if !blocked {
+                        *collider = resized;
+                        transform.translation += shift;
+                        controller.crouched = wants_crouch;
+                    }
+                }
+            }
             let position = transform.translation;
+            // Flying players are not affected by impulses
+            let impulse = std::mem::take(&mut controller.impulse);
 
-            match controller.move_mode {
+            match move_mode {
                 MoveMode::Noclip => {
                     if input.movement == Vec3::ZERO {
                         let friction = controller.fly_friction.clamp(0.0, 1.0);
@@ -261,13 +806,14 @@ pub fn fps_controller_move(
                 MoveMode::Ground => {
                     if let Some(capsule) = collider.as_capsule() {
                         let capsule = capsule.raw;
-                        let mut start_velocity = controller.velocity;
+                        let mut start_velocity = controller.velocity + impulse;
                         let mut end_velocity = start_velocity;
                         let lateral_speed = start_velocity.xz().length();
 
                         // Capsule cast downwards to find ground
                         // Better than single raycast as it handles when you are near the edge of a surface
                         let mut ground_hit = None;
+                        let mut ground_entity = None;
                         let cast_capsule = Collider::capsule(
                             capsule.segment.a.into(),
                             capsule.segment.b.into(),
@@ -276,9 +822,11 @@ pub fn fps_controller_move(
                         let cast_velocity = Vec3::Y * -1.0;
                         let max_distance = 0.125;
                         // Avoid self collisions
-                        let groups = QueryFilter::default().exclude_rigid_body(entity);
+                        let groups = ragdoll::ignore_ragdolls(
+                            QueryFilter::default().exclude_rigid_body(entity),
+                        );
 
-                        if let Some((_handle, hit)) = physics_context.cast_shape(
+                        if let Some((handle, hit)) = physics_context.cast_shape(
                             position,
                             orientation,
                             cast_velocity,
@@ -287,6 +835,37 @@ pub fn fps_controller_move(
                             groups,
                         ) {
                             ground_hit = Some(hit);
+                            ground_entity = Some(handle);
+                        }
+
+                        // Steep slopes are not walkable: no friction and no jumping, the player
+                        // is airborne and slides along the surface
+                        let mut surf_normal = None;
+                        if let Some(hit) = &ground_hit {
+                            if hit.normal1.dot(Vec3::Y) < controller.traction_normal_cutoff {
+                                surf_normal = Some(hit.normal1);
+                                ground_hit = None;
+                            }
+                        }
+
+                        let sliding = surf_normal.is_some();
+                        if sliding != controller.sliding {
+                            controller.sliding = sliding;
+                            movement_events.send(MovementEvent {
+                                entity,
+                                kind: if sliding {
+                                    MovementEventKind::SlideStart
+                                } else {
+                                    MovementEventKind::SlideStop
+                                },
+                                position,
+                            });
+                        }
+
+                        // Upward impulses lift the player off the ground, otherwise ground
+                        // friction would eat them (rocket jumps)
+                        if impulse.y > 0.0 {
+                            ground_hit = None;
                         }
 
                         let mut wish_direction =
@@ -298,17 +877,63 @@ pub fn fps_controller_move(
                             wish_direction /= wish_speed; // Effectively normalize, avoid length computation twice
                         }
 
-                        let max_speed = if input.sprint {
+                        // Exhaustion drops sprint to walk speed until stamina has recovered
+                        let sprinting = input.sprint
+                            && input.movement != Vec3::ZERO
+                            && !controller.is_exhausted();
+                        if let Some(stamina) = controller.stamina.as_mut() {
+                            stamina.update(sprinting, dt);
+                        }
+                        let mut max_speed = if sprinting {
                             controller.run_speed
                         } else {
                             controller.walk_speed
                         };
+                        if let Some(stamina) = &controller.stamina {
+                            if stamina.exhausted {
+                                max_speed *= stamina.exhausted_speed_factor;
+                            }
+                        }
+
+                        max_speed *= controller.status_speed_factor;
 
                         wish_speed = f32::min(wish_speed, max_speed);
+                        if controller.blocking {
+                            wish_speed *= controller.block_speed_factor;
+                        }
+                        if controller.aiming {
+                            wish_speed *= controller.aim_speed_factor;
+                        }
+                        if controller.crouched && ground_hit.is_some() {
+                            wish_speed *= controller.crouch_speed_factor;
+                        }
 
                         if let Some(_ground_hit) = ground_hit {
+                            if controller.ground_tick == 0 {
+                                let impact_speed = (-end_velocity.y).max(0.0);
+                                landed_events.send(LandedEvent {
+                                    entity,
+                                    impact_speed,
+                                    position,
+                                });
+                                movement_events.send(MovementEvent {
+                                    entity,
+                                    kind: MovementEventKind::Land { impact_speed },
+                                    position,
+                                });
+                                controller.step_progress = 0.0;
+                                if let Some(cap) = controller.bhop_speed_cap {
+                                    let max_landing_speed = cap * controller.run_speed;
+                                    let landing_speed = end_velocity.xz().length();
+                                    if landing_speed > max_landing_speed {
+                                        let ratio = max_landing_speed / landing_speed;
+                                        end_velocity.x *= ratio;
+                                        end_velocity.z *= ratio;
+                                    }
+                                }
+                            }
                             // Only apply friction after at least one tick, allows b-hopping without losing speed
-                            if controller.ground_tick >= 1 {
+                            if controller.ground_tick >= 1 || !controller.bhop_friction_grace {
                                 if lateral_speed > controller.friction_cutoff {
                                     friction(
                                         lateral_speed,
@@ -330,32 +955,94 @@ pub fn fps_controller_move(
                                 dt,
                                 &mut end_velocity,
                             );
-                            if input.jump {
-                                // Simulate one update ahead, since this is an instant velocity change
-                                start_velocity.y = controller.jump_speed;
-                                end_velocity.y = start_velocity.y - controller.gravity * dt;
+                            controller.step_progress += end_velocity.xz().length() * dt;
+                            if controller.step_progress >= controller.step_distance {
+                                controller.step_progress -= controller.step_distance;
+                                movement_events.send(MovementEvent {
+                                    entity,
+                                    kind: MovementEventKind::Footstep,
+                                    position,
+                                });
                             }
                             // Increment ground tick but cap at max value
                             controller.ground_tick = controller.ground_tick.saturating_add(1);
+                            controller.coyote_timer = controller.coyote_time;
                         } else {
                             controller.ground_tick = 0;
-                            wish_speed = f32::min(wish_speed, controller.air_speed_cap);
-                            accelerate(
-                                wish_direction,
-                                wish_speed,
-                                controller.air_acceleration,
-                                dt,
-                                &mut end_velocity,
-                            );
-                            end_velocity.y -= controller.gravity * dt;
-                            let air_speed = end_velocity.xz().length();
-                            if air_speed > controller.max_air_speed {
-                                let ratio = controller.max_air_speed / air_speed;
-                                end_velocity.x *= ratio;
-                                end_velocity.z *= ratio;
+                            controller.coyote_timer = (controller.coyote_timer - dt).max(0.0);
+                            match controller.air_control_style {
+                                AirControlStyle::Quake => {
+                                    air_accelerate(
+                                        wish_direction,
+                                        wish_speed,
+                                        controller.air_speed_cap,
+                                        controller.air_acceleration,
+                                        dt,
+                                        &mut end_velocity,
+                                    );
+                                }
+                                AirControlStyle::Source => {
+                                    wish_speed = f32::min(wish_speed, controller.air_speed_cap);
+                                    accelerate(
+                                        wish_direction,
+                                        wish_speed,
+                                        controller.air_acceleration,
+                                        dt,
+                                        &mut end_velocity,
+                                    );
+                                    let air_speed = end_velocity.xz().length();
+                                    if air_speed > controller.max_air_speed {
+                                        let ratio = controller.max_air_speed / air_speed;
+                                        end_velocity.x *= ratio;
+                                        end_velocity.z *= ratio;
+                                    }
+                                }
+                                AirControlStyle::None => (),
+                            }
+                            end_velocity.y -= gravity * dt;
+                            if let Some(normal) = surf_normal {
+                                // Remove the velocity component going into the slope. This projects
+                                // gravity (and air strafing) onto the plane of the surf ramp.
+                                clip_velocity(normal, &mut end_velocity);
                             }
                         }
 
+                        // Jump when grounded or shortly after leaving the ground (coyote time).
+                        // A press shortly before landing is buffered and triggers on touchdown.
+                        // Without auto-bhop only a fresh press counts, holding jump does nothing
+                        let jump_pressed =
+                            input.jump && (controller.auto_bhop || !controller.jump_held);
+                        controller.jump_held = input.jump;
+                        if jump_pressed {
+                            controller.jump_buffer_timer = controller.jump_buffer_time;
+                        }
+                        let wants_jump = jump_pressed || controller.jump_buffer_timer > 0.0;
+                        let can_jump = ground_hit.is_some() || controller.coyote_timer > 0.0;
+                        if wants_jump && can_jump {
+                            // Simulate one update ahead, since this is an instant velocity change
+                            start_velocity.y = match &controller.stamina {
+                                Some(stamina) if stamina.exhausted => {
+                                    controller.jump_speed * stamina.exhausted_jump_factor
+                                }
+                                _ => controller.jump_speed,
+                            };
+                            if let Some(stamina) = controller.stamina.as_mut() {
+                                let jump_cost = stamina.jump_cost;
+                                stamina.consume(jump_cost);
+                            }
+                            end_velocity.y = start_velocity.y - gravity * dt;
+                            controller.coyote_timer = 0.0;
+                            controller.jump_buffer_timer = 0.0;
+                            movement_events.send(MovementEvent {
+                                entity,
+                                kind: MovementEventKind::Jump,
+                                position,
+                            });
+                        } else {
+                            controller.jump_buffer_timer =
+                                (controller.jump_buffer_timer - dt).max(0.0);
+                        }
+
                         // At this point our collider may be intersecting with the ground
                         // Fix up our collider by offsetting it to be flush with the ground
                         // if end_vel.y < -1e6 {
@@ -365,18 +1052,114 @@ pub fn fps_controller_move(
                         //     }
                         // }
 
+                        // Airborne against a ledge with forward input: climb onto it
+                        if ground_hit.is_none() && surf_normal.is_none() && input.movement.z > 0.0 {
+                            if let Some(target) = vault_target(
+                                &physics_context,
+                                entity,
+                                &collider,
+                                position,
+                                look_quat(0.0, controller.yaw) * -Vec3::Z,
+                                capsule.radius,
+                                controller.max_vault_height,
+                                controller.traction_normal_cutoff,
+                            ) {
+                                controller.vault = Some(Vault {
+                                    start: position,
+                                    target,
+                                    elapsed: 0.0,
+                                });
+                                start_velocity = Vec3::ZERO;
+                                end_velocity = Vec3::ZERO;
+                                movement_events.send(MovementEvent {
+                                    entity,
+                                    kind: MovementEventKind::Vault,
+                                    position,
+                                });
+                            }
+                        }
+
+                        // Jump pads override everything else
+                        if let Some((pad, launch_velocity)) =
+                            controller.jump_pad_launch(&jump_pads, position, dt)
+                        {
+                            start_velocity = launch_velocity;
+                            end_velocity = launch_velocity;
+                            controller.coyote_timer = 0.0;
+                            jump_pad_events.send(JumpPadEvent {
+                                player: entity,
+                                pad,
+                                position,
+                            });
+                        }
+
+                        // Standing on a mover carries the player along. The platform velocity is
+                        // not kept, so it doesn't get accelerated or rubbed off by friction.
+                        let carried = ground_hit
+                            .and(ground_entity)
+                            .and_then(|ground| platforms.get(ground).ok())
+                            .map_or(Vec3::ZERO, |platform| platform.velocity);
+
                         controller.velocity = end_velocity;
-                        velocity.linvel = (start_velocity + end_velocity) * 0.5;
+                        velocity.linvel = (start_velocity + end_velocity) * 0.5 + carried;
                     }
                 }
             }
-            debug!("applied: {} {:?}", input.serial, transform.translation);
+            trace!(serial = input.serial, translation = ?transform.translation, "input applied");
             controller.last_applied_serial = input.serial;
         }
     }
 }
 
-fn look_quat(pitch: f32, yaw: f32) -> Quat {
+/// Velocity change caused by an explosion at `center` on a player at `position`: `strength`
+/// at the center, falling off linearly to zero at `radius`. None if out of range.
+pub fn explosion_impulse(center: Vec3, radius: f32, strength: f32, position: Vec3) -> Option<Vec3> {
+    let offset = position - center;
+    let distance = offset.length();
+    if distance >= radius {
+        return None;
+    }
+    let falloff = 1.0 - distance / radius;
+    // Bias upwards, so that explosions at the feet launch the player
+    let direction = (offset.normalize_or_zero() + Vec3::Y * 0.5).normalize();
+    Some(direction * strength * falloff)
+}
+
+/// Standing position on top of a ledge in front of the player, if there is a wall at waist
+/// height, a walkable top within `max_height` above the feet and room for the collider
+#[allow(clippy::too_many_arguments)]
+fn vault_target(
+    physics_context: &RapierContext,
+    entity: Entity,
+    collider: &Collider,
+    position: Vec3,
+    forward: Vec3,
+    radius: f32,
+    max_height: f32,
+    traction_normal_cutoff: f32,
+) -> Option<Vec3> {
+    let filter = ragdoll::ignore_ragdolls(QueryFilter::default().exclude_rigid_body(entity));
+    let waist = position + Vec3::Y * 0.6;
+    let (_, wall) =
+        physics_context.cast_ray_and_get_normal(waist, forward, radius + 0.3, true, filter)?;
+    if wall.normal.y.abs() > 0.3 {
+        return None;
+    }
+    let above_ledge =
+        Vec3::new(wall.point.x, position.y + max_height, wall.point.z) + forward * radius;
+    let (_, top) =
+        physics_context.cast_ray_and_get_normal(above_ledge, -Vec3::Y, max_height, true, filter)?;
+    if top.toi <= 0.0 || top.normal.y < traction_normal_cutoff {
+        return None;
+    }
+    let target = top.point + Vec3::Y * 0.02;
+    physics_context
+        .intersection_with_shape(target, Quat::IDENTITY, collider, filter)
+        .is_none()
+        .then_some(target)
+}
+
+pub fn look_quat(pitch: f32, yaw: f32) -> Quat {
     Quat::from_euler(EulerRot::ZYX, 0.0, yaw, pitch)
 }
 
@@ -401,16 +1184,35 @@ fn accelerate(wish_dir: Vec3, wish_speed: f32, accel: f32, dt: f32, velocity: &m
     velocity.z += wish_direction.z;
 }
 
-fn get_pressed(key_input: &Res<Input<KeyCode>>, key: KeyCode) -> f32 {
-    if key_input.pressed(key) {
-        1.0
-    } else {
-        0.0
+/// Remove the part of the velocity pointing into a surface with the given normal
+fn clip_velocity(normal: Vec3, velocity: &mut Vec3) {
+    let into_surface = velocity.dot(normal);
+    if into_surface < 0.0 {
+        *velocity -= normal * into_surface;
     }
 }
 
-fn get_axis(key_input: &Res<Input<KeyCode>>, key_pos: KeyCode, key_neg: KeyCode) -> f32 {
-    get_pressed(key_input, key_pos) - get_pressed(key_input, key_neg)
+/// Quake 1 style air acceleration: the added speed is limited by the capped wish speed,
+/// but the acceleration rate uses the full wish speed. Strafing gains are unbounded.
+fn air_accelerate(
+    wish_dir: Vec3,
+    wish_speed: f32,
+    speed_cap: f32,
+    accel: f32,
+    dt: f32,
+    velocity: &mut Vec3,
+) {
+    let capped_speed = f32::min(wish_speed, speed_cap);
+    let velocity_projection = Vec3::dot(*velocity, wish_dir);
+    let add_speed = capped_speed - velocity_projection;
+    if add_speed <= 0.0 {
+        return;
+    }
+
+    let accel_speed = f32::min(accel * wish_speed * dt, add_speed);
+    let wish_direction = wish_dir * accel_speed;
+    velocity.x += wish_direction.x;
+    velocity.z += wish_direction.z;
 }
 
 // ██████╗ ███████╗███╗   ██╗██████╗ ███████╗██████╗
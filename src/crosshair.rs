@@ -0,0 +1,175 @@
+//! crosshair style. The lines open up with the spread of the hitscan weapon (movement and
+//! bloom), confirmed hits flash a hitmarker over it.
+//!
+//! settings in crosshair.toml. Colors are hex rgb or rgba:
+//!     crosshair = true
+//!     length = 8
+//!     gap = 3
+//!     thickness = 2
+//!     color = "ffffff"
+//!     dot = false
+//!     dynamic = 1
+//!     hitmarkers = true
+//!     kill_color = "ff2828"
+
+use std::fmt;
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+pub const CROSSHAIR_CONFIG_FILE: &str = "crosshair.toml";
+
+/// gap in pixels per radian of spread, at `dynamic` = 1
+const SPREAD_GAP: f32 = 400.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrosshairSettings {
+    #[serde(rename = "crosshair")]
+    pub enabled: bool,
+    /// of each line (px)
+    pub length: f32,
+    /// from the center to the lines without spread (px)
+    pub gap: f32,
+    pub thickness: f32,
+    #[serde(
+        serialize_with = "serialize_color",
+        deserialize_with = "deserialize_color"
+    )]
+    pub color: egui::Color32,
+    /// draw a dot in the center
+    pub dot: bool,
+    /// scales how far the lines open with the spread, 0 keeps them still
+    pub dynamic: f32,
+    pub hitmarkers: bool,
+    /// hitmarker color of kills
+    #[serde(
+        serialize_with = "serialize_color",
+        deserialize_with = "deserialize_color"
+    )]
+    pub kill_color: egui::Color32,
+}
+
+impl Default for CrosshairSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            length: 8.0,
+            gap: 3.0,
+            thickness: 2.0,
+            color: egui::Color32::WHITE,
+            dot: false,
+            dynamic: 1.0,
+            hitmarkers: true,
+            kill_color: egui::Color32::from_rgb(255, 40, 40),
+        }
+    }
+}
+
+fn parse_color(value: &str) -> Result<egui::Color32, String> {
+    let value = value.trim().trim_start_matches('#');
+    let channel = |i: usize| {
+        value
+            .get(i * 2..i * 2 + 2)
+            .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+    };
+    let color = match value.len() {
+        6 => channel(0)
+            .zip(channel(1))
+            .zip(channel(2))
+            .map(|((r, g), b)| egui::Color32::from_rgb(r, g, b)),
+        8 => channel(0)
+            .zip(channel(1))
+            .zip(channel(2))
+            .zip(channel(3))
+            .map(|(((r, g), b), a)| egui::Color32::from_rgba_unmultiplied(r, g, b, a)),
+        _ => None,
+    };
+    color.ok_or_else(|| format!("invalid color: {}", value))
+}
+
+struct HexColor(egui::Color32);
+
+impl fmt::Display for HexColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b, a] = self.0.to_srgba_unmultiplied();
+        if a == 255 {
+            write!(f, "{:02x}{:02x}{:02x}", r, g, b)
+        } else {
+            write!(f, "{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+        }
+    }
+}
+
+fn serialize_color<S: Serializer>(color: &egui::Color32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&HexColor(*color))
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<egui::Color32, D::Error> {
+    parse_color(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+impl CrosshairSettings {
+    /// draw the crosshair at `center`, opened by the weapon `spread` (radians)
+    pub fn paint(&self, painter: &egui::Painter, center: egui::Pos2, spread: f32) {
+        if !self.enabled {
+            return;
+        }
+        let gap = self.gap + spread * SPREAD_GAP * self.dynamic;
+        let stroke = egui::Stroke::new(self.thickness, self.color);
+        for direction in [
+            egui::vec2(1.0, 0.0),
+            egui::vec2(-1.0, 0.0),
+            egui::vec2(0.0, 1.0),
+            egui::vec2(0.0, -1.0),
+        ] {
+            painter.line_segment(
+                [
+                    center + direction * gap,
+                    center + direction * (gap + self.length),
+                ],
+                stroke,
+            );
+        }
+        if self.dot {
+            painter.circle_filled(center, self.thickness, self.color);
+        }
+    }
+
+    /// flash a hitmarker X, `fade` goes from 1 to 0 over its time. Kills are colored,
+    /// headshots larger.
+    pub fn paint_hitmarker(
+        &self,
+        painter: &egui::Painter,
+        center: egui::Pos2,
+        fade: f32,
+        headshot: bool,
+        kill: bool,
+    ) {
+        if !self.hitmarkers {
+            return;
+        }
+        let [r, g, b, _] = if kill {
+            self.kill_color.to_srgba_unmultiplied()
+        } else {
+            [255, 255, 255, 255]
+        };
+        let color = egui::Color32::from_rgba_unmultiplied(r, g, b, (255.0 * fade) as u8);
+        let size = if headshot { 10.0 } else { 6.0 };
+        let stroke = egui::Stroke::new(self.thickness, color);
+        for direction in [
+            egui::vec2(1.0, 1.0),
+            egui::vec2(-1.0, 1.0),
+            egui::vec2(1.0, -1.0),
+            egui::vec2(-1.0, -1.0),
+        ] {
+            painter.line_segment(
+                [center + direction * 4.0, center + direction * (4.0 + size)],
+                stroke,
+            );
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! particle effects for combat feedback: muzzle flashes, impact sparks, explosion fireballs
+//! and blood puffs. The client sends an EffectEvent where a replicated (or predicted) gameplay
+//! event happens, the effect is emitted as a burst of pooled particles: small unlit spheres
+//! flying ballistically and shrinking away. Purely cosmetic and local.
+
+use std::collections::HashMap;
+
+use bevy::{pbr::NotShadowCaster, prelude::*};
+
+use crate::pool::{EntityPools, PoolKind};
+
+/// particles alive at once, bursts beyond are cut short
+pub const MAX_PARTICLES: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EffectKind {
+    MuzzleFlash,
+    ImpactSparks,
+    Fireball,
+    BloodPuff,
+}
+
+impl EffectKind {
+    const ALL: [EffectKind; 4] = [
+        EffectKind::MuzzleFlash,
+        EffectKind::ImpactSparks,
+        EffectKind::Fireball,
+        EffectKind::BloodPuff,
+    ];
+
+    fn emitter(self) -> Emitter {
+        match self {
+            EffectKind::MuzzleFlash => Emitter {
+                count: 6,
+                speed: (2.0, 6.0),
+                spread: 0.3,
+                lifetime: (0.04, 0.08),
+                size: (0.04, 0.08),
+                end_size: 1.5,
+                gravity: 0.0,
+                drag: 10.0,
+                color: Color::rgb(1.0, 0.85, 0.4),
+            },
+            EffectKind::ImpactSparks => Emitter {
+                count: 8,
+                speed: (2.0, 5.0),
+                spread: 0.8,
+                lifetime: (0.15, 0.35),
+                size: (0.015, 0.03),
+                end_size: 0.0,
+                gravity: 9.81,
+                drag: 1.0,
+                color: Color::rgb(1.0, 0.7, 0.3),
+            },
+            EffectKind::Fireball => Emitter {
+                count: 24,
+                speed: (1.0, 4.0),
+                spread: 1.0,
+                lifetime: (0.3, 0.6),
+                size: (0.15, 0.3),
+                end_size: 0.2,
+                gravity: -1.0,
+                drag: 3.0,
+                color: Color::rgb(1.0, 0.45, 0.1),
+            },
+            EffectKind::BloodPuff => Emitter {
+                count: 10,
+                speed: (0.5, 2.5),
+                spread: 0.6,
+                lifetime: (0.2, 0.45),
+                size: (0.03, 0.07),
+                end_size: 0.3,
+                gravity: 6.0,
+                drag: 2.0,
+                color: Color::rgb(0.6, 0.0, 0.0),
+            },
+        }
+    }
+}
+
+/// how an effect emits its particles. Ranges are (min, max), picked per particle.
+struct Emitter {
+    count: usize,
+    /// m/s
+    speed: (f32, f32),
+    /// 0 emits along the direction only, 1 in all directions
+    spread: f32,
+    /// seconds
+    lifetime: (f32, f32),
+    /// radius at the start (m)
+    size: (f32, f32),
+    /// radius at the end of the lifetime, relative to the start
+    end_size: f32,
+    /// downward acceleration, negative rises
+    gravity: f32,
+    /// velocity lost per second, relative
+    drag: f32,
+    color: Color,
+}
+
+fn random_range((min, max): (f32, f32)) -> f32 {
+    min + (max - min) * rand::random::<f32>()
+}
+
+fn random_direction() -> Vec3 {
+    loop {
+        let v = Vec3::new(
+            rand::random::<f32>() * 2.0 - 1.0,
+            rand::random::<f32>() * 2.0 - 1.0,
+            rand::random::<f32>() * 2.0 - 1.0,
+        );
+        let length = v.length_squared();
+        if length > 0.0001 && length <= 1.0 {
+            return v.normalize();
+        }
+    }
+}
+
+/// emit the effect `kind` at `position`. `direction` is the shot direction or the surface
+/// normal the particles fly along, zero for a spherical burst. `scale` multiplies the size and
+/// speed of the particles, e.g. by the explosion radius.
+#[derive(Debug, Clone, Copy)]
+pub struct EffectEvent {
+    pub kind: EffectKind,
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub scale: f32,
+}
+
+impl EffectEvent {
+    pub fn new(kind: EffectKind, position: Vec3, direction: Vec3) -> Self {
+        Self {
+            kind,
+            position,
+            direction: direction.normalize_or_zero(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+}
+
+/// mesh and the materials of the effect kinds, shared by all particles
+pub struct EffectAssets {
+    mesh: Handle<Mesh>,
+    materials: HashMap<EffectKind, Handle<StandardMaterial>>,
+}
+
+pub fn setup_effects(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mesh = meshes.add(Mesh::from(shape::Icosphere {
+        radius: 1.0,
+        subdivisions: 1,
+    }));
+    let materials = EffectKind::ALL
+        .iter()
+        .map(|kind| {
+            let material = materials.add(StandardMaterial {
+                base_color: kind.emitter().color,
+                unlit: true,
+                ..default()
+            });
+            (*kind, material)
+        })
+        .collect();
+    commands.insert_resource(EffectAssets { mesh, materials });
+}
+
+/// a live particle, released to the pool at the end of its lifetime
+#[derive(Component)]
+pub struct Particle {
+    velocity: Vec3,
+    age: f32,
+    lifetime: f32,
+    size: f32,
+    end_size: f32,
+    gravity: f32,
+    drag: f32,
+}
+
+/// emit the particles of the requested effects
+pub fn spawn_effects_system(
+    mut commands: Commands,
+    assets: Option<Res<EffectAssets>>,
+    mut pools: ResMut<EntityPools>,
+    mut events: EventReader<EffectEvent>,
+    particles: Query<(), With<Particle>>,
+) {
+    let assets = match assets {
+        Some(assets) => assets,
+        None => return,
+    };
+    let mut alive = particles.iter().count();
+    for event in events.iter() {
+        let emitter = event.kind.emitter();
+        let material = assets.materials[&event.kind].clone();
+        for _ in 0..emitter.count {
+            if alive >= MAX_PARTICLES {
+                return;
+            }
+            alive += 1;
+            let direction = if event.direction == Vec3::ZERO {
+                random_direction()
+            } else {
+                (event.direction + random_direction() * emitter.spread).normalize_or_zero()
+            };
+            let size = random_range(emitter.size) * event.scale;
+            let transform =
+                Transform::from_translation(event.position).with_scale(Vec3::splat(size));
+            let entity = pools.acquire(&mut commands, PoolKind::Particle, transform, || {
+                (
+                    PbrBundle {
+                        mesh: assets.mesh.clone(),
+                        material: material.clone(),
+                        ..default()
+                    },
+                    NotShadowCaster,
+                )
+            });
+            commands
+                .entity(entity)
+                .insert(material.clone())
+                .insert(Particle {
+                    velocity: direction * random_range(emitter.speed) * event.scale.sqrt(),
+                    age: 0.0,
+                    lifetime: random_range(emitter.lifetime),
+                    size,
+                    end_size: size * emitter.end_size,
+                    gravity: emitter.gravity,
+                    drag: emitter.drag,
+                });
+        }
+    }
+}
+
+/// move and shrink the particles, release the expired ones
+pub fn particle_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pools: ResMut<EntityPools>,
+    mut particles: Query<(Entity, &mut Particle, &mut Transform)>,
+) {
+    let dt = time.delta_seconds();
+    for (entity, mut particle, mut transform) in &mut particles {
+        particle.age += dt;
+        if particle.age >= particle.lifetime {
+            commands.entity(entity).remove::<Particle>();
+            pools.release(&mut commands, entity, PoolKind::Particle);
+            continue;
+        }
+        particle.velocity.y -= particle.gravity * dt;
+        let velocity = particle.velocity * (1.0 - particle.drag * dt).max(0.0);
+        particle.velocity = velocity;
+        transform.translation += velocity * dt;
+        let t = particle.age / particle.lifetime;
+        transform.scale = Vec3::splat(particle.size + (particle.end_size - particle.size) * t);
+    }
+}
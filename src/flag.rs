@@ -0,0 +1,322 @@
+//! capture the flag: every team has a flag at its base. Enemies take it by touching it and
+//! capture it by bringing it to their own flag while that one is at home. Carriers are slowed
+//! down and drop the flag when they die. A dropped flag returns to its base when a teammate
+//! touches it or after FLAG_RETURN_TIME. The server runs the flags and replicates their state,
+//! clients only show them.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gamemode::{GameModeSettings, MatchEvent},
+    health::{DeathEvent, Health},
+    level::WorldBounds,
+    spectator::Spectator,
+    status::{ApplyStatusEvent, StatusKind},
+    targets::PLAYER_CENTER_OFFSET,
+    team::Team,
+    Player, PLAYER_SPAWN_POINT,
+};
+
+/// players whose center is closer than this to the center of a flag touch it
+pub const FLAG_RADIUS: f32 = 1.0;
+
+/// seconds until a dropped flag returns to its base on its own
+pub const FLAG_RETURN_TIME: f32 = 20.0;
+
+/// carried flags are shown this far above the carrier's feet
+pub const FLAG_CARRY_OFFSET: Vec3 = Vec3::new(0.0, 0.6, 0.0);
+
+const FLAG_POLE_HEIGHT: f32 = 1.6;
+
+/// base of the flag of `team`, on the floor next to the team's spawn points. Players spawn
+/// above the floor and drop onto it.
+pub fn flag_base(team: Team) -> Vec3 {
+    let offset = match team {
+        Team::Red => Vec3::new(-3.0, -1.0, -0.5),
+        Team::Blue => Vec3::new(3.0, -1.0, -0.5),
+    };
+    PLAYER_SPAWN_POINT + offset
+}
+
+/// replicated state of a flag
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FlagState {
+    AtBase,
+    /// carried by the player with this client id
+    Carried {
+        carrier: u64,
+    },
+    Dropped {
+        position: Vec3,
+    },
+}
+
+#[derive(Debug, Component)]
+pub struct Flag {
+    pub team: Team,
+    pub state: FlagState,
+    /// entity of the carrying player, server only
+    carrier: Option<Entity>,
+    /// runs while the flag lies around, server only
+    return_timer: Option<Timer>,
+}
+
+impl Flag {
+    pub fn new(team: Team) -> Self {
+        Self {
+            team,
+            state: FlagState::AtBase,
+            carrier: None,
+            return_timer: None,
+        }
+    }
+
+    pub fn with_state(mut self, state: FlagState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// where the flag is, None while carried
+    pub fn resting_position(&self) -> Option<Vec3> {
+        match self.state {
+            FlagState::AtBase => Some(flag_base(self.team)),
+            FlagState::Carried { .. } => None,
+            FlagState::Dropped { position } => Some(position),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = FlagState::AtBase;
+        self.carrier = None;
+        self.return_timer = None;
+    }
+
+    fn pick_up(&mut self, carrier: Entity, id: u64) {
+        self.state = FlagState::Carried { carrier: id };
+        self.carrier = Some(carrier);
+        self.return_timer = None;
+    }
+
+    fn drop_at(&mut self, position: Vec3) {
+        self.state = FlagState::Dropped { position };
+        self.carrier = None;
+        self.return_timer = Some(Timer::from_seconds(FLAG_RETURN_TIME, false));
+    }
+}
+
+/// spawn a flag pole with the team colors, where the flag rests
+pub fn spawn_flag(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    flag: Flag,
+) -> Entity {
+    let team = flag.team;
+    let position = flag.resting_position().unwrap_or_else(|| flag_base(team));
+    commands
+        .spawn_bundle(SpatialBundle {
+            transform: Transform::from_translation(position),
+            ..default()
+        })
+        .insert(flag)
+        .with_children(|parent| {
+            parent.spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Box::new(0.06, FLAG_POLE_HEIGHT, 0.06))),
+                material: materials.add(Color::rgb(0.3, 0.3, 0.3).into()),
+                transform: Transform::from_xyz(0.0, FLAG_POLE_HEIGHT * 0.5, 0.0),
+                ..default()
+            });
+            parent.spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Box::new(0.6, 0.4, 0.03))),
+                material: materials.add(StandardMaterial {
+                    base_color: team.color(),
+                    emissive: team.color() * 0.3,
+                    ..default()
+                }),
+                transform: Transform::from_xyz(0.3, FLAG_POLE_HEIGHT - 0.2, 0.0),
+                ..default()
+            });
+        })
+        .id()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagEventKind {
+    Taken,
+    Dropped,
+    Returned,
+    Captured,
+}
+
+/// server: something happened to the flag of `team`
+#[derive(Debug, Clone)]
+pub struct FlagEvent {
+    pub team: Team,
+    pub kind: FlagEventKind,
+    /// player involved, None for flags returning on their own
+    pub player: Option<Entity>,
+}
+
+/// server: put up the flags in flag modes
+pub fn setup_flags_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<GameModeSettings>,
+) {
+    if !settings.mode.has_flags() {
+        return;
+    }
+    for team in [Team::Red, Team::Blue] {
+        spawn_flag(&mut commands, &mut meshes, &mut materials, Flag::new(team));
+    }
+}
+
+/// server: take, carry, drop, return and capture flags. A new match starts with both flags at
+/// their bases.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn flag_system(
+    time: Res<Time>,
+    bounds: Res<WorldBounds>,
+    mut match_events: EventReader<MatchEvent>,
+    mut death_events: EventReader<DeathEvent>,
+    mut flags: Query<(Entity, &mut Flag, &mut Transform)>,
+    players: Query<
+        (Entity, &Player, &Transform, &Health, &Team),
+        (Without<Spectator>, Without<Flag>),
+    >,
+    mut flag_events: EventWriter<FlagEvent>,
+    mut status_events: EventWriter<ApplyStatusEvent>,
+) {
+    let restart = match_events
+        .iter()
+        .filter(|event| matches!(event, MatchEvent::Started))
+        .count()
+        > 0;
+    let dead: Vec<Entity> = death_events.iter().map(|event| event.entity).collect();
+    // enemy flags by carrier, for captures
+    let carried: Vec<(Entity, Entity)> = flags
+        .iter()
+        .filter_map(|(entity, flag, _)| Some((flag.carrier?, entity)))
+        .collect();
+    let mut captures = Vec::new();
+
+    for (_, mut flag, mut transform) in &mut flags {
+        if restart && flag.state != FlagState::AtBase {
+            flag.reset();
+        }
+        // carried flags follow their carrier, carriers drop them when they die or leave
+        if let Some(carrier) = flag.carrier {
+            match players.get(carrier) {
+                Ok((_, _, carrier_transform, _, _)) if !dead.contains(&carrier) => {
+                    transform.translation = carrier_transform.translation;
+                    status_events.send(ApplyStatusEvent::new(
+                        carrier,
+                        StatusKind::FlagCarrier,
+                        None,
+                    ));
+                }
+                _ => {
+                    let position = transform.translation;
+                    flag.drop_at(position);
+                    flag_events.send(FlagEvent {
+                        team: flag.team,
+                        kind: FlagEventKind::Dropped,
+                        player: Some(carrier),
+                    });
+                }
+            }
+        }
+        if flag.return_timer.is_some() {
+            let expired = match &mut flag.return_timer {
+                Some(timer) => timer.tick(time.delta()).finished(),
+                None => false,
+            };
+            if expired || transform.translation.y < bounds.kill_z {
+                flag.reset();
+                flag_events.send(FlagEvent {
+                    team: flag.team,
+                    kind: FlagEventKind::Returned,
+                    player: None,
+                });
+            }
+        }
+
+        if let Some(position) = flag.resting_position() {
+            if transform.translation != position {
+                transform.translation = position;
+            }
+            let center = position + PLAYER_CENTER_OFFSET;
+            for (entity, player, player_transform, health, team) in players.iter() {
+                let player_center = player_transform.translation + PLAYER_CENTER_OFFSET;
+                if health.is_dead()
+                    || dead.contains(&entity)
+                    || player_center.distance(center) > FLAG_RADIUS
+                {
+                    continue;
+                }
+                if *team != flag.team {
+                    flag.pick_up(entity, player.id);
+                    flag_events.send(FlagEvent {
+                        team: flag.team,
+                        kind: FlagEventKind::Taken,
+                        player: Some(entity),
+                    });
+                    break;
+                }
+                match flag.state {
+                    FlagState::Dropped { .. } => {
+                        flag.reset();
+                        flag_events.send(FlagEvent {
+                            team: flag.team,
+                            kind: FlagEventKind::Returned,
+                            player: Some(entity),
+                        });
+                        break;
+                    }
+                    FlagState::AtBase => {
+                        if let Some((_, enemy_flag)) =
+                            carried.iter().find(|(carrier, _)| *carrier == entity)
+                        {
+                            captures.push((*enemy_flag, entity));
+                        }
+                    }
+                    FlagState::Carried { .. } => (),
+                }
+            }
+        }
+    }
+
+    for (enemy_flag, player) in captures {
+        if let Ok((_, mut flag, mut transform)) = flags.get_mut(enemy_flag) {
+            // dropped in the meantime
+            if flag.carrier != Some(player) {
+                continue;
+            }
+            flag.reset();
+            transform.translation = flag_base(flag.team);
+            flag_events.send(FlagEvent {
+                team: flag.team,
+                kind: FlagEventKind::Captured,
+                player: Some(player),
+            });
+        }
+    }
+}
+
+/// client: set the state of the flag of `team`, as replicated by the server. False if there is
+/// no such flag yet.
+pub fn set_flag_state(flags: &mut Query<&mut Flag>, team: Team, state: FlagState) -> bool {
+    let mut found = false;
+    for mut flag in flags.iter_mut() {
+        if flag.team != team {
+            continue;
+        }
+        found = true;
+        if flag.state != state {
+            flag.state = state;
+        }
+    }
+    found
+}
@@ -1,5 +1,8 @@
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
+
+use crate::{status::StatusEffect, weapon::Ammo};
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NetworkedEntities {
     pub entities: Vec<Entity>,
@@ -15,10 +18,46 @@ pub struct WithRotation {
     pub rotations: Vec<Quat>,
 }
 
+/// gameplay state of player entities that remote clients need for visuals
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct PlayerStates {
+    pub entities: Vec<Entity>,
+    pub yaws: Vec<f32>,
+    pub pitches: Vec<f32>,
+    pub blocking: Vec<bool>,
+    /// aiming down sights
+    pub aiming: Vec<bool>,
+    pub shields: Vec<f32>,
+    /// took damage within the last RECENT_DAMAGE_TIME
+    pub damaged: Vec<bool>,
+    /// standing on the ground, for the animation state
+    pub grounded: Vec<bool>,
+    pub crouching: Vec<bool>,
+}
+
+/// state of the player the frame is sent to, only replicated to its owning client
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct OwnPlayerState {
+    /// stamina as fraction of max, None if stamina is disabled
+    pub stamina: Option<f32>,
+    pub exhausted: bool,
+    /// shield meter as fraction of max
+    pub shield: f32,
+    /// health as fraction of max
+    pub health: Option<f32>,
+    /// armor as fraction of max, None without armor
+    pub armor: Option<f32>,
+    pub ammo: Option<Ammo>,
+    /// active status effects, for the HUD and to predict their speed modifiers
+    pub status: Vec<StatusEffect>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NetworkFrame {
     pub tick: u32,
     pub last_player_input: u32,
     pub entities: NetworkedEntities,
     pub with_rotation: WithRotation,
+    pub players: PlayerStates,
+    pub own: OwnPlayerState,
 }
@@ -0,0 +1,752 @@
+//! game modes: the rules of a match. The server keeps the score, checks the win condition and
+//! runs the match flow, the server binary replicates the match state and the scores to the
+//! clients. A match waits in warmup until enough players joined, freezes everyone for a
+//! countdown, runs until the win condition triggers (with overtime on a draw when time runs
+//! out) and the next one starts after the restart delay. Modes: free-for-all deathmatch, capture
+//! the flag (see `flag`), king of the hill (see `control`) and co-op against the bots.
+
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bot,
+    control::{self, ControlPoint, ControlPointEvent},
+    controller::FpsController,
+    flag::{self, FlagEvent, FlagEventKind},
+    health::{self, DamageCause, DamageDealtEvent, DeathEvent},
+    spectator::Spectator,
+    stats::StatsStore,
+    team::Team,
+    Player,
+};
+
+pub struct GameModePlugin;
+
+impl Plugin for GameModePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<MatchEvent>()
+            .add_event::<FlagEvent>()
+            .add_event::<ControlPointEvent>()
+            .init_resource::<GameModeSettings>()
+            .init_resource::<Scoreboard>()
+            .init_resource::<MatchState>()
+            .add_system(track_players_system)
+            .add_system(
+                score_kills_system
+                    .after(health::apply_damage_system)
+                    .after(track_players_system),
+            )
+            .add_startup_system(flag::setup_flags_system)
+            .add_system(flag::flag_system.after(health::apply_damage_system))
+            .add_system(
+                score_captures_system
+                    .after(track_players_system)
+                    .after(flag::flag_system),
+            )
+            .add_startup_system(control::setup_control_points_system)
+            .add_system(control::control_point_system.after(health::apply_damage_system))
+            .add_system(score_control_points_system.after(control::control_point_system))
+            .add_system(
+                match_flow_system
+                    .after(score_kills_system)
+                    .after(score_captures_system)
+                    .after(score_control_points_system),
+            )
+            .add_system(freeze_players_system.after(match_flow_system))
+            .add_system(record_stats_system.after(match_flow_system));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameModeKind {
+    /// free-for-all, every kill is a frag, suicides cost one
+    Deathmatch,
+    /// two teams, captures of the enemy flag decide the match
+    CaptureTheFlag,
+    /// two teams, every second a team holds the hill earns it a point
+    KingOfTheHill,
+    /// the players on the red team against the bots on the blue team, every kill is a point
+    /// for the killer's team
+    Coop,
+}
+
+impl Default for GameModeKind {
+    fn default() -> Self {
+        GameModeKind::Deathmatch
+    }
+}
+
+impl GameModeKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            GameModeKind::Deathmatch => "deathmatch",
+            GameModeKind::CaptureTheFlag => "capture the flag",
+            GameModeKind::KingOfTheHill => "king of the hill",
+            GameModeKind::Coop => "co-op",
+        }
+    }
+
+    /// players are split into teams, see `team`
+    pub fn team_based(&self) -> bool {
+        match self {
+            GameModeKind::Deathmatch => false,
+            GameModeKind::CaptureTheFlag | GameModeKind::KingOfTheHill | GameModeKind::Coop => true,
+        }
+    }
+
+    /// team of a joining player, None in free-for-all. `teams` are the teams of the players
+    /// in the game.
+    pub fn join_team(&self, bot: bool, teams: impl Iterator<Item = Team>) -> Option<Team> {
+        match self {
+            GameModeKind::Deathmatch => None,
+            GameModeKind::CaptureTheFlag | GameModeKind::KingOfTheHill => {
+                Some(Team::balanced(teams))
+            }
+            GameModeKind::Coop if bot => Some(Team::Blue),
+            GameModeKind::Coop => Some(Team::Red),
+        }
+    }
+
+    /// the level gets a flag per team
+    pub fn has_flags(&self) -> bool {
+        matches!(self, GameModeKind::CaptureTheFlag)
+    }
+
+    /// the level gets control points, see `control`
+    pub fn has_control_points(&self) -> bool {
+        matches!(self, GameModeKind::KingOfTheHill)
+    }
+
+    /// score a death: `attacker` is the player credited with it, None or the victim itself for
+    /// suicides and accidents
+    fn score_death(&self, scoreboard: &mut Scoreboard, victim: u64, attacker: Option<u64>) {
+        match attacker {
+            Some(attacker) if attacker != victim => {
+                let score = scoreboard.score_mut(attacker);
+                score.frags += 1;
+                score.streak += 1;
+                score.best_streak = score.best_streak.max(score.streak);
+                if *self == GameModeKind::Coop {
+                    let team = if bot::is_bot(attacker) {
+                        Team::Blue
+                    } else {
+                        Team::Red
+                    };
+                    *scoreboard.team_scores.entry(team).or_default() += 1;
+                }
+            }
+            _ => scoreboard.score_mut(victim).frags -= 1,
+        }
+        let score = scoreboard.score_mut(victim);
+        score.deaths += 1;
+        score.streak = 0;
+    }
+
+    /// the player or team in the lead, None on a tie
+    fn leader(&self, scoreboard: &Scoreboard) -> Option<Winner> {
+        match self {
+            GameModeKind::Deathmatch => scoreboard.leader().map(Winner::Player),
+            GameModeKind::CaptureTheFlag | GameModeKind::KingOfTheHill | GameModeKind::Coop => {
+                scoreboard.leading_team().map(Winner::Team)
+            }
+        }
+    }
+
+    /// a player or team reached the score that wins the match
+    fn limit_reached(&self, settings: &GameModeSettings, scoreboard: &Scoreboard) -> bool {
+        let team_limit = match self {
+            GameModeKind::Deathmatch => {
+                return settings.frag_limit > 0
+                    && scoreboard
+                        .scores
+                        .values()
+                        .any(|score| score.frags >= settings.frag_limit as i32);
+            }
+            GameModeKind::CaptureTheFlag => settings.capture_limit,
+            GameModeKind::KingOfTheHill => settings.point_limit,
+            GameModeKind::Coop => settings.frag_limit,
+        };
+        team_limit > 0
+            && scoreboard
+                .team_scores
+                .values()
+                .any(|points| *points >= team_limit)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameModeSettings {
+    pub mode: GameModeKind,
+    /// frags that win the match (team frags in co-op), 0 for no limit
+    pub frag_limit: u32,
+    /// team captures that win a capture the flag match, 0 for no limit
+    pub capture_limit: u32,
+    /// team points that win a king of the hill match, 0 for no limit
+    pub point_limit: u32,
+    /// match length (seconds), 0 for no limit
+    pub time_limit: f32,
+    /// seconds between the end of a match and the start of the next one, clients show the
+    /// match summary meanwhile
+    pub restart_delay: f32,
+    /// players needed to leave the warmup, spectators don't count
+    pub min_players: u32,
+    /// seconds players are frozen before a match starts
+    pub freeze_time: f32,
+    /// a draw when time runs out goes into overtime, the next score decides
+    pub overtime: bool,
+    /// co-op: players keep their weapons and ammo when the map changes. Needs `stats_db`,
+    /// the loadouts are kept there by player identity.
+    pub carry_loadout: bool,
+}
+
+impl Default for GameModeSettings {
+    fn default() -> Self {
+        Self {
+            mode: GameModeKind::default(),
+            frag_limit: 20,
+            capture_limit: 3,
+            point_limit: 120,
+            time_limit: 600.0,
+            restart_delay: 10.0,
+            min_players: 2,
+            freeze_time: 3.0,
+            overtime: true,
+            carry_loadout: false,
+        }
+    }
+}
+
+impl GameModeSettings {
+    /// `carry_loadout` only holds in co-op
+    pub fn carries_loadout(&self) -> bool {
+        self.carry_loadout && self.mode == GameModeKind::Coop
+    }
+
+    /// the match rules in words, for the join splash
+    pub fn rules(&self) -> Vec<String> {
+        let mut rules = Vec::new();
+        let (limit, unit) = match self.mode {
+            GameModeKind::Deathmatch => (self.frag_limit, "frags"),
+            GameModeKind::CaptureTheFlag => (self.capture_limit, "captures"),
+            GameModeKind::KingOfTheHill => (self.point_limit, "points"),
+            GameModeKind::Coop => (self.frag_limit, "team frags"),
+        };
+        if limit > 0 {
+            rules.push(format!("{} {} win the match", limit, unit));
+        }
+        if self.time_limit > 0.0 {
+            let overtime = if self.overtime {
+                ", overtime on a draw"
+            } else {
+                ""
+            };
+            rules.push(format!(
+                "{:.0} minute time limit{}",
+                self.time_limit / 60.0,
+                overtime
+            ));
+        }
+        rules.push(format!("matches start with {} players", self.min_players));
+        if self.carries_loadout() {
+            rules.push("weapons and ammo carry over to the next map".to_string());
+        }
+        rules
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Score {
+    pub frags: i32,
+    pub deaths: u32,
+    /// enemy flags brought home
+    pub captures: u32,
+    /// kills since the last death
+    pub streak: u32,
+    pub best_streak: u32,
+}
+
+/// scores of the connected players by client id, and of the teams in team modes
+#[derive(Debug, Default)]
+pub struct Scoreboard {
+    pub scores: HashMap<u64, Score>,
+    pub team_scores: HashMap<Team, u32>,
+}
+
+impl Scoreboard {
+    fn score_mut(&mut self, id: u64) -> &mut Score {
+        self.scores.entry(id).or_default()
+    }
+
+    /// the player with the most frags, None on a tie or without players
+    pub fn leader(&self) -> Option<u64> {
+        self.best_by(|score| score.frags).map(|(id, _)| id)
+    }
+
+    /// the player with the highest `value` and the value, None on a tie or without players
+    fn best_by(&self, value: impl Fn(&Score) -> i32) -> Option<(u64, i32)> {
+        let best = self.scores.values().map(&value).max()?;
+        let mut leaders = self
+            .scores
+            .iter()
+            .filter(|(_, score)| value(score) == best)
+            .map(|(id, _)| *id);
+        match (leaders.next(), leaders.next()) {
+            (Some(leader), None) => Some((leader, best)),
+            _ => None,
+        }
+    }
+
+    /// awards of the match, for the players clearly ahead in their category
+    pub fn awards(&self, mode: GameModeKind) -> Vec<Award> {
+        let mut kinds = vec![AwardKind::MostFrags, AwardKind::BestStreak];
+        if mode.has_flags() {
+            kinds.push(AwardKind::MostCaptures);
+        }
+        kinds
+            .into_iter()
+            .filter_map(|kind| {
+                let (player, value) = self.best_by(|score| kind.value(score))?;
+                (value > 0).then_some(Award {
+                    kind,
+                    player,
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    /// the team with the most points, None on a tie
+    pub fn leading_team(&self) -> Option<Team> {
+        let red = self.team_scores.get(&Team::Red).copied().unwrap_or(0);
+        let blue = self.team_scores.get(&Team::Blue).copied().unwrap_or(0);
+        match red.cmp(&blue) {
+            std::cmp::Ordering::Greater => Some(Team::Red),
+            std::cmp::Ordering::Less => Some(Team::Blue),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+
+    /// scores of both teams, red first
+    pub fn team_ranking(&self) -> Vec<(Team, u32)> {
+        [Team::Red, Team::Blue]
+            .into_iter()
+            .map(|team| (team, self.team_scores.get(&team).copied().unwrap_or(0)))
+            .collect()
+    }
+
+    /// scores sorted by frags, best first
+    pub fn ranking(&self) -> Vec<(u64, Score)> {
+        let mut ranking: Vec<_> = self
+            .scores
+            .iter()
+            .map(|(id, score)| (*id, *score))
+            .collect();
+        ranking.sort_by_key(|(id, score)| (-score.frags, score.deaths, *id));
+        ranking
+    }
+
+    fn reset(&mut self) {
+        for score in self.scores.values_mut() {
+            *score = Score::default();
+        }
+        self.team_scores.clear();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AwardKind {
+    MostFrags,
+    /// most kills in a row without dying
+    BestStreak,
+    MostCaptures,
+}
+
+impl AwardKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AwardKind::MostFrags => "most frags",
+            AwardKind::BestStreak => "longest streak",
+            AwardKind::MostCaptures => "most captures",
+        }
+    }
+
+    fn value(&self, score: &Score) -> i32 {
+        match self {
+            AwardKind::MostFrags => score.frags,
+            AwardKind::BestStreak => score.best_streak as i32,
+            AwardKind::MostCaptures => score.captures as i32,
+        }
+    }
+}
+
+/// an award for the player with this client id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Award {
+    pub kind: AwardKind,
+    pub player: u64,
+    pub value: i32,
+}
+
+/// final result of a match, broadcast when it ends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchSummary {
+    pub mode: GameModeKind,
+    pub winner: Option<Winner>,
+    /// best first
+    pub scores: Vec<(u64, Score)>,
+    /// red first, empty in free-for-all modes
+    pub team_scores: Vec<(Team, u32)>,
+    pub awards: Vec<Award>,
+}
+
+impl MatchSummary {
+    pub fn new(mode: GameModeKind, winner: Option<Winner>, scoreboard: &Scoreboard) -> Self {
+        Self {
+            mode,
+            winner,
+            scores: scoreboard.ranking(),
+            team_scores: if mode.team_based() {
+                scoreboard.team_ranking()
+            } else {
+                Vec::new()
+            },
+            awards: scoreboard.awards(mode),
+        }
+    }
+}
+
+/// winner of a match, a player by client id or a team
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Winner {
+    Player(u64),
+    Team(Team),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchPhase {
+    /// waiting for enough players, nothing counts
+    Warmup,
+    /// players are frozen until the match starts
+    Countdown,
+    Running,
+    /// time ran out on a draw, the next score decides
+    Overtime,
+    /// the win condition triggered, the next match starts after the restart delay. No winner
+    /// for a draw.
+    Ended {
+        winner: Option<Winner>,
+    },
+}
+
+impl MatchPhase {
+    /// scores count
+    pub fn is_live(&self) -> bool {
+        matches!(self, MatchPhase::Running | MatchPhase::Overtime)
+    }
+
+    /// players can look around, but neither move nor attack
+    pub fn is_frozen(&self) -> bool {
+        matches!(self, MatchPhase::Countdown)
+    }
+}
+
+#[derive(Debug)]
+pub struct MatchState {
+    pub phase: MatchPhase,
+    /// seconds since the match started
+    pub elapsed: f32,
+    /// runs during the countdown and after a match
+    timer: Timer,
+}
+
+impl Default for MatchState {
+    fn default() -> Self {
+        Self {
+            phase: MatchPhase::Warmup,
+            elapsed: 0.0,
+            timer: Timer::from_seconds(0.0, false),
+        }
+    }
+}
+
+impl MatchState {
+    pub fn is_running(&self) -> bool {
+        self.phase.is_live()
+    }
+
+    /// seconds until the match starts, ends by time or the next one starts
+    pub fn time_left(&self, settings: &GameModeSettings) -> Option<f32> {
+        match self.phase {
+            MatchPhase::Running if settings.time_limit > 0.0 => {
+                Some((settings.time_limit - self.elapsed).max(0.0))
+            }
+            MatchPhase::Warmup | MatchPhase::Running | MatchPhase::Overtime => None,
+            MatchPhase::Countdown | MatchPhase::Ended { .. } => {
+                Some(self.timer.duration().as_secs_f32() - self.timer.elapsed_secs())
+            }
+        }
+    }
+
+    /// end the match without a winner and start the next one right away, e.g. after an admin
+    /// changed the map
+    pub fn restart(&mut self) {
+        info!("Match restarted.");
+        self.phase = MatchPhase::Ended { winner: None };
+        self.timer = Timer::from_seconds(0.0, false);
+    }
+
+    fn count_down(&mut self, settings: &GameModeSettings) {
+        info!("Match starts in {} seconds.", settings.freeze_time);
+        self.phase = MatchPhase::Countdown;
+        self.timer = Timer::from_seconds(settings.freeze_time, false);
+    }
+
+    fn end(&mut self, settings: &GameModeSettings, winner: Option<Winner>) -> MatchEvent {
+        info!("Match ended, winner: {:?}.", winner);
+        self.phase = MatchPhase::Ended { winner };
+        self.timer = Timer::from_seconds(settings.restart_delay, false);
+        MatchEvent::Ended { winner }
+    }
+}
+
+/// server: the match flow moved on
+#[derive(Debug, Clone)]
+pub enum MatchEvent {
+    /// a new match is on its way, in warmup or counting down: scores are reset and everyone
+    /// respawns
+    Started,
+    /// players left during the countdown, back to waiting for them
+    Warmup,
+    /// the countdown is over
+    Live,
+    Overtime,
+    Ended {
+        winner: Option<Winner>,
+    },
+}
+
+/// server: keep a score for every connected player
+pub fn track_players_system(mut scoreboard: ResMut<Scoreboard>, players: Query<&Player>) {
+    let ids: HashSet<u64> = players.iter().map(|player| player.id).collect();
+    // only touch the scoreboard on joins and leaves, changes trigger a broadcast
+    let in_sync = scoreboard.scores.len() == ids.len()
+        && ids.iter().all(|id| scoreboard.scores.contains_key(id));
+    if in_sync {
+        return;
+    }
+    scoreboard.scores.retain(|id, _| ids.contains(id));
+    for id in ids {
+        scoreboard.scores.entry(id).or_default();
+    }
+}
+
+/// server: score player deaths by the rules of the mode, deaths after the match ended don't
+/// count
+pub fn score_kills_system(
+    settings: Res<GameModeSettings>,
+    state: Res<MatchState>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut death_events: EventReader<DeathEvent>,
+    players: Query<&Player>,
+) {
+    for event in death_events.iter() {
+        if !state.is_running() {
+            continue;
+        }
+        let victim = match players.get(event.entity) {
+            Ok(victim) => victim.id,
+            Err(_) => continue,
+        };
+        let attacker = event
+            .attacker
+            .and_then(|attacker| players.get(attacker).ok())
+            .map(|attacker| attacker.id);
+        settings.mode.score_death(&mut scoreboard, victim, attacker);
+    }
+}
+
+/// server: record kills, deaths and hits of the running match and the playtime in the stats
+/// store if the server keeps one, and write them when the match ends
+pub fn record_stats_system(
+    time: Res<Time>,
+    state: Res<MatchState>,
+    stats: Option<ResMut<StatsStore>>,
+    mut death_events: EventReader<DeathEvent>,
+    mut damage_events: EventReader<DamageDealtEvent>,
+    mut match_events: EventReader<MatchEvent>,
+    players: Query<&Player>,
+    playing: Query<&Player, Without<Spectator>>,
+) {
+    let mut stats = match stats {
+        Some(stats) => stats,
+        None => return,
+    };
+    let id = |entity| players.get(entity).ok().map(|player| player.id);
+    for event in death_events.iter() {
+        let victim = match id(event.entity) {
+            Some(victim) if state.is_running() => victim,
+            _ => continue,
+        };
+        stats.death(victim);
+        match event.attacker.and_then(id) {
+            Some(attacker) if attacker != victim => stats.kill(attacker),
+            _ => (),
+        }
+    }
+    // one hit per shot: an explosion damaging several players is still a single hit
+    let mut exploded = HashSet::new();
+    for event in damage_events.iter() {
+        if !state.is_running()
+            || event.cause != DamageCause::Attack
+            || players.get(event.target).is_err()
+        {
+            continue;
+        }
+        if let Some(projectile) = event.projectile {
+            if !exploded.insert(projectile) {
+                continue;
+            }
+        }
+        if let Some(attacker) = id(event.attacker) {
+            stats.hit(attacker);
+        }
+    }
+    for player in playing.iter() {
+        stats.play(player.id, &player.name, time.delta_seconds_f64());
+    }
+    for event in match_events.iter() {
+        if let MatchEvent::Ended { .. } = event {
+            if let Err(e) = stats.flush() {
+                warn!("failed to write the stats: {}", e);
+            }
+        }
+    }
+}
+
+/// server: count flag captures for the capturing team and player
+pub fn score_captures_system(
+    state: Res<MatchState>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut flag_events: EventReader<FlagEvent>,
+    players: Query<(&Player, &Team)>,
+) {
+    for event in flag_events.iter() {
+        if event.kind != FlagEventKind::Captured || !state.is_running() {
+            continue;
+        }
+        let (player, team) = match event.player.and_then(|player| players.get(player).ok()) {
+            Some(player) => player,
+            None => continue,
+        };
+        *scoreboard.team_scores.entry(*team).or_default() += 1;
+        scoreboard.score_mut(player.id).captures += 1;
+    }
+}
+
+/// server: every second, award a point to the owner of every uncontested control point
+pub fn score_control_points_system(
+    time: Res<Time>,
+    state: Res<MatchState>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut timer: Local<Option<Timer>>,
+    control_points: Query<&ControlPoint>,
+) {
+    if !state.is_running() {
+        return;
+    }
+    let timer = timer.get_or_insert_with(|| Timer::from_seconds(1.0, true));
+    for _ in 0..timer.tick(time.delta()).times_finished() {
+        for control_point in control_points.iter() {
+            if control_point.state.contested {
+                continue;
+            }
+            if let Some(owner) = control_point.state.owner {
+                *scoreboard.team_scores.entry(owner).or_default() += 1;
+            }
+        }
+    }
+}
+
+/// server: move the match through its phases: warmup until enough players joined, the
+/// countdown, the match itself until the win condition triggers (overtime on a draw by time)
+/// and the next match after the restart delay
+pub fn match_flow_system(
+    time: Res<Time>,
+    settings: Res<GameModeSettings>,
+    mut state: ResMut<MatchState>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut match_events: EventWriter<MatchEvent>,
+    players: Query<&Player, Without<Spectator>>,
+) {
+    let enough_players = players.iter().count() >= settings.min_players as usize;
+    match state.phase {
+        MatchPhase::Warmup => {
+            if enough_players {
+                state.count_down(&settings);
+                scoreboard.reset();
+                match_events.send(MatchEvent::Started);
+            }
+        }
+        MatchPhase::Countdown => {
+            if !enough_players {
+                info!("Not enough players, back to warmup.");
+                state.phase = MatchPhase::Warmup;
+                match_events.send(MatchEvent::Warmup);
+            } else if state.timer.tick(time.delta()).finished() {
+                info!("Match started.");
+                state.phase = MatchPhase::Running;
+                state.elapsed = 0.0;
+                match_events.send(MatchEvent::Live);
+            }
+        }
+        MatchPhase::Running => {
+            state.elapsed += time.delta_seconds();
+            let time_up = settings.time_limit > 0.0 && state.elapsed >= settings.time_limit;
+            let leader = settings.mode.leader(&scoreboard);
+            if settings.mode.limit_reached(&settings, &scoreboard) {
+                let event = state.end(&settings, leader);
+                match_events.send(event);
+            } else if time_up && leader.is_none() && settings.overtime {
+                info!("Draw, overtime.");
+                state.phase = MatchPhase::Overtime;
+                match_events.send(MatchEvent::Overtime);
+            } else if time_up {
+                let event = state.end(&settings, leader);
+                match_events.send(event);
+            }
+        }
+        MatchPhase::Overtime => {
+            state.elapsed += time.delta_seconds();
+            if let Some(leader) = settings.mode.leader(&scoreboard) {
+                let event = state.end(&settings, Some(leader));
+                match_events.send(event);
+            }
+        }
+        MatchPhase::Ended { .. } => {
+            if !state.timer.tick(time.delta()).finished() {
+                return;
+            }
+            scoreboard.reset();
+            match_events.send(MatchEvent::Started);
+            if enough_players {
+                state.count_down(&settings);
+            } else {
+                info!("Not enough players, warmup.");
+                state.phase = MatchPhase::Warmup;
+            }
+        }
+    }
+}
+
+/// server: freeze players during the countdown, clients mirror this from the match state
+pub fn freeze_players_system(state: Res<MatchState>, mut controllers: Query<&mut FpsController>) {
+    let frozen = state.phase.is_frozen();
+    for mut controller in &mut controllers {
+        if controller.frozen != frozen {
+            controller.frozen = frozen;
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! glTF map importer. Meshes of the default scene become static geometry, the custom
+//! properties of a node (glTF extras, e.g. Blender custom properties) tag it:
+//!
+//!     collider = "trimesh" | "convex"     collision shape of the mesh, none without
+//!     surface = "Metal"                   SurfaceMaterial of the collider
+//!     spawn = "any" | "red" | "blue"      spawn point at the node origin
+//!     pickup = "Health"                   PickupKind at the node origin
+//!     light = "point" | "spot" | "directional", with optional color = [r, g, b],
+//!         intensity and range
+//!     trigger = {"action": "damage", "per_second": 10}
+//!                                         trigger volume (TriggerAction) shaped like the
+//!                                         convex hull of the mesh
+//!
+//! Nodes with entity tags (spawn, pickup, light, trigger) don't add their mesh to the level.
+
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use gltf::{mesh::Mode, Node};
+use serde::Deserialize;
+
+use crate::{
+    level::SurfaceMaterial,
+    mapfile::{LightKind, MapCollider, MapData, MapLight, MapMesh, MapTrigger},
+    pickup::PickupKind,
+    spawn::SpawnPoint,
+    team::Team,
+    trigger::{TriggerAction, TriggerShape},
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct NodeProperties {
+    collider: Option<MapCollider>,
+    surface: Option<SurfaceMaterial>,
+    spawn: Option<String>,
+    pickup: Option<PickupKind>,
+    light: Option<LightKind>,
+    color: Option<[f32; 3]>,
+    intensity: Option<f32>,
+    range: Option<f32>,
+    trigger: Option<TriggerAction>,
+}
+
+impl NodeProperties {
+    fn parse(node: &Node) -> Result<Self, String> {
+        match node.extras() {
+            Some(extras) => serde_json::from_str(extras.get()).map_err(|e| e.to_string()),
+            None => Ok(NodeProperties::default()),
+        }
+    }
+
+    fn is_entity(&self) -> bool {
+        self.spawn.is_some()
+            || self.pickup.is_some()
+            || self.light.is_some()
+            || self.trigger.is_some()
+    }
+}
+
+fn spawn_team(spawn: &str) -> Result<Option<Team>, String> {
+    match spawn {
+        "any" => Ok(None),
+        "red" => Ok(Some(Team::Red)),
+        "blue" => Ok(Some(Team::Blue)),
+        _ => Err(format!("invalid spawn {} (any, red, blue)", spawn)),
+    }
+}
+
+struct Importer<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    map: MapData,
+}
+
+impl Importer<'_> {
+    fn visit(&mut self, node: Node, parent: Mat4) -> Result<(), String> {
+        let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+        let node_name = node.name().unwrap_or("unnamed node").to_string();
+        let properties = NodeProperties::parse(&node).unwrap_or_else(|e| {
+            warn!("{}: invalid properties: {}", node_name, e);
+            NodeProperties::default()
+        });
+        let origin = transform.transform_point3(Vec3::ZERO);
+
+        if let Some(spawn) = &properties.spawn {
+            let team = spawn_team(spawn).map_err(|e| format!("{}: {}", node_name, e))?;
+            self.map.spawn_points.push((origin, SpawnPoint { team }));
+        }
+        if let Some(kind) = properties.pickup {
+            self.map.pickups.push((origin, kind));
+        }
+        if let Some(kind) = properties.light {
+            let (_, rotation, translation) = transform.to_scale_rotation_translation();
+            let default_intensity = match kind {
+                LightKind::Point | LightKind::Spot => 800.0,
+                LightKind::Directional => 10000.0,
+            };
+            self.map.lights.push(MapLight {
+                kind,
+                transform: Transform::from_translation(translation).with_rotation(rotation),
+                color: properties
+                    .color
+                    .map_or(Color::WHITE, |[r, g, b]| Color::rgb(r, g, b)),
+                intensity: properties.intensity.unwrap_or(default_intensity),
+                range: properties.range.unwrap_or(20.0),
+            });
+        }
+        if let Some(action) = &properties.trigger {
+            let points = match node.mesh() {
+                Some(mesh) => self.mesh_points(&mesh, transform),
+                None => Vec::new(),
+            };
+            if points.is_empty() {
+                warn!("{}: trigger without mesh, skipped", node_name);
+            } else {
+                self.map.triggers.push(MapTrigger {
+                    transform: Transform::identity(),
+                    shape: TriggerShape::Convex { points },
+                    action: action.clone(),
+                });
+            }
+        }
+        if !properties.is_entity() {
+            if let Some(mesh) = node.mesh() {
+                self.add_mesh(&mesh, transform, &properties);
+            }
+        }
+        for child in node.children() {
+            self.visit(child, transform)?;
+        }
+        Ok(())
+    }
+
+    /// vertices of all triangle primitives of `mesh`, in world space
+    fn mesh_points(&self, mesh: &gltf::Mesh, transform: Mat4) -> Vec<Vec3> {
+        let buffers = self.buffers;
+        mesh.primitives()
+            .filter(|primitive| primitive.mode() == Mode::Triangles)
+            .filter_map(|primitive| {
+                primitive
+                    .reader(|buffer| Some(&buffers[buffer.index()].0[..]))
+                    .read_positions()
+            })
+            .flatten()
+            .map(|p| transform.transform_point3(Vec3::from(p)))
+            .collect()
+    }
+
+    fn add_mesh(&mut self, mesh: &gltf::Mesh, transform: Mat4, properties: &NodeProperties) {
+        let normal_matrix = transform.inverse().transpose();
+        let buffers = self.buffers;
+        for primitive in mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()].0[..]));
+            let positions: Vec<Vec3> = match reader.read_positions() {
+                Some(positions) => positions
+                    .map(|p| transform.transform_point3(Vec3::from(p)))
+                    .collect(),
+                None => continue,
+            };
+            let normals = reader.read_normals().map(|normals| {
+                normals
+                    .map(|n| {
+                        normal_matrix
+                            .transform_vector3(Vec3::from(n))
+                            .normalize_or_zero()
+                    })
+                    .collect()
+            });
+            let mut indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            // mirroring transforms flip the winding
+            if transform.determinant() < 0.0 {
+                for triangle in indices.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+            let [r, g, b, a] = primitive
+                .material()
+                .pbr_metallic_roughness()
+                .base_color_factor();
+            self.map.meshes.push(MapMesh {
+                positions,
+                normals,
+                indices,
+                color: Color::rgba(r, g, b, a),
+                collider: properties.collider,
+                surface: properties.surface,
+                visible: true,
+            });
+        }
+    }
+}
+
+/// external files referenced by a .gltf (buffers and images), .glb files may have some too
+pub fn required_assets(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let gltf = gltf::Gltf::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let buffers = gltf.buffers().filter_map(|buffer| match buffer.source() {
+        gltf::buffer::Source::Uri(uri) => Some(uri),
+        gltf::buffer::Source::Bin => None,
+    });
+    let images = gltf.images().filter_map(|image| match image.source() {
+        gltf::image::Source::Uri { uri, .. } => Some(uri),
+        gltf::image::Source::View { .. } => None,
+    });
+    Ok(buffers
+        .chain(images)
+        .filter(|uri| !uri.starts_with("data:"))
+        .map(|uri| dir.join(uri))
+        .collect())
+}
+
+/// import the default scene (or the first one) of a .gltf or .glb file
+pub fn load(path: &Path) -> Result<MapData, String> {
+    let gltf::Gltf { document, blob } =
+        gltf::Gltf::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let buffers = gltf::import_buffers(&document, path.parent(), blob)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| format!("{}: no scene", path.display()))?;
+    let mut importer = Importer {
+        buffers: &buffers,
+        map: MapData::default(),
+    };
+    for node in scene.nodes() {
+        importer
+            .visit(node, Mat4::IDENTITY)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(importer.map)
+}
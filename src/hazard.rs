@@ -0,0 +1,163 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    health::{DamageCause, DamageEvent, DamageType},
+    status::{ApplyStatusEvent, StatusKind},
+    targets::{PlayerTargets, PLAYER_CENTER_OFFSET},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HazardKind {
+    Fire,
+    Acid,
+}
+
+impl HazardKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            HazardKind::Fire => "fire",
+            HazardKind::Acid => "acid",
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            HazardKind::Fire => Color::rgba(1.0, 0.4, 0.0, 0.6),
+            HazardKind::Acid => Color::rgba(0.3, 0.9, 0.1, 0.6),
+        }
+    }
+
+    /// damage applied to everyone inside the zone per pulse
+    pub fn damage_per_pulse(&self) -> f32 {
+        match self {
+            HazardKind::Fire => 8.0,
+            HazardKind::Acid => 5.0,
+        }
+    }
+
+    /// status effect players inside the zone get on every pulse, lasting after they leave
+    pub fn status(&self) -> StatusKind {
+        match self {
+            HazardKind::Fire => StatusKind::Burn,
+            HazardKind::Acid => StatusKind::Slow,
+        }
+    }
+}
+
+/// area that periodically damages players standing inside (cylinder around the entity translation)
+#[derive(Debug, Component)]
+pub struct HazardZone {
+    pub kind: HazardKind,
+    pub radius: f32,
+    pub height: f32,
+    pub pulse: Timer,
+    /// None for permanent (level) hazards
+    pub lifetime: Option<Timer>,
+    /// player responsible for the hazard (e.g. the shooter of a fireball), credited with kills
+    pub owner: Option<Entity>,
+}
+
+impl HazardZone {
+    pub fn new(kind: HazardKind, radius: f32, lifetime: Option<f32>) -> Self {
+        Self {
+            kind,
+            radius,
+            height: 1.0,
+            pulse: Timer::from_seconds(0.5, true),
+            lifetime: lifetime.map(|secs| Timer::from_seconds(secs, false)),
+            owner: None,
+        }
+    }
+
+    pub fn with_owner(mut self, owner: Option<Entity>) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    pub fn contains(&self, center: Vec3, point: Vec3) -> bool {
+        let offset = point - center;
+        offset.y.abs() <= self.height
+            && Vec2::new(offset.x, offset.z).length_squared() <= self.radius * self.radius
+    }
+}
+
+pub fn spawn_hazard_zone(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    translation: Vec3,
+    zone: HazardZone,
+) -> Entity {
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Plane {
+                size: zone.radius * 2.0,
+            })),
+            material: materials.add(StandardMaterial {
+                base_color: zone.kind.color(),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            }),
+            // lift slightly above the floor to avoid z-fighting
+            transform: Transform::from_translation(translation + Vec3::Y * 0.01),
+            ..default()
+        })
+        .insert(zone)
+        .id()
+}
+
+/// server side: damage players inside hazard zones and apply the zone's status effect on every
+/// pulse
+pub fn hazard_damage_system(
+    time: Res<Time>,
+    mut zones: Query<(&Transform, &mut HazardZone)>,
+    targets: PlayerTargets,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut status_events: EventWriter<ApplyStatusEvent>,
+) {
+    for (zone_transform, mut zone) in &mut zones {
+        zone.pulse.tick(time.delta());
+        if !zone.pulse.just_finished() {
+            continue;
+        }
+        for (entity, position) in targets.iter() {
+            // zones cover the ground, test the feet
+            if zone.contains(zone_transform.translation, position - PLAYER_CENTER_OFFSET) {
+                damage_events.send(DamageEvent {
+                    target: entity,
+                    amount: zone.kind.damage_per_pulse(),
+                    attacker: zone.owner,
+                    source_position: None,
+                    cause: DamageCause::Hazard,
+                    damage_type: DamageType::Environmental,
+                    weapon: None,
+                    headshot: false,
+                    projectile: None,
+                });
+                status_events.send(ApplyStatusEvent::new(
+                    entity,
+                    zone.kind.status(),
+                    zone.owner,
+                ));
+            }
+        }
+    }
+}
+
+/// client and server: despawn temporary hazards when their lifetime runs out
+pub fn hazard_lifetime_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut zones: Query<(Entity, &mut HazardZone)>,
+) {
+    for (entity, mut zone) in &mut zones {
+        if let Some(lifetime) = &mut zone.lifetime {
+            lifetime.tick(time.delta());
+            if lifetime.finished() {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controller::{FpsController, LandedEvent},
+    spectator::Spectator,
+    status::StatusEffects,
+    team::{self, Team, TeamSettings},
+};
+
+/// how long a push keeps the pusher responsible for an environmental death (seconds)
+pub const PUSH_ATTRIBUTION_TIMEOUT: f64 = 5.0;
+
+/// minimum strength for a push to be recorded for kill attribution
+pub const PUSH_ATTRIBUTION_THRESHOLD: f32 = 2.0;
+
+/// landing faster than this (m/s) causes fall damage
+pub const FALL_DAMAGE_MIN_SPEED: f32 = 15.0;
+
+/// fall damage per m/s above FALL_DAMAGE_MIN_SPEED
+pub const FALL_DAMAGE_PER_SPEED: f32 = 5.0;
+
+/// how long a player counts as recently damaged (hit effects on remote models, seconds)
+pub const RECENT_DAMAGE_TIME: f64 = 0.4;
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageEvent>()
+            .add_event::<DeathEvent>()
+            .add_event::<DamageDealtEvent>()
+            .add_event::<DamageTakenEvent>()
+            .add_event::<PushEvent>()
+            .init_resource::<TeamSettings>()
+            .add_system(record_push_system.before(apply_damage_system))
+            .add_system(fall_damage_system.before(apply_damage_system))
+            .add_system(apply_damage_system);
+    }
+}
+
+#[derive(Debug, Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+    /// time of the last damage taken (seconds since startup)
+    pub last_damage_time: Option<f64>,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self {
+            current: max,
+            max,
+            last_damage_time: None,
+        }
+    }
+
+    pub fn recently_damaged(&self, now: f64) -> bool {
+        matches!(self.last_damage_time, Some(time) if now - time <= RECENT_DAMAGE_TIME)
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.max;
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new(100.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DamageCause {
+    /// direct damage dealt by a weapon
+    Attack,
+    /// hazard zones (fire/acid pools)
+    Hazard,
+    /// falling: fall damage or dropping below the kill-Z
+    Fall,
+    /// squashed by a moving platform or door
+    Crushed,
+}
+
+impl DamageCause {
+    pub fn is_environmental(&self) -> bool {
+        !matches!(self, DamageCause::Attack)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DamageCause::Attack => "killed",
+            DamageCause::Hazard => "burned",
+            DamageCause::Fall => "fell",
+            DamageCause::Crushed => "crushed",
+        }
+    }
+}
+
+/// what kind of damage is dealt, decides how much armor absorbs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DamageType {
+    /// hitscan shots and other direct weapon hits
+    Bullet,
+    Explosive,
+    Fall,
+    /// hazard zones, crushing and other level damage
+    Environmental,
+}
+
+impl DamageType {
+    /// fraction of the damage armor takes instead of health
+    pub fn armor_absorption(&self) -> f32 {
+        match self {
+            DamageType::Bullet => 0.6,
+            DamageType::Explosive => 0.4,
+            DamageType::Fall | DamageType::Environmental => 0.0,
+        }
+    }
+}
+
+/// what dealt the damage, for the kill feed. None for level damage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DamageWeapon {
+    Rifle,
+    Rocket,
+    Fireball,
+    Grenade,
+    Melee,
+}
+
+impl DamageWeapon {
+    pub fn label(&self) -> &'static str {
+        match self {
+            DamageWeapon::Rifle => "rifle",
+            DamageWeapon::Rocket => "rocket",
+            DamageWeapon::Fireball => "fireball",
+            DamageWeapon::Grenade => "grenade",
+            DamageWeapon::Melee => "melee",
+        }
+    }
+}
+
+/// armor points absorbing part of the incoming damage, see DamageType::armor_absorption
+#[derive(Debug, Component)]
+pub struct Armor {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Armor {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    /// take the absorbed part of `amount` out of the armor, returns the damage left for health
+    pub fn absorb(&mut self, amount: f32, damage_type: DamageType) -> f32 {
+        let absorbed = (amount * damage_type.armor_absorption()).min(self.current);
+        self.current -= absorbed;
+        amount - absorbed
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.max;
+    }
+}
+
+impl Default for Armor {
+    fn default() -> Self {
+        Self::new(50.0)
+    }
+}
+
+/// request to damage an entity. Only has an effect on entities with a Health component.
+#[derive(Debug, Clone)]
+pub struct DamageEvent {
+    pub target: Entity,
+    pub amount: f32,
+    pub attacker: Option<Entity>,
+    /// world position the damage originates from. Directional mitigation (blocking) only
+    /// applies if this is known.
+    pub source_position: Option<Vec3>,
+    pub cause: DamageCause,
+    pub damage_type: DamageType,
+    pub weapon: Option<DamageWeapon>,
+    /// the hit landed on the head, the headshot bonus is already part of `amount`
+    pub headshot: bool,
+    /// projectile the damage comes from. Its explosion can damage several targets.
+    pub projectile: Option<Entity>,
+}
+
+/// damage an attacker dealt to someone else, after mitigation. Drives the hit confirmation
+/// sent back to the attacker.
+#[derive(Debug, Clone)]
+pub struct DamageDealtEvent {
+    pub attacker: Entity,
+    pub target: Entity,
+    pub amount: f32,
+    pub headshot: bool,
+    /// the damage killed the target
+    pub kill: bool,
+    pub cause: DamageCause,
+    pub projectile: Option<Entity>,
+}
+
+/// damage an entity took, after mitigation. Drives the damage indicator of the victim.
+#[derive(Debug, Clone)]
+pub struct DamageTakenEvent {
+    pub target: Entity,
+    pub amount: f32,
+    /// see DamageEvent
+    pub source_position: Option<Vec3>,
+}
+
+/// sent once when an entity's health drops to zero
+#[derive(Debug, Clone)]
+pub struct DeathEvent {
+    pub entity: Entity,
+    /// entity credited with the kill: the attacker for direct damage, or the last player that
+    /// pushed the victim for environmental deaths
+    pub attacker: Option<Entity>,
+    pub cause: DamageCause,
+    /// true if an environmental death was credited to the player who pushed the victim
+    pub pushed: bool,
+    /// weapon of the killing blow
+    pub weapon: Option<DamageWeapon>,
+    pub headshot: bool,
+}
+
+/// a player was knocked around by another entity (knockback, explosions, ...)
+#[derive(Debug, Clone)]
+pub struct PushEvent {
+    pub target: Entity,
+    pub pusher: Entity,
+    /// magnitude of the applied velocity change
+    pub strength: f32,
+}
+
+/// last significant push an entity received, used to credit environmental deaths
+#[derive(Debug, Component)]
+pub struct PushedBy {
+    pub pusher: Entity,
+    /// Time::seconds_since_startup of the push
+    pub time: f64,
+}
+
+pub fn record_push_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut push_events: EventReader<PushEvent>,
+) {
+    for event in push_events.iter() {
+        if event.pusher == event.target || event.strength < PUSH_ATTRIBUTION_THRESHOLD {
+            continue;
+        }
+        commands.entity(event.target).insert(PushedBy {
+            pusher: event.pusher,
+            time: time.seconds_since_startup(),
+        });
+    }
+}
+
+/// turn hard landings into fall damage
+pub fn fall_damage_system(
+    mut landed_events: EventReader<LandedEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    for event in landed_events.iter() {
+        if event.impact_speed <= FALL_DAMAGE_MIN_SPEED {
+            continue;
+        }
+        damage_events.send(DamageEvent {
+            target: event.entity,
+            amount: (event.impact_speed - FALL_DAMAGE_MIN_SPEED) * FALL_DAMAGE_PER_SPEED,
+            attacker: None,
+            source_position: None,
+            cause: DamageCause::Fall,
+            damage_type: DamageType::Fall,
+            weapon: None,
+            headshot: false,
+            projectile: None,
+        });
+    }
+}
+
+/// spectators take no damage, neither do teammates of the attacker unless friendly fire is on.
+/// Status effects (shield) scale the damage first, then blocking is applied and armor absorbs
+/// from what is left.
+#[allow(clippy::type_complexity)]
+pub fn apply_damage_system(
+    time: Res<Time>,
+    mut damage_events: EventReader<DamageEvent>,
+    mut death_events: EventWriter<DeathEvent>,
+    mut dealt_events: EventWriter<DamageDealtEvent>,
+    mut taken_events: EventWriter<DamageTakenEvent>,
+    team_settings: Res<TeamSettings>,
+    teams: Query<&Team>,
+    mut query: Query<
+        (
+            &mut Health,
+            &Transform,
+            Option<&mut FpsController>,
+            Option<&mut Armor>,
+            Option<&PushedBy>,
+            Option<&StatusEffects>,
+        ),
+        Without<Spectator>,
+    >,
+) {
+    for event in damage_events.iter() {
+        let friendly_fire = match event.attacker {
+            Some(attacker) if attacker != event.target => team::is_friendly_fire(
+                &team_settings,
+                teams.get(attacker).ok().copied(),
+                teams.get(event.target).ok().copied(),
+            ),
+            _ => false,
+        };
+        if friendly_fire {
+            continue;
+        }
+        if let Ok((mut health, transform, controller, armor, pushed_by, status)) =
+            query.get_mut(event.target)
+        {
+            if health.is_dead() {
+                continue;
+            }
+            let mut amount = event.amount;
+            if let Some(status) = status {
+                amount *= status.damage_taken_factor();
+            }
+            if let (Some(mut controller), Some(source)) = (controller, event.source_position) {
+                let factor = controller.block_factor(transform.translation, source);
+                if factor < 1.0 {
+                    // blocked damage is taken out of the shield meter instead
+                    let absorbed = amount * (1.0 - factor);
+                    controller.shield = (controller.shield - absorbed).max(0.0);
+                    amount *= factor;
+                }
+            }
+            if let Some(mut armor) = armor {
+                amount = armor.absorb(amount, event.damage_type);
+            }
+            health.current = (health.current - amount).max(0.0);
+            if amount > 0.0 {
+                health.last_damage_time = Some(time.seconds_since_startup());
+                taken_events.send(DamageTakenEvent {
+                    target: event.target,
+                    amount,
+                    source_position: event.source_position,
+                });
+            }
+            debug!(
+                "damage {:?}: {} -> {}",
+                event.target, amount, health.current
+            );
+            match event.attacker {
+                Some(attacker) if attacker != event.target && amount > 0.0 => {
+                    dealt_events.send(DamageDealtEvent {
+                        attacker,
+                        target: event.target,
+                        amount,
+                        headshot: event.headshot,
+                        kill: health.is_dead(),
+                        cause: event.cause,
+                        projectile: event.projectile,
+                    });
+                }
+                _ => (),
+            }
+            if health.is_dead() {
+                // environmental deaths without a (foreign) attacker are credited to whoever
+                // pushed the victim recently
+                let self_inflicted =
+                    event.attacker.is_none() || event.attacker == Some(event.target);
+                let recent_pusher = pushed_by
+                    .filter(|pushed_by| {
+                        time.seconds_since_startup() - pushed_by.time <= PUSH_ATTRIBUTION_TIMEOUT
+                    })
+                    .map(|pushed_by| pushed_by.pusher);
+                let (attacker, pushed) = match recent_pusher {
+                    Some(pusher) if event.cause.is_environmental() && self_inflicted => {
+                        (Some(pusher), true)
+                    }
+                    _ => (event.attacker, false),
+                };
+                death_events.send(DeathEvent {
+                    entity: event.target,
+                    attacker,
+                    cause: event.cause,
+                    pushed,
+                    weapon: event.weapon,
+                    headshot: event.headshot,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! persistent player identity: a random id the client creates on its first start and keeps in
+//! `identity.cfg`. It travels in the connect token user data next to the name, so the server
+//! can keep statistics across sessions and name changes. With unsecure authentication the
+//! server takes it on trust, it is not proof of anything.
+
+use std::{fmt, fs, io, path::Path, str::FromStr};
+
+use bevy::prelude::*;
+use bevy_renet::renet::NETCODE_USER_DATA_BYTES;
+
+pub const IDENTITY_FILE: &str = "identity.cfg";
+
+/// position of the identity in the user data, the name comes before it
+pub const IDENTITY_OFFSET: usize = NETCODE_USER_DATA_BYTES - 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlayerIdentity(u128);
+
+impl PlayerIdentity {
+    pub fn random() -> Self {
+        // zero stands for no identity in the user data
+        Self(rand::random::<u128>().max(1))
+    }
+
+    /// the identity in `path`, or a new one written there
+    pub fn load_or_create<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        match fs::read_to_string(path).map(|content| content.trim().parse()) {
+            Ok(Ok(identity)) => return identity,
+            Ok(Err(e)) => warn!("invalid identity in {}: {}", path.display(), e),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => warn!("failed to load {}: {}", path.display(), e),
+        }
+        let identity = Self::random();
+        if let Err(e) = fs::write(path, format!("{}\n", identity)) {
+            warn!("failed to save {}: {}", path.display(), e);
+        }
+        identity
+    }
+
+    /// write the identity into connect token user data holding the name
+    pub fn write_user_data(&self, user_data: &mut [u8; NETCODE_USER_DATA_BYTES]) {
+        user_data[IDENTITY_OFFSET..].copy_from_slice(&self.0.to_le_bytes());
+    }
+
+    /// the identity sent by a client, None for clients without one (bots, load tests)
+    pub fn from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Option<Self> {
+        let id = u128::from_le_bytes(user_data[IDENTITY_OFFSET..].try_into().unwrap());
+        (id != 0).then_some(Self(id))
+    }
+}
+
+impl fmt::Display for PlayerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl FromStr for PlayerIdentity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match u128::from_str_radix(s, 16) {
+            Ok(id) if id != 0 => Ok(Self(id)),
+            _ => Err(format!("invalid player identity {}", s)),
+        }
+    }
+}
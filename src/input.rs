@@ -0,0 +1,614 @@
+//! action based input map: gameplay code asks for actions, the bindings of each action
+//! (keys, mouse buttons, mouse wheel, gamepad buttons) are configurable and stored in
+//! input.toml.
+//!
+//! actions switched on and off by presses instead of being held come first, then the mouse
+//! settings (see `MouseSettings`) and the bindings. Actions missing in the file keep their
+//! default bindings:
+//!     toggles = ["Crouch", "Aim"]
+//!
+//!     [mouse]
+//!     sensitivity = 0.001
+//!     invert_y = true
+//!
+//!     [bindings]
+//!     Jump = ["Key(Space)", "Gamepad(South)"]
+//!     Attack = ["Mouse(Left)"]
+//!     Rocket = ["Wheel(Up)"]
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+use bevy::{ecs::system::SystemParam, input::mouse::MouseWheel, prelude::*};
+use serde::{Deserialize, Deserializer, Serialize};
+
+pub const INPUT_CONFIG_FILE: &str = "input.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Sprint,
+    Jump,
+    Fly,
+    Crouch,
+    Block,
+    Attack,
+    /// hitscan weapon
+    Shoot,
+    /// throw a grenade
+    Grenade,
+    /// rocket launcher
+    Rocket,
+    /// melee swing, always available
+    Melee,
+    /// aim down sights, scales the mouse sensitivity by `MouseSettings::ads_multiplier`
+    Aim,
+    /// pick up dropped weapons
+    Use,
+}
+
+impl Action {
+    pub const ALL: [Action; 18] = [
+        Action::MoveForward,
+        Action::MoveBack,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::Sprint,
+        Action::Jump,
+        Action::Fly,
+        Action::Crouch,
+        Action::Block,
+        Action::Attack,
+        Action::Shoot,
+        Action::Grenade,
+        Action::Rocket,
+        Action::Melee,
+        Action::Aim,
+        Action::Use,
+    ];
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::ALL
+            .iter()
+            .find(|action| format!("{:?}", action) == s)
+            .copied()
+            .ok_or_else(|| format!("unknown action: {}", s))
+    }
+}
+
+impl TryFrom<String> for Action {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl From<Action> for String {
+    fn from(action: Action) -> Self {
+        format!("{:?}", action)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WheelDirection {
+    Up,
+    Down,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// mouse wheel scrolled, pressed for the frame of the scroll only
+    Wheel(WheelDirection),
+    /// button on any connected gamepad
+    Gamepad(GamepadButtonType),
+}
+
+macro_rules! name_table {
+    ($ty:ident: $($name:ident),* $(,)?) => {
+        &[$((stringify!($name), $ty::$name)),*]
+    };
+}
+
+/// keys that can be bound in the config file
+const KEY_NAMES: &[(&str, KeyCode)] = name_table!(KeyCode:
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Escape, Space, Return, Tab, Back, Insert, Delete, Home, End, PageUp, PageDown,
+    Up, Down, Left, Right,
+    LShift, RShift, LControl, RControl, LAlt, RAlt,
+    Comma, Period, Minus, Equals, Semicolon, Slash, Backslash, LBracket, RBracket, Grave,
+    Apostrophe,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+);
+
+const GAMEPAD_BUTTON_NAMES: &[(&str, GamepadButtonType)] = name_table!(GamepadButtonType:
+    South, East, North, West, C, Z,
+    LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2,
+    Select, Start, Mode, LeftThumb, RightThumb,
+    DPadUp, DPadDown, DPadLeft, DPadRight,
+);
+
+fn lookup<T: Copy + PartialEq>(table: &[(&'static str, T)], name: &str) -> Option<T> {
+    table
+        .iter()
+        .find(|(entry, _)| *entry == name)
+        .map(|(_, value)| *value)
+}
+
+fn name_of<T: Copy + PartialEq>(table: &[(&'static str, T)], value: T) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(_, entry)| *entry == value)
+        .map(|(name, _)| *name)
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Key(key) => match name_of(KEY_NAMES, *key) {
+                Some(name) => write!(f, "Key({})", name),
+                None => write!(f, "Key({:?})", key),
+            },
+            Binding::Mouse(MouseButton::Other(button)) => write!(f, "Mouse({})", button),
+            Binding::Mouse(button) => write!(f, "Mouse({:?})", button),
+            Binding::Wheel(direction) => write!(f, "Wheel({:?})", direction),
+            Binding::Gamepad(button) => match name_of(GAMEPAD_BUTTON_NAMES, *button) {
+                Some(name) => write!(f, "Gamepad({})", name),
+                None => write!(f, "Gamepad({:?})", button),
+            },
+        }
+    }
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (kind, name) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| format!("invalid binding: {}", s))?;
+        let binding = match (kind.trim(), name.trim()) {
+            ("Key", name) => lookup(KEY_NAMES, name).map(Binding::Key),
+            ("Mouse", "Left") => Some(Binding::Mouse(MouseButton::Left)),
+            ("Mouse", "Right") => Some(Binding::Mouse(MouseButton::Right)),
+            ("Mouse", "Middle") => Some(Binding::Mouse(MouseButton::Middle)),
+            ("Mouse", number) => number
+                .parse()
+                .ok()
+                .map(|button| Binding::Mouse(MouseButton::Other(button))),
+            ("Wheel", "Up") => Some(Binding::Wheel(WheelDirection::Up)),
+            ("Wheel", "Down") => Some(Binding::Wheel(WheelDirection::Down)),
+            ("Gamepad", name) => lookup(GAMEPAD_BUTTON_NAMES, name).map(Binding::Gamepad),
+            _ => None,
+        };
+        binding.ok_or_else(|| format!("invalid binding: {}", s))
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(binding: String) -> Result<Self, Self::Error> {
+        binding.parse()
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+
+/// mouse look tuning, stored in the input config file
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MouseSettings {
+    /// radians per mouse count
+    pub sensitivity: f32,
+    /// per axis multiplier on top of `sensitivity`
+    pub scale: Vec2,
+    pub invert_y: bool,
+    /// sensitivity curve: the gain grows by this much per count of each raw motion event, so
+    /// fast flicks turn further than slow movements over the same distance. 0 is linear.
+    pub acceleration: f32,
+    /// upper limit of the acceleration gain
+    pub max_gain: f32,
+    /// sensitivity multiplier while aiming down sights
+    pub ads_multiplier: f32,
+    /// smoothing filter strength in [0, 1): share of the previous look rate kept per 1/60 s.
+    /// 0 disables smoothing, values above 0.99 count as 0.99.
+    pub smoothing: f32,
+}
+
+impl Default for MouseSettings {
+    fn default() -> Self {
+        Self {
+            sensitivity: 0.001,
+            scale: Vec2::ONE,
+            invert_y: false,
+            acceleration: 0.0,
+            max_gain: 4.0,
+            ads_multiplier: 0.5,
+            smoothing: 0.0,
+        }
+    }
+}
+
+impl MouseSettings {
+    /// apply the sensitivity curve to one raw motion event (counts)
+    pub fn curve(&self, raw: Vec2) -> Vec2 {
+        raw * (1.0 + self.acceleration * raw.length()).min(self.max_gain)
+    }
+
+    /// convert accumulated counts to view angle deltas (radians)
+    pub fn scale(&self, counts: Vec2, aiming: bool) -> Vec2 {
+        let mut delta = counts * self.scale * self.sensitivity;
+        if self.invert_y {
+            delta.y = -delta.y;
+        }
+        if aiming {
+            delta *= self.ads_multiplier;
+        }
+        delta
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputMap {
+    #[serde(skip)]
+    pub enable_input: bool,
+    /// actions switched on and off by presses instead of being held
+    toggles: BTreeSet<Action>,
+    pub mouse: MouseSettings,
+    #[serde(deserialize_with = "deserialize_bindings")]
+    bindings: BTreeMap<Action, Vec<Binding>>,
+}
+
+/// the bindings in the file replace the default bindings of their actions only
+fn deserialize_bindings<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<Action, Vec<Binding>>, D::Error> {
+    let mut bindings = InputMap::default().bindings;
+    bindings.extend(BTreeMap::<Action, Vec<Binding>>::deserialize(deserializer)?);
+    Ok(bindings)
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let bindings = [
+            (
+                Action::MoveForward,
+                vec![Binding::Key(KeyCode::W), Binding::Key(KeyCode::Up)],
+            ),
+            (
+                Action::MoveBack,
+                vec![Binding::Key(KeyCode::S), Binding::Key(KeyCode::Down)],
+            ),
+            (
+                Action::MoveLeft,
+                vec![Binding::Key(KeyCode::A), Binding::Key(KeyCode::Left)],
+            ),
+            (
+                Action::MoveRight,
+                vec![Binding::Key(KeyCode::D), Binding::Key(KeyCode::Right)],
+            ),
+            (Action::MoveUp, vec![Binding::Key(KeyCode::Q)]),
+            (Action::MoveDown, vec![Binding::Key(KeyCode::E)]),
+            (
+                Action::Sprint,
+                vec![
+                    Binding::Key(KeyCode::LShift),
+                    Binding::Gamepad(GamepadButtonType::LeftThumb),
+                ],
+            ),
+            (
+                Action::Jump,
+                vec![
+                    Binding::Key(KeyCode::Space),
+                    Binding::Gamepad(GamepadButtonType::South),
+                ],
+            ),
+            (Action::Fly, vec![Binding::Key(KeyCode::F)]),
+            (
+                Action::Crouch,
+                vec![
+                    Binding::Key(KeyCode::LControl),
+                    Binding::Gamepad(GamepadButtonType::East),
+                ],
+            ),
+            (
+                Action::Block,
+                vec![
+                    Binding::Key(KeyCode::V),
+                    Binding::Mouse(MouseButton::Right),
+                    Binding::Gamepad(GamepadButtonType::LeftTrigger2),
+                ],
+            ),
+            (
+                Action::Attack,
+                vec![
+                    Binding::Mouse(MouseButton::Left),
+                    Binding::Gamepad(GamepadButtonType::RightTrigger2),
+                ],
+            ),
+            (
+                Action::Shoot,
+                vec![
+                    Binding::Key(KeyCode::R),
+                    Binding::Gamepad(GamepadButtonType::RightTrigger),
+                ],
+            ),
+            (
+                Action::Grenade,
+                vec![
+                    Binding::Key(KeyCode::G),
+                    Binding::Gamepad(GamepadButtonType::North),
+                ],
+            ),
+            (
+                Action::Rocket,
+                vec![
+                    Binding::Key(KeyCode::T),
+                    Binding::Gamepad(GamepadButtonType::West),
+                ],
+            ),
+            (
+                Action::Melee,
+                vec![
+                    Binding::Key(KeyCode::C),
+                    Binding::Gamepad(GamepadButtonType::RightThumb),
+                ],
+            ),
+            (
+                Action::Aim,
+                vec![
+                    Binding::Mouse(MouseButton::Middle),
+                    Binding::Gamepad(GamepadButtonType::LeftTrigger),
+                ],
+            ),
+            (
+                Action::Use,
+                vec![
+                    Binding::Key(KeyCode::X),
+                    Binding::Gamepad(GamepadButtonType::DPadUp),
+                ],
+            ),
+        ];
+        Self {
+            enable_input: true,
+            mouse: MouseSettings::default(),
+            bindings: bindings.into_iter().collect(),
+            toggles: BTreeSet::new(),
+        }
+    }
+}
+
+impl InputMap {
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// add a binding to `action`, removing it from any other action
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        for bindings in self.bindings.values_mut() {
+            bindings.retain(|existing| *existing != binding);
+        }
+        self.bindings.entry(action).or_default().push(binding);
+    }
+
+    /// unbind `action`, kept as an empty list so the default bindings don't come back on load
+    pub fn clear(&mut self, action: Action) {
+        self.bindings.insert(action, Vec::new());
+    }
+
+    pub fn is_toggle(&self, action: Action) -> bool {
+        self.toggles.contains(&action)
+    }
+
+    /// switch `action` between toggle and hold mode
+    pub fn set_toggle(&mut self, action: Action, toggle: bool) {
+        if toggle {
+            self.toggles.insert(action);
+        } else {
+            self.toggles.remove(&action);
+        }
+    }
+}
+
+/// button state of all input devices
+#[derive(SystemParam)]
+pub struct ButtonInput<'w, 's> {
+    keys: Res<'w, Input<KeyCode>>,
+    mouse_buttons: Res<'w, Input<MouseButton>>,
+    gamepad_buttons: Res<'w, Input<GamepadButton>>,
+    gamepads: Res<'w, Gamepads>,
+    wheel: Res<'w, WheelInput>,
+    #[system_param(ignore)]
+    _marker: std::marker::PhantomData<&'s ()>,
+}
+
+impl<'w, 's> ButtonInput<'w, 's> {
+    fn binding_pressed(&self, binding: Binding, just: bool) -> bool {
+        match binding {
+            Binding::Key(key) if just => self.keys.just_pressed(key),
+            Binding::Key(key) => self.keys.pressed(key),
+            Binding::Mouse(button) if just => self.mouse_buttons.just_pressed(button),
+            Binding::Mouse(button) => self.mouse_buttons.pressed(button),
+            Binding::Wheel(direction) => self.wheel.scrolled(direction),
+            Binding::Gamepad(button_type) => self.gamepads.iter().any(|gamepad| {
+                let button = GamepadButton(*gamepad, button_type);
+                if just {
+                    self.gamepad_buttons.just_pressed(button)
+                } else {
+                    self.gamepad_buttons.pressed(button)
+                }
+            }),
+        }
+    }
+
+    /// any binding of `action` pressed (or just pressed), ignoring toggle mode
+    fn action_pressed(&self, map: &InputMap, action: Action, just: bool) -> bool {
+        map.enable_input
+            && map
+                .bindings(action)
+                .iter()
+                .any(|binding| self.binding_pressed(*binding, just))
+    }
+}
+
+/// mouse wheel directions scrolled this frame
+#[derive(Debug, Default)]
+pub struct WheelInput {
+    up: bool,
+    down: bool,
+}
+
+impl WheelInput {
+    pub fn scrolled(&self, direction: WheelDirection) -> bool {
+        match direction {
+            WheelDirection::Up => self.up,
+            WheelDirection::Down => self.down,
+        }
+    }
+}
+
+/// collect the mouse wheel events of this frame. Runs before the systems reading actions.
+pub fn update_wheel_input_system(
+    mut events: EventReader<MouseWheel>,
+    mut wheel: ResMut<WheelInput>,
+) {
+    let mut scrolled = WheelInput::default();
+    for event in events.iter() {
+        scrolled.up |= event.y > 0.0;
+        scrolled.down |= event.y < 0.0;
+    }
+    if wheel.up != scrolled.up || wheel.down != scrolled.down {
+        *wheel = scrolled;
+    }
+}
+
+/// on/off state of the actions in toggle mode
+#[derive(Debug, Default)]
+pub struct ActionToggles {
+    active: HashSet<Action>,
+}
+
+/// flip toggle mode actions on each press. Runs before the systems reading actions.
+pub fn update_action_toggles_system(
+    map: Res<InputMap>,
+    buttons: ButtonInput,
+    mut toggles: ResMut<ActionToggles>,
+) {
+    if !map.enable_input {
+        return;
+    }
+    // actions switched back to hold mode do not stay on
+    toggles.active.retain(|action| map.is_toggle(*action));
+    for action in Action::ALL {
+        if map.is_toggle(action) && buttons.action_pressed(&map, action, true) {
+            if !toggles.active.remove(&action) {
+                toggles.active.insert(action);
+            }
+        }
+    }
+}
+
+/// input devices combined with the input map
+#[derive(SystemParam)]
+pub struct ActionInput<'w, 's> {
+    pub map: Res<'w, InputMap>,
+    buttons: ButtonInput<'w, 's>,
+    toggles: Res<'w, ActionToggles>,
+    gamepad_axes: Res<'w, Axis<GamepadAxis>>,
+}
+
+impl<'w, 's> ActionInput<'w, 's> {
+    /// held down, or switched on for actions in toggle mode
+    pub fn pressed(&self, action: Action) -> bool {
+        if self.map.is_toggle(action) {
+            self.map.enable_input && self.toggles.active.contains(&action)
+        } else {
+            self.buttons.action_pressed(&self.map, action, false)
+        }
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.buttons.action_pressed(&self.map, action, true)
+    }
+
+    /// -1, 0 or 1 from two opposing actions
+    pub fn axis(&self, positive: Action, negative: Action) -> f32 {
+        self.pressed(positive) as i8 as f32 - self.pressed(negative) as i8 as f32
+    }
+
+    /// left stick of the first gamepad with the stick out of its dead zone
+    pub fn left_stick(&self) -> Vec2 {
+        if !self.map.enable_input {
+            return Vec2::ZERO;
+        }
+        self.buttons
+            .gamepads
+            .iter()
+            .map(|gamepad| {
+                Vec2::new(
+                    self.gamepad_axes
+                        .get(GamepadAxis(*gamepad, GamepadAxisType::LeftStickX))
+                        .unwrap_or(0.0),
+                    self.gamepad_axes
+                        .get(GamepadAxis(*gamepad, GamepadAxisType::LeftStickY))
+                        .unwrap_or(0.0),
+                )
+            })
+            .find(|stick| *stick != Vec2::ZERO)
+            .unwrap_or(Vec2::ZERO)
+    }
+}
+
+/// first key, mouse button, wheel direction or gamepad button pressed this frame (for
+/// rebinding)
+pub fn any_just_pressed(
+    keys: &Input<KeyCode>,
+    mouse_buttons: &Input<MouseButton>,
+    wheel: &WheelInput,
+    gamepad_buttons: &Input<GamepadButton>,
+) -> Option<Binding> {
+    if let Some(key) = keys.get_just_pressed().next() {
+        return Some(Binding::Key(*key));
+    }
+    if let Some(button) = mouse_buttons.get_just_pressed().next() {
+        return Some(Binding::Mouse(*button));
+    }
+    if let Some(direction) = [WheelDirection::Up, WheelDirection::Down]
+        .into_iter()
+        .find(|direction| wheel.scrolled(*direction))
+    {
+        return Some(Binding::Wheel(direction));
+    }
+    gamepad_buttons
+        .get_just_pressed()
+        .next()
+        .map(|button| Binding::Gamepad(button.1))
+}
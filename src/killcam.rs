@@ -0,0 +1,105 @@
+//! kill cam: after being killed by another player the client replays the last KILL_CAM_TIME
+//! seconds through the eyes of the killer, then returns to the own (respawned) or spectator
+//! view. The replay comes from a short history of the replicated positions and view angles of
+//! the remote players, which the client records anyway for drawing them.
+
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+
+use crate::controller::{look_quat, EYE_HEIGHT};
+
+/// seconds replayed by the kill cam
+pub const KILL_CAM_TIME: f64 = 3.0;
+
+/// seconds of view history kept per player, a bit more than the kill cam needs
+const HISTORY_TIME: f64 = KILL_CAM_TIME + 0.5;
+
+/// view of a remote player at local time `time`, `position` at the feet
+#[derive(Debug, Clone, Copy)]
+pub struct ViewSample {
+    pub time: f64,
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+/// client: recent views of the remote players by client id, oldest first
+#[derive(Debug, Default)]
+pub struct ViewHistory {
+    players: HashMap<u64, VecDeque<ViewSample>>,
+}
+
+impl ViewHistory {
+    /// add the newest view of `player` and drop what the kill cam won't need anymore
+    pub fn record(&mut self, player: u64, sample: ViewSample) {
+        let samples = self.players.entry(player).or_default();
+        samples.push_back(sample);
+        while samples
+            .front()
+            .map_or(false, |oldest| sample.time - oldest.time > HISTORY_TIME)
+        {
+            samples.pop_front();
+        }
+    }
+
+    /// forget players who left
+    pub fn retain_players(&mut self, connected: impl Fn(u64) -> bool) {
+        self.players.retain(|player, _| connected(*player));
+    }
+
+    /// eye transform of `player` at `time`, interpolated between the recorded views. Clamped to
+    /// the oldest and newest view, None without any.
+    pub fn eye_at(&self, player: u64, time: f64) -> Option<Transform> {
+        let samples = self.players.get(&player)?;
+        let next = samples.iter().position(|sample| sample.time >= time);
+        let (a, b) = match next {
+            Some(0) => (samples.front()?, samples.front()?),
+            Some(index) => (&samples[index - 1], &samples[index]),
+            None => (samples.back()?, samples.back()?),
+        };
+        let span = b.time - a.time;
+        let t = if span > 0.0 {
+            ((time - a.time) / span) as f32
+        } else {
+            0.0
+        };
+        // yaw takes the short way around
+        let yaw_delta = (b.yaw - a.yaw + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU)
+            - std::f32::consts::PI;
+        let position = a.position.lerp(b.position, t) + Vec3::Y * EYE_HEIGHT;
+        let rotation = look_quat(a.pitch + (b.pitch - a.pitch) * t, a.yaw + yaw_delta * t);
+        Some(Transform::from_translation(position).with_rotation(rotation))
+    }
+}
+
+/// client: the running kill cam
+#[derive(Debug, Default)]
+pub struct KillCam {
+    /// client id of the killer while the kill cam runs
+    pub killer: Option<u64>,
+    /// local time the kill cam ends
+    pub until: f64,
+}
+
+impl KillCam {
+    /// replay the view of `killer` at the local time `now` of the death
+    pub fn start(&mut self, killer: u64, now: f64) {
+        self.killer = Some(killer);
+        self.until = now + KILL_CAM_TIME;
+    }
+
+    pub fn stop(&mut self) {
+        self.killer = None;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.killer.is_some()
+    }
+
+    /// the time in the view history shown at the local time `now`: the replay runs in real time
+    /// and ends at the moment of death
+    pub fn replay_time(&self, now: f64) -> f64 {
+        now - KILL_CAM_TIME
+    }
+}
@@ -0,0 +1,84 @@
+//! kill feed: the server broadcasts every player death, clients list the latest ones in a
+//! corner of the screen and let them fade out.
+
+use serde::{Deserialize, Serialize};
+
+use crate::health::{DamageCause, DamageWeapon};
+
+/// entries shown at once, older ones are dropped
+pub const KILL_FEED_ENTRIES: usize = 5;
+
+/// seconds an entry stays visible
+pub const KILL_FEED_TIME: f64 = 6.0;
+
+/// entries fade out over the last seconds of their time
+pub const KILL_FEED_FADE_TIME: f64 = 1.5;
+
+/// a player died. Players are referred to by client id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KillFeedEvent {
+    /// player credited with the kill, None for accidents and suicides
+    pub attacker: Option<u64>,
+    pub victim: u64,
+    pub cause: DamageCause,
+    pub weapon: Option<DamageWeapon>,
+    pub headshot: bool,
+    /// an environmental death credited to the player who pushed the victim
+    pub pushed: bool,
+}
+
+impl KillFeedEvent {
+    /// one feed line, `name` resolves client ids to player names
+    pub fn text(&self, name: impl Fn(u64) -> String) -> String {
+        let victim = name(self.victim);
+        let attacker = match self.attacker {
+            Some(attacker) if attacker != self.victim => name(attacker),
+            _ => return format!("{} {}", victim, self.cause.label()),
+        };
+        let mut how = match (self.weapon, self.pushed) {
+            (_, true) => format!("{} (pushed)", self.cause.label()),
+            (Some(weapon), false) => format!("[{}]", weapon.label()),
+            (None, false) => self.cause.label().to_string(),
+        };
+        if self.headshot {
+            how.push_str(" headshot");
+        }
+        format!("{} {} {}", attacker, how, victim)
+    }
+}
+
+struct KillFeedEntry {
+    /// local time the entry arrived
+    time: f64,
+    event: KillFeedEvent,
+}
+
+/// client: recent kills, newest last
+#[derive(Default)]
+pub struct KillFeed {
+    entries: Vec<KillFeedEntry>,
+}
+
+impl KillFeed {
+    pub fn push(&mut self, time: f64, event: KillFeedEvent) {
+        self.entries.push(KillFeedEntry { time, event });
+        if self.entries.len() > KILL_FEED_ENTRIES {
+            self.entries.remove(0);
+        }
+    }
+
+    /// drop expired entries
+    pub fn expire(&mut self, now: f64) {
+        self.entries
+            .retain(|entry| now - entry.time <= KILL_FEED_TIME);
+    }
+
+    /// visible entries with their opacity (0..1)
+    pub fn visible(&self, now: f64) -> impl Iterator<Item = (&KillFeedEvent, f32)> {
+        self.entries.iter().map(move |entry| {
+            let left = KILL_FEED_TIME - (now - entry.time);
+            let alpha = (left / KILL_FEED_FADE_TIME).clamp(0.0, 1.0) as f32;
+            (&entry.event, alpha)
+        })
+    }
+}
@@ -0,0 +1,93 @@
+//! per client input latency on the server, split into network latency and input queue time.
+//!
+//! Client and server clocks are not synchronized, so the one way network latency of an input
+//! is estimated as half the round trip time plus its delay compared to the fastest input seen
+//! (jitter). Queue time is measured on the server clock from receiving an input to the
+//! simulation applying it.
+
+use std::collections::{HashMap, VecDeque};
+
+/// samples kept per client
+const MAX_SAMPLES: usize = 600;
+
+/// latency of one applied input (seconds)
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySample {
+    pub network: f64,
+    pub queue: f64,
+}
+
+impl LatencySample {
+    pub fn total(&self) -> f64 {
+        self.network + self.queue
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ClientInputLatency {
+    /// received, not yet applied inputs: (serial, receive - send time, receive time)
+    pending: Vec<(u32, f64, f64)>,
+    /// smallest receive - send time seen: clock offset plus the minimal one way latency
+    min_transit: Option<f64>,
+    samples: VecDeque<LatencySample>,
+}
+
+impl ClientInputLatency {
+    /// an input sent at `sent_at` (client clock) arrived at `received_at` (server clock)
+    pub fn received(&mut self, serial: u32, sent_at: f64, received_at: f64) {
+        let transit = received_at - sent_at;
+        self.min_transit = Some(self.min_transit.map_or(transit, |min| min.min(transit)));
+        self.pending.push((serial, transit, received_at));
+    }
+
+    /// all inputs up to `serial` were applied at `applied_at`. `rtt` in seconds.
+    pub fn applied(&mut self, serial: u32, applied_at: f64, rtt: f64) {
+        let min_transit = match self.min_transit {
+            Some(min_transit) => min_transit,
+            None => return,
+        };
+        let samples = &mut self.samples;
+        self.pending
+            .retain(|&(pending_serial, transit, received_at)| {
+                if pending_serial > serial {
+                    return true;
+                }
+                samples.push_back(LatencySample {
+                    network: rtt * 0.5 + (transit - min_transit),
+                    queue: applied_at - received_at,
+                });
+                false
+            });
+        while self.samples.len() > MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    /// nearest rank percentiles (0-100) of a sample value, None without samples
+    pub fn percentiles(
+        &self,
+        value: impl Fn(&LatencySample) -> f64,
+        percentiles: &[f64],
+    ) -> Option<Vec<f64>> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut values: Vec<f64> = self.samples.iter().map(value).collect();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        Some(
+            percentiles
+                .iter()
+                .map(|p| {
+                    let rank = (p / 100.0 * values.len() as f64).ceil() as usize;
+                    values[rank.clamp(1, values.len()) - 1]
+                })
+                .collect(),
+        )
+    }
+}
+
+/// server resource: input latency by client id
+#[derive(Debug, Default)]
+pub struct InputLatency {
+    pub clients: HashMap<u64, ClientInputLatency>,
+}
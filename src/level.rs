@@ -0,0 +1,502 @@
+use std::collections::HashSet;
+
+use bevy::{ecs::query::WorldQuery, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{mapfile, projectile::Ballistic, setup_level, testmap, Projectile};
+
+/// level layout, selected with `--map=<name>`. Clients switch to the map of the server, see
+/// CurrentLevel. Serialized as the map name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum LevelKind {
+    Default,
+    /// procedural movement test map, see `testmap`
+    MovementTest,
+    /// map file (glTF or Quake .map) in mapfile::MAPS_DIR, by file name without extension
+    File(String),
+}
+
+impl Default for LevelKind {
+    fn default() -> Self {
+        LevelKind::Default
+    }
+}
+
+impl std::str::FromStr for LevelKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(LevelKind::Default),
+            "movement_test" => Ok(LevelKind::MovementTest),
+            _ if !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') =>
+            {
+                Ok(LevelKind::File(s.to_string()))
+            }
+            _ => Err(format!(
+                "invalid map: {:?} (default, movement_test or a map file name)",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for LevelKind {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl From<LevelKind> for String {
+    fn from(map: LevelKind) -> Self {
+        map.name().to_string()
+    }
+}
+
+impl LevelKind {
+    pub fn name(&self) -> &str {
+        match self {
+            LevelKind::Default => "default",
+            LevelKind::MovementTest => "movement_test",
+            LevelKind::File(name) => name,
+        }
+    }
+
+    /// the map given with `--map=<name>`, if any
+    pub fn arg() -> Option<Self> {
+        std::env::args().skip(1).find_map(|arg| {
+            arg.strip_prefix("--map=").map(|map| {
+                map.parse().unwrap_or_else(|e| {
+                    warn!("{}", e);
+                    LevelKind::default()
+                })
+            })
+        })
+    }
+
+    pub fn from_args() -> Self {
+        Self::arg().unwrap_or_default()
+    }
+
+    /// load this map with the first update, CurrentLevel switches maps later on
+    pub fn add_setup_system(self, app: &mut App) {
+        app.insert_resource(CurrentLevel::new(self))
+            .add_system(load_level_system.exclusive_system().at_start());
+    }
+
+    fn setup(&self, world: &mut World) {
+        match self {
+            LevelKind::Default => run_once(world, setup_level),
+            LevelKind::MovementTest => run_once(world, testmap::setup_movement_test_level),
+            LevelKind::File(name) => {
+                let map = match mapfile::find_map_file(name) {
+                    Some(path) => mapfile::load_map_file(&path),
+                    None => Err(format!("no file for map {} in {}", name, mapfile::MAPS_DIR)),
+                };
+                match map {
+                    Ok(map) => run_once(
+                        world,
+                        move |mut commands: Commands,
+                              mut meshes: ResMut<Assets<Mesh>>,
+                              mut materials: ResMut<Assets<StandardMaterial>>| {
+                            mapfile::spawn_map(&mut commands, &mut meshes, &mut materials, &map);
+                        },
+                    ),
+                    Err(e) => {
+                        // keep something to stand on
+                        error!("failed to load map {}: {}", name, e);
+                        run_once(world, setup_level);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn run_once<Params>(world: &mut World, system: impl IntoSystem<(), (), Params>) {
+    let mut system = IntoSystem::into_system(system);
+    system.initialize(world);
+    system.run((), world);
+    system.apply_buffers(world);
+}
+
+/// everything spawned by the setup of a map (top level entities), removed on a map change
+#[derive(Component)]
+pub struct LevelEntity;
+
+/// the map to play, setting `map` replaces the loaded level with the next update
+pub struct CurrentLevel {
+    pub map: LevelKind,
+    loaded: Option<LevelKind>,
+    reload: bool,
+    generation: u32,
+}
+
+impl CurrentLevel {
+    pub fn new(map: LevelKind) -> Self {
+        Self {
+            map,
+            loaded: None,
+            reload: false,
+            generation: 0,
+        }
+    }
+
+    /// the map whose level is spawned, None before the first load
+    pub fn loaded(&self) -> Option<&LevelKind> {
+        self.loaded.as_ref()
+    }
+
+    /// load the level of `map` again with the next update, e.g. after its file changed
+    pub fn reload(&mut self) {
+        self.reload = true;
+    }
+
+    /// counts level loads: changes with every map change and reload
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+/// replace the loaded level when CurrentLevel changed or is reloaded: despawn the old level entities, run the
+/// setup of the new map and mark everything it spawned
+pub fn load_level_system(world: &mut World) {
+    let current = world.resource::<CurrentLevel>();
+    if current.loaded.as_ref() == Some(&current.map) && !current.reload {
+        return;
+    }
+    let map = current.map.clone();
+    let old: Vec<Entity> = world
+        .query_filtered::<Entity, With<LevelEntity>>()
+        .iter(world)
+        .collect();
+    for entity in old {
+        world.entity_mut(entity).despawn_recursive();
+    }
+    let before: HashSet<Entity> = world.query::<Entity>().iter(world).collect();
+    map.setup(world);
+    let spawned: Vec<Entity> = world
+        .query_filtered::<Entity, Without<Parent>>()
+        .iter(world)
+        .filter(|entity| !before.contains(entity))
+        .collect();
+    for entity in spawned {
+        world.entity_mut(entity).insert(LevelEntity);
+    }
+    info!("Loaded map {}.", map.name());
+    let mut current = world.resource_mut::<CurrentLevel>();
+    current.loaded = Some(map);
+    current.reload = false;
+    current.generation += 1;
+}
+
+/// axis aligned box volume overriding the gravity of players inside.
+/// A gravity of (nearly) zero switches players to fly-style movement.
+#[derive(Debug, Component)]
+pub struct GravityZone {
+    pub half_extents: Vec3,
+    pub gravity: f32,
+}
+
+impl GravityZone {
+    pub fn contains(&self, center: Vec3, point: Vec3) -> bool {
+        let offset = (point - center).abs();
+        offset.x <= self.half_extents.x
+            && offset.y <= self.half_extents.y
+            && offset.z <= self.half_extents.z
+    }
+}
+
+/// box volume launching players with a fixed velocity
+#[derive(Debug, Component)]
+pub struct JumpPad {
+    pub half_extents: Vec3,
+    pub launch_velocity: Vec3,
+}
+
+impl JumpPad {
+    pub fn contains(&self, center: Vec3, point: Vec3) -> bool {
+        let offset = (point - center).abs();
+        offset.x <= self.half_extents.x
+            && offset.y <= self.half_extents.y
+            && offset.z <= self.half_extents.z
+    }
+}
+
+/// time after a launch during which jump pads are ignored (seconds)
+pub const JUMP_PAD_LOCKOUT: f32 = 0.5;
+
+/// sent by the movement simulation when a player is launched by a jump pad
+#[derive(Debug, Clone)]
+pub struct JumpPadEvent {
+    pub player: Entity,
+    pub pad: Entity,
+    pub position: Vec3,
+}
+
+/// teleporter entrance: a box volume (oriented by the entity transform) moving everything
+/// entering it to the `exit` entity. Velocity and view yaw are rotated by the yaw difference
+/// between entrance and exit. Exits must not be placed inside entrances.
+#[derive(Debug, Component)]
+pub struct Teleporter {
+    pub half_extents: Vec3,
+    pub exit: Entity,
+}
+
+/// teleporter exit, players leave it facing the -Z axis of its transform
+#[derive(Debug, Component)]
+pub struct TeleporterExit;
+
+impl Teleporter {
+    pub fn contains(&self, entrance: &Transform, point: Vec3) -> bool {
+        let offset = (entrance.rotation.inverse() * (point - entrance.translation)).abs();
+        offset.x <= self.half_extents.x
+            && offset.y <= self.half_extents.y
+            && offset.z <= self.half_extents.z
+    }
+}
+
+/// sent when a player or projectile went through a teleporter
+#[derive(Debug, Clone)]
+pub struct TeleportEvent {
+    pub entity: Entity,
+    pub teleporter: Entity,
+    pub from: Vec3,
+    pub to: Vec3,
+}
+
+/// result of entering a teleporter
+#[derive(Debug, Clone, Copy)]
+pub struct TeleportDestination {
+    pub teleporter: Entity,
+    pub translation: Vec3,
+    /// yaw difference between entrance and exit, to be applied to velocity and view
+    pub yaw_delta: f32,
+}
+
+impl TeleportDestination {
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        Quat::from_rotation_y(self.yaw_delta) * v
+    }
+}
+
+fn yaw_of(rotation: Quat) -> f32 {
+    rotation.to_euler(EulerRot::YXZ).0
+}
+
+/// destination of the first teleporter entrance containing `position`
+pub fn teleport_destination<'a, F: WorldQuery>(
+    teleporters: impl IntoIterator<Item = (Entity, &'a Transform, &'a Teleporter)>,
+    exits: &Query<&Transform, F>,
+    position: Vec3,
+) -> Option<TeleportDestination> {
+    teleporters
+        .into_iter()
+        .filter(|(_, transform, teleporter)| teleporter.contains(transform, position))
+        .find_map(|(entity, transform, teleporter)| {
+            let exit = exits.get(teleporter.exit).ok()?;
+            Some(TeleportDestination {
+                teleporter: entity,
+                translation: exit.translation,
+                yaw_delta: yaw_of(exit.rotation) - yaw_of(transform.rotation),
+            })
+        })
+}
+
+/// server side: move projectiles through teleporters. Clients follow through the regular
+/// network frame updates.
+pub fn teleport_projectiles_system(
+    mut projectiles: Query<(Entity, &mut Transform, &mut Ballistic), With<Projectile>>,
+    teleporters: Query<(Entity, &Transform, &Teleporter), Without<Projectile>>,
+    exits: Query<&Transform, (With<TeleporterExit>, Without<Projectile>)>,
+    mut teleport_events: EventWriter<TeleportEvent>,
+) {
+    for (entity, mut transform, mut ballistic) in &mut projectiles {
+        let from = transform.translation;
+        if let Some(destination) = teleport_destination(&teleporters, &exits, from) {
+            // keep the projectile's height above the exit
+            transform.translation = destination.translation + Vec3::Y * 0.5;
+            ballistic.velocity = destination.rotate(ballistic.velocity);
+            teleport_events.send(TeleportEvent {
+                entity,
+                teleporter: destination.teleporter,
+                from,
+                to: transform.translation,
+            });
+        }
+    }
+}
+
+/// limits of the playable space. Players falling below kill_z die (DamageCause::Fall) and
+/// props are removed.
+pub struct WorldBounds {
+    pub kill_z: f32,
+}
+
+impl Default for WorldBounds {
+    fn default() -> Self {
+        Self { kill_z: -20.0 }
+    }
+}
+
+/// surface material of a level collider. Hitscan shots pass through thin layers of the soft
+/// materials and lose damage on the way, colliders without a material stop every shot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Component)]
+pub enum SurfaceMaterial {
+    Concrete,
+    Metal,
+    Wood,
+    Glass,
+}
+
+impl SurfaceMaterial {
+    /// thickest layer a shot can pass through (m), 0 for impenetrable materials
+    pub fn max_penetration(&self) -> f32 {
+        match self {
+            SurfaceMaterial::Concrete => 0.0,
+            SurfaceMaterial::Metal => 0.05,
+            SurfaceMaterial::Wood => 0.3,
+            SurfaceMaterial::Glass => 0.1,
+        }
+    }
+
+    /// fraction of the damage a shot keeps after passing through one layer
+    pub fn damage_kept(&self) -> f32 {
+        match self {
+            SurfaceMaterial::Concrete => 0.0,
+            SurfaceMaterial::Metal => 0.5,
+            SurfaceMaterial::Wood => 0.7,
+            SurfaceMaterial::Glass => 0.9,
+        }
+    }
+
+    /// color of bullet hole decals
+    pub fn decal_color(&self) -> Color {
+        match self {
+            SurfaceMaterial::Concrete => Color::rgb(0.15, 0.15, 0.15),
+            SurfaceMaterial::Metal => Color::rgb(0.35, 0.35, 0.4),
+            SurfaceMaterial::Wood => Color::rgb(0.3, 0.2, 0.1),
+            SurfaceMaterial::Glass => Color::rgba(0.9, 0.95, 1.0, 0.7),
+        }
+    }
+}
+
+/// activation of a level device, replicated to clients so they can play effects
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum LevelEvent {
+    JumpPad {
+        position: Vec3,
+    },
+    Teleport {
+        from: Vec3,
+        to: Vec3,
+    },
+    /// a usable (identified by its rest position) was switched
+    Use {
+        position: Vec3,
+        active: bool,
+    },
+}
+
+/// gravity at `position`: the first zone containing it, or `default_gravity`
+pub fn gravity_at<'a>(
+    zones: impl IntoIterator<Item = (&'a Transform, &'a GravityZone)>,
+    position: Vec3,
+    default_gravity: f32,
+) -> f32 {
+    zones
+        .into_iter()
+        .find(|(transform, zone)| zone.contains(transform.translation, position))
+        .map_or(default_gravity, |(_, zone)| zone.gravity)
+}
+
+pub fn spawn_jump_pad(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    translation: Vec3,
+    pad: JumpPad,
+) -> Entity {
+    let size = pad.half_extents * 2.0;
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(size.x, 0.05, size.z))),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.1, 0.8, 0.9),
+                unlit: true,
+                ..default()
+            }),
+            transform: Transform::from_translation(translation),
+            ..default()
+        })
+        .insert(pad)
+        .id()
+}
+
+/// spawn a linked teleporter entrance and exit, returns the entrance
+pub fn spawn_teleporter(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    entrance: Transform,
+    half_extents: Vec3,
+    exit: Transform,
+) -> Entity {
+    let exit = commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(0.8, 0.05, 0.8))),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.8, 0.3, 1.0),
+                unlit: true,
+                ..default()
+            }),
+            transform: exit,
+            ..default()
+        })
+        .insert(TeleporterExit)
+        .id();
+    let size = half_extents * 2.0;
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(size.x, size.y, size.z))),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgba(0.8, 0.3, 1.0, 0.4),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            }),
+            transform: entrance,
+            ..default()
+        })
+        .insert(Teleporter { half_extents, exit })
+        .id()
+}
+
+pub fn spawn_gravity_zone(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    translation: Vec3,
+    zone: GravityZone,
+) -> Entity {
+    let size = zone.half_extents * 2.0;
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(size.x, size.y, size.z))),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgba(0.5, 0.5, 1.0, 0.15),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            }),
+            transform: Transform::from_translation(translation),
+            ..default()
+        })
+        .insert(zone)
+        .id()
+}
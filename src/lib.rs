@@ -6,20 +6,98 @@ use bevy_renet::renet::{
     ChannelConfig, ReliableChannelConfig, RenetConnectionConfig, UnreliableChannelConfig,
     NETCODE_KEY_BYTES,
 };
+use control::ControlPointState;
+use controller::{MovementEventKind, MovementSettings};
+use flag::FlagState;
+use gamemode::{GameModeKind, MatchPhase, MatchSummary, Score};
+use hazard::{spawn_hazard_zone, HazardKind, HazardZone};
+use health::DamageCause;
+use killfeed::KillFeedEvent;
+use level::{
+    spawn_gravity_zone, spawn_jump_pad, spawn_teleporter, GravityZone, JumpPad, LevelEvent,
+    LevelKind, SurfaceMaterial,
+};
+use mapfile::MapManifest;
+use pickup::{spawn_pickup, PickupKind};
+use pool::{EntityPools, Pooled};
+use projectile::{Ballistic, ProjectileKinds, ProjectileSettings};
 use serde::{Deserialize, Serialize};
+use team::Team;
+use vote::{VoteKind, VoteState};
+use weapon::{Inventory, SurfaceImpact, WeaponKind};
 
+pub mod animation;
+pub mod audio;
+pub mod bot;
 pub mod camera;
+pub mod character;
+pub mod config;
+pub mod control;
 pub mod controller;
+pub mod crosshair;
+pub mod effects;
+pub mod flag;
+pub mod gamemode;
+pub mod gltf_map;
+pub mod hazard;
+pub mod health;
+pub mod identity;
+pub mod input;
+pub mod killcam;
+pub mod killfeed;
+pub mod latency;
+pub mod level;
+pub mod lod;
+pub mod logging;
+pub mod mapfile;
+pub mod mapvote;
+pub mod metrics;
+pub mod minimap;
+pub mod mover;
+pub mod names;
+pub mod netcheck;
+pub mod perf;
+pub mod pickup;
+pub mod pool;
+pub mod practice;
 pub mod predict;
+pub mod prefab;
+pub mod projectile;
+pub mod quake_map;
+pub mod ragdoll;
+pub mod rcon;
+pub mod replay;
+pub mod server;
+pub mod spawn;
+pub mod spectator;
+pub mod stats;
+pub mod status;
+pub mod targets;
+pub mod team;
+pub mod testmap;
+pub mod trigger;
+pub mod usable;
+pub mod video;
+pub mod view;
+pub mod viewmodel;
+pub mod vote;
+pub mod weapon;
 
 pub const PRIVATE_KEY: &[u8; NETCODE_KEY_BYTES] = b"an example very very secret key."; // 32-bytes
 pub const PROTOCOL_ID: u64 = 7;
 
 pub const PLAYER_MOVE_SPEED: f32 = 2.0;
+pub const PLAYER_SPAWN_POINT: Vec3 = Vec3::new(0.0, 0.51, 0.0);
+pub const FIREBALL_SPEED: f32 = 10.0;
+pub const CRATE_HALF_SIZE: f32 = 0.4;
+pub const CRATE_COLOR: Color = Color::rgb(0.6, 0.45, 0.25);
+pub const FIREBALL_EXPLOSION_RADIUS: f32 = 2.0;
+pub const FIREBALL_EXPLOSION_STRENGTH: f32 = 12.0;
 
 #[derive(Debug, Component)]
 pub struct Player {
     pub id: u64,
+    pub name: String,
 }
 
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, Component)]
@@ -34,7 +112,74 @@ pub struct PlayerInput {
 
 #[derive(Debug, Serialize, Deserialize, Component)]
 pub enum PlayerCommand {
-    BasicAttack { cast_at: Vec3 },
+    BasicAttack {
+        cast_at: Vec3,
+    },
+    /// start or stop spectating
+    Spectate {
+        spectate: bool,
+    },
+    /// hitscan shot from the view `origin` along `direction`, `tick` is the newest server
+    /// tick the client had seen (for lag compensation). `shot` numbers the spread pattern.
+    Hitscan {
+        origin: Vec3,
+        direction: Vec3,
+        tick: Option<u32>,
+        shot: u32,
+    },
+    /// throw a grenade from the view `origin`, `direction` is the view direction
+    Grenade {
+        origin: Vec3,
+        direction: Vec3,
+    },
+    /// fire a rocket from the view `origin` along `direction`, `shot` numbers the spread
+    /// pattern
+    Rocket {
+        origin: Vec3,
+        direction: Vec3,
+        shot: u32,
+    },
+    /// melee swing from the view `origin` along `direction`
+    Melee {
+        origin: Vec3,
+        direction: Vec3,
+    },
+    /// use key pressed, looking from `origin` along `direction`: picks up dropped weapons
+    /// in reach
+    Use {
+        origin: Vec3,
+        direction: Vec3,
+    },
+    /// vote for option `option` of the running map vote
+    Vote {
+        option: usize,
+    },
+    /// call a vote, see `vote::Votes::call`
+    CallVote {
+        kind: VoteKind,
+    },
+    /// answer the running vote
+    CastVote {
+        yes: bool,
+    },
+}
+
+impl PlayerCommand {
+    /// fires a weapon or swings, not allowed while frozen
+    pub fn is_attack(&self) -> bool {
+        match self {
+            PlayerCommand::BasicAttack { .. }
+            | PlayerCommand::Hitscan { .. }
+            | PlayerCommand::Grenade { .. }
+            | PlayerCommand::Rocket { .. }
+            | PlayerCommand::Melee { .. } => true,
+            PlayerCommand::Spectate { .. }
+            | PlayerCommand::Use { .. }
+            | PlayerCommand::Vote { .. }
+            | PlayerCommand::CallVote { .. }
+            | PlayerCommand::CastVote { .. } => false,
+        }
+    }
 }
 
 pub enum ClientChannel {
@@ -48,49 +193,245 @@ pub enum ServerChannel {
     NetworkFrame,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub enum ObjectType {
-    Projectile,
-    Box,
-}
-
-impl ObjectType {
-    pub fn representation_bundle(
-        &self,
-        meshes: &mut Assets<Mesh>,
-        materials: &mut Assets<StandardMaterial>,
-    ) -> PbrBundle {
-        match self {
-            ObjectType::Projectile => todo!(),
-            ObjectType::Box => PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Cube::new(0.2))),
-                material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
-                transform: Transform::from_xyz(0.0, 3.0, 0.0),
-                ..default()
-            },
-        }
-    }
+/// what a joining player connected to, shown on the join splash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    /// message of the day, may span several lines
+    pub motd: String,
+    pub map: LevelKind,
+    pub mode: GameModeKind,
+    /// network frames per second
+    pub tick_rate: f32,
+    pub max_players: usize,
+    /// the match rules followed by the rules of the server admin
+    pub rules: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Component)]
 pub enum ServerMessages {
+    /// the client got a player slot, sent before the catch-up messages for joining
+    JoinAccepted,
+    /// the server is full, the client waits at `position` (1 = next) in the join queue
+    QueuePosition {
+        position: usize,
+    },
+    /// the server is full and the join queue is disabled or full, the client gets disconnected
+    ServerFull,
+    /// sent to everyone when a player joins. For the joining client it also confirms the
+    /// name the server assigned (after filtering and de-duplication).
     PlayerCreate {
         entity: Entity,
         id: u64,
+        name: String,
         translation: Vec3,
+        /// None in free-for-all modes
+        team: Option<Team>,
     },
     PlayerRemove {
         id: u64,
     },
+    /// also sent for already flying projectiles when a client joins. translation and
+    /// velocity are the state at `tick`, so the client can extrapolate to the current tick.
     SpawnProjectile {
         entity: Entity,
         translation: Vec3,
-        object_type: ObjectType,
-        // velocity: Vec3,
+        /// id in the PrefabRegistry
+        prefab: String,
+        velocity: Vec3,
+        tick: u32,
+        /// client id of the player who fired it
+        owner: Option<u64>,
     },
     DespawnProjectile {
         entity: Entity,
     },
+    SpawnHazardZone {
+        translation: Vec3,
+        kind: HazardKind,
+        radius: f32,
+        lifetime: Option<f32>,
+    },
+    MovementSettings {
+        settings: MovementSettings,
+    },
+    /// projectile ballistics, clients predict projectiles with them
+    ProjectileSettings {
+        settings: ProjectileKinds,
+    },
+    /// external velocity change applied to the receiving client's player on the server,
+    /// replicated so that client prediction (e.g. rocket jumps) follows
+    PlayerImpulse {
+        impulse: Vec3,
+        /// server entity of the exploding projectile, if caused by one. The shooter may have
+        /// applied the impulse already when predicting the explosion.
+        source: Option<Entity>,
+    },
+    /// movement events of players since the last message, each with the player's client id.
+    /// Clients skip their own events, those are emitted by local prediction.
+    MovementEvents {
+        events: Vec<(u64, MovementEventKind, Vec3)>,
+    },
+    /// a player started or stopped spectating. Spectators are not replicated in network
+    /// frames, clients hide their models.
+    PlayerSpectate {
+        id: u64,
+        spectating: bool,
+    },
+    /// hitscan shot of the player `shooter`, for tracer and impact effects
+    HitscanShot {
+        shooter: u64,
+        origin: Vec3,
+        end: Vec3,
+        /// the shot hit a player
+        hit_player: bool,
+        /// entry and exit points on surfaces along the shot, for decals
+        impacts: Vec<SurfaceImpact>,
+    },
+    /// the player `player` took the pickup at `position`. None for pickups that were taken
+    /// before the receiving client joined.
+    PickupTaken {
+        position: Vec3,
+        kind: PickupKind,
+        player: Option<u64>,
+    },
+    /// weapons carried by the player `id` changed (picked up, dropped or respawned)
+    InventoryChanged {
+        id: u64,
+        inventory: Inventory,
+    },
+    /// the pickup at `position` is available again
+    PickupRespawned {
+        position: Vec3,
+    },
+    /// damage the receiving player dealt, for hitmarkers and damage numbers
+    HitConfirm {
+        amount: f32,
+        headshot: bool,
+        kill: bool,
+        /// feet of the victim at the time of the hit
+        position: Vec3,
+    },
+    /// damage the receiving player took, for the damage indicator. `source` is where the
+    /// damage came from, None for falls and hazards.
+    DamageTaken {
+        amount: f32,
+        source: Option<Vec3>,
+    },
+    /// melee swing of the player `attacker`, for the swing animation
+    MeleeSwing {
+        attacker: u64,
+        origin: Vec3,
+        direction: Vec3,
+        /// the swing hit a player
+        hit_player: bool,
+    },
+    /// something exploded at `position`, for effects. `projectile` is the server entity of the
+    /// projectile that exploded.
+    Explosion {
+        position: Vec3,
+        radius: f32,
+        projectile: Entity,
+    },
+    /// mode and phase of the match, sent to joining players and on every phase change
+    MatchState {
+        mode: GameModeKind,
+        phase: MatchPhase,
+        /// seconds until the match ends by time, or the next one starts
+        time_left: Option<f32>,
+        frag_limit: u32,
+    },
+    /// scores of all players, best first, and of the teams, sent whenever they change
+    Scores {
+        scores: Vec<(u64, Score)>,
+        team_scores: Vec<(Team, u32)>,
+    },
+    /// a match ended, clients show the summary for `duration` seconds
+    MatchSummary {
+        summary: MatchSummary,
+        duration: f32,
+    },
+    /// the server plays the map of `manifest`, sent to joining players and on map changes
+    ChangeMap {
+        manifest: MapManifest,
+    },
+    /// the files of the map changed on the server and it loaded the level again, clients do the
+    /// same
+    ReloadMap {
+        manifest: MapManifest,
+    },
+    /// a vote on the next map started, `options` in order of the rotation
+    MapVote {
+        options: Vec<LevelKind>,
+    },
+    /// votes per option of the running map vote, sent on every vote
+    MapVoteTally {
+        votes: Vec<u32>,
+    },
+    /// state of the running vote, sent to joining players and on every change
+    Vote {
+        state: VoteState,
+    },
+    /// the running vote ended
+    VoteEnded {
+        kind: VoteKind,
+        passed: bool,
+    },
+    /// a vote the player called was turned down, only sent to that player
+    VoteRejected {
+        reason: String,
+    },
+    /// state of the flags in flag modes, sent to joining players and on every change
+    Flags {
+        flags: Vec<(Team, FlagState)>,
+    },
+    /// state of the control points by position, sent to joining players, on ownership changes
+    /// and every CONTROL_POINT_SYNC_INTERVAL while their progress moves
+    ControlPoints {
+        points: Vec<(Vec3, ControlPointState)>,
+    },
+    /// a player died, for the kill feed
+    KillFeed {
+        event: KillFeedEvent,
+    },
+    /// a player died at `translation` (feet) looking along `yaw`, moving with `velocity`.
+    /// Clients drop a ragdoll there, see `ragdoll`.
+    PlayerDied {
+        id: u64,
+        translation: Vec3,
+        yaw: f32,
+        velocity: Vec3,
+    },
+    /// a player was put back at `translation` after dying, or for a new match (no cause)
+    PlayerRespawn {
+        id: u64,
+        translation: Vec3,
+        cause: Option<DamageCause>,
+    },
+    /// level device activation. `player` is the client id of the activating player, its own
+    /// client already predicted the activation and skips the effect.
+    LevelEvent {
+        event: LevelEvent,
+        player: Option<u64>,
+    },
+    /// a destructible prop was destroyed at `position`, for the debris
+    PropDestroyed {
+        entity: Entity,
+        position: Vec3,
+    },
+    /// server entities of the level movers with their rest positions, sent to joining clients
+    /// to map the movers replicated in `with_rotation` to their own level geometry
+    Movers {
+        movers: Vec<(Entity, Vec3)>,
+    },
+    /// message of the server admin to all players
+    AdminMessage {
+        text: String,
+    },
+    /// sent to a joining player right after its PlayerCreate
+    ServerInfo {
+        info: ServerInfo,
+    },
 }
 
 pub mod frame;
@@ -181,7 +522,76 @@ pub fn setup_level(
             transform: Transform::from_xyz(0.0, -1.0, 0.0),
             ..Default::default()
         })
-        .insert(Collider::cuboid(5., 0.5, 5.));
+        .insert(Collider::cuboid(5., 0.5, 5.))
+        .insert(SurfaceMaterial::Concrete);
+    // surf ramp, steeper than FpsController::traction_normal_cutoff
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(3., 0.2, 8.))),
+            material: materials.add(Color::rgb(0.4, 0.4, 0.6).into()),
+            transform: Transform::from_xyz(-4.0, 0.5, 0.0)
+                .with_rotation(Quat::from_rotation_z(-1.0)),
+            ..Default::default()
+        })
+        .insert(Collider::cuboid(1.5, 0.1, 4.))
+        .insert(SurfaceMaterial::Metal);
+    // acid pool
+    spawn_hazard_zone(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        Vec3::new(3.0, -0.5, 3.0),
+        HazardZone::new(HazardKind::Acid, 1.0, None),
+    );
+    // jump pad
+    spawn_jump_pad(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        Vec3::new(3.0, -0.5, -3.0),
+        JumpPad {
+            half_extents: Vec3::new(0.5, 0.3, 0.5),
+            launch_velocity: Vec3::new(0.0, 12.0, -3.0),
+        },
+    );
+    // teleporter, the exit faces the opposite direction
+    spawn_teleporter(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        Transform::from_xyz(-1.5, 0.5, 4.0),
+        Vec3::new(0.5, 1.0, 0.3),
+        Transform::from_xyz(1.5, -0.45, -4.0)
+            .with_rotation(Quat::from_rotation_y(std::f32::consts::PI)),
+    );
+    // pickups in front of the teleporter
+    for (x, kind) in [
+        (-3.0, PickupKind::SpeedBoost),
+        (-1.5, PickupKind::Health),
+        (0.0, PickupKind::Armor),
+        (1.5, PickupKind::Ammo),
+        (3.0, PickupKind::Shield),
+    ] {
+        spawn_pickup(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            Vec3::new(x, 0.0, 2.0),
+            kind,
+        );
+    }
+    // low gravity area
+    spawn_gravity_zone(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        Vec3::new(0.0, 2.5, -3.5),
+        GravityZone {
+            half_extents: Vec3::new(1.5, 3.0, 1.5),
+            gravity: 5.0,
+        },
+    );
+    spawn::spawn_default_spawn_points(&mut commands);
     // light
     commands.spawn_bundle(PointLightBundle {
         point_light: PointLight {
@@ -197,14 +607,18 @@ pub fn setup_level(
 #[derive(Debug, Component)]
 pub struct Projectile {
     pub duration: Timer,
+    /// entity that fired the projectile
+    pub owner: Option<Entity>,
 }
 
 pub fn spawn_fireball(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
+    settings: &ProjectileSettings,
     translation: Vec3,
     mut direction: Vec3,
+    owner: Option<Entity>,
 ) -> Entity {
     if !direction.is_normalized() {
         direction = Vec3::X;
@@ -212,24 +626,85 @@ pub fn spawn_fireball(
     commands
         .spawn_bundle(PbrBundle {
             mesh: meshes.add(Mesh::from(shape::Icosphere {
-                radius: 0.1,
+                radius: settings.radius,
                 subdivisions: 5,
             })),
             material: materials.add(Color::rgb(1.0, 0.0, 0.0).into()),
             transform: Transform::from_translation(translation),
             ..Default::default()
         })
-        .insert(RigidBody::Dynamic)
-        .insert(LockedAxes::ROTATION_LOCKED | LockedAxes::TRANSLATION_LOCKED_Y)
-        .insert(Collider::ball(0.1))
-        .insert(Velocity::linear(direction * 10.))
-        .insert(ActiveEvents::COLLISION_EVENTS)
+        .insert(Ballistic::new(settings, direction * settings.speed, owner))
         .insert(Projectile {
-            duration: Timer::from_seconds(1.5, false),
+            duration: Timer::from_seconds(settings.lifetime, false),
+            owner,
         })
         .id()
 }
 
+/// projectile exploding when its duration (the fuse) runs out instead of on impact
+#[derive(Debug, Component)]
+pub struct Grenade;
+
+pub fn spawn_grenade(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    settings: &ProjectileSettings,
+    translation: Vec3,
+    velocity: Vec3,
+    owner: Option<Entity>,
+) -> Entity {
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Icosphere {
+                radius: settings.radius,
+                subdivisions: 3,
+            })),
+            material: materials.add(Color::rgb(0.2, 0.4, 0.1).into()),
+            transform: Transform::from_translation(translation),
+            ..Default::default()
+        })
+        .insert(Ballistic::new(settings, velocity, owner))
+        .insert(Projectile {
+            duration: Timer::from_seconds(settings.lifetime, false),
+            owner,
+        })
+        .insert(Grenade)
+        .id()
+}
+
+/// projectile exploding on impact with splash damage, hurting and pushing its shooter as well
+#[derive(Debug, Component)]
+pub struct Rocket;
+
+pub fn spawn_rocket(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    settings: &ProjectileSettings,
+    translation: Vec3,
+    velocity: Vec3,
+    owner: Option<Entity>,
+) -> Entity {
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Icosphere {
+                radius: settings.radius,
+                subdivisions: 3,
+            })),
+            material: materials.add(Color::rgb(1.0, 0.5, 0.1).into()),
+            transform: Transform::from_translation(translation),
+            ..Default::default()
+        })
+        .insert(Ballistic::new(settings, velocity, owner))
+        .insert(Projectile {
+            duration: Timer::from_seconds(settings.lifetime, false),
+            owner,
+        })
+        .insert(Rocket)
+        .id()
+}
+
 /// A 3D ray, with an origin and direction. The direction is guaranteed to be normalized.
 #[derive(Debug, PartialEq, Copy, Clone, Default)]
 pub struct Ray3d {
@@ -294,5 +769,28 @@ pub fn exit_on_esc_system(
 #[derive(Component)]
 pub struct ControlledPlayer;
 
+/// despawn the entity after the timer finished (short lived effects). Pooled entities are
+/// returned to their pool instead.
+#[derive(Component)]
+pub struct Lifetime(pub Timer);
+
+pub fn despawn_lifetime_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pools: Option<ResMut<EntityPools>>,
+    mut query: Query<(Entity, &mut Lifetime, Option<&Pooled>)>,
+) {
+    for (entity, mut lifetime, pooled) in &mut query {
+        lifetime.0.tick(time.delta());
+        if !lifetime.0.just_finished() {
+            continue;
+        }
+        match (pooled, pools.as_mut()) {
+            (Some(pooled), Some(pools)) => pools.release(&mut commands, entity, pooled.0),
+            _ => commands.entity(entity).despawn_recursive(),
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct WorldSpacePointer;
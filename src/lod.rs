@@ -0,0 +1,115 @@
+//! server side simulation level of detail: entities far away from all players are
+//! simulated and replicated at a lower rate. Bots are measured against the human players, far
+//! from all of them they think at a lower rate.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::Player;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LodLevel {
+    Near,
+    Far,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LodSettings {
+    /// entities further away than this from every player switch to LodLevel::Far
+    pub far_distance: f32,
+    /// far entities switch back once a player comes closer than this. Must be smaller than
+    /// far_distance, the gap avoids flipping back and forth at the border.
+    pub near_distance: f32,
+    /// far entities are only replicated in every n-th network frame sent
+    pub far_replication_interval: u32,
+    /// far bots only think every n-th frame
+    pub far_ai_interval: u32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            far_distance: 40.0,
+            near_distance: 30.0,
+            far_replication_interval: 6,
+            far_ai_interval: 4,
+        }
+    }
+}
+
+/// marks props and AI that are subject to simulation LOD
+#[derive(Debug, Component)]
+pub struct SimulationLod {
+    pub level: LodLevel,
+}
+
+impl Default for SimulationLod {
+    fn default() -> Self {
+        Self {
+            level: LodLevel::Near,
+        }
+    }
+}
+
+impl SimulationLod {
+    pub fn is_far(&self) -> bool {
+        self.level == LodLevel::Far
+    }
+
+    /// whether the entity's state should go into the `sent`-th network frame sent
+    pub fn should_replicate(&self, settings: &LodSettings, sent: u32) -> bool {
+        !self.is_far() || sent % settings.far_replication_interval.max(1) == 0
+    }
+
+    /// whether AI systems should update the entity in `frame`
+    pub fn should_think(&self, settings: &LodSettings, frame: u32) -> bool {
+        !self.is_far() || frame % settings.far_ai_interval.max(1) == 0
+    }
+
+    fn next_level(&self, settings: &LodSettings, nearest_player: f32) -> LodLevel {
+        match self.level {
+            LodLevel::Near if nearest_player > settings.far_distance => LodLevel::Far,
+            LodLevel::Far if nearest_player < settings.near_distance => LodLevel::Near,
+            level => level,
+        }
+    }
+}
+
+/// update LOD levels from the distance to the closest player, for bots the closest human
+/// player. Rigid bodies entering LodLevel::Far are put to sleep and are woken up again when a
+/// player approaches.
+#[allow(clippy::type_complexity)]
+pub fn update_simulation_lod_system(
+    settings: Res<LodSettings>,
+    players: Query<&Transform, With<Player>>,
+    // bots are the players with a SimulationLod
+    humans: Query<&Transform, (With<Player>, Without<SimulationLod>)>,
+    mut entities: Query<(&Transform, &mut SimulationLod, Option<&mut Sleeping>), Without<Player>>,
+    mut bots: Query<(&Transform, &mut SimulationLod), With<Player>>,
+) {
+    for (transform, mut lod, sleeping) in &mut entities {
+        let level = lod.next_level(&settings, nearest(players.iter(), transform.translation));
+        if level == lod.level {
+            continue;
+        }
+        lod.level = level;
+        if let Some(mut sleeping) = sleeping {
+            sleeping.sleeping = level == LodLevel::Far;
+        }
+    }
+    for (transform, mut lod) in &mut bots {
+        let level = lod.next_level(&settings, nearest(humans.iter(), transform.translation));
+        if level != lod.level {
+            lod.level = level;
+        }
+    }
+}
+
+/// distance from `position` to the closest of `players`
+fn nearest<'a>(players: impl Iterator<Item = &'a Transform>, position: Vec3) -> f32 {
+    players
+        .map(|player| player.translation.distance(position))
+        .fold(f32::INFINITY, f32::min)
+}
@@ -0,0 +1,49 @@
+//! log setup of the dedicated server: the console plus a JSON log file, rotated daily. Log lines
+//! carry the spans they were written in (tick, client id, input serial), so the lines of one
+//! client can be matched against the client's log, which has the same fields.
+//!
+//! `--log-dir=<dir>` sets the directory of the log files, `RUST_LOG` filters as usual.
+
+use std::path::{Path, PathBuf};
+
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{fmt, prelude::*, EnvFilter, Registry};
+
+pub const DEFAULT_LOG_DIR: &str = "logs";
+
+/// name of the log files in the log directory, the date is appended
+const LOG_FILE_PREFIX: &str = "server.log";
+
+/// filter without RUST_LOG, same as the default of bevy's LogPlugin
+const DEFAULT_FILTER: &str = "info,wgpu=error";
+
+/// flushes the log file when dropped, keep it as long as the server runs
+pub struct LogGuard(WorkerGuard);
+
+pub fn log_dir_from_args() -> PathBuf {
+    std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.strip_prefix("--log-dir=").map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_LOG_DIR))
+}
+
+/// install the console and file log, replaces bevy's LogPlugin
+pub fn init_server_logging(dir: &Path) -> Result<LogGuard, String> {
+    let file_appender = tracing_appender::rolling::daily(dir, LOG_FILE_PREFIX);
+    let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    Registry::default()
+        .with(filter)
+        .with(fmt::layer())
+        .with(
+            fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_writer(file_writer),
+        )
+        .try_init()
+        .map_err(|e| format!("failed to set up logging: {}", e))?;
+    Ok(LogGuard(guard))
+}
@@ -0,0 +1,366 @@
+//! maps loaded from files in MAPS_DIR, selected by file name without extension. The importers
+//! turn a file into MapData (geometry and entities in world space), which client and server
+//! spawn the same way. The server sends a MapManifest with its map, so clients lacking the map
+//! or having another version of it notice instead of playing on different geometry.
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gltf_map,
+    level::{spawn_jump_pad, spawn_teleporter, JumpPad, LevelKind, SurfaceMaterial},
+    pickup::{spawn_pickup, PickupKind},
+    quake_map,
+    spawn::{spawn_spawn_point, SpawnPoint},
+    trigger::{spawn_trigger_volume, TriggerAction, TriggerShape},
+};
+
+/// map files are looked up here, relative to the working directory
+pub const MAPS_DIR: &str = "assets/maps";
+
+/// supported map file extensions, in lookup order
+const MAP_EXTENSIONS: [&str; 3] = ["glb", "gltf", "map"];
+
+/// collision shape generated for a map mesh
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MapCollider {
+    /// the triangles as they are, for static level geometry
+    Trimesh,
+    /// convex hull of the vertices, cheaper and solid inside
+    Convex,
+}
+
+/// static mesh of a map, vertices in world space
+#[derive(Debug, Clone)]
+pub struct MapMesh {
+    pub positions: Vec<Vec3>,
+    /// computed (flat) if missing
+    pub normals: Option<Vec<Vec3>>,
+    /// triangle list
+    pub indices: Vec<u32>,
+    pub color: Color,
+    /// None for visual only meshes
+    pub collider: Option<MapCollider>,
+    pub surface: Option<SurfaceMaterial>,
+    /// false for collision only geometry
+    pub visible: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightKind {
+    Point,
+    /// shines along -Z of its transform
+    Spot,
+    /// shines along -Z of its transform, `intensity` is the illuminance (lux)
+    Directional,
+}
+
+#[derive(Debug, Clone)]
+pub struct MapLight {
+    pub kind: LightKind,
+    pub transform: Transform,
+    pub color: Color,
+    pub intensity: f32,
+    /// reach of point and spot lights
+    pub range: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct MapJumpPad {
+    pub translation: Vec3,
+    pub half_extents: Vec3,
+    pub launch_velocity: Vec3,
+}
+
+#[derive(Debug, Clone)]
+pub struct MapTeleporter {
+    pub entrance: Transform,
+    pub half_extents: Vec3,
+    pub exit: Transform,
+}
+
+#[derive(Debug, Clone)]
+pub struct MapTrigger {
+    pub transform: Transform,
+    pub shape: TriggerShape,
+    pub action: TriggerAction,
+}
+
+/// everything a map file places
+#[derive(Debug, Clone, Default)]
+pub struct MapData {
+    pub meshes: Vec<MapMesh>,
+    pub spawn_points: Vec<(Vec3, SpawnPoint)>,
+    pub pickups: Vec<(Vec3, PickupKind)>,
+    pub lights: Vec<MapLight>,
+    pub jump_pads: Vec<MapJumpPad>,
+    pub teleporters: Vec<MapTeleporter>,
+    pub triggers: Vec<MapTrigger>,
+}
+
+/// the file of the map `name`, if there is one
+pub fn find_map_file(name: &str) -> Option<PathBuf> {
+    MAP_EXTENSIONS
+        .iter()
+        .map(|extension| Path::new(MAPS_DIR).join(format!("{}.{}", name, extension)))
+        .find(|path| path.is_file())
+}
+
+/// other files a map file loads, e.g. the buffers of a .gltf
+fn required_assets(path: &Path) -> Result<Vec<PathBuf>, String> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("glb" | "gltf") => gltf_map::required_assets(path),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// content hash of map files, FNV-1a: the same on every platform and build
+fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// a file of a map, `path` relative to MAPS_DIR with `/` separators
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MapFile {
+    pub path: String,
+    pub hash: u64,
+}
+
+/// the files a map is loaded from, sent by the server with the map
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MapManifest {
+    pub map: LevelKind,
+    /// the map file first, then the assets it needs. Empty for the built-in maps.
+    pub files: Vec<MapFile>,
+}
+
+/// the local files `map` is loaded from: the map file first, then the assets it needs
+pub fn map_files(map: &LevelKind) -> Result<Vec<PathBuf>, String> {
+    let name = match map {
+        LevelKind::File(name) => name,
+        LevelKind::Default | LevelKind::MovementTest => return Ok(Vec::new()),
+    };
+    let path =
+        find_map_file(name).ok_or_else(|| format!("no file for map {} in {}", name, MAPS_DIR))?;
+    let mut paths = required_assets(&path)?;
+    paths.insert(0, path);
+    Ok(paths)
+}
+
+impl MapManifest {
+    /// manifest of the local files of `map`
+    pub fn local(map: &LevelKind) -> Result<Self, String> {
+        let mut manifest = MapManifest {
+            map: map.clone(),
+            files: Vec::new(),
+        };
+        for path in map_files(map)? {
+            let data = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let relative = path
+                .strip_prefix(MAPS_DIR)
+                .map_err(|_| format!("{}: outside of {}", path.display(), MAPS_DIR))?;
+            let relative: Vec<String> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            manifest.files.push(MapFile {
+                path: relative.join("/"),
+                hash: content_hash(&data),
+            });
+        }
+        Ok(manifest)
+    }
+
+    /// check the local map files against the manifest of the server, Err tells what is missing
+    /// or different
+    pub fn verify(&self) -> Result<(), String> {
+        for file in &self.files {
+            let path = Path::new(MAPS_DIR).join(&file.path);
+            if !path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+            {
+                return Err(format!("invalid map file {:?}", file.path));
+            }
+            let data = fs::read(&path).map_err(|_| {
+                format!(
+                    "map {} is not installed: {} is missing",
+                    self.map.name(),
+                    path.display()
+                )
+            })?;
+            if content_hash(&data) != file.hash {
+                return Err(format!(
+                    "map {}: {} differs from the version on the server",
+                    self.map.name(),
+                    path.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// get the files of `manifest` from the server. Map downloads are not supported yet, this is
+/// where they go: on success the client loads the map instead of leaving.
+pub fn fetch_map(_manifest: &MapManifest) -> Result<(), String> {
+    Err("map downloads are not supported".to_string())
+}
+
+/// load a map file with the importer for its extension
+pub fn load_map_file(path: &Path) -> Result<MapData, String> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("glb" | "gltf") => gltf_map::load(path),
+        Some("map") => quake_map::load(path),
+        _ => Err(format!("{}: unknown map format", path.display())),
+    }
+}
+
+fn build_mesh(map_mesh: &MapMesh) -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    let positions: Vec<[f32; 3]> = map_mesh.positions.iter().map(|p| p.to_array()).collect();
+    let uvs = vec![[0.0f32, 0.0]; positions.len()];
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(map_mesh.indices.clone())));
+    match &map_mesh.normals {
+        Some(normals) => {
+            let normals: Vec<[f32; 3]> = normals.iter().map(|n| n.to_array()).collect();
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        }
+        None => {
+            mesh.duplicate_vertices();
+            mesh.compute_flat_normals();
+        }
+    }
+    mesh
+}
+
+fn build_collider(map_mesh: &MapMesh, kind: MapCollider) -> Option<Collider> {
+    match kind {
+        MapCollider::Trimesh => {
+            let triangles = map_mesh
+                .indices
+                .chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                .collect();
+            Some(Collider::trimesh(map_mesh.positions.clone(), triangles))
+        }
+        MapCollider::Convex => Collider::convex_hull(&map_mesh.positions),
+    }
+}
+
+/// spawn everything a map places: static geometry, lights, spawn points, pickups, devices and
+/// triggers
+pub fn spawn_map(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    map: &MapData,
+) {
+    for map_mesh in &map.meshes {
+        let mut entity = if map_mesh.visible {
+            commands.spawn_bundle(PbrBundle {
+                mesh: meshes.add(build_mesh(map_mesh)),
+                material: materials.add(map_mesh.color.into()),
+                ..default()
+            })
+        } else {
+            commands.spawn_bundle(TransformBundle::default())
+        };
+        if let Some(kind) = map_mesh.collider {
+            match build_collider(map_mesh, kind) {
+                Some(collider) => {
+                    entity.insert(collider);
+                }
+                None => warn!("degenerate map mesh, no collider generated"),
+            }
+        }
+        if let Some(surface) = map_mesh.surface {
+            entity.insert(surface);
+        }
+    }
+    for light in &map.lights {
+        match light.kind {
+            LightKind::Point => commands.spawn_bundle(PointLightBundle {
+                point_light: PointLight {
+                    color: light.color,
+                    intensity: light.intensity,
+                    range: light.range,
+                    shadows_enabled: true,
+                    ..default()
+                },
+                transform: light.transform,
+                ..default()
+            }),
+            LightKind::Spot => commands.spawn_bundle(SpotLightBundle {
+                spot_light: SpotLight {
+                    color: light.color,
+                    intensity: light.intensity,
+                    range: light.range,
+                    shadows_enabled: true,
+                    ..default()
+                },
+                transform: light.transform,
+                ..default()
+            }),
+            LightKind::Directional => commands.spawn_bundle(DirectionalLightBundle {
+                directional_light: DirectionalLight {
+                    color: light.color,
+                    illuminance: light.intensity,
+                    shadows_enabled: true,
+                    ..default()
+                },
+                transform: light.transform,
+                ..default()
+            }),
+        };
+    }
+    for (translation, spawn_point) in &map.spawn_points {
+        spawn_spawn_point(commands, *translation, *spawn_point);
+    }
+    for (translation, kind) in &map.pickups {
+        spawn_pickup(commands, meshes, materials, *translation, *kind);
+    }
+    for pad in &map.jump_pads {
+        spawn_jump_pad(
+            commands,
+            meshes,
+            materials,
+            pad.translation,
+            JumpPad {
+                half_extents: pad.half_extents,
+                launch_velocity: pad.launch_velocity,
+            },
+        );
+    }
+    for teleporter in &map.teleporters {
+        spawn_teleporter(
+            commands,
+            meshes,
+            materials,
+            teleporter.entrance,
+            teleporter.half_extents,
+            teleporter.exit,
+        );
+    }
+    for trigger in &map.triggers {
+        let action = trigger.action.clone();
+        if spawn_trigger_volume(commands, trigger.transform, &trigger.shape, action).is_none() {
+            warn!("degenerate {} trigger, skipped", trigger.action.label());
+        }
+    }
+}
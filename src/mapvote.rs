@@ -0,0 +1,103 @@
+//! map rotation and voting: when a match ends the server offers the next maps of the rotation,
+//! players vote with PlayerCommand::Vote while the summary is up and the map with the most
+//! votes is played next. Without a vote (or without votes) the rotation simply moves on.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::level::LevelKind;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MapRotation {
+    /// maps played in turn, empty to stay on the current map
+    pub maps: Vec<LevelKind>,
+    /// let players vote on the next map at the end of a match
+    pub vote: bool,
+    /// maps offered in a vote
+    pub vote_options: usize,
+}
+
+impl Default for MapRotation {
+    fn default() -> Self {
+        Self {
+            maps: Vec::new(),
+            vote: true,
+            vote_options: 3,
+        }
+    }
+}
+
+impl MapRotation {
+    /// the maps after `current` in the rotation, up to `count`, wrapping around. The current map
+    /// comes last, if at all.
+    pub fn upcoming(&self, current: &LevelKind, count: usize) -> Vec<LevelKind> {
+        let start = self
+            .maps
+            .iter()
+            .position(|map| map == current)
+            .map_or(0, |index| index + 1);
+        let mut upcoming = Vec::new();
+        for offset in 0..self.maps.len() {
+            let map = &self.maps[(start + offset) % self.maps.len()];
+            if !upcoming.contains(map) {
+                upcoming.push(map.clone());
+            }
+        }
+        upcoming.truncate(count);
+        upcoming
+    }
+}
+
+/// server: the running vote, by client id
+#[derive(Debug, Default)]
+pub struct MapVote {
+    pub options: Vec<LevelKind>,
+    votes: HashMap<u64, usize>,
+}
+
+impl MapVote {
+    pub fn new(options: Vec<LevelKind>) -> Self {
+        Self {
+            options,
+            votes: HashMap::new(),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        !self.options.is_empty()
+    }
+
+    /// count the vote of `player` for `option`, a new vote replaces the old one. False for
+    /// invalid options.
+    pub fn vote(&mut self, player: u64, option: usize) -> bool {
+        if option >= self.options.len() {
+            return false;
+        }
+        self.votes.insert(player, option);
+        true
+    }
+
+    /// drop the votes of players who left
+    pub fn retain_voters(&mut self, connected: impl Fn(u64) -> bool) {
+        self.votes.retain(|player, _| connected(*player));
+    }
+
+    /// votes per option
+    pub fn tally(&self) -> Vec<u32> {
+        let mut tally = vec![0; self.options.len()];
+        for option in self.votes.values() {
+            tally[*option] += 1;
+        }
+        tally
+    }
+
+    /// the option with the most votes, the earlier one on a tie (the first without votes)
+    pub fn winner(&self) -> Option<LevelKind> {
+        let tally = self.tally();
+        let best = tally.iter().copied().max()?;
+        let index = tally.iter().position(|votes| *votes == best)?;
+        self.options.get(index).cloned()
+    }
+}
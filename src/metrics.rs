@@ -0,0 +1,113 @@
+//! optional Prometheus metrics endpoint for dedicated servers. With `metrics_addr` set in
+//! server.toml, a background thread answers `GET /metrics` with the metrics the server renders
+//! once per second, in the Prometheus text format.
+//!
+//!     metrics_addr = "0.0.0.0:9100"
+
+use std::{
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use bevy::prelude::*;
+
+/// seconds between renders of the metrics
+pub const METRICS_INTERVAL: f64 = 1.0;
+
+/// scrapers taking longer than this to send their request or read the answer are dropped
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// the latest rendered metrics, shared with the HTTP thread
+pub struct MetricsEndpoint {
+    text: Arc<Mutex<String>>,
+}
+
+impl MetricsEndpoint {
+    /// listen on `addr` in a background thread
+    pub fn spawn(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        info!(
+            "metrics endpoint on http://{}/metrics",
+            listener.local_addr()?
+        );
+        let text = Arc::new(Mutex::new(String::new()));
+        let shared = text.clone();
+        thread::Builder::new()
+            .name("metrics".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            if let Err(e) = answer(stream, &shared) {
+                                debug!("metrics request failed: {}", e);
+                            }
+                        }
+                        Err(e) => warn!("metrics connection failed: {}", e),
+                    }
+                }
+            })?;
+        Ok(Self { text })
+    }
+
+    pub fn update(&self, text: String) {
+        *self.text.lock().unwrap() = text;
+    }
+}
+
+fn answer(mut stream: TcpStream, text: &Mutex<String>) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    // only the request line matters, it fits into the first read
+    let mut request = [0; 1024];
+    let len = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..len]);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let response = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = text.lock().unwrap().clone();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes())
+}
+
+/// builds metrics in the Prometheus text format
+#[derive(Default)]
+pub struct MetricsWriter(String);
+
+impl MetricsWriter {
+    /// start a metric family, `kind` is counter or gauge
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, value))
+                .collect();
+            let _ = write!(self.0, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.0, " {}", value);
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+    }
+}
@@ -0,0 +1,100 @@
+//! client minimap: a top-down outline of the level built from the bounds of its colliders,
+//! drawn north-up around the local player together with teammates and recent gunfire.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::level::CurrentLevel;
+
+/// meters from the local player to the edge of the minimap
+pub const MINIMAP_RANGE: f32 = 40.0;
+
+/// seconds a gunfire ping stays on the minimap
+pub const PING_TIME: f64 = 3.0;
+
+/// colliders taller than this are drawn as walls, the others as floor (m)
+const WALL_HEIGHT: f32 = 1.0;
+
+/// ground plan of one collider
+#[derive(Debug, Clone, Copy)]
+pub struct OutlineRect {
+    /// x and z of the corners
+    pub min: Vec2,
+    pub max: Vec2,
+    pub wall: bool,
+}
+
+/// top-down outline of the loaded level
+#[derive(Debug, Default)]
+pub struct MinimapOutline {
+    /// floors first, walls drawn over them
+    pub rects: Vec<OutlineRect>,
+    generation: Option<u32>,
+}
+
+/// shots heard recently, by position and local time
+#[derive(Debug, Default)]
+pub struct GunfirePings(pub Vec<(Vec3, f64)>);
+
+impl GunfirePings {
+    pub fn push(&mut self, position: Vec3, time: f64) {
+        self.0.push((position, time));
+    }
+}
+
+/// world space bounds of `collider`, from its local bounds moved by `transform`
+fn world_bounds(collider: &Collider, transform: &GlobalTransform) -> (Vec3, Vec3) {
+    let aabb = collider.raw.compute_local_aabb();
+    let (min, max) = (aabb.mins, aabb.maxs);
+    let matrix = transform.compute_matrix();
+    let mut world_min = Vec3::splat(f32::MAX);
+    let mut world_max = Vec3::splat(f32::MIN);
+    for corner in 0..8 {
+        let local = Vec3::new(
+            if corner & 1 == 0 { min.x } else { max.x },
+            if corner & 2 == 0 { min.y } else { max.y },
+            if corner & 4 == 0 { min.z } else { max.z },
+        );
+        let world = matrix.transform_point3(local);
+        world_min = world_min.min(world);
+        world_max = world_max.max(world);
+    }
+    (world_min, world_max)
+}
+
+/// outline the level after every load. Colliders reach the world one frame after the level
+/// was spawned, so this waits a frame. Moving bodies and sensors are not part of the outline.
+#[allow(clippy::type_complexity)]
+pub fn build_outline_system(
+    current_level: Res<CurrentLevel>,
+    mut outline: ResMut<MinimapOutline>,
+    mut pending: Local<Option<u32>>,
+    colliders: Query<(&Collider, &GlobalTransform, Option<&RigidBody>), Without<Sensor>>,
+) {
+    let generation = current_level.generation();
+    if current_level.loaded().is_none() || outline.generation == Some(generation) {
+        return;
+    }
+    if *pending != Some(generation) {
+        *pending = Some(generation);
+        return;
+    }
+    let mut rects: Vec<OutlineRect> = colliders
+        .iter()
+        .filter(|(_, _, body)| !matches!(body, Some(RigidBody::Dynamic)))
+        .map(|(collider, transform, _)| {
+            let (min, max) = world_bounds(collider, transform);
+            OutlineRect {
+                min: Vec2::new(min.x, min.z),
+                max: Vec2::new(max.x, max.z),
+                wall: max.y - min.y > WALL_HEIGHT,
+            }
+        })
+        .collect();
+    rects.sort_by_key(|rect| rect.wall);
+    info!("minimap outline: {} colliders", rects.len());
+    *outline = MinimapOutline {
+        rects,
+        generation: Some(generation),
+    };
+}
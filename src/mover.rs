@@ -0,0 +1,297 @@
+//! movers: doors and elevators, kinematic level geometry travelling along waypoints. The
+//! server runs their state machines and replicates their poses in the `with_rotation` frame
+//! section, clients only follow. Players standing on a mover are carried along by the
+//! controller, see MovingPlatform. A player caught between a mover and other geometry blocks
+//! it and gets crushed.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    controller::FpsController,
+    health::{DamageCause, DamageEvent, DamageType},
+    usable::Usable,
+};
+
+/// replicated positions closer than this belong to the same mover
+const MOVER_MATCH_DISTANCE: f32 = 0.1;
+
+/// shortest time a segment between two waypoints takes (seconds)
+const MIN_SEGMENT_TIME: f32 = 0.05;
+
+/// a player this deep in a mover can't get out of its way, the mover stops and crushes it (m)
+const CRUSH_DEPTH: f32 = 0.1;
+
+/// damage to crushed players per second
+const CRUSH_DAMAGE_PER_SECOND: f32 = 200.0;
+
+/// what drives a mover
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoverMode {
+    /// follows the Usable on the same entity: the first waypoint while inactive, the last one
+    /// while active (doors)
+    Usable,
+    /// travels the waypoints back and forth on its own, waiting `wait` seconds at both ends
+    /// (elevators)
+    PingPong { wait: f32 },
+}
+
+/// level geometry moving players standing on it along. The server sets the velocity from the
+/// mover, clients from the replicated velocity.
+#[derive(Debug, Default, Component)]
+pub struct MovingPlatform {
+    pub velocity: Vec3,
+}
+
+#[derive(Debug, Component)]
+pub struct Mover {
+    /// poses the mover travels between, the first one is the rest pose
+    pub waypoints: Vec<Transform>,
+    /// travel speed (m/s)
+    pub speed: f32,
+    /// turn speed (radians/s), segments take as long as the slower of both
+    pub angular_speed: f32,
+    pub mode: MoverMode,
+    /// position along the path: the integer part is the waypoint left, the fraction the
+    /// progress towards the next one
+    position: f32,
+    /// position the mover heads to
+    goal: f32,
+    /// runs while a ping-pong mover waits at an end
+    wait: Option<Timer>,
+}
+
+impl Mover {
+    pub fn new(waypoints: Vec<Transform>, mode: MoverMode) -> Self {
+        Self {
+            waypoints,
+            speed: 2.0,
+            angular_speed: std::f32::consts::FRAC_PI_2,
+            mode,
+            position: 0.0,
+            goal: 0.0,
+            wait: None,
+        }
+    }
+
+    pub fn with_speed(mut self, speed: f32, angular_speed: f32) -> Self {
+        self.speed = speed;
+        self.angular_speed = angular_speed;
+        self
+    }
+
+    fn last(&self) -> f32 {
+        self.waypoints.len().saturating_sub(1) as f32
+    }
+
+    /// true if the replicated `position` refers to this mover
+    pub fn matches(&self, position: Vec3) -> bool {
+        self.waypoints.first().map_or(false, |rest| {
+            rest.translation.distance(position) < MOVER_MATCH_DISTANCE
+        })
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.position != self.goal
+    }
+
+    /// time the segment starting at waypoint `index` takes
+    fn segment_time(&self, index: usize) -> f32 {
+        let from = &self.waypoints[index];
+        let to = &self.waypoints[(index + 1).min(self.waypoints.len() - 1)];
+        let distance = from.translation.distance(to.translation);
+        let angle = from.rotation.angle_between(to.rotation);
+        (distance / self.speed)
+            .max(angle / self.angular_speed)
+            .max(MIN_SEGMENT_TIME)
+    }
+
+    /// advance towards the goal by `dt` seconds
+    fn advance(&mut self, dt: f32) {
+        let mut remaining = dt;
+        while remaining > 0.0 && self.is_moving() {
+            let forward = self.goal > self.position;
+            // segment the mover is in, looking in travel direction
+            let index = if forward {
+                self.position.floor()
+            } else {
+                self.position.ceil() - 1.0
+            };
+            let segment_time = self.segment_time(index as usize);
+            let (boundary, step) = if forward {
+                ((index + 1.0).min(self.goal), remaining / segment_time)
+            } else {
+                (index.max(self.goal), -remaining / segment_time)
+            };
+            let next = self.position + step;
+            if (forward && next >= boundary) || (!forward && next <= boundary) {
+                remaining -= (boundary - self.position).abs() * segment_time;
+                self.position = boundary;
+            } else {
+                self.position = next;
+                remaining = 0.0;
+            }
+        }
+    }
+
+    /// pose at the current position along the path
+    pub fn pose(&self) -> Transform {
+        let index = (self.position.floor() as usize).min(self.waypoints.len() - 1);
+        let next = (index + 1).min(self.waypoints.len() - 1);
+        let t = self.position - index as f32;
+        let from = &self.waypoints[index];
+        let to = &self.waypoints[next];
+        Transform {
+            translation: from.translation.lerp(to.translation, t),
+            rotation: from.rotation.slerp(to.rotation, t),
+            scale: from.scale,
+        }
+    }
+}
+
+/// kinematic body of a mover, insert it on spawned level geometry
+#[derive(Bundle)]
+pub struct MoverBundle {
+    pub mover: Mover,
+    pub platform: MovingPlatform,
+    pub rigid_body: RigidBody,
+}
+
+impl MoverBundle {
+    pub fn new(mover: Mover) -> Self {
+        Self {
+            mover,
+            platform: MovingPlatform::default(),
+            rigid_body: RigidBody::KinematicPositionBased,
+        }
+    }
+}
+
+/// spawn a mover box of `half_extents` at its first waypoint
+pub fn spawn_mover(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    half_extents: Vec3,
+    color: Color,
+    mover: Mover,
+) -> Entity {
+    let size = half_extents * 2.0;
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(size.x, size.y, size.z))),
+            material: materials.add(color.into()),
+            transform: mover.pose(),
+            ..default()
+        })
+        .insert(Collider::cuboid(
+            half_extents.x,
+            half_extents.y,
+            half_extents.z,
+        ))
+        .insert_bundle(MoverBundle::new(mover))
+        .id()
+}
+
+/// players pushed deeper than CRUSH_DEPTH into the collider of `mover`
+fn crushed_players(
+    physics_context: &RapierContext,
+    mover: Entity,
+    players: &Query<(), With<FpsController>>,
+) -> Vec<Entity> {
+    physics_context
+        .contacts_with(mover)
+        .filter_map(|contact| {
+            let other = if contact.collider1() == mover {
+                contact.collider2()
+            } else {
+                contact.collider1()
+            };
+            let depth = contact
+                .manifolds()
+                .map(|manifold| {
+                    manifold
+                        .points()
+                        .map(|point| -point.dist())
+                        .fold(0.0, f32::max)
+                })
+                .fold(0.0, f32::max);
+            (depth > CRUSH_DEPTH && players.get(other).is_ok()).then_some(other)
+        })
+        .collect()
+}
+
+/// server: pick the goals of movers, move them along their waypoints and track their velocity.
+/// A blocked mover waits and damages the players in its way, deaths are credited to whoever
+/// pushed them there (see PushedBy).
+pub fn mover_system(
+    time: Res<Time>,
+    physics_context: Res<RapierContext>,
+    mut movers: Query<(
+        Entity,
+        &mut Mover,
+        &mut Transform,
+        &mut MovingPlatform,
+        Option<&Usable>,
+    )>,
+    players: Query<(), With<FpsController>>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    let dt = time.delta_seconds();
+    for (entity, mut mover, mut transform, mut platform, usable) in &mut movers {
+        match mover.mode {
+            MoverMode::Usable => {
+                let open = usable.map_or(false, |usable| usable.active);
+                mover.goal = if open { mover.last() } else { 0.0 };
+            }
+            MoverMode::PingPong { wait } => {
+                if !mover.is_moving() {
+                    if mover.wait.is_none() {
+                        mover.wait = Some(Timer::from_seconds(wait, false));
+                    }
+                    let waited = mover
+                        .wait
+                        .as_mut()
+                        .map_or(false, |timer| timer.tick(time.delta()).finished());
+                    if waited {
+                        mover.wait = None;
+                        mover.goal = if mover.position > 0.0 {
+                            0.0
+                        } else {
+                            mover.last()
+                        };
+                    }
+                }
+            }
+        }
+        let before = transform.translation;
+        if mover.is_moving() {
+            let crushed = crushed_players(&physics_context, entity, &players);
+            for target in &crushed {
+                damage_events.send(DamageEvent {
+                    target: *target,
+                    amount: CRUSH_DAMAGE_PER_SECOND * dt,
+                    attacker: None,
+                    source_position: None,
+                    cause: DamageCause::Crushed,
+                    damage_type: DamageType::Environmental,
+                    weapon: None,
+                    headshot: false,
+                    projectile: None,
+                });
+            }
+            if crushed.is_empty() {
+                mover.advance(dt);
+                *transform = mover.pose();
+            }
+        }
+        let velocity = if dt > 0.0 {
+            (transform.translation - before) / dt
+        } else {
+            Vec3::ZERO
+        };
+        if platform.velocity != velocity {
+            platform.velocity = velocity;
+        }
+    }
+}
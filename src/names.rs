@@ -0,0 +1,169 @@
+use std::{fs, io, path::Path};
+
+use bevy_renet::renet::NETCODE_USER_DATA_BYTES;
+use serde::{Deserialize, Serialize};
+
+use crate::identity::IDENTITY_OFFSET;
+
+pub const DEFAULT_NAME: &str = "player";
+
+/// encode a player name into the connect token user data: one length byte followed by
+/// the utf-8 bytes (truncated to fit in front of the player identity)
+pub fn name_to_user_data(name: &str) -> [u8; NETCODE_USER_DATA_BYTES] {
+    let mut user_data = [0u8; NETCODE_USER_DATA_BYTES];
+    let mut len = name.len().min(IDENTITY_OFFSET - 1).min(u8::MAX as usize);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    user_data[0] = len as u8;
+    user_data[1..=len].copy_from_slice(&name.as_bytes()[..len]);
+    user_data
+}
+
+pub fn name_from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Option<String> {
+    let len = user_data[0] as usize;
+    if len == 0 {
+        return None;
+    }
+    String::from_utf8(user_data[1..=len].to_vec()).ok()
+}
+
+/// server side rules for player names
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NameRules {
+    pub min_len: usize,
+    pub max_len: usize,
+    /// allow non-ascii letters and digits
+    pub allow_unicode: bool,
+    /// lower case words that must not appear anywhere in a name
+    pub profanity: Vec<String>,
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        Self {
+            min_len: 2,
+            max_len: 16,
+            allow_unicode: false,
+            profanity: Vec::new(),
+        }
+    }
+}
+
+impl NameRules {
+    /// load a profanity list file: one word per line, empty lines and lines starting with #
+    /// are ignored
+    pub fn load_profanity_list<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let content = fs::read_to_string(path)?;
+        self.profanity = content
+            .lines()
+            .map(|line| line.trim().to_lowercase())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        Ok(())
+    }
+
+    fn allowed_char(&self, c: char) -> bool {
+        if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ' ') {
+            true
+        } else {
+            self.allow_unicode && c.is_alphanumeric()
+        }
+    }
+
+    /// strip disallowed characters and whitespace runs, truncate to max_len. Names that are
+    /// too short or contain profanity are replaced by DEFAULT_NAME.
+    pub fn sanitize(&self, raw: &str) -> String {
+        let filtered: String = raw.chars().filter(|c| self.allowed_char(*c)).collect();
+        let name: String = filtered
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(self.max_len)
+            .collect();
+
+        let lower = name.to_lowercase();
+        if name.chars().count() < self.min_len
+            || self
+                .profanity
+                .iter()
+                .any(|word| lower.contains(word.as_str()))
+        {
+            DEFAULT_NAME.to_string()
+        } else {
+            name
+        }
+    }
+
+    /// sanitize and de-duplicate a requested name by appending a number
+    pub fn resolve<F: Fn(&str) -> bool>(&self, raw: &str, is_taken: F) -> String {
+        let name = self.sanitize(raw);
+        if !is_taken(&name) {
+            return name;
+        }
+        (2..)
+            .map(|n| {
+                let suffix = n.to_string();
+                let base: String = name
+                    .chars()
+                    .take(self.max_len.saturating_sub(suffix.len()))
+                    .collect();
+                format!("{}{}", base, suffix)
+            })
+            .find(|candidate| !is_taken(candidate))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize() {
+        let rules = NameRules {
+            profanity: vec!["darn".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(rules.sanitize("  fast\t  eddie! "), "fast eddie");
+        assert_eq!(rules.sanitize("x"), DEFAULT_NAME);
+        assert_eq!(rules.sanitize("DarnIt"), DEFAULT_NAME);
+        assert_eq!(rules.sanitize("jürgen"), "jrgen");
+        assert_eq!(
+            rules.sanitize("abcdefghijklmnopqrstuvwxyz"),
+            "abcdefghijklmnop"
+        );
+    }
+
+    #[test]
+    fn resolve_appends_a_number() {
+        let rules = NameRules::default();
+        let taken = ["eddie", "eddie2", DEFAULT_NAME];
+        let is_taken = |name: &str| taken.contains(&name);
+        assert_eq!(rules.resolve("frank", is_taken), "frank");
+        assert_eq!(rules.resolve("eddie", is_taken), "eddie3");
+        assert_eq!(rules.resolve("?", is_taken), "player2");
+    }
+
+    #[test]
+    fn resolve_keeps_max_len() {
+        let rules = NameRules::default();
+        let name = rules.resolve("abcdefghijklmnop", |name| name == "abcdefghijklmnop");
+        assert_eq!(name, "abcdefghijklmno2");
+    }
+
+    #[test]
+    fn user_data_round_trip() {
+        assert_eq!(
+            name_from_user_data(&name_to_user_data("eddie")).as_deref(),
+            Some("eddie")
+        );
+        assert_eq!(name_from_user_data(&name_to_user_data("")), None);
+        // truncated on a char boundary in front of the identity
+        let long = "ä".repeat(IDENTITY_OFFSET);
+        let name = name_from_user_data(&name_to_user_data(&long)).unwrap();
+        assert!(name.len() < IDENTITY_OFFSET && long.starts_with(&name));
+    }
+}
@@ -0,0 +1,224 @@
+//! pre-connect network diagnostics: UDP echo probes against the server's echo responder,
+//! measuring round trip time and packet loss.
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    net::{SocketAddr, UdpSocket},
+    path::Path,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+const PROBE_MAGIC: &[u8; 8] = b"SHTRPROB";
+const PROBE_LEN: usize = PROBE_MAGIC.len() + 4;
+
+/// the echo responder listens next to the game port
+pub fn echo_addr(server_addr: SocketAddr) -> SocketAddr {
+    let mut addr = server_addr;
+    addr.set_port(server_addr.port().wrapping_add(1));
+    addr
+}
+
+/// server side: answer probe packets on echo_addr(server_addr) from a background thread
+pub fn spawn_echo_responder(server_addr: SocketAddr) -> io::Result<JoinHandle<()>> {
+    let socket = UdpSocket::bind(echo_addr(server_addr))?;
+    Ok(thread::spawn(move || {
+        let mut buf = [0u8; PROBE_LEN];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((PROBE_LEN, from)) if buf.starts_with(PROBE_MAGIC) => {
+                    let _ = socket.send_to(&buf, from);
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    warn!("echo responder: {}", e);
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+        }
+    }))
+}
+
+#[derive(Debug, Default)]
+pub struct NetcheckReport {
+    pub server_addr: Option<SocketAddr>,
+    pub sent: u32,
+    pub received: u32,
+    pub rtt_min: Option<Duration>,
+    pub rtt_avg: Option<Duration>,
+    pub rtt_max: Option<Duration>,
+    /// mean absolute difference between consecutive round trip times
+    pub jitter: Option<Duration>,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl NetcheckReport {
+    pub fn loss(&self) -> f32 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        1.0 - self.received as f32 / self.sent as f32
+    }
+
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    fn analyze(&mut self, server_addr: SocketAddr) {
+        if self.received == 0 {
+            self.warnings.push(
+                "no echo replies: the server is not running, the address is wrong or UDP is \
+                 blocked by a firewall"
+                    .to_string(),
+            );
+            if !is_private(server_addr) {
+                self.warnings.push(format!(
+                    "server is behind a public address: make sure UDP ports {} and {} are \
+                     forwarded to the server host if it is behind a NAT router",
+                    server_addr.port(),
+                    echo_addr(server_addr).port()
+                ));
+            }
+            return;
+        }
+        if self.loss() > 0.05 {
+            self.warnings.push(format!(
+                "high packet loss ({:.0}%): expect rubber banding",
+                self.loss() * 100.0
+            ));
+        }
+        if matches!(self.rtt_avg, Some(rtt) if rtt > Duration::from_millis(150)) {
+            self.warnings
+                .push("high latency: consider a closer server".to_string());
+        }
+        if matches!(self.jitter, Some(jitter) if jitter > Duration::from_millis(30)) {
+            self.warnings
+                .push("high jitter: wireless or congested connection?".to_string());
+        }
+    }
+}
+
+impl fmt::Display for NetcheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(addr) = self.server_addr {
+            writeln!(f, "server: {} (echo {})", addr, echo_addr(addr))?;
+        }
+        writeln!(
+            f,
+            "probes: {} sent, {} received, {:.1}% loss",
+            self.sent,
+            self.received,
+            self.loss() * 100.0
+        )?;
+        if let (Some(min), Some(avg), Some(max)) = (self.rtt_min, self.rtt_avg, self.rtt_max) {
+            writeln!(f, "rtt: min {:?} avg {:?} max {:?}", min, avg, max)?;
+        }
+        if let Some(jitter) = self.jitter {
+            writeln!(f, "jitter: {:?}", jitter)?;
+        }
+        for error in &self.errors {
+            writeln!(f, "error: {}", error)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_private(addr: SocketAddr) -> bool {
+    match addr {
+        SocketAddr::V4(addr) => {
+            let ip = addr.ip();
+            ip.is_private() || ip.is_loopback() || ip.is_link_local()
+        }
+        SocketAddr::V6(addr) => addr.ip().is_loopback(),
+    }
+}
+
+/// send a probe every `interval` for `duration` and collect the replies
+pub fn run_netcheck(
+    server_addr: SocketAddr,
+    duration: Duration,
+    interval: Duration,
+) -> io::Result<NetcheckReport> {
+    let bind_addr = if server_addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind_addr)?;
+    socket.set_read_timeout(Some(Duration::from_millis(5)))?;
+    let target = echo_addr(server_addr);
+
+    let mut report = NetcheckReport {
+        server_addr: Some(server_addr),
+        ..Default::default()
+    };
+    let mut in_flight = HashMap::new();
+    let mut rtts = Vec::new();
+    let start = Instant::now();
+    let mut next_send = start;
+    // keep listening a bit longer for late replies
+    let grace = Duration::from_secs(1);
+    let mut buf = [0u8; PROBE_LEN];
+
+    while start.elapsed() < duration + grace {
+        if start.elapsed() < duration && Instant::now() >= next_send {
+            let mut probe = [0u8; PROBE_LEN];
+            probe[..PROBE_MAGIC.len()].copy_from_slice(PROBE_MAGIC);
+            probe[PROBE_MAGIC.len()..].copy_from_slice(&report.sent.to_le_bytes());
+            match socket.send_to(&probe, target) {
+                Ok(_) => {
+                    in_flight.insert(report.sent, Instant::now());
+                }
+                Err(e) => report.errors.push(format!("send: {}", e)),
+            }
+            report.sent += 1;
+            next_send += interval;
+        }
+
+        match socket.recv_from(&mut buf) {
+            Ok((PROBE_LEN, from)) if from == target && buf.starts_with(PROBE_MAGIC) => {
+                let mut seq = [0u8; 4];
+                seq.copy_from_slice(&buf[PROBE_MAGIC.len()..]);
+                if let Some(sent_at) = in_flight.remove(&u32::from_le_bytes(seq)) {
+                    report.received += 1;
+                    rtts.push(sent_at.elapsed());
+                }
+            }
+            Ok(_) => (),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => report.errors.push(format!("receive: {}", e)),
+        }
+    }
+
+    if !rtts.is_empty() {
+        report.rtt_min = rtts.iter().min().copied();
+        report.rtt_max = rtts.iter().max().copied();
+        report.rtt_avg = Some(rtts.iter().sum::<Duration>() / rtts.len() as u32);
+        if rtts.len() > 1 {
+            let jitter_sum: Duration = rtts
+                .windows(2)
+                .map(|pair| {
+                    if pair[0] > pair[1] {
+                        pair[0] - pair[1]
+                    } else {
+                        pair[1] - pair[0]
+                    }
+                })
+                .sum();
+            report.jitter = Some(jitter_sum / (rtts.len() - 1) as u32);
+        }
+    }
+    report.analyze(server_addr);
+    Ok(report)
+}
@@ -0,0 +1,173 @@
+//! server performance statistics: frame and physics time, entity counts, and per client input
+//! queue depth and snapshot bandwidth. Servers with a display show them in a window, headless
+//! servers log a summary periodically.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    time::Instant,
+};
+
+use bevy::prelude::*;
+
+/// frames of frame and physics time history
+pub const HISTORY_FRAMES: usize = 300;
+
+/// seconds of bandwidth history per client
+pub const HISTORY_SECONDS: usize = 60;
+
+/// seconds between the log lines of headless servers
+const LOG_INTERVAL: f64 = 10.0;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EntityCounts {
+    pub total: usize,
+    pub players: usize,
+    pub bots: usize,
+    pub projectiles: usize,
+    pub crates: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct ClientPerf {
+    /// received inputs the simulation did not apply yet
+    pub input_queue: usize,
+    /// snapshot bytes sent in the running second
+    bytes: usize,
+    /// snapshot bytes per second, oldest first
+    pub bytes_per_second: VecDeque<f32>,
+}
+
+#[derive(Debug, Default)]
+pub struct ServerPerf {
+    frame_start: Option<Instant>,
+    physics_start: Option<Instant>,
+    /// start of the running bandwidth second
+    second_start: f64,
+    /// duration of each server frame (ms), oldest first
+    pub frame_times: VecDeque<f32>,
+    /// time spent in the physics stages per frame (ms), oldest first
+    pub physics_times: VecDeque<f32>,
+    pub entities: EntityCounts,
+    pub clients: HashMap<u64, ClientPerf>,
+    /// bytes by channel since the start
+    pub sent_bytes: HashMap<&'static str, u64>,
+    pub received_bytes: HashMap<&'static str, u64>,
+    /// client connections since the start
+    pub connects: u64,
+    pub disconnects: u64,
+    /// clients turned away because the server and its join queue were full
+    pub rejected_clients: u64,
+}
+
+fn push_sample(samples: &mut VecDeque<f32>, value: f32, max_len: usize) {
+    if samples.len() >= max_len {
+        samples.pop_front();
+    }
+    samples.push_back(value);
+}
+
+fn elapsed_ms(start: Instant) -> f32 {
+    start.elapsed().as_secs_f32() * 1000.0
+}
+
+pub fn average(samples: &VecDeque<f32>) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    samples.iter().sum::<f32>() / samples.len() as f32
+}
+
+pub fn max(samples: &VecDeque<f32>) -> f32 {
+    samples.iter().copied().fold(0.0, f32::max)
+}
+
+impl ServerPerf {
+    /// a snapshot of `bytes` went out to `client_id`
+    pub fn snapshot_sent(&mut self, client_id: u64, bytes: usize) {
+        self.clients.entry(client_id).or_default().bytes += bytes;
+        *self.sent_bytes.entry("network_frame").or_default() += bytes as u64;
+    }
+
+    /// a message of `bytes` arrived on the client channel `channel`
+    pub fn message_received(&mut self, channel: &'static str, bytes: usize) {
+        *self.received_bytes.entry(channel).or_default() += bytes as u64;
+    }
+
+    /// one line for the log
+    pub fn summary(&self) -> String {
+        let entities = &self.entities;
+        let mut summary = format!(
+            "frame {:.2} ms (max {:.2}), physics {:.2} ms (max {:.2}), {} entities: {} players, {} bots, {} projectiles, {} crates",
+            average(&self.frame_times),
+            max(&self.frame_times),
+            average(&self.physics_times),
+            max(&self.physics_times),
+            entities.total,
+            entities.players,
+            entities.bots,
+            entities.projectiles,
+            entities.crates,
+        );
+        let mut clients: Vec<_> = self.clients.iter().collect();
+        clients.sort_unstable_by_key(|(id, _)| **id);
+        for (id, client) in clients {
+            let _ = write!(
+                summary,
+                ", client {}: {} queued inputs, {:.0} B/s",
+                id,
+                client.input_queue,
+                client.bytes_per_second.back().copied().unwrap_or_default()
+            );
+        }
+        summary
+    }
+}
+
+pub fn frame_start_system(mut perf: ResMut<ServerPerf>) {
+    perf.frame_start = Some(Instant::now());
+}
+
+/// close the frame time sample, and the bandwidth samples once per second
+pub fn frame_end_system(time: Res<Time>, mut perf: ResMut<ServerPerf>) {
+    if let Some(start) = perf.frame_start.take() {
+        push_sample(&mut perf.frame_times, elapsed_ms(start), HISTORY_FRAMES);
+    }
+    let now = time.seconds_since_startup();
+    let elapsed = now - perf.second_start;
+    if elapsed < 1.0 {
+        return;
+    }
+    perf.second_start = now;
+    for client in perf.clients.values_mut() {
+        let bytes_per_second = client.bytes as f32 / elapsed as f32;
+        push_sample(
+            &mut client.bytes_per_second,
+            bytes_per_second,
+            HISTORY_SECONDS,
+        );
+        client.bytes = 0;
+    }
+}
+
+/// runs in the first physics stage, before the simulation step
+pub fn physics_start_system(mut perf: ResMut<ServerPerf>) {
+    perf.physics_start = Some(Instant::now());
+}
+
+/// runs in the physics writeback stage, after the simulation step
+pub fn physics_end_system(mut perf: ResMut<ServerPerf>) {
+    if let Some(start) = perf.physics_start.take() {
+        push_sample(&mut perf.physics_times, elapsed_ms(start), HISTORY_FRAMES);
+    }
+}
+
+/// headless servers: log the summary every LOG_INTERVAL
+pub fn log_perf_system(time: Res<Time>, perf: Res<ServerPerf>, mut last_log: Local<f64>) {
+    let now = time.seconds_since_startup();
+    if now - *last_log < LOG_INTERVAL {
+        return;
+    }
+    *last_log = now;
+    info!("perf: {}", perf.summary());
+}
@@ -0,0 +1,288 @@
+//! pickups: health packs, armor, ammo and power-ups placed by the map. The level setup spawns them on
+//! the server and on clients alike. The server hands them out to players touching them and
+//! replicates who took which pickup and when it respawns, clients only show them. Like level
+//! devices, pickups are identified by their position across the network.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    health::{Armor, Health},
+    spectator::Spectator,
+    status::{StatusEffects, StatusKind},
+    targets::PLAYER_CENTER_OFFSET,
+    weapon::Ammo,
+    Player,
+};
+
+/// players whose center is closer than this to a pickup take it
+pub const PICKUP_RADIUS: f32 = 1.0;
+
+/// replicated positions closer than this belong to the same pickup
+const PICKUP_MATCH_DISTANCE: f32 = 0.1;
+
+/// turns per second of the pickup visuals
+const PICKUP_SPIN_SPEED: f32 = 0.5;
+
+pub const HEALTH_PACK_AMOUNT: f32 = 25.0;
+pub const ARMOR_PICKUP_AMOUNT: f32 = 25.0;
+pub const AMMO_PICKUP_GRENADES: u32 = 2;
+pub const AMMO_PICKUP_ROCKETS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PickupKind {
+    Health,
+    Armor,
+    Ammo,
+    /// power-ups granting a status effect
+    SpeedBoost,
+    Shield,
+}
+
+impl PickupKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PickupKind::Health => "health",
+            PickupKind::Armor => "armor",
+            PickupKind::Ammo => "ammo",
+            PickupKind::SpeedBoost => "speed boost",
+            PickupKind::Shield => "shield",
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            PickupKind::Health => Color::rgb(0.9, 0.2, 0.2),
+            PickupKind::Armor => Color::rgb(0.2, 0.5, 0.9),
+            PickupKind::Ammo => Color::rgb(0.9, 0.8, 0.2),
+            PickupKind::SpeedBoost | PickupKind::Shield => self.status().unwrap().color(),
+        }
+    }
+
+    /// status effect of power-ups
+    pub fn status(&self) -> Option<StatusKind> {
+        match self {
+            PickupKind::SpeedBoost => Some(StatusKind::SpeedBoost),
+            PickupKind::Shield => Some(StatusKind::Shield),
+            PickupKind::Health | PickupKind::Armor | PickupKind::Ammo => None,
+        }
+    }
+
+    /// seconds until a taken pickup is back
+    pub fn respawn_time(&self) -> f32 {
+        match self {
+            PickupKind::Health => 20.0,
+            PickupKind::Armor => 30.0,
+            PickupKind::Ammo => 15.0,
+            PickupKind::SpeedBoost | PickupKind::Shield => 45.0,
+        }
+    }
+}
+
+#[derive(Debug, Component)]
+pub struct Pickup {
+    pub kind: PickupKind,
+    /// runs while the pickup is taken, None while it can be picked up
+    pub respawn: Option<Timer>,
+}
+
+impl Pickup {
+    pub fn new(kind: PickupKind) -> Self {
+        Self {
+            kind,
+            respawn: None,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.respawn.is_none()
+    }
+
+    pub fn take(&mut self) {
+        self.respawn = Some(Timer::from_seconds(self.kind.respawn_time(), false));
+    }
+
+    /// server: a taken pickup comes back with the next update, for a new match
+    pub fn respawn_now(&mut self) {
+        if let Some(respawn) = &mut self.respawn {
+            let duration = respawn.duration();
+            respawn.set_elapsed(duration);
+        }
+    }
+
+    /// true if the replicated `position` refers to the pickup at `translation`
+    pub fn matches(translation: Vec3, position: Vec3) -> bool {
+        translation.distance(position) < PICKUP_MATCH_DISTANCE
+    }
+}
+
+/// give the item of a pickup to a player. False if the player has no use for it (e.g. full
+/// health or the power-up still active), the pickup stays then.
+pub fn grant(
+    kind: PickupKind,
+    health: &mut Health,
+    armor: Option<Mut<Armor>>,
+    ammo: Option<Mut<Ammo>>,
+    status: Option<Mut<StatusEffects>>,
+) -> bool {
+    match kind {
+        PickupKind::Health if health.current < health.max => {
+            health.current = (health.current + HEALTH_PACK_AMOUNT).min(health.max);
+            true
+        }
+        PickupKind::Armor => match armor {
+            Some(mut armor) if armor.current < armor.max => {
+                armor.current = (armor.current + ARMOR_PICKUP_AMOUNT).min(armor.max);
+                true
+            }
+            _ => false,
+        },
+        PickupKind::Ammo => match ammo {
+            Some(mut ammo) if !ammo.is_full() => {
+                ammo.refill(AMMO_PICKUP_GRENADES, AMMO_PICKUP_ROCKETS);
+                true
+            }
+            _ => false,
+        },
+        PickupKind::SpeedBoost | PickupKind::Shield => match (kind.status(), status) {
+            (Some(effect), Some(mut status)) if !status.has(effect) => {
+                status.apply(effect, effect.duration(), None);
+                true
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+pub fn spawn_pickup(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    translation: Vec3,
+    kind: PickupKind,
+) -> Entity {
+    let mesh = match kind {
+        PickupKind::Health => Mesh::from(shape::Cube::new(0.4)),
+        PickupKind::Armor => Mesh::from(shape::Icosphere {
+            radius: 0.25,
+            subdivisions: 2,
+        }),
+        PickupKind::Ammo => Mesh::from(shape::Box::new(0.5, 0.25, 0.25)),
+        PickupKind::SpeedBoost | PickupKind::Shield => Mesh::from(shape::Torus {
+            radius: 0.25,
+            ring_radius: 0.06,
+            ..default()
+        }),
+    };
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(mesh),
+            material: materials.add(StandardMaterial {
+                base_color: kind.color(),
+                emissive: kind.color() * 0.3,
+                ..default()
+            }),
+            transform: Transform::from_translation(translation),
+            ..default()
+        })
+        .insert(Pickup::new(kind))
+        .id()
+}
+
+/// server: a player took a pickup
+#[derive(Debug, Clone)]
+pub struct PickupTakenEvent {
+    pub position: Vec3,
+    pub kind: PickupKind,
+    pub player: Entity,
+}
+
+/// server: a taken pickup is available again
+#[derive(Debug, Clone)]
+pub struct PickupRespawnEvent {
+    pub position: Vec3,
+}
+
+/// server: bring back taken pickups when their timer runs out and hand out available ones to
+/// the first player touching them
+#[allow(clippy::type_complexity)]
+pub fn pickup_system(
+    time: Res<Time>,
+    mut pickups: Query<(&Transform, &mut Pickup)>,
+    mut players: Query<
+        (
+            Entity,
+            &Transform,
+            &mut Health,
+            Option<&mut Armor>,
+            Option<&mut Ammo>,
+            Option<&mut StatusEffects>,
+        ),
+        (With<Player>, Without<Spectator>, Without<Pickup>),
+    >,
+    mut taken_events: EventWriter<PickupTakenEvent>,
+    mut respawn_events: EventWriter<PickupRespawnEvent>,
+) {
+    for (transform, mut pickup) in &mut pickups {
+        if let Some(respawn) = &mut pickup.respawn {
+            respawn.tick(time.delta());
+            if !respawn.finished() {
+                continue;
+            }
+            pickup.respawn = None;
+            respawn_events.send(PickupRespawnEvent {
+                position: transform.translation,
+            });
+        }
+        for (entity, player_transform, mut health, armor, ammo, status) in &mut players {
+            let center = player_transform.translation + PLAYER_CENTER_OFFSET;
+            if health.is_dead()
+                || center.distance(transform.translation) > PICKUP_RADIUS
+                || !grant(pickup.kind, &mut health, armor, ammo, status)
+            {
+                continue;
+            }
+            pickup.take();
+            taken_events.send(PickupTakenEvent {
+                position: transform.translation,
+                kind: pickup.kind,
+                player: entity,
+            });
+            break;
+        }
+    }
+}
+
+/// client: spin available pickups, hide taken ones
+pub fn pickup_visual_system(
+    time: Res<Time>,
+    mut pickups: Query<(&Pickup, &mut Transform, &mut Visibility)>,
+) {
+    let angle = time.seconds_since_startup() as f32 * PICKUP_SPIN_SPEED * std::f32::consts::TAU;
+    for (pickup, mut transform, mut visibility) in &mut pickups {
+        if visibility.is_visible != pickup.is_available() {
+            visibility.is_visible = pickup.is_available();
+        }
+        transform.rotation = Quat::from_rotation_y(angle);
+    }
+}
+
+/// client: mark the pickup at `position` taken or available, as replicated by the server
+pub fn set_pickup_available(
+    pickups: &mut Query<(&Transform, &mut Pickup)>,
+    position: Vec3,
+    available: bool,
+) {
+    for (transform, mut pickup) in pickups.iter_mut() {
+        if !Pickup::matches(transform.translation, position) {
+            continue;
+        }
+        if available {
+            pickup.respawn = None;
+        } else {
+            // clients never tick the timer, the server reports the respawn
+            pickup.take();
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! entity pools for frequently spawned, short lived things (projectiles, effects).
+//! Released entities are hidden, marked Free and kept around instead of being despawned, which
+//! avoids archetype churn and asset allocations at high spawn rates.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PoolKind {
+    Projectile,
+    Grenade,
+    Rocket,
+    ExplosionEffect,
+    JumpPadEffect,
+    TeleportEffect,
+    Particle,
+    Tracer,
+    ImpactFlash,
+    Decal,
+}
+
+/// marks an entity owned by a pool
+#[derive(Debug, Component)]
+pub struct Pooled(pub PoolKind);
+
+/// marks a pooled entity waiting for reuse. It keeps the components of its last use, systems
+/// working on pooled entities skip it with `Without<Free>`.
+#[derive(Debug, Component)]
+pub struct Free;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PoolStats {
+    /// entities spawned because the pool was empty
+    pub spawned: usize,
+    /// acquisitions served from the pool
+    pub reused: usize,
+    pub in_use: usize,
+    pub free: usize,
+}
+
+#[derive(Default)]
+pub struct EntityPools {
+    free: HashMap<PoolKind, Vec<Entity>>,
+    stats: HashMap<PoolKind, PoolStats>,
+}
+
+impl EntityPools {
+    /// take an entity out of the pool and move it to `transform`, or spawn a new one from
+    /// `make_bundle` if the pool is empty. Reused entities keep the components of their first
+    /// spawn, per instance state has to be (re-)inserted by the caller.
+    pub fn acquire<B: Bundle>(
+        &mut self,
+        commands: &mut Commands,
+        kind: PoolKind,
+        transform: Transform,
+        make_bundle: impl FnOnce() -> B,
+    ) -> Entity {
+        let stats = self.stats.entry(kind).or_default();
+        stats.in_use += 1;
+        match self.free.entry(kind).or_default().pop() {
+            Some(entity) => {
+                stats.reused += 1;
+                stats.free -= 1;
+                commands
+                    .entity(entity)
+                    .insert(transform)
+                    .insert(Visibility { is_visible: true })
+                    .remove::<Free>();
+                entity
+            }
+            None => {
+                stats.spawned += 1;
+                commands
+                    .spawn_bundle(make_bundle())
+                    .insert(transform)
+                    .insert(Pooled(kind))
+                    .id()
+            }
+        }
+    }
+
+    /// hide the entity and keep it for reuse
+    pub fn release(&mut self, commands: &mut Commands, entity: Entity, kind: PoolKind) {
+        commands
+            .entity(entity)
+            .insert(Visibility { is_visible: false })
+            .insert(Free);
+        self.free.entry(kind).or_default().push(entity);
+        let stats = self.stats.entry(kind).or_default();
+        stats.in_use = stats.in_use.saturating_sub(1);
+        stats.free += 1;
+    }
+
+    pub fn stats(&self) -> impl Iterator<Item = (&PoolKind, &PoolStats)> {
+        self.stats.iter()
+    }
+}
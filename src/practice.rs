@@ -0,0 +1,97 @@
+//! practice runs: the client records speed and height of the local player over a time limited
+//! run and keeps the previous run as a ghost to compare the next one against.
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+
+use crate::controller::FpsController;
+
+/// practice runs stop recording after this long (seconds)
+pub const MAX_RUN_TIME: f32 = 60.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RunSample {
+    /// time since the start of the run (seconds)
+    pub time: f32,
+    /// lateral speed
+    pub speed: f32,
+    pub height: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RunRecording {
+    pub samples: Vec<RunSample>,
+}
+
+impl RunRecording {
+    pub fn duration(&self) -> f32 {
+        self.samples.last().map_or(0.0, |sample| sample.time)
+    }
+
+    /// last sample at or before `time`
+    pub fn sample_at(&self, time: f32) -> Option<&RunSample> {
+        let index = self.samples.partition_point(|sample| sample.time <= time);
+        index.checked_sub(1).map(|index| &self.samples[index])
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PracticeRuns {
+    /// run being recorded
+    pub current: Option<RunRecording>,
+    /// last finished run
+    pub ghost: Option<RunRecording>,
+}
+
+impl PracticeRuns {
+    pub fn is_recording(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn start(&mut self) {
+        self.current = Some(RunRecording::default());
+    }
+
+    /// finish the current run, it becomes the ghost of the next one
+    pub fn stop(&mut self) {
+        if let Some(run) = self.current.take() {
+            if !run.samples.is_empty() {
+                self.ghost = Some(run);
+            }
+        }
+    }
+
+    /// add a sample to the current run, stops the run at MAX_RUN_TIME
+    pub fn record(&mut self, dt: f32, speed: f32, height: f32) {
+        let run = match self.current.as_mut() {
+            Some(run) => run,
+            None => return,
+        };
+        let time = run.samples.last().map_or(0.0, |sample| sample.time + dt);
+        run.samples.push(RunSample {
+            time,
+            speed,
+            height,
+        });
+        if time >= MAX_RUN_TIME {
+            self.stop();
+        }
+    }
+}
+
+/// record the locally simulated player into the current practice run
+pub fn record_practice_run_system(
+    time: Res<Time>,
+    mut runs: ResMut<PracticeRuns>,
+    player_query: Query<(&Transform, &FpsController)>,
+) {
+    if !runs.is_recording() {
+        return;
+    }
+    if let Ok((transform, controller)) = player_query.get_single() {
+        runs.record(
+            time.delta_seconds(),
+            controller.velocity.xz().length(),
+            transform.translation.y,
+        );
+    }
+}
@@ -1,19 +1,83 @@
+//! client side extrapolation of replicated entities. The limits are network settings, in
+//! network.toml:
+//!     max_ticks_steady = 30
+//!     max_ticks_erratic = 6
+//!     max_distance = 3
+//!     erratic_variance = 25
+
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const NETWORK_CONFIG_FILE: &str = "network.toml";
+
+/// how far the client extrapolates replicated entities past the last server update
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtrapolationLimits {
+    /// tick limit for entities with constant velocity (projectiles)
+    pub max_ticks_steady: u32,
+    /// tick limit for entities changing velocity all the time (players)
+    pub max_ticks_erratic: u32,
+    /// distance limit from the last server position
+    pub max_distance: f32,
+    /// velocity variance at which an entity counts as fully erratic
+    pub erratic_variance: f32,
+}
+
+impl Default for ExtrapolationLimits {
+    fn default() -> Self {
+        Self {
+            max_ticks_steady: 30,
+            max_ticks_erratic: 6,
+            max_distance: 3.0,
+            erratic_variance: 25.0,
+        }
+    }
+}
 
 #[derive(Component, Default, Debug)]
 pub struct VelocityExtrapolate {
     pub velocity: Vec3,
     pub base_tick: u32,
+    /// smoothed squared velocity change between server updates
+    pub variance: f32,
+    /// extrapolation hit the limits, the entity is shown as stale
+    pub stale: bool,
 }
 
 impl VelocityExtrapolate {
-    pub fn apply(&self, tick: u32, base_translation: Vec3) -> Vec3 {
+    /// new server state
+    pub fn update(&mut self, velocity: Vec3, tick: u32) {
+        let change = (velocity - self.velocity).length_squared();
+        self.variance += (change - self.variance) * 0.2;
+        self.velocity = velocity;
+        self.base_tick = tick;
+    }
+
+    pub fn max_ticks(&self, limits: &ExtrapolationLimits) -> u32 {
+        let erratic = (self.variance / limits.erratic_variance).clamp(0.0, 1.0);
+        let steady = limits.max_ticks_steady as f32;
+        let erratic_ticks = limits.max_ticks_erratic as f32;
+        (steady + (erratic_ticks - steady) * erratic).round() as u32
+    }
+
+    /// extrapolated translation at `tick` and whether the limits were hit
+    pub fn apply(
+        &self,
+        tick: u32,
+        base_translation: Vec3,
+        limits: &ExtrapolationLimits,
+    ) -> (Vec3, bool) {
         if tick <= self.base_tick {
-            return base_translation;
+            return (base_translation, false);
         }
+        let max_ticks = self.max_ticks(limits);
         let ticks = tick - self.base_tick;
-        let f = (ticks as f32) / 60.0;
+        let stale = ticks > max_ticks;
+        let f = (ticks.min(max_ticks) as f32) / 60.0;
 
-        base_translation + self.velocity * f
+        let offset = (self.velocity * f).clamp_length_max(limits.max_distance);
+        let stale = stale || offset.length() >= limits.max_distance;
+        (base_translation + offset, stale)
     }
 }
@@ -0,0 +1,291 @@
+//! prefab registry: replicated objects (props, dropped weapons, projectiles) defined by data.
+//! Spawn messages name the prefab, server and client build the object from the same
+//! definitions. `prefabs.ron` adds prefabs or replaces built-in ones by id:
+//!
+//!     {
+//!         "crate": (
+//!             mesh: Cube(size: 0.8),
+//!             color: (0.55, 0.35, 0.15),
+//!             collider: Some(Cuboid(half_extents: (0.4, 0.4, 0.4))),
+//!             surface: Some(Wood),
+//!         ),
+//!     }
+
+use std::{collections::HashMap, fs, path::Path};
+
+use bevy::{ecs::system::EntityCommands, prelude::*};
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::SurfaceMaterial, lod::SimulationLod, projectile::ProjectileKind, weapon::WeaponKind,
+    CRATE_COLOR, CRATE_HALF_SIZE,
+};
+
+pub const PREFABS_FILE: &str = "prefabs.ron";
+
+pub const FIREBALL: &str = "fireball";
+pub const GRENADE: &str = "grenade";
+pub const ROCKET: &str = "rocket";
+pub const BOX: &str = "box";
+pub const CRATE: &str = "crate";
+
+/// id of the prefab of a weapon lying around
+pub fn weapon(kind: WeaponKind) -> &'static str {
+    match kind {
+        WeaponKind::Hitscan => "weapon_hitscan",
+        WeaponKind::Rocket => "weapon_rocket",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PrefabMesh {
+    Cube { size: f32 },
+    Box { size: Vec3 },
+    Sphere { radius: f32 },
+}
+
+impl PrefabMesh {
+    fn mesh(&self) -> Mesh {
+        match self {
+            PrefabMesh::Cube { size } => Mesh::from(shape::Cube::new(*size)),
+            PrefabMesh::Box { size } => Mesh::from(shape::Box::new(size.x, size.y, size.z)),
+            PrefabMesh::Sphere { radius } => Mesh::from(shape::Icosphere {
+                radius: *radius,
+                subdivisions: 5,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PrefabCollider {
+    Cuboid { half_extents: Vec3 },
+    Ball { radius: f32 },
+}
+
+impl PrefabCollider {
+    fn collider(&self) -> Collider {
+        match self {
+            PrefabCollider::Cuboid { half_extents } => {
+                Collider::cuboid(half_extents.x, half_extents.y, half_extents.z)
+            }
+            PrefabCollider::Ball { radius } => Collider::ball(*radius),
+        }
+    }
+}
+
+/// how a prefab is simulated and replicated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrefabReplication {
+    /// clients extrapolate the position with the velocity of the last network frame
+    pub extrapolate: bool,
+    /// the server simulates the object less often far away from players, see `lod`
+    pub simulation_lod: bool,
+}
+
+impl Default for PrefabReplication {
+    fn default() -> Self {
+        Self {
+            extrapolate: true,
+            simulation_lod: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Prefab {
+    pub mesh: PrefabMesh,
+    /// rgb
+    pub color: [f32; 3],
+    /// dynamic rigid body with this collider on the server, None for objects moved by code
+    pub collider: Option<PrefabCollider>,
+    pub surface: Option<SurfaceMaterial>,
+    /// simulated as a projectile of this kind (kinematic, see `projectile`) instead
+    pub projectile: Option<ProjectileKind>,
+    pub replication: PrefabReplication,
+}
+
+impl Default for Prefab {
+    /// magenta cube, stands in for unknown prefabs
+    fn default() -> Self {
+        Self {
+            mesh: PrefabMesh::Cube { size: 0.3 },
+            color: [1.0, 0.0, 1.0],
+            collider: None,
+            surface: None,
+            projectile: None,
+            replication: PrefabReplication::default(),
+        }
+    }
+}
+
+impl Prefab {
+    fn prop(mesh: PrefabMesh, color: [f32; 3], half_extents: Vec3) -> Self {
+        Self {
+            mesh,
+            color,
+            collider: Some(PrefabCollider::Cuboid { half_extents }),
+            ..default()
+        }
+    }
+
+    fn projectile(kind: ProjectileKind, radius: f32, color: [f32; 3]) -> Self {
+        Self {
+            mesh: PrefabMesh::Sphere { radius },
+            color,
+            projectile: Some(kind),
+            ..default()
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        let [r, g, b] = self.color;
+        Color::rgb(r, g, b)
+    }
+
+    /// the visible part of the prefab at `transform`
+    pub fn pbr_bundle(
+        &self,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        transform: Transform,
+    ) -> PbrBundle {
+        PbrBundle {
+            mesh: meshes.add(self.mesh.mesh()),
+            material: materials.add(self.color().into()),
+            transform,
+            ..default()
+        }
+    }
+
+    /// server: physics of the prefab, a dynamic rigid body with collider and surface
+    pub fn insert_physics(&self, entity: &mut EntityCommands, velocity: Vec3) {
+        if let Some(collider) = &self.collider {
+            entity
+                .insert(RigidBody::Dynamic)
+                .insert(collider.collider())
+                .insert(Velocity {
+                    linvel: velocity,
+                    ..default()
+                })
+                .insert(Sleeping::default());
+        }
+        if let Some(surface) = self.surface {
+            entity.insert(surface);
+        }
+        if self.replication.simulation_lod {
+            entity.insert(SimulationLod::default());
+        }
+    }
+}
+
+/// all prefabs by id, the built-in ones plus PREFABS_FILE
+pub struct PrefabRegistry {
+    prefabs: HashMap<String, Prefab>,
+    missing: Prefab,
+}
+
+impl Default for PrefabRegistry {
+    fn default() -> Self {
+        let prefabs = [
+            (
+                FIREBALL,
+                Prefab::projectile(ProjectileKind::Fireball, 0.1, [1.0, 0.0, 0.0]),
+            ),
+            (
+                GRENADE,
+                Prefab::projectile(ProjectileKind::Grenade, 0.12, [0.2, 0.4, 0.1]),
+            ),
+            (
+                ROCKET,
+                Prefab::projectile(ProjectileKind::Rocket, 0.15, [1.0, 0.5, 0.1]),
+            ),
+            (
+                BOX,
+                Prefab {
+                    surface: Some(SurfaceMaterial::Wood),
+                    ..Prefab::prop(
+                        PrefabMesh::Cube { size: 0.2 },
+                        [0.8, 0.7, 0.6],
+                        Vec3::splat(0.1),
+                    )
+                },
+            ),
+            (
+                CRATE,
+                Prefab {
+                    surface: Some(SurfaceMaterial::Wood),
+                    ..Prefab::prop(
+                        PrefabMesh::Cube {
+                            size: CRATE_HALF_SIZE * 2.0,
+                        },
+                        [CRATE_COLOR.r(), CRATE_COLOR.g(), CRATE_COLOR.b()],
+                        Vec3::splat(CRATE_HALF_SIZE),
+                    )
+                },
+            ),
+            (
+                weapon(WeaponKind::Hitscan),
+                Prefab::prop(
+                    PrefabMesh::Box {
+                        size: Vec3::new(0.6, 0.15, 0.15),
+                    },
+                    [0.3, 0.3, 0.3],
+                    Vec3::new(0.3, 0.075, 0.075),
+                ),
+            ),
+            (
+                weapon(WeaponKind::Rocket),
+                Prefab::prop(
+                    PrefabMesh::Box {
+                        size: Vec3::new(0.6, 0.15, 0.15),
+                    },
+                    [0.3, 0.4, 0.2],
+                    Vec3::new(0.3, 0.075, 0.075),
+                ),
+            ),
+        ];
+        Self {
+            prefabs: prefabs
+                .into_iter()
+                .map(|(id, prefab)| (id.to_string(), prefab))
+                .collect(),
+            missing: Prefab::default(),
+        }
+    }
+}
+
+impl PrefabRegistry {
+    /// the built-in prefabs extended by the file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let prefabs: HashMap<String, Prefab> =
+            ron::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut registry = Self::default();
+        registry.prefabs.extend(prefabs);
+        Ok(registry)
+    }
+
+    /// load the file if there is one, the built-in prefabs otherwise
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Self {
+        if !path.as_ref().exists() {
+            return Self::default();
+        }
+        Self::load(path).unwrap_or_else(|e| {
+            warn!("failed to load prefabs: {}", e);
+            Self::default()
+        })
+    }
+
+    /// the prefab `id`, a placeholder for unknown ids
+    pub fn get(&self, id: &str) -> &Prefab {
+        self.prefabs.get(id).unwrap_or_else(|| {
+            warn!("unknown prefab {:?}", id);
+            &self.missing
+        })
+    }
+}
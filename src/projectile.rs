@@ -0,0 +1,233 @@
+//! kinematic projectile simulation. Projectiles are not rigid bodies: they move by their own
+//! velocity (with optional gravity) and sweep their sphere against the physics world every
+//! step, so fast projectiles can't tunnel through thin walls. The server runs it
+//! authoritatively, clients use the same step to predict projectiles between frames.
+
+use bevy::prelude::*;
+use bevy_rapier3d::{prelude::*, rapier::geometry::InteractionGroups};
+use serde::{Deserialize, Serialize};
+
+use crate::FIREBALL_SPEED;
+
+/// sweeps per step, each bounce restarts the sweep with the remaining time
+const MAX_SWEEPS: usize = 4;
+
+/// distance kept from surfaces after a bounce, so the next sweep doesn't start in contact
+const BOUNCE_SKIN: f32 = 0.01;
+
+/// projectiles slower than this after a bounce come to rest (m/s)
+const REST_SPEED: f32 = 0.5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProjectileSettings {
+    /// launch speed (m/s)
+    pub speed: f32,
+    /// downward acceleration (m/s²), 0 flies straight
+    pub gravity: f32,
+    /// radius of the swept sphere
+    pub radius: f32,
+    /// seconds until the projectile is removed without impact
+    pub lifetime: f32,
+    /// surfaces the projectile bounces off before it explodes on contact (or stops, for
+    /// projectiles exploding on a fuse)
+    pub max_bounces: u32,
+    /// fraction of the speed kept on a bounce
+    pub restitution: f32,
+}
+
+impl Default for ProjectileSettings {
+    fn default() -> Self {
+        Self {
+            speed: FIREBALL_SPEED,
+            gravity: 0.0,
+            radius: 0.1,
+            lifetime: 1.5,
+            max_bounces: 0,
+            restitution: 0.6,
+        }
+    }
+}
+
+impl ProjectileSettings {
+    /// fast and straight, explodes on the first contact
+    pub fn rocket() -> Self {
+        Self {
+            speed: 20.0,
+            gravity: 0.0,
+            radius: 0.15,
+            lifetime: 5.0,
+            max_bounces: 0,
+            restitution: 0.0,
+        }
+    }
+
+    /// thrown in an arc, bounces around until the fuse (`lifetime`) runs out
+    pub fn grenade() -> Self {
+        Self {
+            speed: 12.0,
+            gravity: 9.81,
+            radius: 0.12,
+            lifetime: 2.5,
+            max_bounces: 16,
+            restitution: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProjectileKind {
+    Fireball,
+    Grenade,
+    Rocket,
+}
+
+/// ballistics of all projectile weapons, clients get them to predict projectiles
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectileKinds {
+    pub fireball: ProjectileSettings,
+    pub grenade: ProjectileSettings,
+    pub rocket: ProjectileSettings,
+}
+
+impl Default for ProjectileKinds {
+    fn default() -> Self {
+        Self {
+            fireball: ProjectileSettings::default(),
+            grenade: ProjectileSettings::grenade(),
+            rocket: ProjectileSettings::rocket(),
+        }
+    }
+}
+
+impl ProjectileKinds {
+    pub fn get(&self, kind: ProjectileKind) -> &ProjectileSettings {
+        match kind {
+            ProjectileKind::Fireball => &self.fireball,
+            ProjectileKind::Grenade => &self.grenade,
+            ProjectileKind::Rocket => &self.rocket,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Component)]
+pub struct Ballistic {
+    pub velocity: Vec3,
+    pub gravity: f32,
+    pub radius: f32,
+    pub bounces_left: u32,
+    pub restitution: f32,
+    /// rigid body the sweeps ignore (the shooter)
+    pub ignore: Option<Entity>,
+}
+
+impl Ballistic {
+    pub fn new(settings: &ProjectileSettings, velocity: Vec3, ignore: Option<Entity>) -> Self {
+        Self {
+            velocity,
+            gravity: settings.gravity,
+            radius: settings.radius,
+            bounces_left: settings.max_bounces,
+            restitution: settings.restitution,
+            ignore,
+        }
+    }
+
+    fn filter(&self) -> QueryFilter {
+        // respect collision groups, so projectiles pass through spectators
+        let filter = QueryFilter::default()
+            .exclude_sensors()
+            .groups(InteractionGroups::all());
+        match self.ignore {
+            Some(ignore) => filter.exclude_rigid_body(ignore),
+            None => filter,
+        }
+    }
+
+    /// move `translation` by `dt` seconds, bouncing off surfaces while bounces are left.
+    /// Returns the entity hit once the projectile impacts; `translation` is then the position
+    /// of the projectile at the time of impact.
+    pub fn step(
+        &mut self,
+        physics_context: &RapierContext,
+        translation: &mut Vec3,
+        dt: f32,
+    ) -> Option<Entity> {
+        self.velocity.y -= self.gravity * dt;
+        let shape = Collider::ball(self.radius);
+        let filter = self.filter();
+        let mut remaining = dt;
+        for _ in 0..MAX_SWEEPS {
+            if remaining <= 0.0 || self.velocity == Vec3::ZERO {
+                break;
+            }
+            let (entity, toi) = match physics_context.cast_shape(
+                *translation,
+                Quat::IDENTITY,
+                self.velocity,
+                &shape,
+                remaining,
+                filter,
+            ) {
+                Some(hit) => hit,
+                None => {
+                    *translation += self.velocity * remaining;
+                    break;
+                }
+            };
+            *translation += self.velocity * toi.toi;
+            if self.bounces_left == 0 {
+                return Some(entity);
+            }
+            // the closest surface point gives the contact normal for the sphere
+            let normal = physics_context
+                .project_point(*translation, true, filter)
+                .map(|(_, projection)| (*translation - projection.point).normalize_or_zero())
+                .filter(|normal| *normal != Vec3::ZERO)
+                .unwrap_or(-self.velocity.normalize_or_zero());
+            self.bounces_left -= 1;
+            self.velocity -= 2.0 * self.velocity.dot(normal) * normal;
+            self.velocity *= self.restitution;
+            *translation += normal * BOUNCE_SKIN;
+            if self.velocity.length() < REST_SPEED {
+                self.velocity = Vec3::ZERO;
+                break;
+            }
+            remaining -= toi.toi;
+        }
+        None
+    }
+}
+
+/// a projectile hit something and should explode
+#[derive(Debug, Clone)]
+pub struct ProjectileImpactEvent {
+    pub projectile: Entity,
+    /// entity of the collider that was hit
+    pub hit: Entity,
+    pub position: Vec3,
+}
+
+/// server: move projectiles and report impacts. The projectile stops at the impact, removing
+/// it is up to the receiver of the event. Stopped projectiles stay where they are.
+pub fn projectile_motion_system(
+    time: Res<Time>,
+    physics_context: Res<RapierContext>,
+    mut projectiles: Query<(Entity, &mut Transform, &mut Ballistic)>,
+    mut impact_events: EventWriter<ProjectileImpactEvent>,
+) {
+    let dt = time.delta_seconds();
+    for (entity, mut transform, mut ballistic) in &mut projectiles {
+        if ballistic.velocity == Vec3::ZERO {
+            continue;
+        }
+        if let Some(hit) = ballistic.step(&physics_context, &mut transform.translation, dt) {
+            ballistic.velocity = Vec3::ZERO;
+            impact_events.send(ProjectileImpactEvent {
+                projectile: entity,
+                hit,
+                position: transform.translation,
+            });
+        }
+    }
+}
@@ -0,0 +1,643 @@
+//! Quake .map importer, for levels built in TrenchBroom (standard and Valve 220 format).
+//!
+//! Every brush becomes a mesh with a convex collider. Brushes of `worldspawn`, `func_group`,
+//! `func_detail` and `func_wall` are solid, `func_illusionary` only visual, brushes textured
+//! `clip` only solid. Texture names containing metal, wood or glass set the SurfaceMaterial.
+//! Entities:
+//!
+//!     info_player_start, info_player_deathmatch   spawn point, "team" "red" | "blue"
+//!     light                                       point light, "light" 300, "_color" "1 1 1"
+//!     item_health, item_armor, item_ammo,
+//!     item_speed_boost, item_shield               pickups
+//!     trigger_push                                jump pad, "velocity" "x y z" or "speed"
+//!     trigger_teleport                            teleporter to the entity named by "target",
+//!                                                 e.g. info_teleport_destination with "angle"
+//!     trigger_hurt                                damage per second "dmg"
+//!     trigger_kill                                kills players, removes props
+//!     trigger_objective                           objective area named "objective"
+//!
+//! Quake is Z up in inches, positions are converted to Y up meters (QUAKE_UNITS_PER_METER).
+
+use std::{collections::HashMap, fs, path::Path};
+
+use bevy::prelude::*;
+
+use crate::{
+    level::SurfaceMaterial,
+    mapfile::{
+        LightKind, MapCollider, MapData, MapJumpPad, MapLight, MapMesh, MapTeleporter, MapTrigger,
+    },
+    pickup::PickupKind,
+    spawn::SpawnPoint,
+    team::Team,
+    trigger::{TriggerAction, TriggerShape},
+};
+
+/// map units per meter, a 56 units tall Quake player is 1.75 m
+pub const QUAKE_UNITS_PER_METER: f32 = 32.0;
+
+/// tolerance for plane tests, in map units
+const PLANE_EPSILON: f32 = 0.01;
+
+const BRUSH_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+
+/// jump pad launch speed without "velocity" or "speed" (map units per second)
+const DEFAULT_PUSH_SPEED: f32 = 320.0;
+
+/// trigger_hurt damage per second without "dmg"
+const DEFAULT_HURT_DAMAGE: f32 = 10.0;
+
+/// Quake light level to bevy point light intensity (lumen) and range (map units per level)
+const LIGHT_INTENSITY_SCALE: f32 = 3.0;
+const LIGHT_RANGE_SCALE: f32 = 1.0;
+
+const DEFAULT_LIGHT_LEVEL: f32 = 300.0;
+
+/// Quake position (Z up, map units) to world space
+fn to_world(v: Vec3) -> Vec3 {
+    Vec3::new(v.x, v.z, -v.y) / QUAKE_UNITS_PER_METER
+}
+
+/// Quake direction to world space, not scaled
+fn to_world_direction(v: Vec3) -> Vec3 {
+    Vec3::new(v.x, v.z, -v.y)
+}
+
+#[derive(Debug, PartialEq)]
+struct Token {
+    text: String,
+    /// quoted string, can't be a structural token
+    quoted: bool,
+    line: usize,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let mut chars = line.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if line[start..].starts_with("//") {
+                break;
+            } else if c == '"' {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => text.push(c),
+                        None => return Err(format!("line {}: unterminated string", line_number)),
+                    }
+                }
+                tokens.push(Token {
+                    text,
+                    quoted: true,
+                    line: line_number,
+                });
+            } else if "{}()[]".contains(c) {
+                chars.next();
+                tokens.push(Token {
+                    text: c.to_string(),
+                    quoted: false,
+                    line: line_number,
+                });
+            } else {
+                let mut text = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || "{}()[]\"".contains(c) {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push(Token {
+                    text,
+                    quoted: false,
+                    line: line_number,
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// brush face plane, `normal` pointing out of the brush: normal . p = distance
+#[derive(Debug, Clone)]
+struct Face {
+    normal: Vec3,
+    distance: f32,
+    texture: String,
+}
+
+#[derive(Debug, Default)]
+struct MapEntity {
+    properties: HashMap<String, String>,
+    brushes: Vec<Vec<Face>>,
+}
+
+impl MapEntity {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    fn class(&self) -> &str {
+        self.get("classname").unwrap_or("")
+    }
+
+    fn vector(&self, key: &str) -> Result<Option<Vec3>, String> {
+        match self.get(key) {
+            Some(value) => parse_vector(value)
+                .map(Some)
+                .ok_or_else(|| format!("{}: invalid {} {:?}", self.class(), key, value)),
+            None => Ok(None),
+        }
+    }
+
+    fn number(&self, key: &str) -> Result<Option<f32>, String> {
+        match self.get(key) {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("{}: invalid {} {:?}", self.class(), key, value)),
+            None => Ok(None),
+        }
+    }
+
+    /// position in world space
+    fn origin(&self) -> Result<Vec3, String> {
+        Ok(to_world(self.vector("origin")?.unwrap_or(Vec3::ZERO)))
+    }
+}
+
+fn parse_vector(value: &str) -> Option<Vec3> {
+    let components: Vec<f32> = value
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match components[..] {
+        [x, y, z] => Some(Vec3::new(x, y, z)),
+        _ => None,
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<&Token, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| "unexpected end of file".to_string())?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn is_next(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(token) if !token.quoted && token.text == symbol)
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token.quoted || token.text != symbol {
+            return Err(format!(
+                "line {}: expected {}, found {:?}",
+                token.line, symbol, token.text
+            ));
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<f32, String> {
+        let token = self.next()?;
+        token.text.parse().map_err(|_| {
+            format!(
+                "line {}: expected a number, found {:?}",
+                token.line, token.text
+            )
+        })
+    }
+
+    fn point(&mut self) -> Result<Vec3, String> {
+        self.expect("(")?;
+        let point = Vec3::new(self.number()?, self.number()?, self.number()?);
+        self.expect(")")?;
+        Ok(point)
+    }
+
+    fn face(&mut self) -> Result<Face, String> {
+        let line = self.peek().map_or(0, |token| token.line);
+        let (a, b, c) = (self.point()?, self.point()?, self.point()?);
+        let texture = self.next()?.text.clone();
+        // texture alignment: "[ u ] [ v ] rotation scale" (Valve 220) or offsets, rotation and
+        // scale (standard), Quake 2 and 3 add surface flags. None of it matters here.
+        while !self.is_next("(") && !self.is_next("}") {
+            self.next()?;
+        }
+        let normal = (c - a).cross(b - a).normalize_or_zero();
+        if normal == Vec3::ZERO {
+            return Err(format!("line {}: degenerate brush face", line));
+        }
+        Ok(Face {
+            normal,
+            distance: normal.dot(a),
+            texture,
+        })
+    }
+
+    fn brush(&mut self) -> Result<Vec<Face>, String> {
+        self.expect("{")?;
+        let mut faces = Vec::new();
+        while !self.is_next("}") {
+            faces.push(self.face()?);
+        }
+        self.expect("}")?;
+        Ok(faces)
+    }
+
+    fn entity(&mut self) -> Result<MapEntity, String> {
+        self.expect("{")?;
+        let mut entity = MapEntity::default();
+        loop {
+            if self.is_next("}") {
+                self.next()?;
+                return Ok(entity);
+            }
+            if self.is_next("{") {
+                entity.brushes.push(self.brush()?);
+                continue;
+            }
+            let key = self.next()?;
+            if !key.quoted {
+                return Err(format!(
+                    "line {}: expected a key, found {:?}",
+                    key.line, key.text
+                ));
+            }
+            let key = key.text.clone();
+            let value = self.next()?.text.clone();
+            entity.properties.insert(key, value);
+        }
+    }
+
+    fn entities(&mut self) -> Result<Vec<MapEntity>, String> {
+        let mut entities = Vec::new();
+        while self.peek().is_some() {
+            entities.push(self.entity()?);
+        }
+        Ok(entities)
+    }
+}
+
+/// corners of the convex polyhedron bounded by the faces, in map units
+fn brush_vertices(faces: &[Face]) -> Vec<Vec3> {
+    let mut vertices: Vec<Vec3> = Vec::new();
+    for (i, a) in faces.iter().enumerate() {
+        for (j, b) in faces.iter().enumerate().skip(i + 1) {
+            for c in faces.iter().skip(j + 1) {
+                let denominator = a.normal.dot(b.normal.cross(c.normal));
+                if denominator.abs() < 1e-6 {
+                    continue;
+                }
+                let point = (b.normal.cross(c.normal) * a.distance
+                    + c.normal.cross(a.normal) * b.distance
+                    + a.normal.cross(b.normal) * c.distance)
+                    / denominator;
+                let inside = faces
+                    .iter()
+                    .all(|face| face.normal.dot(point) <= face.distance + PLANE_EPSILON);
+                if inside
+                    && !vertices
+                        .iter()
+                        .any(|vertex| vertex.distance(point) < PLANE_EPSILON)
+                {
+                    vertices.push(point);
+                }
+            }
+        }
+    }
+    vertices
+}
+
+/// the corners on a face, counter clockwise seen from outside
+fn face_polygon(face: &Face, vertices: &[Vec3]) -> Vec<Vec3> {
+    let mut polygon: Vec<Vec3> = vertices
+        .iter()
+        .copied()
+        .filter(|vertex| (face.normal.dot(*vertex) - face.distance).abs() < PLANE_EPSILON)
+        .collect();
+    if polygon.len() < 3 {
+        return Vec::new();
+    }
+    let center = polygon.iter().copied().sum::<Vec3>() / polygon.len() as f32;
+    let axis = if face.normal.x.abs() < 0.9 {
+        Vec3::X
+    } else {
+        Vec3::Y
+    };
+    let u = axis.cross(face.normal).normalize();
+    let v = face.normal.cross(u);
+    let angle = |point: &Vec3| {
+        let offset = *point - center;
+        offset.dot(v).atan2(offset.dot(u))
+    };
+    polygon.sort_by(|a, b| angle(a).total_cmp(&angle(b)));
+    polygon
+}
+
+fn surface_of_texture(texture: &str) -> Option<SurfaceMaterial> {
+    let texture = texture.to_ascii_lowercase();
+    if texture.contains("metal") {
+        Some(SurfaceMaterial::Metal)
+    } else if texture.contains("wood") {
+        Some(SurfaceMaterial::Wood)
+    } else if texture.contains("glass") {
+        Some(SurfaceMaterial::Glass)
+    } else {
+        None
+    }
+}
+
+/// world space mesh of a brush, None if the brush is degenerate
+fn brush_mesh(faces: &[Face], collider: Option<MapCollider>) -> Option<MapMesh> {
+    let vertices = brush_vertices(faces);
+    let mut mesh = MapMesh {
+        positions: Vec::new(),
+        normals: Some(Vec::new()),
+        indices: Vec::new(),
+        color: BRUSH_COLOR,
+        collider,
+        surface: faces
+            .first()
+            .and_then(|face| surface_of_texture(&face.texture)),
+        visible: !faces
+            .iter()
+            .all(|face| face.texture.eq_ignore_ascii_case("clip")),
+    };
+    for face in faces {
+        let polygon = face_polygon(face, &vertices);
+        let first = mesh.positions.len() as u32;
+        for corner in 1..polygon.len().saturating_sub(1) as u32 {
+            mesh.indices
+                .extend_from_slice(&[first, first + corner, first + corner + 1]);
+        }
+        mesh.positions
+            .extend(polygon.iter().map(|vertex| to_world(*vertex)));
+        if let Some(normals) = &mut mesh.normals {
+            normals.extend(polygon.iter().map(|_| to_world_direction(face.normal)));
+        }
+    }
+    if mesh.indices.is_empty() {
+        None
+    } else {
+        Some(mesh)
+    }
+}
+
+/// world space bounding box of the brushes of an entity: center and half extents
+fn brush_bounds(entity: &MapEntity) -> Option<(Vec3, Vec3)> {
+    let vertices: Vec<Vec3> = entity
+        .brushes
+        .iter()
+        .flat_map(|faces| brush_vertices(faces))
+        .map(to_world)
+        .collect();
+    let first = *vertices.first()?;
+    let (min, max) = vertices
+        .iter()
+        .fold((first, first), |(min, max), v| (min.min(*v), max.max(*v)));
+    Some(((min + max) * 0.5, (max - min) * 0.5))
+}
+
+fn add_brushes(map: &mut MapData, entity: &MapEntity, collider: Option<MapCollider>) {
+    for faces in &entity.brushes {
+        match brush_mesh(faces, collider) {
+            Some(mesh) => map.meshes.push(mesh),
+            None => warn!("{}: skipping degenerate brush", entity.class()),
+        }
+    }
+}
+
+/// a trigger volume per brush of `entity`
+fn add_triggers(map: &mut MapData, entity: &MapEntity, action: TriggerAction) {
+    for faces in &entity.brushes {
+        let points: Vec<Vec3> = brush_vertices(faces).into_iter().map(to_world).collect();
+        map.triggers.push(MapTrigger {
+            transform: Transform::identity(),
+            shape: TriggerShape::Convex { points },
+            action: action.clone(),
+        });
+    }
+}
+
+fn spawn_team(entity: &MapEntity) -> Result<Option<Team>, String> {
+    match entity.get("team") {
+        None => Ok(None),
+        Some("red") => Ok(Some(Team::Red)),
+        Some("blue") => Ok(Some(Team::Blue)),
+        Some(team) => Err(format!("{}: invalid team {:?}", entity.class(), team)),
+    }
+}
+
+/// facing of a Quake "angle" (degrees counter clockwise from +X, seen from above) as
+/// rotation of the -Z axis
+fn yaw_rotation(entity: &MapEntity) -> Result<Quat, String> {
+    let angle = entity.number("angle")?.unwrap_or(0.0);
+    Ok(Quat::from_rotation_y((angle - 90.0).to_radians()))
+}
+
+fn import(entities: &[MapEntity]) -> Result<MapData, String> {
+    let mut map = MapData::default();
+    let targets: HashMap<&str, &MapEntity> = entities
+        .iter()
+        .filter_map(|entity| entity.get("targetname").map(|name| (name, entity)))
+        .collect();
+    for entity in entities {
+        match entity.class() {
+            "worldspawn" | "func_group" | "func_detail" | "func_wall" => {
+                add_brushes(&mut map, entity, Some(MapCollider::Convex));
+            }
+            "func_illusionary" => add_brushes(&mut map, entity, None),
+            "info_player_start" | "info_player_deathmatch" => {
+                map.spawn_points.push((
+                    entity.origin()?,
+                    SpawnPoint {
+                        team: spawn_team(entity)?,
+                    },
+                ));
+            }
+            "light" => {
+                let level = entity.number("light")?.unwrap_or(DEFAULT_LIGHT_LEVEL);
+                let color = match entity.vector("_color")? {
+                    // either 0 - 1 or 0 - 255
+                    Some(color) if color.max_element() > 1.0 => color / 255.0,
+                    Some(color) => color,
+                    None => Vec3::ONE,
+                };
+                map.lights.push(MapLight {
+                    kind: LightKind::Point,
+                    transform: Transform::from_translation(entity.origin()?),
+                    color: Color::rgb(color.x, color.y, color.z),
+                    intensity: level * LIGHT_INTENSITY_SCALE,
+                    range: level * LIGHT_RANGE_SCALE / QUAKE_UNITS_PER_METER,
+                });
+            }
+            "item_health" => map.pickups.push((entity.origin()?, PickupKind::Health)),
+            "item_armor" => map.pickups.push((entity.origin()?, PickupKind::Armor)),
+            "item_ammo" => map.pickups.push((entity.origin()?, PickupKind::Ammo)),
+            "item_speed_boost" => map.pickups.push((entity.origin()?, PickupKind::SpeedBoost)),
+            "item_shield" => map.pickups.push((entity.origin()?, PickupKind::Shield)),
+            "trigger_push" => {
+                let (center, half_extents) = match brush_bounds(entity) {
+                    Some(bounds) => bounds,
+                    None => return Err("trigger_push without brushes".to_string()),
+                };
+                let velocity = match entity.vector("velocity")? {
+                    Some(velocity) => velocity,
+                    None => Vec3::Z * entity.number("speed")?.unwrap_or(DEFAULT_PUSH_SPEED),
+                };
+                map.jump_pads.push(MapJumpPad {
+                    translation: center,
+                    half_extents,
+                    launch_velocity: to_world(velocity),
+                });
+            }
+            "trigger_teleport" => {
+                let (center, half_extents) = match brush_bounds(entity) {
+                    Some(bounds) => bounds,
+                    None => return Err("trigger_teleport without brushes".to_string()),
+                };
+                let target = entity
+                    .get("target")
+                    .and_then(|target| targets.get(target))
+                    .ok_or_else(|| "trigger_teleport without a valid target".to_string())?;
+                map.teleporters.push(MapTeleporter {
+                    entrance: Transform::from_translation(center),
+                    half_extents,
+                    exit: Transform::from_translation(target.origin()?)
+                        .with_rotation(yaw_rotation(target)?),
+                });
+            }
+            "trigger_hurt" => {
+                let per_second = entity.number("dmg")?.unwrap_or(DEFAULT_HURT_DAMAGE);
+                add_triggers(&mut map, entity, TriggerAction::Damage { per_second });
+            }
+            "trigger_kill" => add_triggers(&mut map, entity, TriggerAction::Kill),
+            "trigger_objective" => {
+                let name = entity.get("objective").unwrap_or_default().to_string();
+                add_triggers(&mut map, entity, TriggerAction::Objective { name });
+            }
+            // teleport targets are resolved by their teleporters
+            "info_teleport_destination" | "info_null" | "info_notnull" => (),
+            class => {
+                warn!("unsupported map entity {:?}", class);
+            }
+        }
+    }
+    Ok(map)
+}
+
+/// import the text of a .map file
+fn parse(text: &str) -> Result<MapData, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+    };
+    import(&parser.entities()?)
+}
+
+/// import a .map file
+pub fn load(path: &Path) -> Result<MapData, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 64 units cube at the origin, standard format
+    const CUBE: &str = "{
+( 0 0 0 ) ( 0 1 0 ) ( 0 0 1 ) wall 0 0 0 1 1
+( 64 0 0 ) ( 64 0 1 ) ( 64 1 0 ) wall 0 0 0 1 1
+( 0 0 0 ) ( 0 0 1 ) ( 1 0 0 ) wall 0 0 0 1 1
+( 0 64 0 ) ( 1 64 0 ) ( 0 64 1 ) wall 0 0 0 1 1
+( 0 0 0 ) ( 1 0 0 ) ( 0 1 0 ) wall 0 0 0 1 1
+( 0 0 64 ) ( 0 1 64 ) ( 1 0 64 ) wall 0 0 0 1 1
+}";
+
+    fn entities(text: &str) -> Result<Vec<MapEntity>, String> {
+        Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+        }
+        .entities()
+    }
+
+    #[test]
+    fn cube_brush_has_8_vertices() {
+        let entities = entities(&format!("{{ \"classname\" \"worldspawn\" {} }}", CUBE)).unwrap();
+        let vertices = brush_vertices(&entities[0].brushes[0]);
+        assert_eq!(vertices.len(), 8);
+        for vertex in vertices {
+            assert!([vertex.x, vertex.y, vertex.z]
+                .iter()
+                .all(|c| c.abs() < PLANE_EPSILON || (c - 64.0).abs() < PLANE_EPSILON));
+        }
+    }
+
+    #[test]
+    fn cube_brush_mesh() {
+        let map = parse(&format!("{{ \"classname\" \"worldspawn\" {} }}", CUBE)).unwrap();
+        assert_eq!(map.meshes.len(), 1);
+        // two triangles per face
+        assert_eq!(map.meshes[0].indices.len(), 6 * 2 * 3);
+    }
+
+    #[test]
+    fn degenerate_face_is_an_error() {
+        let error = parse(
+            "{ \"classname\" \"worldspawn\"
+{
+( 0 0 0 ) ( 1 0 0 ) ( 2 0 0 ) wall 0 0 0 1 1
+}
+}",
+        )
+        .unwrap_err();
+        assert!(error.contains("degenerate brush face"), "{}", error);
+    }
+
+    #[test]
+    fn teleport_without_target_is_an_error() {
+        let error = parse(&format!(
+            "{{ \"classname\" \"worldspawn\" }}
+{{ \"classname\" \"trigger_teleport\" {} }}",
+            CUBE
+        ))
+        .unwrap_err();
+        assert!(error.contains("without a valid target"), "{}", error);
+    }
+
+    #[test]
+    fn teleport_to_destination() {
+        let map = parse(&format!(
+            "{{ \"classname\" \"trigger_teleport\" \"target\" \"exit\" {} }}
+{{ \"classname\" \"info_teleport_destination\" \"targetname\" \"exit\" \"origin\" \"320 0 32\" }}",
+            CUBE
+        ))
+        .unwrap();
+        assert_eq!(map.teleporters.len(), 1);
+        let exit = map.teleporters[0].exit.translation;
+        assert!(exit.distance(Vec3::new(10.0, 1.0, 0.0)) < 1e-4);
+    }
+}
@@ -0,0 +1,221 @@
+//! client side ragdolls: when a player dies the client drops a ragdoll of boxes and capsules
+//! held together by spherical joints where the player was, thrown by the death velocity. The
+//! ragdoll is local physics only, it is despawned after RAGDOLL_LIFETIME and nothing about it
+//! is replicated beyond the death message.
+//!
+//! Ragdolls collide with the level, not with players or each other. The controller ignores
+//! them in its shape casts, so they don't disturb prediction.
+
+use bevy::prelude::*;
+use bevy_rapier3d::{prelude::*, rapier::geometry::InteractionGroups};
+
+use crate::Lifetime;
+
+/// collision group of the ragdoll parts
+pub const RAGDOLL_GROUP: u32 = 1 << 31;
+
+/// seconds a ragdoll lies around
+pub const RAGDOLL_LIFETIME: f32 = 8.0;
+
+/// velocity added to the body away from the killer (m/s)
+pub const DEATH_KNOCKBACK: f32 = 4.0;
+
+/// extra velocity of the upper body along the knockback, tips the ragdoll over
+const TOPPLE_FACTOR: f32 = 0.5;
+
+/// collision groups of the locally simulated player, not touching ragdolls
+pub fn player_collision_groups() -> CollisionGroups {
+    CollisionGroups::new(u32::MAX, !RAGDOLL_GROUP)
+}
+
+/// scene queries of the controller skip ragdolls
+pub fn ignore_ragdolls(filter: QueryFilter) -> QueryFilter {
+    filter.groups(InteractionGroups::new(u32::MAX, !RAGDOLL_GROUP))
+}
+
+/// velocity a player dies with: its movement plus a push away from the killer at
+/// `attacker_position`
+pub fn death_velocity(velocity: Vec3, position: Vec3, attacker_position: Option<Vec3>) -> Vec3 {
+    let knockback = attacker_position
+        .map(|attacker| (position - attacker).normalize_or_zero() * DEATH_KNOCKBACK)
+        .unwrap_or(Vec3::ZERO);
+    velocity + knockback
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PartShape {
+    Box {
+        half_extents: Vec3,
+    },
+    Ball {
+        radius: f32,
+    },
+    /// along y
+    Capsule {
+        half_height: f32,
+        radius: f32,
+    },
+}
+
+impl PartShape {
+    fn collider(self) -> Collider {
+        match self {
+            PartShape::Box { half_extents } => {
+                Collider::cuboid(half_extents.x, half_extents.y, half_extents.z)
+            }
+            PartShape::Ball { radius } => Collider::ball(radius),
+            PartShape::Capsule {
+                half_height,
+                radius,
+            } => Collider::capsule_y(half_height, radius),
+        }
+    }
+
+    fn mesh(self) -> Mesh {
+        match self {
+            PartShape::Box { half_extents } => Mesh::from(shape::Box::new(
+                half_extents.x * 2.0,
+                half_extents.y * 2.0,
+                half_extents.z * 2.0,
+            )),
+            PartShape::Ball { radius } => Mesh::from(shape::Icosphere {
+                radius,
+                subdivisions: 3,
+            }),
+            PartShape::Capsule {
+                half_height,
+                radius,
+            } => Mesh::from(shape::Capsule {
+                radius,
+                depth: half_height * 2.0,
+                ..default()
+            }),
+        }
+    }
+}
+
+/// body part, positions relative to the feet of a player looking along -Z
+struct Part {
+    center: Vec3,
+    shape: PartShape,
+    /// index of the part it hangs on and the joint position
+    joint: Option<(usize, Vec3)>,
+    /// the upper body topples along the knockback
+    upper: bool,
+}
+
+fn parts() -> [Part; 7] {
+    let arm = PartShape::Capsule {
+        half_height: 0.22,
+        radius: 0.06,
+    };
+    let leg = PartShape::Capsule {
+        half_height: 0.3,
+        radius: 0.08,
+    };
+    [
+        // pelvis, the root
+        Part {
+            center: Vec3::new(0.0, 0.95, 0.0),
+            shape: PartShape::Box {
+                half_extents: Vec3::new(0.18, 0.12, 0.1),
+            },
+            joint: None,
+            upper: false,
+        },
+        // torso
+        Part {
+            center: Vec3::new(0.0, 1.35, 0.0),
+            shape: PartShape::Box {
+                half_extents: Vec3::new(0.2, 0.25, 0.12),
+            },
+            joint: Some((0, Vec3::new(0.0, 1.08, 0.0))),
+            upper: true,
+        },
+        // head
+        Part {
+            center: Vec3::new(0.0, 1.8, 0.0),
+            shape: PartShape::Ball { radius: 0.15 },
+            joint: Some((1, Vec3::new(0.0, 1.62, 0.0))),
+            upper: true,
+        },
+        // arms
+        Part {
+            center: Vec3::new(-0.28, 1.3, 0.0),
+            shape: arm,
+            joint: Some((1, Vec3::new(-0.26, 1.58, 0.0))),
+            upper: true,
+        },
+        Part {
+            center: Vec3::new(0.28, 1.3, 0.0),
+            shape: arm,
+            joint: Some((1, Vec3::new(0.26, 1.58, 0.0))),
+            upper: true,
+        },
+        // legs
+        Part {
+            center: Vec3::new(-0.1, 0.45, 0.0),
+            shape: leg,
+            joint: Some((0, Vec3::new(-0.1, 0.85, 0.0))),
+            upper: false,
+        },
+        Part {
+            center: Vec3::new(0.1, 0.45, 0.0),
+            shape: leg,
+            joint: Some((0, Vec3::new(0.1, 0.85, 0.0))),
+            upper: false,
+        },
+    ]
+}
+
+/// marks the parts of a ragdoll
+#[derive(Component)]
+pub struct RagdollPart;
+
+/// drop a ragdoll at `position` (feet) turned to `yaw`, thrown with `velocity`
+pub fn spawn_ragdoll(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    position: Vec3,
+    yaw: f32,
+    velocity: Vec3,
+    color: Color,
+) {
+    let rotation = Quat::from_rotation_y(yaw);
+    let material = materials.add(color.into());
+    let topple = Vec3::new(velocity.x, 0.0, velocity.z) * TOPPLE_FACTOR;
+    let mut entities: Vec<Entity> = Vec::new();
+    let parts = parts();
+    for part in parts.iter() {
+        let mut entity = commands.spawn_bundle(PbrBundle {
+            mesh: meshes.add(part.shape.mesh()),
+            material: material.clone(),
+            transform: Transform::from_translation(position + rotation * part.center)
+                .with_rotation(rotation),
+            ..default()
+        });
+        entity
+            .insert(RigidBody::Dynamic)
+            .insert(part.shape.collider())
+            .insert(CollisionGroups::new(RAGDOLL_GROUP, !RAGDOLL_GROUP))
+            .insert(Velocity {
+                linvel: velocity + if part.upper { topple } else { Vec3::ZERO },
+                ..default()
+            })
+            .insert(Damping {
+                linear_damping: 0.1,
+                angular_damping: 1.0,
+            })
+            .insert(RagdollPart)
+            .insert(Lifetime(Timer::from_seconds(RAGDOLL_LIFETIME, false)));
+        // anchors in the frames of the bodies, which are all turned by the same yaw
+        if let Some((parent, joint)) = part.joint {
+            let joint = SphericalJointBuilder::new()
+                .local_anchor1(joint - parts[parent].center)
+                .local_anchor2(joint - part.center);
+            entity.insert(ImpulseJoint::new(entities[parent], joint));
+        }
+        entities.push(entity.id());
+    }
+}
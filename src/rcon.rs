@@ -0,0 +1,487 @@
+//! remote administration of dedicated servers. With `rcon_addr` and `rcon_password` set in
+//! server.toml, a background thread accepts TCP connections, e.g. from the `rcon` tool.
+//!
+//!     rcon_addr = "127.0.0.1:5010"
+//!     rcon_password = "secret"
+//!
+//! The protocol is line based: the first line of a connection is the password, the server
+//! answers `ok` or closes the connection. Then every line is a command. Replies are one or more
+//! lines, followed by an empty line.
+//!
+//! Bans of connected players hold their address. Identities and names are chosen by the
+//! client, bans holding only those are advisory: a new identity or name gets past them.
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufRead, BufReader, Lines, Read, Write},
+    net::{IpAddr, TcpListener, TcpStream},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use bevy::prelude::*;
+
+use crate::identity::PlayerIdentity;
+
+pub const HELP: &str = "\
+status              map, mode and the connected players
+kick <player>       disconnect a player, by client id or name
+ban <player>        kick the player and turn away its address and identity (or name) from
+                    now on. Offline players are banned by name only, which is advisory:
+                    clients choose their names and identities
+unban <entry>       remove a ban, `ip:<address>`, `id:<identity>` or a name as ban shows it
+map <name>          change the map and start a new match
+set <key> <value>   change a server.toml setting until the next reload, e.g. set game.frag_limit 50
+say <text>          message to all players";
+
+/// the server answers a command within this time, or the connection gets an error reply
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// connections without a command for this long are closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// a wrong password is answered after this delay, to slow down guessing
+const LOGIN_FAILURE_DELAY: Duration = Duration::from_secs(1);
+
+/// connections beyond this are closed right away
+const MAX_CONNECTIONS: usize = 4;
+
+/// longer lines close the connection
+const MAX_LINE_LENGTH: u64 = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RconCommand {
+    Help,
+    Status,
+    Kick { player: String },
+    Ban { player: String },
+    Unban { entry: String },
+    Map { map: String },
+    Set { key: String, value: String },
+    Say { text: String },
+}
+
+impl FromStr for RconCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (command, args) = match line.split_once(char::is_whitespace) {
+            Some((command, args)) => (command, args.trim()),
+            None => (line, ""),
+        };
+        let required = |usage: &str| {
+            if args.is_empty() {
+                Err(format!("usage: {} {}", command, usage))
+            } else {
+                Ok(args.to_string())
+            }
+        };
+        match command {
+            "help" => Ok(RconCommand::Help),
+            "status" => Ok(RconCommand::Status),
+            "kick" => Ok(RconCommand::Kick {
+                player: required("<player>")?,
+            }),
+            "ban" => Ok(RconCommand::Ban {
+                player: required("<player>")?,
+            }),
+            "unban" => Ok(RconCommand::Unban {
+                entry: required("<entry>")?,
+            }),
+            "map" => Ok(RconCommand::Map {
+                map: required("<name>")?,
+            }),
+            "set" => {
+                let (key, value) = args
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| "usage: set <key> <value>".to_string())?;
+                Ok(RconCommand::Set {
+                    key: key.to_string(),
+                    value: value.trim().to_string(),
+                })
+            }
+            "say" => Ok(RconCommand::Say {
+                text: required("<text>")?,
+            }),
+            _ => Err(format!("unknown command {}, see help", command)),
+        }
+    }
+}
+
+/// a command waiting for the server, answered with `reply`
+pub struct RconRequest {
+    pub command: RconCommand,
+    reply: mpsc::Sender<String>,
+}
+
+impl RconRequest {
+    pub fn reply(self, text: impl Into<String>) {
+        // the connection may be gone already
+        let _ = self.reply.send(text.into());
+    }
+}
+
+/// commands of the admin connections, handled by the server's rcon system
+pub struct RconServer {
+    requests: Mutex<mpsc::Receiver<RconRequest>>,
+}
+
+impl RconServer {
+    /// listen on `addr` in a background thread, every connection gets its own thread. At most
+    /// MAX_CONNECTIONS are open at a time.
+    pub fn spawn(addr: &str, password: String) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        info!("rcon on {}", listener.local_addr()?);
+        let (sender, receiver) = mpsc::channel();
+        let connections = Arc::new(AtomicUsize::new(0));
+        thread::Builder::new()
+            .name("rcon".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            warn!("rcon connection failed: {}", e);
+                            continue;
+                        }
+                    };
+                    if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                        connections.fetch_sub(1, Ordering::SeqCst);
+                        let mut stream = stream;
+                        let _ = write_reply(&mut stream, "too many connections");
+                        continue;
+                    }
+                    let sender = sender.clone();
+                    let password = password.clone();
+                    let open = connections.clone();
+                    let spawned = thread::Builder::new()
+                        .name("rcon connection".to_string())
+                        .spawn(move || {
+                            if let Err(e) = serve(stream, &password, &sender) {
+                                debug!("rcon connection closed: {}", e);
+                            }
+                            open.fetch_sub(1, Ordering::SeqCst);
+                        });
+                    if let Err(e) = spawned {
+                        connections.fetch_sub(1, Ordering::SeqCst);
+                        warn!("failed to start rcon connection thread: {}", e);
+                    }
+                }
+            })?;
+        Ok(Self {
+            requests: Mutex::new(receiver),
+        })
+    }
+
+    /// the commands that arrived since the last call
+    pub fn requests(&self) -> Vec<RconRequest> {
+        self.requests.lock().unwrap().try_iter().collect()
+    }
+}
+
+fn serve(
+    stream: TcpStream,
+    password: &str,
+    requests: &mpsc::Sender<RconRequest>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let peer = stream.peer_addr()?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    if read_line(&mut reader)?.as_deref().map(str::trim) != Some(password) {
+        warn!(peer = %peer, "rcon login failed");
+        thread::sleep(LOGIN_FAILURE_DELAY);
+        return write_reply(&mut writer, "wrong password");
+    }
+    info!(peer = %peer, "rcon login");
+    write_reply(&mut writer, "ok")?;
+    while let Some(line) = read_line(&mut reader)? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match line.parse() {
+            Ok(RconCommand::Help) => HELP.to_string(),
+            Ok(command) => {
+                info!(peer = %peer, command = line.trim(), "rcon command");
+                let (reply, reply_receiver) = mpsc::channel();
+                if requests.send(RconRequest { command, reply }).is_err() {
+                    // the server stopped
+                    return Ok(());
+                }
+                reply_receiver
+                    .recv_timeout(REPLY_TIMEOUT)
+                    .unwrap_or_else(|_| "error: no reply from the server".to_string())
+            }
+            Err(e) => e,
+        };
+        write_reply(&mut writer, &reply)?;
+    }
+    Ok(())
+}
+
+/// the next line without its line end, None at the end of the stream. Lines longer than
+/// MAX_LINE_LENGTH are an error, so that clients can't make us buffer without limit.
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let len = reader.by_ref().take(MAX_LINE_LENGTH).read_line(&mut line)?;
+    if len == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') && len as u64 == MAX_LINE_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).to_string()))
+}
+
+/// the reply lines and the empty line ending the reply. Empty lines within the reply are
+/// dropped.
+fn write_reply(writer: &mut impl Write, reply: &str) -> io::Result<()> {
+    let mut text = String::new();
+    for line in reply.lines().filter(|line| !line.trim().is_empty()) {
+        text.push_str(line);
+        text.push('\n');
+    }
+    text.push('\n');
+    writer.write_all(text.as_bytes())
+}
+
+/// connection to a server's rcon listener
+pub struct RconClient {
+    lines: Lines<BufReader<TcpStream>>,
+    writer: TcpStream,
+}
+
+impl RconClient {
+    pub fn connect(addr: &str, password: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr).map_err(|e| format!("{}: {}", addr, e))?;
+        let writer = stream.try_clone().map_err(|e| e.to_string())?;
+        let mut client = Self {
+            lines: BufReader::new(stream).lines(),
+            writer,
+        };
+        match client.send(password).map_err(|e| e.to_string())?.as_str() {
+            "ok" => Ok(client),
+            reply => Err(format!("login failed: {}", reply)),
+        }
+    }
+
+    /// send a command line and wait for the reply
+    pub fn send(&mut self, line: &str) -> io::Result<String> {
+        writeln!(self.writer, "{}", line.trim())?;
+        let mut reply = Vec::new();
+        loop {
+            match self.lines.next().transpose()? {
+                Some(line) if line.is_empty() => break,
+                Some(line) => reply.push(line),
+                None if reply.is_empty() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed",
+                    ))
+                }
+                None => break,
+            }
+        }
+        Ok(reply.join("\n"))
+    }
+}
+
+/// players turned away on connect, kept in the `ban_list` file if set. Entries are addresses
+/// written as `ip:<address>`, player identities written as `id:<identity>`, or names as
+/// `NameRules::sanitize` leaves them. Name bans only matter for clients without an identity.
+#[derive(Debug, Default)]
+pub struct BanList {
+    path: Option<String>,
+    /// lowercase
+    entries: HashSet<String>,
+}
+
+fn identity_entry(identity: PlayerIdentity) -> String {
+    format!("id:{}", identity)
+}
+
+fn address_entry(addr: IpAddr) -> String {
+    format!("ip:{}", addr).to_lowercase()
+}
+
+impl BanList {
+    /// one entry per line, empty lines and lines starting with # are skipped. A missing file
+    /// is an empty list.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let entries = match fs::read_to_string(path) {
+            Ok(content) => content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_lowercase)
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: Some(path.to_string()),
+            entries,
+        })
+    }
+
+    /// `name` is the sanitized requested name
+    pub fn is_banned(
+        &self,
+        addr: Option<IpAddr>,
+        identity: Option<PlayerIdentity>,
+        name: &str,
+    ) -> bool {
+        if matches!(addr, Some(addr) if self.entries.contains(&address_entry(addr))) {
+            return true;
+        }
+        match identity {
+            Some(identity) => self.entries.contains(&identity_entry(identity)),
+            None => self.entries.contains(&name.to_lowercase()),
+        }
+    }
+
+    /// returns the new entry
+    pub fn ban_address(&mut self, addr: IpAddr) -> io::Result<String> {
+        let entry = address_entry(addr);
+        self.entries.insert(entry.clone());
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// returns the new entry
+    pub fn ban_identity(&mut self, identity: PlayerIdentity) -> io::Result<String> {
+        let entry = identity_entry(identity);
+        self.entries.insert(entry.clone());
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// `name` is sanitized, returns the new entry
+    pub fn ban_name(&mut self, name: &str) -> io::Result<String> {
+        let entry = name.to_lowercase();
+        self.entries.insert(entry.clone());
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// returns false if there was no such entry
+    pub fn unban(&mut self, entry: &str) -> io::Result<bool> {
+        let removed = self.entries.remove(&entry.trim().to_lowercase());
+        self.save()?;
+        Ok(removed)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut entries: Vec<&String> = self.entries.iter().collect();
+        entries.sort();
+        let mut content = String::new();
+        for entry in entries {
+            content.push_str(entry);
+            content.push('\n');
+        }
+        fs::write(path, content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!("help".parse::<RconCommand>(), Ok(RconCommand::Help));
+        assert_eq!(
+            " status \r\n".parse::<RconCommand>(),
+            Ok(RconCommand::Status)
+        );
+        assert_eq!(
+            "kick  fast eddie".parse::<RconCommand>(),
+            Ok(RconCommand::Kick {
+                player: "fast eddie".to_string()
+            })
+        );
+        assert_eq!(
+            "unban id:0123".parse::<RconCommand>(),
+            Ok(RconCommand::Unban {
+                entry: "id:0123".to_string()
+            })
+        );
+        assert_eq!(
+            "set game.frag_limit  50".parse::<RconCommand>(),
+            Ok(RconCommand::Set {
+                key: "game.frag_limit".to_string(),
+                value: "50".to_string()
+            })
+        );
+        assert_eq!(
+            "say hello  there".parse::<RconCommand>(),
+            Ok(RconCommand::Say {
+                text: "hello  there".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            "kick".parse::<RconCommand>(),
+            Err("usage: kick <player>".to_string())
+        );
+        assert_eq!(
+            "map   ".parse::<RconCommand>(),
+            Err("usage: map <name>".to_string())
+        );
+        assert!("set game.frag_limit".parse::<RconCommand>().is_err());
+        assert!("reboot".parse::<RconCommand>().is_err());
+    }
+
+    #[test]
+    fn long_lines_are_an_error() {
+        let mut reader = BufReader::new(&b"ok\r\n"[..]);
+        assert_eq!(read_line(&mut reader).unwrap().as_deref(), Some("ok"));
+        assert_eq!(read_line(&mut reader).unwrap(), None);
+        let long = vec![b'x'; MAX_LINE_LENGTH as usize + 1];
+        assert!(read_line(&mut BufReader::new(&long[..])).is_err());
+    }
+
+    #[test]
+    fn reply_ends_with_an_empty_line() {
+        let mut reply = Vec::new();
+        write_reply(&mut reply, "a\n\nb").unwrap();
+        assert_eq!(reply, b"a\nb\n\n");
+    }
+
+    #[test]
+    fn ban_list() {
+        let mut bans = BanList::default();
+        let identity = PlayerIdentity::random();
+        bans.ban_identity(identity).unwrap();
+        bans.ban_name("Eddie").unwrap();
+        assert!(bans.is_banned(None, Some(identity), "frank"));
+        // name bans only hold clients without an identity
+        assert!(!bans.is_banned(None, Some(PlayerIdentity::random()), "eddie"));
+        assert!(bans.is_banned(None, None, "EDDIE"));
+        assert!(bans.unban(" eddie ").unwrap());
+        assert!(!bans.unban("eddie").unwrap());
+        assert!(!bans.is_banned(None, None, "eddie"));
+        // address bans hold whatever identity and name the client comes up with
+        let addr: IpAddr = "192.0.2.7".parse().unwrap();
+        assert_eq!(bans.ban_address(addr).unwrap(), "ip:192.0.2.7");
+        assert!(bans.is_banned(Some(addr), Some(PlayerIdentity::random()), "frank"));
+        assert!(bans.is_banned(Some(addr), None, "frank"));
+        assert!(!bans.is_banned(Some("192.0.2.8".parse().unwrap()), None, "frank"));
+        assert!(bans.unban("ip:192.0.2.7").unwrap());
+        assert!(!bans.is_banned(Some(addr), None, "frank"));
+    }
+}
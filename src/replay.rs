@@ -0,0 +1,329 @@
+//! session recording and replay for netcode regression checks ("netcode CI").
+//!
+//! The server records the movement inputs it receives per frame (`--record=<file>`). The
+//! `replay` binary runs a recording through the movement simulation of the current build and
+//! writes a per-frame trace of player states. Traces of two builds are then compared with
+//! `diff_traces`.
+//!
+//! Frames are appended to the file as they close and flushed every few seconds, a server that
+//! is killed loses at most the last seconds of its recording.
+//!
+//! Only the movement inputs are recorded, not what the server applies on top of them
+//! (respawns, teleports, knockback).
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    controller::{FpsControllerInput, MovementSettings},
+    level::LevelKind,
+};
+
+fn save<T: Serialize, P: AsRef<Path>>(value: &T, path: P) -> io::Result<()> {
+    let data = bincode::serialize(value).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    fs::write(path, data)
+}
+
+fn load<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> io::Result<T> {
+    let data = fs::read(path)?;
+    bincode::deserialize(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// seconds between two flushes of the recording file
+pub const FLUSH_INTERVAL: f32 = 5.0;
+
+fn bincode_error(e: bincode::Error) -> io::Error {
+    match *e {
+        bincode::ErrorKind::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// start of a recording file, followed by the frames
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordingHeader {
+    level: LevelKind,
+    settings: MovementSettings,
+}
+
+/// inputs received during one server frame
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// frame delta time (seconds)
+    pub dt: f32,
+    /// movement inputs by client id, in the order they were received
+    pub inputs: Vec<(u64, FpsControllerInput)>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionRecording {
+    pub level: LevelKind,
+    pub settings: MovementSettings,
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl SessionRecording {
+    /// load a recording. A frame cut off by a killed server ends the recording.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let header: RecordingHeader =
+            bincode::deserialize_from(&mut reader).map_err(bincode_error)?;
+        let mut frames = Vec::new();
+        loop {
+            match bincode::deserialize_from(&mut reader).map_err(bincode_error) {
+                Ok(frame) => frames.push(frame),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Self {
+            level: header.level,
+            settings: header.settings,
+            frames,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = RecordingHeader {
+            level: self.level.clone(),
+            settings: self.settings.clone(),
+        };
+        bincode::serialize_into(&mut writer, &header).map_err(bincode_error)?;
+        for frame in &self.frames {
+            bincode::serialize_into(&mut writer, frame).map_err(bincode_error)?;
+        }
+        writer.flush()
+    }
+
+    /// all client ids appearing in the recording, in order of first input
+    pub fn clients(&self) -> Vec<u64> {
+        let mut clients = Vec::new();
+        for (client, _) in self.frames.iter().flat_map(|frame| &frame.inputs) {
+            if !clients.contains(client) {
+                clients.push(*client);
+            }
+        }
+        clients
+    }
+}
+
+/// server resource: writes the session to the file while it runs
+pub struct SessionRecorder {
+    pub path: String,
+    writer: BufWriter<File>,
+    current: RecordedFrame,
+    frames: usize,
+    since_flush: f32,
+}
+
+impl SessionRecorder {
+    /// create the recording file and write its header
+    pub fn new(path: String, level: LevelKind, settings: MovementSettings) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        bincode::serialize_into(&mut writer, &RecordingHeader { level, settings })
+            .map_err(bincode_error)?;
+        Ok(Self {
+            path,
+            writer,
+            current: RecordedFrame::default(),
+            frames: 0,
+            since_flush: 0.0,
+        })
+    }
+
+    pub fn record_input(&mut self, client: u64, input: &FpsControllerInput) {
+        self.current.inputs.push((client, input.clone()));
+    }
+
+    /// close the current frame and append it to the file
+    pub fn end_frame(&mut self, dt: f32) -> io::Result<()> {
+        let mut frame = std::mem::take(&mut self.current);
+        frame.dt = dt;
+        bincode::serialize_into(&mut self.writer, &frame).map_err(bincode_error)?;
+        self.frames += 1;
+        self.since_flush += dt;
+        if self.since_flush >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.since_flush = 0.0;
+        self.writer.flush()
+    }
+
+    /// frames recorded so far
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+}
+
+pub fn end_recorded_frame_system(time: Res<Time>, mut recorder: ResMut<SessionRecorder>) {
+    if let Err(e) = recorder.end_frame(time.delta_seconds()) {
+        warn!("failed to write recording {}: {}", recorder.path, e);
+    }
+}
+
+pub fn save_recording_on_exit_system(
+    mut app_exit_events: EventReader<bevy::app::AppExit>,
+    mut recorder: ResMut<SessionRecorder>,
+) {
+    if app_exit_events.iter().next().is_none() {
+        return;
+    }
+    match recorder.flush() {
+        Ok(()) => info!("recorded {} frames to {}", recorder.frames(), recorder.path),
+        Err(e) => warn!("failed to save recording {}: {}", recorder.path, e),
+    }
+}
+
+/// simulated state of one player at the end of a frame
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TraceSample {
+    pub frame: u32,
+    pub client: u64,
+    pub position: Vec3,
+    pub velocity: Vec3,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SimTrace {
+    pub samples: Vec<TraceSample>,
+}
+
+impl SimTrace {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        load(path)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        save(self, path)
+    }
+}
+
+/// state difference of one player in one frame, what a client predicting with one build
+/// would have to correct if the other build was authoritative
+#[derive(Debug, Clone, Copy)]
+pub struct Correction {
+    pub frame: u32,
+    pub client: u64,
+    pub position_delta: Vec3,
+    pub velocity_delta: Vec3,
+}
+
+#[derive(Debug, Default)]
+pub struct TraceDiff {
+    pub compared: usize,
+    /// samples present in only one of the traces
+    pub unmatched: usize,
+    pub max_position_error: f32,
+    pub mean_position_error: f32,
+    /// all samples differing by more than the threshold, in frame order
+    pub corrections: Vec<Correction>,
+}
+
+impl TraceDiff {
+    pub fn first_divergence(&self) -> Option<&Correction> {
+        self.corrections.first()
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.corrections.is_empty() && self.unmatched == 0
+    }
+
+    /// one line per correction: frame,client,dx,dy,dz,dvx,dvy,dvz
+    pub fn write_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "frame,client,dx,dy,dz,dvx,dvy,dvz")?;
+        for c in &self.corrections {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{}",
+                c.frame,
+                c.client,
+                c.position_delta.x,
+                c.position_delta.y,
+                c.position_delta.z,
+                c.velocity_delta.x,
+                c.velocity_delta.y,
+                c.velocity_delta.z
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TraceDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "compared {} samples ({} unmatched)",
+            self.compared, self.unmatched
+        )?;
+        writeln!(
+            f,
+            "position error: max {:.6} mean {:.6}",
+            self.max_position_error, self.mean_position_error
+        )?;
+        writeln!(f, "corrections: {}", self.corrections.len())?;
+        if let Some(first) = self.first_divergence() {
+            writeln!(
+                f,
+                "first divergence: frame {} client {} position delta {:?}",
+                first.frame, first.client, first.position_delta
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// compare two traces sample by sample. Differences in position or velocity above
+/// `threshold` count as corrections.
+pub fn diff_traces(a: &SimTrace, b: &SimTrace, threshold: f32) -> TraceDiff {
+    let b_samples: HashMap<(u32, u64), &TraceSample> = b
+        .samples
+        .iter()
+        .map(|sample| ((sample.frame, sample.client), sample))
+        .collect();
+
+    let mut diff = TraceDiff::default();
+    let mut error_sum = 0.0;
+    for sample in &a.samples {
+        let other = match b_samples.get(&(sample.frame, sample.client)) {
+            Some(other) => other,
+            None => {
+                diff.unmatched += 1;
+                continue;
+            }
+        };
+        diff.compared += 1;
+        let position_delta = other.position - sample.position;
+        let velocity_delta = other.velocity - sample.velocity;
+        let error = position_delta.length();
+        error_sum += error;
+        diff.max_position_error = diff.max_position_error.max(error);
+        if error > threshold || velocity_delta.length() > threshold {
+            diff.corrections.push(Correction {
+                frame: sample.frame,
+                client: sample.client,
+                position_delta,
+                velocity_delta,
+            });
+        }
+    }
+    diff.unmatched += b.samples.len() - diff.compared;
+    if diff.compared > 0 {
+        diff.mean_position_error = error_sum / diff.compared as f32;
+    }
+    diff.corrections.sort_by_key(|c| (c.frame, c.client));
+    diff
+}
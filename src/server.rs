@@ -0,0 +1,3853 @@
+//! the game server. The dedicated server binary runs it with a window for the debug views, or
+//! headless with `--headless`. The client embeds it headless on a loopback port for offline
+//! play.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs, io,
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use bevy::{
+    app::ScheduleRunnerSettings, asset::AssetPlugin, diagnostic::FrameTimeDiagnosticsPlugin,
+    input::InputPlugin, log::LogPlugin, math::Vec3Swizzles, prelude::*,
+};
+
+use crate::{
+    bot::{self, Bot, BotCommands, NavMesh},
+    config::{PhysicsConfig, ServerSettings, SERVER_CONFIG_FILE},
+    control::{self, ControlPoint, ControlPointEvent, ControlPointState},
+    controller::{
+        self, explosion_impulse, FpsController, FpsControllerInput, FpsControllerInputQueue,
+        FpsControllerPhysicsBundle, LandedEvent, MovementEvent, MovementSettings, Stamina,
+        MAX_MOVE_DT,
+    },
+    exit_on_esc_system,
+    flag::{self, Flag, FlagEvent, FlagEventKind},
+    frame::NetworkFrame,
+    gamemode::{
+        self, GameModePlugin, GameModeSettings, MatchEvent, MatchState, MatchSummary, Scoreboard,
+    },
+    hazard::{self, HazardKind, HazardZone},
+    health::{
+        self, Armor, DamageCause, DamageDealtEvent, DamageEvent, DamageTakenEvent, DamageType,
+        DamageWeapon, DeathEvent, Health, HealthPlugin, PushEvent, PushedBy,
+    },
+    identity::PlayerIdentity,
+    killfeed::KillFeedEvent,
+    latency::{InputLatency, LatencySample},
+    level::{
+        self, CurrentLevel, JumpPadEvent, LevelEvent, LevelKind, SurfaceMaterial, TeleportEvent,
+        WorldBounds,
+    },
+    lod::{self, LodSettings, SimulationLod},
+    logging,
+    mapfile::{self, MapManifest},
+    mapvote::MapVote,
+    metrics::{MetricsEndpoint, MetricsWriter, METRICS_INTERVAL},
+    mover::{self, Mover, MovingPlatform},
+    names::{name_from_user_data, NameRules, DEFAULT_NAME},
+    netcheck,
+    perf::{self, EntityCounts, ServerPerf},
+    pickup::{self, Pickup, PickupRespawnEvent, PickupTakenEvent},
+    prefab::{self, PrefabRegistry, PREFABS_FILE},
+    projectile::{self, Ballistic, ProjectileImpactEvent, ProjectileKinds},
+    ragdoll,
+    rcon::{BanList, RconCommand, RconServer},
+    replay::{self, SessionRecorder},
+    server_connection_config,
+    spawn::{self, SpawnPoint},
+    spawn_fireball, spawn_grenade, spawn_rocket,
+    spectator::Spectator,
+    stats::{self, StatsStore},
+    status::{StatusEffects, StatusPlugin},
+    targets::{PlayerTargets, PLAYER_CENTER_OFFSET},
+    team::{Team, TeamSettings},
+    trigger::{self, TriggerAction, TriggerEvent, TriggerEventKind, TriggerVolume},
+    usable::{self, Usable, UsableChangedEvent, UseEvent},
+    vote::{VoteKind, Votes},
+    weapon::{self, Ammo, Explosion, Inventory, MeleeCooldown, WeaponKind, WeaponSpread},
+    ClientChannel, Grenade, Player, PlayerCommand, PlayerInput, Projectile, Rocket, ServerChannel,
+    ServerMessages, CRATE_HALF_SIZE, PLAYER_MOVE_SPEED, PLAYER_SPAWN_POINT, PROTOCOL_ID,
+};
+use bevy_egui::{egui, EguiContext, EguiPlugin};
+use bevy_rapier3d::prelude::*;
+use bevy_renet::{
+    renet::{NetworkInfo, RenetServer, ServerAuthentication, ServerConfig, ServerEvent},
+    RenetServerPlugin,
+};
+use renet_visualizer::RenetServerVisualizer;
+
+#[derive(Debug, Default)]
+pub struct ServerLobby {
+    pub players: HashMap<u64, Entity>,
+    pub names: HashMap<u64, String>,
+    /// identities sent by the connected clients, see `identity`
+    pub identities: HashMap<u64, PlayerIdentity>,
+    /// client ids of spectating players
+    pub spectators: HashSet<u64>,
+}
+
+#[derive(Debug, Default)]
+struct NetworkTick(u32);
+
+/// split long server frames into several physics steps, so that collisions stay stable
+/// when the server hitches
+struct PhysicsSubsteps {
+    max_substep_dt: f32,
+    max_substeps: usize,
+}
+
+impl PhysicsSubsteps {
+    fn from_config(config: &PhysicsConfig) -> Self {
+        Self {
+            max_substep_dt: config.max_substep_dt,
+            max_substeps: config.max_substeps,
+        }
+    }
+}
+
+// Clients last received ticks
+#[derive(Debug, Default)]
+struct ClientTicks(HashMap<u64, Option<u32>>);
+
+/// clients that can wait for a free player slot, connections beyond are turned away
+const MAX_QUEUE_LENGTH: usize = 16;
+
+/// rejected clients are disconnected after this delay, so that the ServerFull message arrives
+const REJECT_DISCONNECT_DELAY: f64 = 1.0;
+
+/// player slots. While all `max_players` slots are taken, new clients wait in the join queue
+/// (if enabled) and are admitted when a slot frees up.
+#[derive(Debug)]
+struct ClientSlots {
+    max_players: usize,
+    queue_enabled: bool,
+    /// waiting clients with their requested names, first in line first
+    queue: VecDeque<(u64, String)>,
+    /// rejected clients and the time to disconnect them
+    rejected: Vec<(u64, f64)>,
+}
+
+impl ClientSlots {
+    fn from_settings(settings: &ServerSettings) -> Self {
+        Self {
+            max_players: settings.max_players,
+            queue_enabled: settings.join_queue,
+            queue: VecDeque::new(),
+            rejected: Vec::new(),
+        }
+    }
+}
+
+fn disconnect_rejected_clients_system(
+    time: Res<Time>,
+    mut slots: ResMut<ClientSlots>,
+    mut server: ResMut<RenetServer>,
+    mut perf: ResMut<ServerPerf>,
+) {
+    let now = time.seconds_since_startup();
+    slots.rejected.retain(|(id, disconnect_at)| {
+        if *disconnect_at > now {
+            return true;
+        }
+        server.disconnect(*id);
+        perf.rejected_clients += 1;
+        false
+    });
+}
+
+/// address of the dedicated server
+pub const DEFAULT_SERVER_ADDR: &str = "127.0.0.1:5000";
+
+/// frame rate of the headless server, it has no display to wait for
+const HEADLESS_FRAME_RATE: f64 = 60.0;
+
+/// where and how the server runs
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// address to listen on, port 0 picks a free port
+    pub addr: SocketAddr,
+    /// no window or debug views, the perf summary goes to the log instead
+    pub headless: bool,
+    /// runs inside the client for offline play: no own log or netcheck echo responder
+    pub embedded: bool,
+    /// set up the console and file log with the log files in this directory. Embedded servers
+    /// log to the app running them.
+    pub log_dir: Option<PathBuf>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            addr: DEFAULT_SERVER_ADDR.parse().unwrap(),
+            headless: false,
+            embedded: false,
+            log_dir: None,
+        }
+    }
+}
+
+/// the server listening on `socket`
+fn new_renet_server(socket: UdpSocket, max_clients: usize) -> RenetServer {
+    let server_addr = socket.local_addr().unwrap();
+    let connection_config = server_connection_config();
+    let server_config = ServerConfig::new(
+        max_clients,
+        PROTOCOL_ID,
+        server_addr,
+        ServerAuthentication::Unsecure,
+    );
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    RenetServer::new(current_time, server_config, connection_config, socket).unwrap()
+}
+
+/// apply movement related command line options, e.g. `--air-control=quake` or `--no-stamina`
+fn movement_settings_from_args(mut settings: MovementSettings) -> MovementSettings {
+    for arg in std::env::args().skip(1) {
+        if let Some(style) = arg.strip_prefix("--air-control=") {
+            match style.parse() {
+                Ok(style) => settings.air_control_style = style,
+                Err(e) => warn!("{}", e),
+            }
+        } else if let Some(gravity) = arg.strip_prefix("--gravity=") {
+            match gravity.parse() {
+                Ok(gravity) => settings.gravity = gravity,
+                Err(e) => warn!("invalid gravity {}: {}", gravity, e),
+            }
+        } else if let Some(cap) = arg.strip_prefix("--bhop-cap=") {
+            match cap.parse() {
+                Ok(cap) => settings.bhop_speed_cap = Some(cap),
+                Err(e) => warn!("invalid bhop cap {}: {}", cap, e),
+            }
+        } else if arg == "--no-stamina" {
+            settings.stamina = false;
+        } else if arg == "--no-auto-bhop" {
+            settings.auto_bhop = false;
+        } else if arg == "--no-bhop-grace" {
+            settings.bhop_friction_grace = false;
+        }
+    }
+    settings
+}
+
+/// apply player name rules from the command line, e.g. `--profanity-list=profanity.txt`
+fn name_rules_from_args(mut rules: NameRules) -> NameRules {
+    for arg in std::env::args().skip(1) {
+        if let Some(path) = arg.strip_prefix("--profanity-list=") {
+            if let Err(e) = rules.load_profanity_list(path) {
+                warn!("failed to load profanity list {}: {}", path, e);
+            }
+        } else if let Some(len) = arg.strip_prefix("--name-max-len=") {
+            match len.parse() {
+                Ok(len) => rules.max_len = len,
+                Err(e) => warn!("invalid name length {}: {}", len, e),
+            }
+        } else if arg == "--name-unicode" {
+            rules.allow_unicode = true;
+        }
+    }
+    rules
+}
+
+/// server config file, `--config=<file>` or server.toml
+struct ConfigFile(String);
+
+impl ConfigFile {
+    fn from_args() -> Self {
+        ConfigFile(
+            std::env::args()
+                .skip(1)
+                .find_map(|arg| arg.strip_prefix("--config=").map(str::to_string))
+                .unwrap_or_else(|| SERVER_CONFIG_FILE.to_string()),
+        )
+    }
+
+    /// load the config for `map` with the command line options applied on top
+    fn load(&self, map: Option<LevelKind>) -> Result<ServerSettings, String> {
+        let mut settings = ServerSettings::load(&self.0, map)?;
+        if let Some(path) = &settings.profanity_list {
+            if let Err(e) = settings.names.load_profanity_list(path) {
+                warn!("failed to load profanity list {}: {}", path, e);
+            }
+        }
+        settings.movement = movement_settings_from_args(settings.movement);
+        settings.names = name_rules_from_args(settings.names);
+        Ok(settings)
+    }
+}
+
+/// the rotation or an admin moved on to another map
+struct MapChangeEvent(LevelKind);
+
+/// an admin changed settings of the running server, see `rcon_system`
+struct SettingsChangedEvent(ServerSettings);
+
+/// F5: reload the server config. The map stays, everything else is applied to the running
+/// game and movement settings are sent to the clients again. A map change loads the config
+/// with the overrides of the new map, settings changed by an admin are applied as they are.
+#[allow(clippy::too_many_arguments)]
+fn reload_server_config_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut map_changes: EventReader<MapChangeEvent>,
+    mut settings_changes: EventReader<SettingsChangedEvent>,
+    config_file: Res<ConfigFile>,
+    mut settings: ResMut<ServerSettings>,
+    mut movement_settings: ResMut<MovementSettings>,
+    mut projectile_settings: ResMut<ProjectileKinds>,
+    mut name_rules: ResMut<NameRules>,
+    mut lod_settings: ResMut<LodSettings>,
+    mut team_settings: ResMut<TeamSettings>,
+    mut game_settings: ResMut<GameModeSettings>,
+    mut world_bounds: ResMut<WorldBounds>,
+    mut substeps: ResMut<PhysicsSubsteps>,
+    mut timers: (ResMut<SendTickTimer>, ResMut<AddCubeTimer>),
+    mut server: ResMut<RenetServer>,
+    mut controllers: Query<&mut FpsController>,
+) {
+    let map = match map_changes.iter().last() {
+        Some(MapChangeEvent(map)) => Some(map.clone()),
+        None if keyboard_input.just_pressed(KeyCode::F5) => Some(settings.map.clone()),
+        None => None,
+    };
+    let new_settings = match (map, settings_changes.iter().last()) {
+        (Some(map), _) => match config_file.load(Some(map.clone())) {
+            Ok(new_settings) => {
+                info!("reloaded server config {}", config_file.0);
+                new_settings
+            }
+            Err(e) => {
+                warn!("failed to reload server config: {}", e);
+                settings.map = map;
+                return;
+            }
+        },
+        (None, Some(SettingsChangedEvent(new_settings))) => new_settings.clone(),
+        (None, None) => return,
+    };
+
+    *movement_settings = new_settings.movement.clone();
+    for mut controller in &mut controllers {
+        movement_settings.apply(&mut controller);
+    }
+    let message = bincode::serialize(&ServerMessages::MovementSettings {
+        settings: movement_settings.clone(),
+    })
+    .unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+
+    *projectile_settings = new_settings.projectiles();
+    let message = bincode::serialize(&ServerMessages::ProjectileSettings {
+        settings: projectile_settings.clone(),
+    })
+    .unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+
+    *name_rules = new_settings.names.clone();
+    *lod_settings = new_settings.lod.clone();
+    *team_settings = new_settings.teams.clone();
+    // like the map, the mode stays until restart: flags are only put up on startup
+    *game_settings = GameModeSettings {
+        mode: game_settings.mode,
+        ..new_settings.game.clone()
+    };
+    *world_bounds = WorldBounds {
+        kill_z: new_settings.physics.kill_z,
+    };
+    *substeps = PhysicsSubsteps::from_config(&new_settings.physics);
+    timers.0 .0 = Timer::from_seconds(1.0 / new_settings.tick_rate, true);
+    timers.1 .0 = Timer::from_seconds(new_settings.cube_spawn_interval, true);
+    *settings = new_settings;
+}
+
+/// run a headless server on a free loopback port in a background thread, for offline play.
+/// Returns the address to connect to once the server listens.
+pub fn spawn_local_server() -> Result<SocketAddr, String> {
+    let (addr_sender, addr_receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name("local server".to_string())
+        .spawn(move || {
+            let options = ServerOptions {
+                addr: "127.0.0.1:0".parse().unwrap(),
+                headless: true,
+                embedded: true,
+                log_dir: None,
+            };
+            let mut app = App::new();
+            match build_server_app(&mut app, &options) {
+                Ok(addr) => {
+                    let _ = addr_sender.send(Ok(addr));
+                    app.run();
+                }
+                Err(e) => {
+                    let _ = addr_sender.send(Err(e));
+                }
+            }
+        })
+        .map_err(|e| e.to_string())?;
+    addr_receiver
+        .recv()
+        .map_err(|_| "local server stopped".to_string())?
+}
+
+/// set up the server in `app` and bind its socket, returns the bound address
+pub fn build_server_app(app: &mut App, options: &ServerOptions) -> Result<SocketAddr, String> {
+    // before anything is logged
+    let log_guard = match &options.log_dir {
+        Some(dir) if !options.embedded => Some(logging::init_server_logging(dir)?),
+        _ => None,
+    };
+    let socket = UdpSocket::bind(options.addr).map_err(|e| format!("{}: {}", options.addr, e))?;
+    let server_addr = socket.local_addr().map_err(|e| e.to_string())?;
+    if !options.embedded {
+        // answers the client's pre-connect diagnostics probes
+        netcheck::spawn_echo_responder(server_addr).map_err(|e| e.to_string())?;
+    }
+
+    let config_file = ConfigFile::from_args();
+    let settings = config_file.load(LevelKind::arg()).unwrap_or_else(|e| {
+        warn!("failed to load server config: {}", e);
+        let mut settings = ServerSettings {
+            map: LevelKind::from_args(),
+            ..Default::default()
+        };
+        settings.movement = movement_settings_from_args(settings.movement);
+        settings.names = name_rules_from_args(settings.names);
+        settings
+    });
+
+    if options.headless {
+        app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+            1.0 / HEADLESS_FRAME_RATE,
+        )))
+        .add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(InputPlugin)
+        .add_plugin(AssetPlugin)
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>();
+        match log_guard {
+            Some(log_guard) => {
+                app.insert_resource(log_guard);
+            }
+            // the log of embedded servers is set up by the app running them
+            None if !options.embedded => {
+                app.add_plugin(LogPlugin);
+            }
+            None => (),
+        }
+    } else if let Some(log_guard) = log_guard {
+        app.insert_resource(log_guard)
+            .add_plugins_with(DefaultPlugins, |group| group.disable::<LogPlugin>());
+    } else {
+        app.add_plugins(DefaultPlugins);
+    }
+
+    app.add_plugin(RenetServerPlugin)
+        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(HealthPlugin)
+        .add_plugin(StatusPlugin)
+        .add_plugin(GameModePlugin);
+
+    if !options.headless {
+        app.add_plugin(RapierDebugRenderPlugin::default())
+            .add_plugin(EguiPlugin)
+            .add_system(update_visulizer_system)
+            .add_system(input_latency_window_system)
+            .add_system(perf_window_system.after(perf_stats_system))
+            .add_system(exit_on_esc_system)
+            .add_startup_system(setup_simple_camera);
+    }
+
+    app.insert_resource(ServerLobby::default())
+        .insert_resource(NetworkTick(0))
+        .insert_resource(ClientTicks::default())
+        .insert_resource(settings.movement.clone())
+        .insert_resource(settings.projectiles())
+        .insert_resource(settings.names.clone())
+        .insert_resource(settings.lod.clone())
+        .insert_resource(settings.teams.clone())
+        .insert_resource(settings.game.clone())
+        .insert_resource(WorldBounds {
+            kill_z: settings.physics.kill_z,
+        })
+        .insert_resource(InputLatency::default())
+        .insert_resource(PhysicsSubsteps::from_config(&settings.physics))
+        .insert_resource(new_renet_server(
+            socket,
+            settings.max_players + MAX_QUEUE_LENGTH,
+        ))
+        .insert_resource(ClientSlots::from_settings(&settings))
+        .insert_resource(RenetServerVisualizer::<200>::default())
+        .insert_resource(SendTickTimer(Timer::from_seconds(
+            1.0 / settings.tick_rate,
+            true,
+        )))
+        .insert_resource(AddCubeTimer(Timer::from_seconds(
+            settings.cube_spawn_interval,
+            true,
+        )))
+        .init_resource::<CrateRespawns>()
+        .insert_resource(PrefabRegistry::load_or_default(PREFABS_FILE))
+        .init_resource::<MapManifestCache>()
+        .init_resource::<MapVote>()
+        .init_resource::<Votes>()
+        .init_resource::<NavMesh>()
+        .init_resource::<BotCommands>()
+        .add_event::<MapChangeEvent>()
+        .add_event::<SettingsChangedEvent>();
+
+    // `--record=<file>`: record movement inputs for the replay tool
+    if let Some(path) = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.strip_prefix("--record=").map(str::to_string))
+    {
+        match SessionRecorder::new(
+            path.clone(),
+            settings.map.clone(),
+            settings.movement.clone(),
+        ) {
+            Ok(recorder) => {
+                app.insert_resource(recorder)
+                    .add_system_to_stage(CoreStage::Last, replay::end_recorded_frame_system)
+                    .add_system_to_stage(CoreStage::Last, replay::save_recording_on_exit_system);
+            }
+            Err(e) => warn!("failed to create recording {}: {}", path, e),
+        }
+    }
+
+    app.add_system(server_update_system)
+        .add_system(disconnect_rejected_clients_system)
+        .add_system(server_network_sync)
+        .add_system(move_players_system)
+        .add_system(update_projectiles_system)
+        .add_system(grenade_fuse_system.after(projectile::projectile_motion_system))
+        .add_system(melee_system.after(server_update_system))
+        .add_system(projectile::projectile_motion_system.before(despawn_projectile_system))
+        .add_system(despawn_projectile_system)
+        .add_system(hazard::hazard_damage_system)
+        .add_system(hazard::hazard_lifetime_system)
+        .add_system(hit_confirm_system.after(health::apply_damage_system))
+        .add_system(damage_taken_system.after(health::apply_damage_system))
+        .add_system(pickup::pickup_system)
+        .add_system(drop_weapon_system.after(respawn_dead_players_system))
+        .add_system(weapon_pickup_system.after(server_update_system))
+        .add_system(usable::use_system.after(server_update_system))
+        .add_system(usable::button_reset_system)
+        .add_system(usable::usable_pose_system.after(usable::use_system))
+        .add_system(
+            mover::mover_system
+                .after(usable::use_system)
+                .before(controller::fps_controller_move),
+        )
+        .add_system(broadcast_pickup_events_system.after(pickup::pickup_system))
+        .add_system(kill_z_system)
+        .add_system(trigger::trigger_volume_system.before(trigger_action_system))
+        .add_system(trigger_action_system.before(health::apply_damage_system))
+        .add_system(destroy_crates_system.after(health::apply_damage_system))
+        .add_system(crate_respawn_system)
+        .add_system(reset_world_system.after(gamemode::match_flow_system))
+        .add_system(map_rotation_system.after(gamemode::match_flow_system))
+        .add_system(watch_map_system.after(map_rotation_system))
+        .add_system(vote_system.after(server_update_system))
+        .add_system(bot::build_nav_mesh_system)
+        .add_system(bot::bot_system.before(server_update_system))
+        .add_system(bot_count_system)
+        .add_system(broadcast_match_system.after(gamemode::match_flow_system))
+        .add_system(broadcast_flags_system.after(flag::flag_system))
+        .add_system(broadcast_control_points_system.after(control::control_point_system))
+        .add_system(carry_loadouts_system.after(map_rotation_system))
+        .add_system(
+            respawn_dead_players_system
+                .after(gamemode::match_flow_system)
+                .after(carry_loadouts_system),
+        )
+        .add_system(lod::update_simulation_lod_system)
+        // .add_system(add_cube_system)
+        ;
+
+    app.add_event::<JumpPadEvent>()
+        .add_event::<ProjectileImpactEvent>()
+        .add_event::<MeleeEvent>()
+        .add_event::<UseEvent>()
+        .add_event::<UsableChangedEvent>()
+        .add_event::<WeaponDropEvent>()
+        .add_event::<PickupTakenEvent>()
+        .add_event::<PickupRespawnEvent>()
+        .add_event::<TeleportEvent>()
+        .add_event::<LandedEvent>()
+        .add_event::<MovementEvent>()
+        .add_event::<TriggerEvent>()
+        .add_system(broadcast_movement_events_system.after(controller::fps_controller_move))
+        .add_system(level::teleport_projectiles_system)
+        .add_system(
+            broadcast_level_events_system
+                .after(controller::fps_controller_move)
+                .after(level::teleport_projectiles_system),
+        );
+    app.add_system(controller::fps_controller_move);
+    app.add_system(record_input_latency_system.after(controller::fps_controller_move));
+
+    app.add_system_to_stage(CoreStage::PostUpdate, projectile_on_removal_system);
+    app.add_system_to_stage(CoreStage::First, update_physics_substeps_system);
+
+    app.init_resource::<ServerPerf>()
+        .add_system_to_stage(CoreStage::First, perf::frame_start_system)
+        .add_system_to_stage(CoreStage::Last, perf::frame_end_system)
+        .add_system_to_stage(PhysicsStages::SyncBackend, perf::physics_start_system)
+        .add_system_to_stage(PhysicsStages::Writeback, perf::physics_end_system)
+        .add_system(perf_stats_system);
+    if options.headless && !options.embedded {
+        app.add_system(perf::log_perf_system.after(perf_stats_system));
+    }
+    app.add_system(count_connections_system);
+    // the offline server of the client reads the same server.toml as a dedicated server on the
+    // machine: leave its listeners, ban list and stats to that one
+    if options.embedded {
+        app.init_resource::<BanList>();
+    } else {
+        add_admin_services(app, &settings);
+    }
+
+    settings.map.clone().add_setup_system(app);
+    app.insert_resource(settings)
+        .insert_resource(config_file)
+        .add_system(reload_server_config_system.after(map_rotation_system));
+    app.add_startup_system(setup_crates_system);
+    Ok(server_addr)
+}
+
+/// metrics endpoint, rcon, ban list and stats store of a dedicated server, as configured
+fn add_admin_services(app: &mut App, settings: &ServerSettings) {
+    if let Some(addr) = &settings.metrics_addr {
+        match MetricsEndpoint::spawn(addr) {
+            Ok(endpoint) => {
+                app.insert_resource(endpoint)
+                    .add_system(metrics_system.after(perf_stats_system));
+            }
+            Err(e) => warn!("failed to start the metrics endpoint on {}: {}", addr, e),
+        }
+    }
+
+    let bans = match &settings.ban_list {
+        Some(path) => BanList::load(path).unwrap_or_else(|e| {
+            warn!("failed to load ban list {}: {}", path, e);
+            BanList::default()
+        }),
+        None => BanList::default(),
+    };
+    app.insert_resource(bans);
+    match (&settings.rcon_addr, &settings.rcon_password) {
+        (Some(addr), Some(password)) if !password.is_empty() => {
+            match RconServer::spawn(addr, password.clone()) {
+                Ok(rcon) => {
+                    app.insert_resource(rcon)
+                        .add_system(rcon_system.before(reload_server_config_system));
+                }
+                Err(e) => warn!("failed to start rcon on {}: {}", addr, e),
+            }
+        }
+        (Some(_), _) => warn!("rcon_addr is set without rcon_password, rcon is off"),
+        (None, _) => (),
+    }
+    if let Some(path) = &settings.stats_db {
+        match StatsStore::open(path) {
+            Ok(stats) => {
+                app.insert_resource(stats)
+                    .add_system_to_stage(CoreStage::Last, stats::flush_on_exit_system);
+            }
+            Err(e) => warn!("failed to open the stats database {}: {}", path, e),
+        }
+    }
+}
+
+#[derive(Component, Default)]
+struct PlayerInputQueue {
+    queue: VecDeque<PlayerInput>,
+    last_applied_serial: u32,
+}
+
+#[derive(Component, Default)]
+struct PlayerVelocity {
+    velocity: Vec3,
+}
+
+/// spawn the entity of a joining player or bot at `transform`
+#[allow(clippy::too_many_arguments)]
+fn spawn_player(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    movement_settings: &MovementSettings,
+    id: u64,
+    name: String,
+    team: Option<Team>,
+    transform: Transform,
+) -> Entity {
+    let mut fps_controller = FpsController::default();
+    movement_settings.apply(&mut fps_controller);
+    let player_entity = commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Capsule::default())),
+            material: materials.add(
+                team.map_or(Color::rgb(0.8, 0.7, 0.6), |team| team.color())
+                    .into(),
+            ),
+            transform,
+            ..Default::default()
+        })
+        // .insert(RigidBody::Dynamic)
+        // .insert(
+        //     LockedAxes::ROTATION_LOCKED, /*| LockedAxes::TRANSLATION_LOCKED_Y*/
+        // )
+        // .insert(Collider::capsule_y(0.5, 0.5))
+        // .insert(PlayerInput::default())
+        // // .insert(Velocity::default())
+        // .insert(PlayerInputQueue::default())
+        .insert(PlayerVelocity::default())
+        .insert(Player { id, name })
+        .insert(Health::default())
+        .insert(Armor::default())
+        .insert(Ammo::default())
+        .insert(Inventory::default())
+        .insert(StatusEffects::default())
+        .insert(MeleeCooldown::default())
+        .insert(WeaponSpread::default())
+        // .insert(ExternalImpulse::default())
+        .insert_bundle(FpsControllerPhysicsBundle::default())
+        .insert(FpsControllerInputQueue::default())
+        .insert(fps_controller)
+        .id();
+    if let Some(team) = team {
+        commands.entity(player_entity).insert(team);
+    }
+    player_entity
+}
+
+///
+/// recive ServerEvent
+/// - ClientConnected
+/// - ClientDisconnected
+///
+/// receive ClientChannel::Command
+/// - PlayerCommand
+/// - PlayerInput: put nnto player entity as component
+#[allow(clippy::too_many_arguments)]
+fn server_update_system(
+    mut server_events: EventReader<ServerEvent>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut lobby: ResMut<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    mut visualizer: ResMut<RenetServerVisualizer<200>>,
+    mut client_ticks: ResMut<ClientTicks>,
+    mut players: Query<(Entity, &Player, &Transform, Option<&mut PlayerInputQueue>)>,
+    mut players_fc: Query<&mut FpsControllerInputQueue, Without<Spectator>>,
+    movement_settings: Res<MovementSettings>,
+    tick: Res<NetworkTick>,
+    projectiles: Query<(
+        Entity,
+        &Transform,
+        &Ballistic,
+        &Projectile,
+        Option<&Grenade>,
+        Option<&Rocket>,
+    )>,
+    name_rules: Res<NameRules>,
+    mut recorder: Option<ResMut<SessionRecorder>>,
+    (
+        time,
+        mut input_latency,
+        mut slots,
+        physics_context,
+        mut damage_events,
+        projectile_settings,
+        mut melee_events,
+        mut shooters,
+        surfaces,
+        mut ammo,
+        pickups,
+        mut inventories,
+        mut use_events,
+        props,
+        usables,
+        (
+            movers,
+            teams,
+            game_settings,
+            match_state,
+            mut map_vote,
+            mut votes,
+            server_settings,
+            spawn_points,
+            mut bot_commands,
+            mut perf,
+            bans,
+            mut stats,
+        ),
+    ): (
+        Res<Time>,
+        ResMut<InputLatency>,
+        ResMut<ClientSlots>,
+        Res<RapierContext>,
+        EventWriter<DamageEvent>,
+        Res<ProjectileKinds>,
+        EventWriter<MeleeEvent>,
+        Query<(&FpsController, &mut WeaponSpread)>,
+        Query<&SurfaceMaterial>,
+        Query<&mut Ammo>,
+        Query<(&Transform, &Pickup)>,
+        Query<&mut Inventory>,
+        EventWriter<UseEvent>,
+        Query<
+            (
+                Entity,
+                &Transform,
+                &Velocity,
+                Option<&DroppedWeapon>,
+                Option<&Crate>,
+            ),
+            With<CubeMarker>,
+        >,
+        Query<&Usable>,
+        (
+            Query<(Entity, &Mover)>,
+            Query<&Team>,
+            Res<GameModeSettings>,
+            Res<MatchState>,
+            ResMut<MapVote>,
+            ResMut<Votes>,
+            Res<ServerSettings>,
+            Query<(&Transform, &SpawnPoint)>,
+            ResMut<BotCommands>,
+            ResMut<ServerPerf>,
+            Res<BanList>,
+            Option<ResMut<StatsStore>>,
+        ),
+    ),
+) {
+    // clients getting a player slot this frame, with their requested names
+    let mut joining = Vec::new();
+    let mut queue_changed = false;
+    // bots give up their slots to joining humans, see bot_count_system
+    let mut humans = lobby.players.keys().filter(|id| !bot::is_bot(**id)).count();
+    for event in server_events.iter() {
+        match event {
+            ServerEvent::ClientConnected(id, user_data) => {
+                visualizer.add_client(*id);
+                let requested_name = name_from_user_data(user_data).unwrap_or_default();
+                let identity = PlayerIdentity::from_user_data(user_data);
+                if let Some(identity) = identity {
+                    lobby.identities.insert(*id, identity);
+                    if let Some(stats) = &mut stats {
+                        stats.connected(*id, identity);
+                    }
+                }
+                if bans.is_banned(
+                    server.client_addr(*id).map(|addr| addr.ip()),
+                    identity,
+                    &name_rules.sanitize(&requested_name),
+                ) {
+                    info!(
+                        client_id = id,
+                        name = %requested_name,
+                        "banned client, disconnecting"
+                    );
+                    server.disconnect(*id);
+                } else if humans + joining.len() < slots.max_players {
+                    joining.push((*id, requested_name));
+                } else if slots.queue_enabled && slots.queue.len() < MAX_QUEUE_LENGTH {
+                    slots.queue.push_back((*id, requested_name));
+                    info!(
+                        client_id = id,
+                        position = slots.queue.len(),
+                        "server full, client queued"
+                    );
+                    let message = bincode::serialize(&ServerMessages::QueuePosition {
+                        position: slots.queue.len(),
+                    })
+                    .unwrap();
+                    server.send_message(*id, ServerChannel::ServerMessages.id(), message);
+                } else {
+                    info!(client_id = id, "server full, rejecting client");
+                    let message = bincode::serialize(&ServerMessages::ServerFull).unwrap();
+                    server.send_message(*id, ServerChannel::ServerMessages.id(), message);
+                    slots
+                        .rejected
+                        .push((*id, time.seconds_since_startup() + REJECT_DISCONNECT_DELAY));
+                }
+            }
+            ServerEvent::ClientDisconnected(id) => {
+                info!(client_id = id, "player disconnected");
+                visualizer.remove_client(*id);
+                client_ticks.0.remove(id);
+                input_latency.clients.remove(id);
+                if let Some(stats) = &mut stats {
+                    stats.disconnected(*id);
+                }
+                lobby.names.remove(id);
+                lobby.identities.remove(id);
+                lobby.spectators.remove(id);
+                if let Some(player_entity) = lobby.players.remove(id) {
+                    commands.entity(player_entity).despawn();
+                    humans -= 1;
+                }
+                if let Some(index) = slots.queue.iter().position(|(queued, _)| queued == id) {
+                    slots.queue.remove(index);
+                    queue_changed = true;
+                }
+                slots.rejected.retain(|(rejected, _)| rejected != id);
+
+                let message =
+                    bincode::serialize(&ServerMessages::PlayerRemove { id: *id }).unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+            }
+        }
+    }
+
+    // admit waiting clients into free slots
+    while humans + joining.len() < slots.max_players {
+        match slots.queue.pop_front() {
+            Some(waiting) => {
+                joining.push(waiting);
+                queue_changed = true;
+            }
+            None => break,
+        }
+    }
+    if queue_changed {
+        for (index, (id, _)) in slots.queue.iter().enumerate() {
+            let message = bincode::serialize(&ServerMessages::QueuePosition {
+                position: index + 1,
+            })
+            .unwrap();
+            server.send_message(*id, ServerChannel::ServerMessages.id(), message);
+        }
+    }
+
+    // teams of the players already in the game and of the ones joining now, for balancing
+    // and spawn points
+    let mut team_positions: Vec<(Entity, Option<Team>, Vec3)> = players
+        .iter()
+        .map(|(entity, _, transform, _)| {
+            (
+                entity,
+                teams.get(entity).ok().copied(),
+                transform.translation,
+            )
+        })
+        .collect();
+
+    // accept all joining clients first, so they take the PlayerCreate broadcasts of each other
+    for (id, _) in &joining {
+        let message = bincode::serialize(&ServerMessages::JoinAccepted).unwrap();
+        server.send_message(*id, ServerChannel::ServerMessages.id(), message);
+    }
+    for (id, requested_name) in joining {
+        let name = name_rules.resolve(&requested_name, |name| {
+            lobby.names.values().any(|taken| taken == name)
+        });
+        info!(client_id = id, name = %name, "player connected");
+
+        // Initialize other players for this new client
+        for (entity, player, transform, _) in players.iter() {
+            let team = teams.get(entity).ok().copied();
+            // let translation: [f32; 3] = transform.translation.into();
+            let message = bincode::serialize(&ServerMessages::PlayerCreate {
+                id: player.id,
+                name: player.name.clone(),
+                entity,
+                translation: transform.translation,
+                team,
+            })
+            .unwrap();
+            server.send_message(id, ServerChannel::ServerMessages.id(), message);
+        }
+        for spectator in lobby.spectators.iter() {
+            let message = bincode::serialize(&ServerMessages::PlayerSpectate {
+                id: *spectator,
+                spectating: true,
+            })
+            .unwrap();
+            server.send_message(id, ServerChannel::ServerMessages.id(), message);
+        }
+        let movers = movers
+            .iter()
+            .filter_map(|(entity, mover)| {
+                let rest = mover.waypoints.first()?;
+                Some((entity, rest.translation))
+            })
+            .collect();
+        let message = bincode::serialize(&ServerMessages::Movers { movers }).unwrap();
+        server.send_message(id, ServerChannel::ServerMessages.id(), message);
+        for usable in usables.iter().filter(|usable| usable.active) {
+            let message = bincode::serialize(&ServerMessages::LevelEvent {
+                event: LevelEvent::Use {
+                    position: usable.rest.translation,
+                    active: true,
+                },
+                player: None,
+            })
+            .unwrap();
+            server.send_message(id, ServerChannel::ServerMessages.id(), message);
+        }
+
+        // Catch up on projectiles that are already in flight
+        for (entity, transform, ballistic, projectile, grenade, rocket) in projectiles.iter() {
+            let prefab = match (grenade, rocket) {
+                (Some(_), _) => prefab::GRENADE,
+                (_, Some(_)) => prefab::ROCKET,
+                _ => prefab::FIREBALL,
+            };
+            let owner = projectile
+                .owner
+                .and_then(|owner| players.get(owner).ok())
+                .map(|(_, player, _, _)| player.id);
+            let message = bincode::serialize(&ServerMessages::SpawnProjectile {
+                entity,
+                translation: transform.translation,
+                prefab: prefab.to_string(),
+                velocity: ballistic.velocity,
+                tick: tick.0,
+                owner,
+            })
+            .unwrap();
+            server.send_message(id, ServerChannel::ServerMessages.id(), message);
+        }
+        for (entity, transform, velocity, dropped, crate_) in props.iter() {
+            let prefab = match (dropped, crate_) {
+                (Some(dropped), _) => prefab::weapon(dropped.kind),
+                (_, Some(_)) => prefab::CRATE,
+                _ => prefab::BOX,
+            };
+            let message = bincode::serialize(&ServerMessages::SpawnProjectile {
+                entity,
+                translation: transform.translation,
+                prefab: prefab.to_string(),
+                velocity: velocity.linvel,
+                tick: tick.0,
+                owner: None,
+            })
+            .unwrap();
+            server.send_message(id, ServerChannel::ServerMessages.id(), message);
+        }
+
+        let message = bincode::serialize(&ServerMessages::MovementSettings {
+            settings: movement_settings.clone(),
+        })
+        .unwrap();
+        server.send_message(id, ServerChannel::ServerMessages.id(), message);
+        let message = bincode::serialize(&ServerMessages::ProjectileSettings {
+            settings: projectile_settings.clone(),
+        })
+        .unwrap();
+        server.send_message(id, ServerChannel::ServerMessages.id(), message);
+        for (transform, pickup) in pickups.iter() {
+            if pickup.is_available() {
+                continue;
+            }
+            let message = bincode::serialize(&ServerMessages::PickupTaken {
+                position: transform.translation,
+                kind: pickup.kind,
+                player: None,
+            })
+            .unwrap();
+            server.send_message(id, ServerChannel::ServerMessages.id(), message);
+        }
+
+        // Spawn new player
+        let team = game_settings.mode.join_team(
+            bot::is_bot(id),
+            team_positions.iter().filter_map(|(_, team, _)| *team),
+        );
+        if let Some(team) = team {
+            info!(client_id = id, team = team.label(), "player joins team");
+        }
+        let transform = Transform::from_translation(spawn::select_spawn_point(
+            team,
+            spawn_points
+                .iter()
+                .map(|(transform, spawn_point)| (transform.translation, spawn_point)),
+            team_positions.iter().copied(),
+            |point, player, position| spawn::visible(&physics_context, point, player, position),
+        ));
+        let player_entity = spawn_player(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &movement_settings,
+            id,
+            name.clone(),
+            team,
+            transform,
+        );
+        team_positions.push((player_entity, team, transform.translation));
+
+        lobby.players.insert(id, player_entity);
+        lobby.names.insert(id, name.clone());
+
+        // let translation: [f32; 3] = transform.translation.into();
+        let message = bincode::serialize(&ServerMessages::PlayerCreate {
+            id,
+            name,
+            entity: player_entity,
+            translation: transform.translation,
+            team,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+        let message = bincode::serialize(&ServerMessages::ServerInfo {
+            info: server_settings.info(&game_settings),
+        })
+        .unwrap();
+        server.send_message(id, ServerChannel::ServerMessages.id(), message);
+    }
+
+    // commands of the clients and of the bots, bots play by the same rules
+    let mut player_commands = std::mem::take(&mut bot_commands.0);
+    for client_id in server.clients_id().into_iter() {
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Command.id()) {
+            perf.message_received("command", message.len());
+            player_commands.push((client_id, bincode::deserialize(&message).unwrap()));
+        }
+    }
+    for (client_id, command) in player_commands {
+        // clients waiting in the join queue have no say yet
+        if !lobby.players.contains_key(&client_id) {
+            continue;
+        }
+        // nobody fights during the countdown
+        if command.is_attack() && match_state.phase.is_frozen() {
+            continue;
+        }
+        match command {
+            PlayerCommand::BasicAttack { mut cast_at } => {
+                debug!(client_id, ?cast_at, "basic attack");
+
+                if let Some(player_entity) = lobby.players.get(&client_id) {
+                    // fireballs faster than the fire interval are dropped
+                    match shooters.get_mut(*player_entity) {
+                        Ok((_, mut spread))
+                            if !spread.try_fireball(
+                                time.seconds_since_startup(),
+                                weapon::FIRE_INTERVAL_SLACK,
+                            ) =>
+                        {
+                            continue
+                        }
+                        _ => (),
+                    }
+                    if let Ok((_, _, player_transform, _)) = players.get(*player_entity) {
+                        count_shot(&mut stats, &match_state, client_id);
+                        cast_at[1] = player_transform.translation[1];
+
+                        let direction =
+                            (cast_at - player_transform.translation).normalize_or_zero();
+                        let mut translation = player_transform.translation + (direction * 0.7);
+                        translation[1] = 1.0;
+
+                        let fireball_entity = spawn_fireball(
+                            &mut commands,
+                            &mut meshes,
+                            &mut materials,
+                            &projectile_settings.fireball,
+                            translation,
+                            direction,
+                            Some(*player_entity),
+                        );
+                        let message = ServerMessages::SpawnProjectile {
+                            entity: fireball_entity,
+                            translation,
+                            prefab: prefab::FIREBALL.to_string(),
+                            velocity: direction * projectile_settings.fireball.speed,
+                            tick: tick.0,
+                            owner: Some(client_id),
+                        };
+                        let message = bincode::serialize(&message).unwrap();
+                        // info!("spawn projectile: {}", message.len());
+                        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+                    }
+                }
+            }
+            PlayerCommand::Hitscan {
+                origin,
+                direction,
+                // not lag compensated yet, the shot is traced against the current state
+                tick: _,
+                shot,
+            } => {
+                let shooter = match lobby.players.get(&client_id) {
+                    Some(shooter) if !lobby.spectators.contains(&client_id) => *shooter,
+                    _ => continue,
+                };
+                let origin = match players.get(shooter) {
+                    Ok((_, _, transform, _)) => {
+                        weapon::hitscan_origin(transform.translation, origin)
+                    }
+                    Err(_) => continue,
+                };
+                if !fire_ready(
+                    &shooters,
+                    shooter,
+                    WeaponKind::Hitscan,
+                    time.seconds_since_startup(),
+                ) {
+                    continue;
+                }
+                count_shot(&mut stats, &match_state, client_id);
+                if let Ok(mut inventory) = inventories.get_mut(shooter) {
+                    inventory.active = WeaponKind::Hitscan;
+                }
+                let direction = spread_shot(
+                    &mut shooters,
+                    shooter,
+                    WeaponKind::Hitscan,
+                    time.seconds_since_startup(),
+                    client_id,
+                    shot,
+                    direction,
+                );
+                let hit =
+                    weapon::trace_hitscan(&physics_context, origin, direction, shooter, |entity| {
+                        surfaces.get(entity).ok().copied()
+                    });
+                let target_feet = hit.entity.and_then(|entity| {
+                    players
+                        .get(entity)
+                        .ok()
+                        .map(|(_, _, transform, _)| transform.translation)
+                });
+                let hit_player = target_feet.is_some();
+                let headshot = matches!(
+                    target_feet,
+                    Some(feet) if hit.end.y - feet.y >= weapon::HEADSHOT_HEIGHT
+                );
+                if let Some(target) = hit.entity {
+                    let mut amount = weapon::HITSCAN_DAMAGE * hit.damage_factor;
+                    if headshot {
+                        amount *= weapon::HEADSHOT_MULTIPLIER;
+                    }
+                    damage_events.send(DamageEvent {
+                        target,
+                        amount,
+                        attacker: Some(shooter),
+                        source_position: Some(origin),
+                        cause: DamageCause::Attack,
+                        damage_type: DamageType::Bullet,
+                        weapon: Some(DamageWeapon::Rifle),
+                        headshot,
+                        projectile: None,
+                    });
+                }
+                let message = bincode::serialize(&ServerMessages::HitscanShot {
+                    shooter: client_id,
+                    origin,
+                    end: hit.end,
+                    hit_player,
+                    impacts: hit.impacts,
+                })
+                .unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+            }
+            PlayerCommand::Grenade { origin, direction } => {
+                let thrower = match lobby.players.get(&client_id) {
+                    Some(thrower) if !lobby.spectators.contains(&client_id) => *thrower,
+                    _ => continue,
+                };
+                match ammo.get_mut(thrower) {
+                    Ok(mut ammo) if !ammo.take_grenade() => continue,
+                    _ => (),
+                }
+                let origin = match players.get(thrower) {
+                    Ok((_, _, transform, _)) => {
+                        weapon::hitscan_origin(transform.translation, origin)
+                    }
+                    Err(_) => continue,
+                };
+                count_shot(&mut stats, &match_state, client_id);
+                let direction = direction.normalize_or_zero();
+                let translation = origin + direction * weapon::LAUNCH_OFFSET;
+                let velocity =
+                    weapon::grenade_velocity(direction, projectile_settings.grenade.speed);
+                let grenade_entity = spawn_grenade(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    &projectile_settings.grenade,
+                    translation,
+                    velocity,
+                    Some(thrower),
+                );
+                let message = bincode::serialize(&ServerMessages::SpawnProjectile {
+                    entity: grenade_entity,
+                    translation,
+                    prefab: prefab::GRENADE.to_string(),
+                    velocity,
+                    tick: tick.0,
+                    owner: Some(client_id),
+                })
+                .unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+            }
+            PlayerCommand::Rocket {
+                origin,
+                direction,
+                shot,
+            } => {
+                let shooter = match lobby.players.get(&client_id) {
+                    Some(shooter) if !lobby.spectators.contains(&client_id) => *shooter,
+                    _ => continue,
+                };
+                let origin = match players.get(shooter) {
+                    Ok((_, _, transform, _)) => {
+                        weapon::hitscan_origin(transform.translation, origin)
+                    }
+                    Err(_) => continue,
+                };
+                if !fire_ready(
+                    &shooters,
+                    shooter,
+                    WeaponKind::Rocket,
+                    time.seconds_since_startup(),
+                ) {
+                    continue;
+                }
+                match inventories.get_mut(shooter) {
+                    Ok(inventory) if !inventory.has(WeaponKind::Rocket) => continue,
+                    Ok(mut inventory) => inventory.active = WeaponKind::Rocket,
+                    Err(_) => (),
+                }
+                match ammo.get_mut(shooter) {
+                    Ok(mut ammo) if !ammo.take_rocket() => continue,
+                    _ => (),
+                }
+                count_shot(&mut stats, &match_state, client_id);
+                let direction = spread_shot(
+                    &mut shooters,
+                    shooter,
+                    WeaponKind::Rocket,
+                    time.seconds_since_startup(),
+                    client_id,
+                    shot,
+                    direction,
+                );
+                let translation = origin + direction * weapon::LAUNCH_OFFSET;
+                let velocity = direction * projectile_settings.rocket.speed;
+                let rocket_entity = spawn_rocket(
+                    &mut commands,
+                    &mut meshes,
+                    &mut materials,
+                    &projectile_settings.rocket,
+                    translation,
+                    velocity,
+                    Some(shooter),
+                );
+                let message = bincode::serialize(&ServerMessages::SpawnProjectile {
+                    entity: rocket_entity,
+                    translation,
+                    prefab: prefab::ROCKET.to_string(),
+                    velocity,
+                    tick: tick.0,
+                    owner: Some(client_id),
+                })
+                .unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+            }
+            PlayerCommand::Melee { origin, direction } => {
+                let attacker = match lobby.players.get(&client_id) {
+                    Some(attacker) if !lobby.spectators.contains(&client_id) => *attacker,
+                    _ => continue,
+                };
+                let origin = match players.get(attacker) {
+                    Ok((_, _, transform, _)) => {
+                        weapon::hitscan_origin(transform.translation, origin)
+                    }
+                    Err(_) => continue,
+                };
+                melee_events.send(MeleeEvent {
+                    attacker,
+                    client_id,
+                    origin,
+                    direction,
+                });
+            }
+            PlayerCommand::Use { origin, direction } => {
+                let player = match lobby.players.get(&client_id) {
+                    Some(player) if !lobby.spectators.contains(&client_id) => *player,
+                    _ => continue,
+                };
+                let origin = match players.get(player) {
+                    Ok((_, _, transform, _)) => {
+                        weapon::hitscan_origin(transform.translation, origin)
+                    }
+                    Err(_) => continue,
+                };
+                use_events.send(UseEvent {
+                    player,
+                    origin,
+                    direction,
+                });
+            }
+            PlayerCommand::Spectate { spectate } => {
+                let player_entity = match lobby.players.get(&client_id) {
+                    Some(player_entity) => *player_entity,
+                    None => continue,
+                };
+                let changed = if spectate {
+                    lobby.spectators.insert(client_id)
+                } else {
+                    lobby.spectators.remove(&client_id)
+                };
+                if !changed {
+                    continue;
+                }
+                info!(client_id, spectate, "player spectating");
+                // spectators do not collide with anything, projectiles fly through them
+                if spectate {
+                    commands
+                        .entity(player_entity)
+                        .insert(Spectator)
+                        .insert(CollisionGroups::new(0, 0));
+                } else {
+                    commands
+                        .entity(player_entity)
+                        .remove::<Spectator>()
+                        .remove::<CollisionGroups>();
+                }
+                let message = bincode::serialize(&ServerMessages::PlayerSpectate {
+                    id: client_id,
+                    spectating: spectate,
+                })
+                .unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+            }
+            PlayerCommand::Vote { option } => {
+                if !map_vote.vote(client_id, option) {
+                    continue;
+                }
+                info!(client_id, option, "map vote");
+                let message = bincode::serialize(&ServerMessages::MapVoteTally {
+                    votes: map_vote.tally(),
+                })
+                .unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+            }
+            PlayerCommand::CallVote { kind } => {
+                let called = votes.call(
+                    &server_settings.votes,
+                    kind,
+                    client_id,
+                    lobby.players.keys().copied().filter(|id| !bot::is_bot(*id)),
+                );
+                let message = match called {
+                    Ok(()) => {
+                        let question =
+                            kind.question(|id| lobby.names.get(&id).cloned().unwrap_or_default());
+                        info!(client_id, question = %question, "vote called");
+                        let state = match &votes.current {
+                            Some(vote) => vote.state(&server_settings.votes),
+                            None => continue,
+                        };
+                        bincode::serialize(&ServerMessages::Vote { state }).unwrap()
+                    }
+                    Err(reason) => {
+                        let message = ServerMessages::VoteRejected { reason };
+                        let message = bincode::serialize(&message).unwrap();
+                        server.send_message(client_id, ServerChannel::ServerMessages.id(), message);
+                        continue;
+                    }
+                };
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+            }
+            PlayerCommand::CastVote { yes } => {
+                if !votes.cast(client_id, yes) {
+                    continue;
+                }
+                let state = match &votes.current {
+                    Some(vote) => vote.state(&server_settings.votes),
+                    None => continue,
+                };
+                let message = bincode::serialize(&ServerMessages::Vote { state }).unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+            }
+        }
+    }
+    for client_id in server.clients_id().into_iter() {
+        let _client_span = debug_span!("client", client_id).entered();
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Input.id()) {
+            perf.message_received("input", message.len());
+            let input: PlayerInput = bincode::deserialize(&message).unwrap();
+            client_ticks.0.insert(client_id, input.most_recent_tick);
+            if let Some(player_entity) = lobby.players.get(&client_id) {
+                if let Ok((_, _, _, Some(mut player_input_queue))) = players.get_mut(*player_entity)
+                {
+                    // commands.entity(*player_entity).insert(input);
+                    player_input_queue.queue.push_back(input)
+                }
+            }
+        }
+        let mut inputs = Vec::new();
+        while let Some(message) = server.receive_message(client_id, ClientChannel::FcInput.id()) {
+            perf.message_received("fc_input", message.len());
+            let input: FpsControllerInput = bincode::deserialize(&message).unwrap();
+            // waiting in the join queue or spectating
+            if !lobby.players.contains_key(&client_id) || lobby.spectators.contains(&client_id) {
+                continue;
+            }
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.record_input(client_id, &input);
+            }
+            trace!(serial = input.serial, "input received");
+            input_latency
+                .clients
+                .entry(client_id)
+                .or_default()
+                .received(input.serial, input.sent_at, time.seconds_since_startup());
+            inputs.push(input);
+            // client_ticks.0.insert(client_id, input.most_recent_tick);
+            // if let Some(player_entity) = lobby.players.get(&client_id) {
+            //     // if let Ok((_, _, _, mut player_input_queue)) = players.get_mut(*player_entity) {
+            //     //     // commands.entity(*player_entity).insert(input);
+            //     //     player_input_queue.queue.push_back(input)
+            //     // }
+            //     info!("input: {:?}", input);
+            // }
+        }
+        inputs.sort_by_key(|i| i.serial);
+        let input_queue = lobby
+            .players
+            .get(&client_id)
+            .and_then(|player_entity| players_fc.get_mut(*player_entity).ok());
+        if let Some(mut input_queue) = input_queue {
+            input_queue.queue.extend(inputs);
+        }
+    }
+}
+
+fn update_projectiles_system(
+    mut commands: Commands,
+    mut projectiles: Query<(Entity, &mut Projectile), Without<Grenade>>,
+    time: Res<Time>,
+) {
+    for (entity, mut projectile) in projectiles.iter_mut() {
+        projectile.duration.tick(time.delta());
+        if projectile.duration.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// entity counts and client input queues for the performance statistics
+fn perf_stats_system(
+    mut perf: ResMut<ServerPerf>,
+    entities: Query<Entity>,
+    players: Query<(&Player, &FpsController, &FpsControllerInputQueue)>,
+    projectiles: Query<(), With<Projectile>>,
+    crates: Query<(), With<CubeMarker>>,
+) {
+    let perf = &mut *perf;
+    let mut counts = EntityCounts {
+        total: entities.iter().count(),
+        projectiles: projectiles.iter().count(),
+        crates: crates.iter().count(),
+        ..default()
+    };
+    let mut clients = HashSet::new();
+    for (player, controller, input_queue) in &players {
+        if bot::is_bot(player.id) {
+            counts.bots += 1;
+            continue;
+        }
+        counts.players += 1;
+        clients.insert(player.id);
+        perf.clients.entry(player.id).or_default().input_queue = input_queue
+            .queue
+            .iter()
+            .filter(|input| input.serial > controller.last_applied_serial)
+            .count();
+    }
+    perf.clients.retain(|id, _| clients.contains(id));
+    perf.entities = counts;
+}
+
+/// count connections and disconnections for the metrics
+fn count_connections_system(
+    mut server_events: EventReader<ServerEvent>,
+    mut perf: ResMut<ServerPerf>,
+) {
+    for event in server_events.iter() {
+        match event {
+            ServerEvent::ClientConnected(..) => perf.connects += 1,
+            ServerEvent::ClientDisconnected(_) => perf.disconnects += 1,
+        }
+    }
+}
+
+/// render the metrics for the endpoint every METRICS_INTERVAL
+fn metrics_system(
+    time: Res<Time>,
+    endpoint: Res<MetricsEndpoint>,
+    perf: Res<ServerPerf>,
+    server: Res<RenetServer>,
+    mut last_update: Local<f64>,
+) {
+    let now = time.seconds_since_startup();
+    if now - *last_update < METRICS_INTERVAL {
+        return;
+    }
+    *last_update = now;
+
+    let mut metrics = MetricsWriter::default();
+    let times = [
+        (
+            "shooter_frame_seconds",
+            "server frame time",
+            &perf.frame_times,
+        ),
+        (
+            "shooter_physics_seconds",
+            "physics time per frame",
+            &perf.physics_times,
+        ),
+    ];
+    for (name, help, samples) in times {
+        metrics.family(name, "gauge", help);
+        metrics.sample(
+            name,
+            &[("stat", "avg")],
+            perf::average(samples) as f64 / 1000.0,
+        );
+        metrics.sample(name, &[("stat", "max")], perf::max(samples) as f64 / 1000.0);
+    }
+
+    let clients = server.clients_id();
+    metrics.family("shooter_clients", "gauge", "connected clients");
+    metrics.sample("shooter_clients", &[], clients.len() as f64);
+    let entities = perf.entities;
+    metrics.family("shooter_entities", "gauge", "entities by kind");
+    for (kind, count) in [
+        ("all", entities.total),
+        ("player", entities.players),
+        ("bot", entities.bots),
+        ("projectile", entities.projectiles),
+        ("crate", entities.crates),
+    ] {
+        metrics.sample("shooter_entities", &[("kind", kind)], count as f64);
+    }
+
+    for (name, help, bytes) in [
+        (
+            "shooter_sent_bytes_total",
+            "bytes sent by channel",
+            &perf.sent_bytes,
+        ),
+        (
+            "shooter_received_bytes_total",
+            "bytes received by channel",
+            &perf.received_bytes,
+        ),
+    ] {
+        metrics.family(name, "counter", help);
+        let mut channels: Vec<_> = bytes.iter().collect();
+        channels.sort_unstable();
+        for (channel, bytes) in channels {
+            metrics.sample(name, &[("channel", channel)], *bytes as f64);
+        }
+    }
+    for (name, help, count) in [
+        (
+            "shooter_connects_total",
+            "client connections",
+            perf.connects,
+        ),
+        (
+            "shooter_disconnects_total",
+            "client disconnections",
+            perf.disconnects,
+        ),
+        (
+            "shooter_rejected_clients_total",
+            "clients turned away by a full server",
+            perf.rejected_clients,
+        ),
+    ] {
+        metrics.family(name, "counter", help);
+        metrics.sample(name, &[], count as f64);
+    }
+
+    let infos: Vec<_> = clients
+        .iter()
+        .filter_map(|id| Some((id.to_string(), server.network_info(*id)?)))
+        .collect();
+    let client_gauges: [(&str, &str, fn(&NetworkInfo) -> f64); 4] = [
+        (
+            "shooter_client_sent_kbps",
+            "bandwidth to the client",
+            |info| info.sent_kbps,
+        ),
+        (
+            "shooter_client_received_kbps",
+            "bandwidth from the client",
+            |info| info.received_kbps,
+        ),
+        ("shooter_client_rtt_seconds", "round trip time", |info| {
+            info.rtt / 1000.0
+        }),
+        (
+            "shooter_client_packet_loss",
+            "packet loss fraction",
+            |info| info.packet_loss,
+        ),
+    ];
+    for (name, help, value) in client_gauges {
+        metrics.family(name, "gauge", help);
+        for (id, info) in &infos {
+            metrics.sample(name, &[("client", id)], value(info));
+        }
+    }
+    metrics.family(
+        "shooter_client_input_queue",
+        "gauge",
+        "received inputs not simulated yet",
+    );
+    for (id, client) in &perf.clients {
+        metrics.sample(
+            "shooter_client_input_queue",
+            &[("client", &id.to_string())],
+            client.input_queue as f64,
+        );
+    }
+    endpoint.update(metrics.finish());
+}
+
+/// a player by client id or name (case insensitive)
+fn find_player(lobby: &ServerLobby, player: &str) -> Result<u64, String> {
+    if let Ok(id) = player.parse() {
+        if lobby.names.contains_key(&id) {
+            return Ok(id);
+        }
+    }
+    lobby
+        .names
+        .iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(player))
+        .map(|(id, _)| *id)
+        .ok_or_else(|| format!("no player {}", player))
+}
+
+/// answer the commands of the rcon connections
+#[allow(clippy::too_many_arguments)]
+fn rcon_system(
+    rcon: Res<RconServer>,
+    mut server: ResMut<RenetServer>,
+    lobby: Res<ServerLobby>,
+    mut bans: ResMut<BanList>,
+    name_rules: Res<NameRules>,
+    settings: Res<ServerSettings>,
+    game_settings: Res<GameModeSettings>,
+    mut match_state: ResMut<MatchState>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut manifest: ResMut<MapManifestCache>,
+    mut map_changes: EventWriter<MapChangeEvent>,
+    mut settings_changes: EventWriter<SettingsChangedEvent>,
+    teams: Query<&Team>,
+) {
+    for request in rcon.requests() {
+        let reply = match &request.command {
+            // answered by the connection
+            RconCommand::Help => continue,
+            RconCommand::Status => {
+                let mut ids: Vec<u64> = lobby.names.keys().copied().collect();
+                ids.sort_unstable();
+                let mut status = format!(
+                    "map {}, {}, {} players",
+                    current_level.map.name(),
+                    game_settings.mode.label(),
+                    ids.len()
+                );
+                for id in ids {
+                    let mut line = format!("{} {}", id, lobby.names[&id]);
+                    let team = lobby
+                        .players
+                        .get(&id)
+                        .and_then(|entity| teams.get(*entity).ok());
+                    if let Some(team) = team {
+                        line.push_str(&format!(", {} team", team.label()));
+                    }
+                    if lobby.spectators.contains(&id) {
+                        line.push_str(", spectating");
+                    }
+                    if bot::is_bot(id) {
+                        line.push_str(", bot");
+                    } else if let Some(info) = server.network_info(id) {
+                        line.push_str(&format!(", {:.0} ms", info.rtt));
+                    }
+                    status.push('\n');
+                    status.push_str(&line);
+                }
+                Ok(status)
+            }
+            RconCommand::Kick { player } => find_player(&lobby, player).and_then(|id| {
+                if bot::is_bot(id) {
+                    return Err("bots are set with bots.count".to_string());
+                }
+                // the regular disconnect handling cleans up after the player
+                server.disconnect(id);
+                Ok(format!("kicked {}", lobby.names[&id]))
+            }),
+            RconCommand::Ban { player } => {
+                let online = find_player(&lobby, player)
+                    .ok()
+                    .filter(|id| !bot::is_bot(*id));
+                // identities and names are up to the client, the address of an online player
+                // is what holds the ban
+                let addr = online
+                    .and_then(|id| server.client_addr(id))
+                    .map(|addr| addr.ip());
+                // players without an identity are banned by name, like offline ones
+                let name = name_rules
+                    .sanitize(online.map_or(player.as_str(), |id| lobby.names[&id].as_str()));
+                let mut banned = Vec::new();
+                if let Some(addr) = addr {
+                    banned.push(bans.ban_address(addr));
+                }
+                match online.and_then(|id| lobby.identities.get(&id)) {
+                    Some(identity) => banned.push(bans.ban_identity(*identity)),
+                    None if name == DEFAULT_NAME => (),
+                    None => banned.push(bans.ban_name(&name)),
+                }
+                match banned.into_iter().collect::<io::Result<Vec<_>>>() {
+                    Ok(entries) if entries.is_empty() => Err(format!(
+                        "{} is everyone without a name, kick instead",
+                        DEFAULT_NAME
+                    )),
+                    Ok(entries) => {
+                        if let Some(id) = online {
+                            server.disconnect(id);
+                        }
+                        Ok(format!("banned {}", entries.join(" ")))
+                    }
+                    Err(e) => Err(format!(
+                        "banned {}, but saving the ban list failed: {}",
+                        player, e
+                    )),
+                }
+            }
+            RconCommand::Unban { entry } => match bans.unban(entry) {
+                Ok(true) => Ok(format!("unbanned {}", entry)),
+                Ok(false) => Err(format!("{} is not banned", entry)),
+                Err(e) => Err(format!(
+                    "unbanned {}, but saving the ban list failed: {}",
+                    entry, e
+                )),
+            },
+            RconCommand::Map { map } => map.parse::<LevelKind>().map(|map| {
+                info!("Admin changed the map to {}.", map.name());
+                let reply = format!("changing to {}", map.name());
+                change_map(
+                    map,
+                    &mut current_level,
+                    &mut manifest,
+                    &mut server,
+                    &mut map_changes,
+                );
+                // everyone respawns on the new map
+                match_state.restart();
+                reply
+            }),
+            RconCommand::Set { key, .. } if key == "map" => {
+                Err("use map <name> to change the map".to_string())
+            }
+            RconCommand::Set { key, value } => {
+                settings.with_setting(key, value).map(|new_settings| {
+                    info!("Admin set {} to {}.", key, value);
+                    settings_changes.send(SettingsChangedEvent(new_settings));
+                    format!("{} = {}", key, value)
+                })
+            }
+            RconCommand::Say { text } => {
+                info!("Admin: {}", text);
+                let message =
+                    bincode::serialize(&ServerMessages::AdminMessage { text: text.clone() })
+                        .unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+                Ok("sent".to_string())
+            }
+        };
+        request.reply(reply.unwrap_or_else(|e| format!("error: {}", e)));
+    }
+}
+
+fn perf_graph(ui: &mut egui::Ui, id: impl std::hash::Hash, samples: &VecDeque<f32>) {
+    let line = egui::plot::Line::new(egui::plot::Values::from_values_iter(
+        samples
+            .iter()
+            .enumerate()
+            .map(|(i, value)| egui::plot::Value::new(i as f64, *value)),
+    ));
+    egui::plot::Plot::new(id)
+        .height(60.0)
+        .width(300.0)
+        .include_y(0.0)
+        .show(ui, |plot_ui| plot_ui.line(line));
+}
+
+/// frame and physics time, entity counts and per client input queue and snapshot bandwidth
+fn perf_window_system(
+    mut egui_context: ResMut<EguiContext>,
+    perf: Res<ServerPerf>,
+    lobby: Res<ServerLobby>,
+) {
+    egui::Window::new("performance").show(egui_context.ctx_mut(), |ui| {
+        for (name, samples) in [
+            ("frame (ms)", &perf.frame_times),
+            ("physics (ms)", &perf.physics_times),
+        ] {
+            ui.label(format!(
+                "{}: {:.2} avg, {:.2} max",
+                name,
+                perf::average(samples),
+                perf::max(samples)
+            ));
+            perf_graph(ui, name, samples);
+        }
+        let entities = perf.entities;
+        ui.label(format!(
+            "entities: {} ({} players, {} bots, {} projectiles, {} crates)",
+            entities.total, entities.players, entities.bots, entities.projectiles, entities.crates
+        ));
+        let mut clients: Vec<_> = perf.clients.iter().collect();
+        clients.sort_unstable_by_key(|(id, _)| **id);
+        for (id, client) in clients {
+            let name = lobby
+                .names
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| id.to_string());
+            ui.label(format!(
+                "{}: {} queued inputs, {:.0} B/s",
+                name,
+                client.input_queue,
+                client.bytes_per_second.back().copied().unwrap_or_default()
+            ));
+            perf_graph(ui, id, &client.bytes_per_second);
+        }
+    });
+}
+
+fn update_visulizer_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut visualizer: ResMut<RenetServerVisualizer<200>>,
+    server: Res<RenetServer>,
+) {
+    visualizer.update(&server);
+    visualizer.show_window(egui_context.ctx_mut());
+}
+
+/// resolve the latency of inputs the movement simulation applied this frame
+fn record_input_latency_system(
+    time: Res<Time>,
+    server: Res<RenetServer>,
+    players: Query<(&Player, &FpsController)>,
+    mut input_latency: ResMut<InputLatency>,
+) {
+    for (player, controller) in &players {
+        if let Some(latency) = input_latency.clients.get_mut(&player.id) {
+            let rtt = server
+                .network_info(player.id)
+                .map_or(0.0, |info| info.rtt / 1000.0);
+            latency.applied(
+                controller.last_applied_serial,
+                time.seconds_since_startup(),
+                rtt,
+            );
+        }
+    }
+}
+
+/// input latency percentiles per client: network latency vs. time spent in the input queue
+fn input_latency_window_system(
+    mut egui_context: ResMut<EguiContext>,
+    input_latency: Res<InputLatency>,
+    lobby: Res<ServerLobby>,
+) {
+    const PERCENTILES: [f64; 3] = [50.0, 90.0, 99.0];
+    egui::Window::new("input latency").show(egui_context.ctx_mut(), |ui| {
+        egui::Grid::new("input_latency").show(ui, |ui| {
+            ui.label("client");
+            ui.label("network p50/p90/p99");
+            ui.label("queue p50/p90/p99");
+            ui.label("total p50/p90/p99");
+            ui.end_row();
+            for (id, latency) in &input_latency.clients {
+                let name = lobby
+                    .names
+                    .get(id)
+                    .cloned()
+                    .unwrap_or_else(|| id.to_string());
+                ui.label(name);
+                let columns: [fn(&LatencySample) -> f64; 3] = [
+                    |sample| sample.network,
+                    |sample| sample.queue,
+                    LatencySample::total,
+                ];
+                for value in columns {
+                    match latency.percentiles(value, &PERCENTILES) {
+                        Some(ms) => ui.label(format!(
+                            "{:.1} / {:.1} / {:.1} ms",
+                            ms[0] * 1000.0,
+                            ms[1] * 1000.0,
+                            ms[2] * 1000.0
+                        )),
+                        None => ui.label("-"),
+                    };
+                }
+                ui.end_row();
+            }
+        });
+    });
+}
+
+struct SendTickTimer(Timer);
+
+/// send out NetworkFrame messages to clients
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn server_network_sync(
+    mut tick: ResMut<NetworkTick>,
+    mut server: ResMut<RenetServer>,
+    time: Res<Time>,
+    mut timer: ResMut<SendTickTimer>,
+    players: Query<
+        (Entity, &Transform, &PlayerVelocity),
+        (
+            Without<Projectile>,
+            With<Player>,
+            Without<CubeMarker>,
+            Without<Spectator>,
+        ),
+    >,
+    projectiles: Query<
+        (Entity, &Transform, &Ballistic),
+        (With<Projectile>, Without<Player>, Without<CubeMarker>),
+    >,
+    cubes: Query<
+        (Entity, &Transform, &Velocity, Option<&SimulationLod>),
+        (Without<Projectile>, Without<Player>, With<CubeMarker>),
+    >,
+    movers: Query<(Entity, &Transform, &MovingPlatform), (With<Mover>, Without<Player>)>,
+    player_query: Query<(
+        Entity,
+        &FpsController,
+        &Player,
+        Option<&Health>,
+        Option<&Armor>,
+        Option<&Ammo>,
+        Option<&StatusEffects>,
+        Option<&Spectator>,
+    )>,
+    lod_settings: Res<LodSettings>,
+    mut perf: ResMut<ServerPerf>,
+    // network frames sent so far, far entities go into every few of them
+    mut sent_frames: Local<u32>,
+) {
+    let _tick_span = debug_span!("tick", tick = tick.0).entered();
+    let mut frame = NetworkFrame::default();
+
+    for (entity, transform, velocity) in players.iter() {
+        frame.entities.entities.push(entity);
+        frame.entities.translations.push(transform.translation);
+        frame.entities.velocities.push(velocity.velocity);
+        // frame.entities.rotations.push(default());
+    }
+
+    for (entity, transform, ballistic) in projectiles.iter() {
+        frame.entities.entities.push(entity);
+        frame.entities.translations.push(transform.translation);
+        frame.entities.velocities.push(ballistic.velocity);
+        // frame.entities.rotations.push(default());
+    }
+
+    for (entity, transform, velocity, lod) in cubes.iter() {
+        if !lod.map_or(true, |lod| {
+            lod.should_replicate(&lod_settings, *sent_frames)
+        }) {
+            continue;
+        }
+        frame.with_rotation.entities.push(entity);
+        frame.with_rotation.translations.push(transform.translation);
+        frame.with_rotation.velocities.push(velocity.linvel);
+        frame.with_rotation.rotations.push(transform.rotation);
+        // info!("rot: {:?}", velocity.angvel);
+    }
+
+    for (entity, transform, platform) in movers.iter() {
+        frame.with_rotation.entities.push(entity);
+        frame.with_rotation.translations.push(transform.translation);
+        frame.with_rotation.velocities.push(platform.velocity);
+        frame.with_rotation.rotations.push(transform.rotation);
+    }
+
+    let now = time.seconds_since_startup();
+    for (entity, fps_controller, _, health, _, _, _, spectator) in &player_query {
+        if spectator.is_some() {
+            continue;
+        }
+        frame.players.entities.push(entity);
+        frame.players.yaws.push(fps_controller.yaw);
+        frame.players.pitches.push(fps_controller.pitch);
+        frame.players.blocking.push(fps_controller.blocking);
+        frame.players.aiming.push(fps_controller.aiming);
+        frame.players.shields.push(fps_controller.shield);
+        frame
+            .players
+            .damaged
+            .push(health.map_or(false, |health| health.recently_damaged(now)));
+        frame.players.grounded.push(fps_controller.ground_tick >= 1);
+        frame.players.crouching.push(fps_controller.crouched);
+    }
+
+    frame.tick = tick.0;
+    tick.0 += 1;
+    // info!("tick: {}", tick.0);
+    timer.0.tick(time.delta());
+    if timer.0.just_finished() {
+        *sent_frames = sent_frames.wrapping_add(1);
+        for (_, fps_controller, player, health, armor, ammo, status, _) in &player_query {
+            // bots have no client
+            if bot::is_bot(player.id) {
+                continue;
+            }
+            let _client_span = debug_span!(
+                "client",
+                client_id = player.id,
+                serial = fps_controller.last_applied_serial
+            )
+            .entered();
+            frame.last_player_input = fps_controller.last_applied_serial;
+            frame.own.stamina = fps_controller.stamina.as_ref().map(Stamina::fraction);
+            frame.own.exhausted = fps_controller.is_exhausted();
+            frame.own.shield = fps_controller.shield / fps_controller.max_shield;
+            frame.own.health = health.map(|health| health.current / health.max);
+            frame.own.armor = armor.map(|armor| armor.current / armor.max);
+            frame.own.ammo = ammo.copied();
+            frame.own.status = status.map_or_else(Vec::new, |status| status.effects.clone());
+            let sync_message = bincode::serialize(&frame).unwrap();
+            trace!(bytes = sync_message.len(), "network frame");
+            perf.snapshot_sent(player.id, sync_message.len());
+            // server.broadcast_message(ServerChannel::NetworkFrame.id(), sync_message);
+            server.send_message(player.id, ServerChannel::NetworkFrame.id(), sync_message);
+        }
+    }
+}
+
+// apply PlayerInput to client entities
+fn move_players_system(
+    mut query: Query<(
+        &mut Transform,
+        &mut PlayerInputQueue,
+        &mut PlayerVelocity,
+        &mut ExternalImpulse,
+    )>,
+) {
+    for (mut _transform, mut input_queue, mut player_velocity, mut impulse) in query.iter_mut() {
+        while let Some(input) = input_queue.queue.pop_front() {
+            trace!(serial = input.serial, "apply player input");
+            let x = (input.right as i8 - input.left as i8) as f32;
+            let y = (input.down as i8 - input.up as i8) as f32;
+            let direction = Vec2::new(x, y).normalize_or_zero();
+            let offs = direction * PLAYER_MOVE_SPEED; // * (1.0 / 60.0);
+                                                      // transform.translation.x += offs.x;
+                                                      // transform.translation.z += offs.y;
+            impulse.impulse.x = offs.x;
+            impulse.impulse.z = offs.y;
+
+            player_velocity.velocity = (direction * PLAYER_MOVE_SPEED).extend(0.0).xzy();
+            input_queue.last_applied_serial = input.serial;
+            // velocity.linvel.x = direction.x * PLAYER_MOVE_SPEED;
+            // velocity.linvel.z = direction.y * PLAYER_MOVE_SPEED;
+        }
+    }
+}
+
+/// pick the number of physics substeps for this frame's delta time
+fn update_physics_substeps_system(
+    time: Res<Time>,
+    substeps: Res<PhysicsSubsteps>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    let dt = time.delta_seconds().min(MAX_MOVE_DT);
+    let count = ((dt / substeps.max_substep_dt).ceil() as usize).clamp(1, substeps.max_substeps);
+    rapier_config.timestep_mode = TimestepMode::Variable {
+        max_dt: substeps.max_substep_dt * substeps.max_substeps as f32,
+        time_scale: 1.0,
+        substeps: count,
+    };
+}
+
+pub fn setup_simple_camera(mut commands: Commands) {
+    // camera
+    commands.spawn_bundle(Camera3dBundle {
+        transform: Transform::from_xyz(-5.5, 5.0, 5.5).looking_at(Vec3::ZERO, Vec3::Y),
+        ..Default::default()
+    });
+}
+
+/// despawn fireballs and rockets on impact and explode them. Fireballs knock back nearby
+/// players and leave a short-lived fire pool on the ground below, rockets deal splash damage.
+#[allow(clippy::too_many_arguments)]
+fn despawn_projectile_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut server: ResMut<RenetServer>,
+    physics_context: Res<RapierContext>,
+    mut impact_events: EventReader<ProjectileImpactEvent>,
+    projectile_query: Query<(&Projectile, Option<&Rocket>), Without<Grenade>>,
+    targets: PlayerTargets,
+    mut players: Query<(&Player, &mut FpsController)>,
+    mut push_events: EventWriter<PushEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    crates: Query<(Entity, &Transform), With<Crate>>,
+) {
+    for event in impact_events.iter() {
+        let (projectile, rocket) = match projectile_query.get(event.projectile) {
+            Ok(projectile) => projectile,
+            Err(_) => continue,
+        };
+        commands.entity(event.projectile).despawn();
+        let (explosion, weapon) = match rocket {
+            Some(_) => (&weapon::ROCKET_EXPLOSION, DamageWeapon::Rocket),
+            None => (&weapon::FIREBALL_EXPLOSION, DamageWeapon::Fireball),
+        };
+        apply_explosion(
+            &mut server,
+            &targets,
+            &mut players,
+            &crates,
+            &mut push_events,
+            &mut damage_events,
+            event.projectile,
+            event.position,
+            explosion,
+            weapon,
+            projectile.owner,
+        );
+        if rocket.is_some() {
+            continue;
+        }
+        spawn_fire_pool(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            &mut server,
+            &physics_context,
+            event.position,
+            projectile.owner,
+        );
+    }
+}
+
+/// count a shot for the accuracy stats, only while the match runs
+fn count_shot(stats: &mut Option<ResMut<StatsStore>>, match_state: &MatchState, client_id: u64) {
+    if let Some(stats) = stats {
+        if match_state.is_running() {
+            stats.shot(client_id);
+        }
+    }
+}
+
+/// whether the fire interval of `weapon` has passed since the shooter's last shot with it,
+/// shots arriving earlier are dropped
+fn fire_ready(
+    shooters: &Query<(&FpsController, &mut WeaponSpread)>,
+    shooter: Entity,
+    weapon: WeaponKind,
+    now: f64,
+) -> bool {
+    shooters.get(shooter).map_or(true, |(_, spread)| {
+        spread.ready(weapon, now, weapon::FIRE_INTERVAL_SLACK)
+    })
+}
+
+/// direction of a shot after spread: the weapon's bloom plus the shooter's movement
+/// inaccuracy, in the pattern of the shooting client
+fn spread_shot(
+    shooters: &mut Query<(&FpsController, &mut WeaponSpread)>,
+    shooter: Entity,
+    weapon: WeaponKind,
+    now: f64,
+    client_id: u64,
+    shot: u32,
+    direction: Vec3,
+) -> Vec3 {
+    match shooters.get_mut(shooter) {
+        Ok((controller, mut spread)) => {
+            let shot = spread.accept_shot(shot);
+            let cone = weapon::aimed_cone(
+                spread.fire(weapon, now) + controller.movement_spread(),
+                controller.aiming,
+            );
+            weapon::spread_direction(direction, cone, client_id, shot)
+        }
+        Err(_) => direction.normalize_or_zero(),
+    }
+}
+
+/// weapon lying around after its carrier died, with the rockets it was loaded with
+#[derive(Component)]
+struct DroppedWeapon {
+    kind: WeaponKind,
+    rockets: u32,
+    lifetime: Timer,
+}
+
+/// a dead player drops its active weapon at `position`
+struct WeaponDropEvent {
+    kind: WeaponKind,
+    position: Vec3,
+    rockets: u32,
+}
+
+/// toss dropped weapons into the world as physics props, replicated like the cubes
+fn drop_weapon_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut server: ResMut<RenetServer>,
+    tick: Res<NetworkTick>,
+    prefabs: Res<PrefabRegistry>,
+    mut drop_events: EventReader<WeaponDropEvent>,
+) {
+    for event in drop_events.iter() {
+        let id = prefab::weapon(event.kind);
+        let prefab = prefabs.get(id);
+        let velocity = Vec3::Y * 2.0;
+        let mut entity = commands.spawn_bundle(prefab.pbr_bundle(
+            &mut meshes,
+            &mut materials,
+            Transform::from_translation(event.position),
+        ));
+        prefab.insert_physics(&mut entity, velocity);
+        let entity = entity
+            .insert(CubeMarker)
+            .insert(DroppedWeapon {
+                kind: event.kind,
+                rockets: event.rockets,
+                lifetime: Timer::from_seconds(weapon::DROPPED_WEAPON_LIFETIME, false),
+            })
+            .id();
+        let message = bincode::serialize(&ServerMessages::SpawnProjectile {
+            entity,
+            translation: event.position,
+            prefab: id.to_string(),
+            velocity,
+            tick: tick.0,
+            owner: None,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+/// hand dropped weapons to players touching them: automatically if they don't carry that
+/// weapon yet, on the use key otherwise (for the rockets). Unclaimed weapons disappear after
+/// their lifetime.
+#[allow(clippy::type_complexity)]
+fn weapon_pickup_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    mut use_events: EventReader<UseEvent>,
+    mut dropped_weapons: Query<(Entity, &Transform, &mut DroppedWeapon)>,
+    mut players: Query<
+        (
+            Entity,
+            &Player,
+            &Transform,
+            &Health,
+            &mut Inventory,
+            Option<&mut Ammo>,
+        ),
+        (Without<Spectator>, Without<DroppedWeapon>),
+    >,
+) {
+    let using: HashSet<Entity> = use_events.iter().map(|event| event.player).collect();
+    for (entity, transform, mut dropped) in &mut dropped_weapons {
+        dropped.lifetime.tick(time.delta());
+        let taker = if dropped.lifetime.finished() {
+            None
+        } else {
+            players.iter_mut().find(
+                |(player_entity, _, player_transform, health, inventory, _)| {
+                    let center = player_transform.translation + PLAYER_CENTER_OFFSET;
+                    !health.is_dead()
+                        && center.distance(transform.translation) <= weapon::WEAPON_PICKUP_RADIUS
+                        && (!inventory.has(dropped.kind) || using.contains(player_entity))
+                },
+            )
+        };
+        match taker {
+            Some((_, player, _, _, mut inventory, ammo)) => {
+                inventory.add(dropped.kind);
+                if let Some(mut ammo) = ammo {
+                    ammo.refill(0, dropped.rockets);
+                }
+                info!("Player {} picked up a {}.", player.id, dropped.kind.label());
+                let message = bincode::serialize(&ServerMessages::InventoryChanged {
+                    id: player.id,
+                    inventory: inventory.clone(),
+                })
+                .unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+            }
+            None if dropped.lifetime.finished() => (),
+            None => continue,
+        }
+        commands.entity(entity).despawn();
+        let message = bincode::serialize(&ServerMessages::DespawnProjectile { entity }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+/// validated melee command of a player
+struct MeleeEvent {
+    attacker: Entity,
+    client_id: u64,
+    origin: Vec3,
+    direction: Vec3,
+}
+
+/// resolve melee swings: sweep in front of the attacker, damage and knock back the first thing
+/// hit and send the swing to all clients. Swings during the cooldown are dropped.
+#[allow(clippy::too_many_arguments)]
+fn melee_system(
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    physics_context: Res<RapierContext>,
+    mut melee_events: EventReader<MeleeEvent>,
+    mut cooldowns: Query<&mut MeleeCooldown>,
+    mut players: Query<(&Player, &mut FpsController)>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut push_events: EventWriter<PushEvent>,
+    match_state: Res<MatchState>,
+    mut stats: Option<ResMut<StatsStore>>,
+) {
+    for event in melee_events.iter() {
+        match cooldowns.get_mut(event.attacker) {
+            Ok(mut cooldown) if cooldown.try_swing(time.seconds_since_startup()) => (),
+            _ => continue,
+        }
+        count_shot(&mut stats, &match_state, event.client_id);
+        let hit = weapon::trace_melee(
+            &physics_context,
+            event.origin,
+            event.direction,
+            event.attacker,
+        );
+        let mut hit_player = false;
+        if let Some((target, _)) = hit {
+            damage_events.send(DamageEvent {
+                target,
+                amount: weapon::MELEE_DAMAGE,
+                attacker: Some(event.attacker),
+                source_position: Some(event.origin),
+                cause: DamageCause::Attack,
+                damage_type: DamageType::Bullet,
+                weapon: Some(DamageWeapon::Melee),
+                headshot: false,
+                projectile: None,
+            });
+            if let Ok((player, mut controller)) = players.get_mut(target) {
+                hit_player = true;
+                let impulse = weapon::melee_knockback(event.direction);
+                controller.add_impulse(impulse);
+                if !bot::is_bot(player.id) {
+                    let message = bincode::serialize(&ServerMessages::PlayerImpulse {
+                        impulse,
+                        source: None,
+                    })
+                    .unwrap();
+                    server.send_message(player.id, ServerChannel::ServerMessages.id(), message);
+                }
+                push_events.send(PushEvent {
+                    target,
+                    pusher: event.attacker,
+                    strength: impulse.length(),
+                });
+            }
+        }
+        let message = bincode::serialize(&ServerMessages::MeleeSwing {
+            attacker: event.client_id,
+            origin: event.origin,
+            direction: event.direction,
+            hit_player,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+/// explode grenades when their fuse runs out
+#[allow(clippy::too_many_arguments)]
+fn grenade_fuse_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    mut grenades: Query<(Entity, &Transform, &mut Projectile), With<Grenade>>,
+    targets: PlayerTargets,
+    mut players: Query<(&Player, &mut FpsController)>,
+    mut push_events: EventWriter<PushEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    crates: Query<(Entity, &Transform), With<Crate>>,
+) {
+    for (entity, transform, mut grenade) in &mut grenades {
+        grenade.duration.tick(time.delta());
+        if !grenade.duration.just_finished() {
+            continue;
+        }
+        commands.entity(entity).despawn();
+        apply_explosion(
+            &mut server,
+            &targets,
+            &mut players,
+            &crates,
+            &mut push_events,
+            &mut damage_events,
+            entity,
+            transform.translation,
+            &weapon::GRENADE_EXPLOSION,
+            DamageWeapon::Grenade,
+            grenade.owner,
+        );
+    }
+}
+
+/// knock back and damage players in reach of the explosion of `projectile`, damage crates and
+/// send the explosion to the clients. Walls between the explosion and a player block the
+/// damage, not the knockback.
+#[allow(clippy::too_many_arguments)]
+fn apply_explosion(
+    server: &mut RenetServer,
+    targets: &PlayerTargets,
+    players: &mut Query<(&Player, &mut FpsController)>,
+    crates: &Query<(Entity, &Transform), With<Crate>>,
+    push_events: &mut EventWriter<PushEvent>,
+    damage_events: &mut EventWriter<DamageEvent>,
+    projectile: Entity,
+    center: Vec3,
+    explosion: &Explosion,
+    weapon: DamageWeapon,
+    owner: Option<Entity>,
+) {
+    for (entity, position) in targets.players_within(center, explosion.radius) {
+        let mut damage = explosion.damage_at(center.distance(position));
+        if owner == Some(entity) {
+            damage *= explosion.self_damage;
+        }
+        if damage > 0.0 && targets.visible_from(center, entity, None) {
+            damage_events.send(DamageEvent {
+                target: entity,
+                amount: damage,
+                attacker: owner,
+                source_position: Some(center),
+                cause: DamageCause::Attack,
+                damage_type: DamageType::Explosive,
+                weapon: Some(weapon),
+                headshot: false,
+                projectile: Some(projectile),
+            });
+        }
+        let (player, mut controller) = match players.get_mut(entity) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        let impulse =
+            match explosion_impulse(center, explosion.radius, explosion.strength, position) {
+                Some(impulse) => impulse,
+                None => continue,
+            };
+        controller.add_impulse(impulse);
+        if !bot::is_bot(player.id) {
+            let message = bincode::serialize(&ServerMessages::PlayerImpulse {
+                impulse,
+                source: Some(projectile),
+            })
+            .unwrap();
+            server.send_message(player.id, ServerChannel::ServerMessages.id(), message);
+        }
+        if let Some(owner) = owner {
+            push_events.send(PushEvent {
+                target: entity,
+                pusher: owner,
+                strength: impulse.length(),
+            });
+        }
+    }
+    for (entity, transform) in crates.iter() {
+        let distance = center.distance(transform.translation);
+        if distance >= explosion.radius {
+            continue;
+        }
+        damage_events.send(DamageEvent {
+            target: entity,
+            amount: explosion.damage_at(distance),
+            attacker: owner,
+            source_position: Some(center),
+            cause: DamageCause::Attack,
+            damage_type: DamageType::Explosive,
+            weapon: Some(weapon),
+            headshot: false,
+            projectile: Some(projectile),
+        });
+    }
+    let message = bincode::serialize(&ServerMessages::Explosion {
+        position: center,
+        radius: explosion.radius,
+        projectile,
+    })
+    .unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+}
+
+fn spawn_fire_pool(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    server: &mut RenetServer,
+    physics_context: &RapierContext,
+    impact: Vec3,
+    owner: Option<Entity>,
+) {
+    let toi =
+        match physics_context.cast_ray(impact, -Vec3::Y, 10.0, true, QueryFilter::only_fixed()) {
+            Some((_, toi)) => toi,
+            None => return,
+        };
+    let translation = impact - Vec3::Y * toi;
+    let kind = HazardKind::Fire;
+    let radius = 0.75;
+    let lifetime = Some(3.0);
+    hazard::spawn_hazard_zone(
+        commands,
+        meshes,
+        materials,
+        translation,
+        HazardZone::new(kind, radius, lifetime).with_owner(owner),
+    );
+    let message = bincode::serialize(&ServerMessages::SpawnHazardZone {
+        translation,
+        kind,
+        radius,
+        lifetime,
+    })
+    .unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+}
+
+/// kill players and remove props that fell out of the world
+fn kill_z_system(
+    mut commands: Commands,
+    mut server: ResMut<RenetServer>,
+    bounds: Res<WorldBounds>,
+    players: Query<(Entity, &Transform, &Health), With<Player>>,
+    props: Query<(Entity, &Transform), With<CubeMarker>>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    for (entity, transform, health) in &players {
+        if transform.translation.y < bounds.kill_z && !health.is_dead() {
+            damage_events.send(DamageEvent {
+                target: entity,
+                amount: health.max,
+                attacker: None,
+                source_position: None,
+                cause: DamageCause::Fall,
+                damage_type: DamageType::Fall,
+                weapon: None,
+                headshot: false,
+                projectile: None,
+            });
+        }
+    }
+    for (entity, transform) in &props {
+        if transform.translation.y < bounds.kill_z {
+            commands.entity(entity).despawn();
+            let message =
+                bincode::serialize(&ServerMessages::DespawnProjectile { entity }).unwrap();
+            server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+        }
+    }
+}
+
+/// interval of the damage of trigger volumes (seconds)
+const TRIGGER_DAMAGE_INTERVAL: f32 = 0.5;
+
+/// carry out the actions of trigger volumes. Teleports, launches and kills happen on entering,
+/// damage volumes hurt everyone inside every TRIGGER_DAMAGE_INTERVAL.
+#[allow(clippy::too_many_arguments)]
+fn trigger_action_system(
+    mut commands: Commands,
+    mut server: ResMut<RenetServer>,
+    time: Res<Time>,
+    mut damage_timer: Local<f32>,
+    mut trigger_events: EventReader<TriggerEvent>,
+    triggers: Query<&TriggerVolume>,
+    mut players: Query<(&Player, &mut Transform, &mut FpsController, &Health)>,
+    props: Query<(), With<CubeMarker>>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    let mut level_events = Vec::new();
+    for event in trigger_events.iter() {
+        let volume = match triggers.get(event.trigger) {
+            Ok(volume) if event.kind == TriggerEventKind::Enter => volume,
+            _ => continue,
+        };
+        if let TriggerAction::Kill = volume.action {
+            if props.contains(event.entity) {
+                commands.entity(event.entity).despawn();
+                let message = bincode::serialize(&ServerMessages::DespawnProjectile {
+                    entity: event.entity,
+                })
+                .unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+                continue;
+            }
+        }
+        let (player, mut transform, mut controller, health) = match players.get_mut(event.entity) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        if health.is_dead() {
+            continue;
+        }
+        match &volume.action {
+            TriggerAction::Teleport { translation } => {
+                let from = transform.translation;
+                transform.translation = *translation;
+                controller.velocity = Vec3::ZERO;
+                level_events.push(LevelEvent::Teleport {
+                    from,
+                    to: *translation,
+                });
+            }
+            TriggerAction::Kill => damage_events.send(DamageEvent {
+                target: event.entity,
+                amount: health.max,
+                attacker: None,
+                source_position: None,
+                cause: DamageCause::Fall,
+                damage_type: DamageType::Fall,
+                weapon: None,
+                headshot: false,
+                projectile: None,
+            }),
+            TriggerAction::JumpPad { launch_velocity } => {
+                controller.velocity = *launch_velocity;
+                level_events.push(LevelEvent::JumpPad {
+                    position: transform.translation,
+                });
+            }
+            TriggerAction::Objective { name } => {
+                info!("Player {} entered objective {:?}.", player.id, name);
+            }
+            TriggerAction::Damage { .. } => (),
+        }
+    }
+    // not predicted, so the effects are played for everyone
+    for event in level_events {
+        let message = bincode::serialize(&ServerMessages::LevelEvent {
+            event,
+            player: None,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+
+    *damage_timer += time.delta_seconds();
+    if *damage_timer < TRIGGER_DAMAGE_INTERVAL {
+        return;
+    }
+    *damage_timer -= TRIGGER_DAMAGE_INTERVAL;
+    for volume in triggers.iter() {
+        let per_second = match volume.action {
+            TriggerAction::Damage { per_second } => per_second,
+            _ => continue,
+        };
+        for entity in volume.occupants() {
+            match players.get(entity) {
+                Ok((.., health)) if !health.is_dead() => (),
+                _ => continue,
+            }
+            damage_events.send(DamageEvent {
+                target: entity,
+                amount: per_second * TRIGGER_DAMAGE_INTERVAL,
+                attacker: None,
+                source_position: None,
+                cause: DamageCause::Hazard,
+                damage_type: DamageType::Environmental,
+                weapon: None,
+                headshot: false,
+                projectile: None,
+            });
+        }
+    }
+}
+
+/// put dead players, and everyone when a new match starts, back at the safest spawn point of
+/// the map (see `spawn`) with full health
+#[allow(clippy::too_many_arguments)]
+fn respawn_dead_players_system(
+    mut commands: Commands,
+    mut server: ResMut<RenetServer>,
+    mut death_events: EventReader<DeathEvent>,
+    mut match_events: EventReader<MatchEvent>,
+    mut players: Query<(
+        Entity,
+        &Player,
+        &mut Transform,
+        &mut Health,
+        Option<&mut Armor>,
+        Option<&mut Ammo>,
+        Option<&mut Inventory>,
+        Option<&mut StatusEffects>,
+        &mut FpsController,
+        Option<&Team>,
+    )>,
+    player_ids: Query<&Player>,
+    spawn_points: Query<(&Transform, &SpawnPoint), Without<Player>>,
+    physics_context: Res<RapierContext>,
+    mut drop_events: EventWriter<WeaponDropEvent>,
+    lobby: Res<ServerLobby>,
+    game_settings: Res<GameModeSettings>,
+    stats: Option<Res<StatsStore>>,
+) {
+    // players to respawn, with the cause of their death
+    let mut respawns: Vec<(Entity, Option<DamageCause>)> = Vec::new();
+    for event in death_events.iter() {
+        let attacker = event
+            .attacker
+            .and_then(|attacker| player_ids.get(attacker).ok())
+            .map(|attacker| attacker.id);
+        let attacker_position = event
+            .attacker
+            .filter(|attacker| *attacker != event.entity)
+            .and_then(|attacker| players.get(attacker).ok())
+            .map(|(_, _, transform, ..)| transform.translation);
+        let (_, player, transform, _, _, ammo, inventory, _, controller, _) =
+            match players.get_mut(event.entity) {
+                Ok(player) => player,
+                Err(_) => continue,
+            };
+        match (attacker, event.pushed) {
+            (Some(attacker), true) => info!(
+                "Player {} {} (pushed by {}).",
+                player.id,
+                event.cause.label(),
+                attacker
+            ),
+            (Some(attacker), false) => info!(
+                "Player {} {} by {}.",
+                player.id,
+                event.cause.label(),
+                attacker
+            ),
+            (None, _) => info!("Player {} {}.", player.id, event.cause.label()),
+        }
+        let message = bincode::serialize(&ServerMessages::KillFeed {
+            event: KillFeedEvent {
+                attacker,
+                victim: player.id,
+                cause: event.cause,
+                weapon: event.weapon,
+                headshot: event.headshot,
+                pushed: event.pushed,
+            },
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+        let message = bincode::serialize(&ServerMessages::PlayerDied {
+            id: player.id,
+            translation: transform.translation,
+            yaw: controller.yaw,
+            velocity: ragdoll::death_velocity(
+                controller.velocity,
+                transform.translation,
+                attacker_position,
+            ),
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+        commands.entity(event.entity).remove::<PushedBy>();
+        if let Some(kind) = inventory.and_then(|mut inventory| inventory.drop_active()) {
+            let rockets = match (kind, &ammo) {
+                (WeaponKind::Rocket, Some(ammo)) => ammo.rockets,
+                _ => 0,
+            };
+            drop_events.send(WeaponDropEvent {
+                kind,
+                position: transform.translation + PLAYER_CENTER_OFFSET,
+                rockets,
+            });
+        }
+        respawns.push((event.entity, Some(event.cause)));
+    }
+    // everyone starts over when a new match begins
+    let restart = match_events
+        .iter()
+        .filter(|event| matches!(event, MatchEvent::Started))
+        .count()
+        > 0;
+    if restart {
+        for (entity, ..) in players.iter() {
+            if !respawns.iter().any(|(respawn, _)| *respawn == entity) {
+                respawns.push((entity, None));
+            }
+        }
+    }
+
+    for (entity, cause) in respawns {
+        let spawn_point = match players.get(entity) {
+            Ok((.., team)) => spawn::select_spawn_point(
+                team.copied(),
+                spawn_points
+                    .iter()
+                    .map(|(transform, spawn_point)| (transform.translation, spawn_point)),
+                players.iter().filter(|(other, ..)| *other != entity).map(
+                    |(other, _, transform, .., team)| (other, team.copied(), transform.translation),
+                ),
+                |point, player, position| spawn::visible(&physics_context, point, player, position),
+            ),
+            Err(_) => continue,
+        };
+        let (
+            _,
+            player,
+            mut transform,
+            mut health,
+            armor,
+            ammo,
+            inventory,
+            status,
+            mut controller,
+            _,
+        ) = match players.get_mut(entity) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        // only the restart after a map change finds carried loadouts, players dying in the
+        // restart frame get theirs as well
+        let loadout = match (&stats, lobby.identities.get(&player.id)) {
+            (Some(stats), Some(identity)) if restart && game_settings.carries_loadout() => {
+                stats.take_loadout(*identity).unwrap_or_else(|e| {
+                    warn!("failed to load the carried loadout of {}: {}", player.id, e);
+                    None
+                })
+            }
+            _ => None,
+        };
+        let (new_inventory, new_ammo) = loadout.unwrap_or_default();
+        if let Some(mut inventory) = inventory {
+            *inventory = new_inventory;
+            let message = bincode::serialize(&ServerMessages::InventoryChanged {
+                id: player.id,
+                inventory: inventory.clone(),
+            })
+            .unwrap();
+            server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+        }
+        *transform = Transform::from_translation(spawn_point);
+        health.reset();
+        if let Some(mut armor) = armor {
+            armor.reset();
+        }
+        if let Some(mut ammo) = ammo {
+            *ammo = new_ammo;
+        }
+        if let Some(mut status) = status {
+            status.clear();
+        }
+        controller.status_speed_factor = 1.0;
+        controller.velocity = Vec3::ZERO;
+        let message = bincode::serialize(&ServerMessages::PlayerRespawn {
+            id: player.id,
+            translation: spawn_point,
+            cause,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+    // players that left during the map change don't get theirs on a later restart
+    if let (true, Some(stats)) = (restart, &stats) {
+        if let Err(e) = stats.clear_loadouts() {
+            warn!("failed to drop the carried loadouts: {}", e);
+        }
+    }
+}
+
+/// replicate the match: its state to joining players and on phase changes, the scores
+/// whenever they change and the summary when it ends
+fn broadcast_match_system(
+    mut server: ResMut<RenetServer>,
+    settings: Res<GameModeSettings>,
+    state: Res<MatchState>,
+    scoreboard: Res<Scoreboard>,
+    mut match_events: EventReader<MatchEvent>,
+    joined: Query<&Player, (Added<Player>, Without<Bot>)>,
+) {
+    let message = bincode::serialize(&ServerMessages::MatchState {
+        mode: settings.mode,
+        phase: state.phase,
+        time_left: state.time_left(&settings),
+        frag_limit: settings.frag_limit,
+    })
+    .unwrap();
+    let mut changed = false;
+    let mut ended = None;
+    for event in match_events.iter() {
+        changed = true;
+        if let MatchEvent::Ended { winner } = event {
+            ended = Some(*winner);
+        }
+    }
+    if changed {
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    } else {
+        for player in joined.iter() {
+            server.send_message(
+                player.id,
+                ServerChannel::ServerMessages.id(),
+                message.clone(),
+            );
+        }
+    }
+    if let Some(winner) = ended {
+        let message = bincode::serialize(&ServerMessages::MatchSummary {
+            summary: MatchSummary::new(settings.mode, winner, &scoreboard),
+            duration: settings.restart_delay,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+    if scoreboard.is_changed() {
+        let message = bincode::serialize(&ServerMessages::Scores {
+            scores: scoreboard.ranking(),
+            team_scores: scoreboard.team_ranking(),
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+/// control point states last sent to the clients
+#[derive(Default)]
+struct ControlPointSync {
+    sent: Vec<(Vec3, ControlPointState)>,
+    since: f32,
+}
+
+/// manifest of the last map sent to the clients
+#[derive(Default)]
+struct MapManifestCache(Option<MapManifest>);
+
+/// manifest of `map`, the files are hashed once per map. A map without files falls back to the
+/// default level, clients just learn its name then.
+fn map_manifest(cached: &mut MapManifestCache, map: &LevelKind) -> MapManifest {
+    match &cached.0 {
+        Some(manifest) if manifest.map == *map => manifest.clone(),
+        _ => {
+            let manifest = MapManifest::local(map).unwrap_or_else(|e| {
+                error!("map manifest: {}", e);
+                MapManifest {
+                    map: map.clone(),
+                    files: Vec::new(),
+                }
+            });
+            cached.0 = Some(manifest.clone());
+            manifest
+        }
+    }
+}
+
+/// offer the next maps of the rotation for a vote when a match ends and switch to the winner
+/// (or just the next map) when the next match starts. Joining players learn the map and the
+/// running vote.
+#[allow(clippy::too_many_arguments)]
+fn map_rotation_system(
+    mut server: ResMut<RenetServer>,
+    settings: Res<ServerSettings>,
+    lobby: Res<ServerLobby>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut map_vote: ResMut<MapVote>,
+    mut match_over: Local<bool>,
+    mut match_events: EventReader<MatchEvent>,
+    mut map_changes: EventWriter<MapChangeEvent>,
+    mut manifest: ResMut<MapManifestCache>,
+    joined: Query<&Player, (Added<Player>, Without<Bot>)>,
+) {
+    for player in joined.iter() {
+        let manifest = map_manifest(&mut manifest, &current_level.map);
+        let mut messages = vec![ServerMessages::ChangeMap { manifest }];
+        if map_vote.is_open() {
+            messages.push(ServerMessages::MapVote {
+                options: map_vote.options.clone(),
+            });
+            messages.push(ServerMessages::MapVoteTally {
+                votes: map_vote.tally(),
+            });
+        }
+        for message in messages {
+            let message = bincode::serialize(&message).unwrap();
+            server.send_message(player.id, ServerChannel::ServerMessages.id(), message);
+        }
+    }
+    if map_vote.is_open() {
+        map_vote.retain_voters(|player| lobby.players.contains_key(&player));
+    }
+
+    for event in match_events.iter() {
+        match event {
+            MatchEvent::Ended { .. } => {
+                *match_over = true;
+                let rotation = &settings.rotation;
+                let options = rotation.upcoming(&current_level.map, rotation.vote_options);
+                if !rotation.vote || options.len() < 2 {
+                    continue;
+                }
+                info!("Map vote: {:?}.", options);
+                *map_vote = MapVote::new(options.clone());
+                let message = bincode::serialize(&ServerMessages::MapVote { options }).unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+            }
+            // the first match after the warmup stays on the map
+            MatchEvent::Started if *match_over => {
+                *match_over = false;
+                let next = if map_vote.is_open() {
+                    map_vote.winner()
+                } else {
+                    settings
+                        .rotation
+                        .upcoming(&current_level.map, 1)
+                        .first()
+                        .cloned()
+                };
+                *map_vote = MapVote::default();
+                let next = match next {
+                    Some(next) if next != current_level.map => next,
+                    _ => continue,
+                };
+                info!("Next map: {}.", next.name());
+                change_map(
+                    next,
+                    &mut current_level,
+                    &mut manifest,
+                    &mut server,
+                    &mut map_changes,
+                );
+            }
+            _ => (),
+        }
+    }
+}
+
+/// load `map` and send it to the clients
+fn change_map(
+    map: LevelKind,
+    current_level: &mut CurrentLevel,
+    manifest_cache: &mut MapManifestCache,
+    server: &mut RenetServer,
+    map_changes: &mut EventWriter<MapChangeEvent>,
+) {
+    current_level.map = map.clone();
+    let manifest = map_manifest(manifest_cache, &map);
+    map_changes.send(MapChangeEvent(map));
+    let message = bincode::serialize(&ServerMessages::ChangeMap { manifest }).unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+}
+
+/// co-op with `game.carry_loadout`: keep the weapons and ammo of the players in the stats store
+/// when the map changes, by identity. The respawn at the start of the next match hands them
+/// back.
+fn carry_loadouts_system(
+    settings: Res<GameModeSettings>,
+    lobby: Res<ServerLobby>,
+    stats: Option<Res<StatsStore>>,
+    mut map_changes: EventReader<MapChangeEvent>,
+    players: Query<(&Player, &Inventory, &Ammo)>,
+) {
+    if map_changes.iter().count() == 0 || !settings.carries_loadout() {
+        return;
+    }
+    let stats = match stats {
+        Some(stats) => stats,
+        None => {
+            warn!("game.carry_loadout needs stats_db, the loadouts are not carried");
+            return;
+        }
+    };
+    // bots and clients without an identity start over
+    let loadouts: Vec<_> = players
+        .iter()
+        .filter_map(|(player, inventory, ammo)| {
+            let identity = lobby.identities.get(&player.id)?;
+            Some((*identity, (inventory.clone(), *ammo)))
+        })
+        .collect();
+    match stats.carry_loadouts(&loadouts) {
+        Ok(()) => info!("Carrying the loadouts of {} players.", loadouts.len()),
+        Err(e) => warn!("failed to store the carried loadouts: {}", e),
+    }
+}
+
+/// modification times of the files of the loaded map
+struct MapFileTimes {
+    map: LevelKind,
+    modified: Vec<Option<SystemTime>>,
+}
+
+fn map_file_times(map: &LevelKind) -> MapFileTimes {
+    let modified = mapfile::map_files(map)
+        .unwrap_or_default()
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect();
+    MapFileTimes {
+        map: map.clone(),
+        modified,
+    }
+}
+
+/// with `watch_map`: look for changes of the map files once a second. A changed map is loaded
+/// again, clients get the new manifest and reload it too.
+fn watch_map_system(
+    time: Res<Time>,
+    settings: Res<ServerSettings>,
+    mut server: ResMut<RenetServer>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut manifest: ResMut<MapManifestCache>,
+    mut next_check: Local<f64>,
+    mut watched: Local<Option<MapFileTimes>>,
+) {
+    let now = time.seconds_since_startup();
+    if !settings.watch_map || now < *next_check {
+        return;
+    }
+    *next_check = now + 1.0;
+    let map = match current_level.loaded() {
+        Some(map) => map.clone(),
+        None => return,
+    };
+    let times = map_file_times(&map);
+    let changed = match &*watched {
+        Some(watched) => watched.map == map && watched.modified != times.modified,
+        None => false,
+    };
+    *watched = Some(times);
+    if !changed {
+        return;
+    }
+    info!("Map {} changed, reloading.", map.name());
+    current_level.reload();
+    manifest.0 = None;
+    let manifest = map_manifest(&mut manifest, &map);
+    let message = bincode::serialize(&ServerMessages::ReloadMap { manifest }).unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+}
+
+/// keep `bots.count` bots in the game, as far as humans leave slots for them. Bots join one per
+/// frame and leave for joining humans, like clients they show up on every client.
+#[allow(clippy::too_many_arguments)]
+fn bot_count_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut server: ResMut<RenetServer>,
+    mut lobby: ResMut<ServerLobby>,
+    settings: Res<ServerSettings>,
+    slots: Res<ClientSlots>,
+    movement_settings: Res<MovementSettings>,
+    name_rules: Res<NameRules>,
+    game_settings: Res<GameModeSettings>,
+    physics_context: Res<RapierContext>,
+    players: Query<(Entity, &Transform, Option<&Team>), With<Player>>,
+    spawn_points: Query<(&Transform, &SpawnPoint)>,
+) {
+    let mut bots: Vec<u64> = lobby
+        .players
+        .keys()
+        .copied()
+        .filter(|id| bot::is_bot(*id))
+        .collect();
+    bots.sort_unstable();
+    let humans = lobby.players.len() - bots.len() + slots.queue.len();
+    let wanted = settings
+        .bots
+        .count
+        .min(slots.max_players.saturating_sub(humans));
+
+    while bots.len() > wanted {
+        let id = match bots.pop() {
+            Some(id) => id,
+            None => break,
+        };
+        info!("Bot {} leaves.", id);
+        lobby.names.remove(&id);
+        if let Some(player_entity) = lobby.players.remove(&id) {
+            commands.entity(player_entity).despawn();
+        }
+        let message = bincode::serialize(&ServerMessages::PlayerRemove { id }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+    if bots.len() >= wanted {
+        return;
+    }
+
+    let id = match (0..).map(bot::bot_id).find(|id| !bots.contains(id)) {
+        Some(id) => id,
+        None => return,
+    };
+    let name = name_rules.resolve(&settings.bots.name, |name| {
+        lobby.names.values().any(|taken| taken == name)
+    });
+    let team = game_settings.mode.join_team(
+        true,
+        players.iter().filter_map(|(_, _, team)| team.copied()),
+    );
+    let transform = Transform::from_translation(spawn::select_spawn_point(
+        team,
+        spawn_points
+            .iter()
+            .map(|(transform, spawn_point)| (transform.translation, spawn_point)),
+        players
+            .iter()
+            .map(|(entity, transform, team)| (entity, team.copied(), transform.translation)),
+        |point, player, position| spawn::visible(&physics_context, point, player, position),
+    ));
+    info!("Bot {} joins as {:?}.", id, name);
+    let player_entity = spawn_player(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        &movement_settings,
+        id,
+        name.clone(),
+        team,
+        transform,
+    );
+    commands
+        .entity(player_entity)
+        .insert(Bot::default())
+        .insert(SimulationLod::default());
+    lobby.players.insert(id, player_entity);
+    lobby.names.insert(id, name.clone());
+    let message = bincode::serialize(&ServerMessages::PlayerCreate {
+        id,
+        name,
+        entity: player_entity,
+        translation: transform.translation,
+        team,
+    })
+    .unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+}
+
+/// run the player votes: catch joining players up on the running vote, drop players who left
+/// from it and carry out the decided vote
+fn vote_system(
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    settings: Res<ServerSettings>,
+    lobby: Res<ServerLobby>,
+    mut votes: ResMut<Votes>,
+    joined: Query<&Player, (Added<Player>, Without<Bot>)>,
+) {
+    if let Some(vote) = &votes.current {
+        let message = bincode::serialize(&ServerMessages::Vote {
+            state: vote.state(&settings.votes),
+        })
+        .unwrap();
+        for player in joined.iter() {
+            server.send_message(
+                player.id,
+                ServerChannel::ServerMessages.id(),
+                message.clone(),
+            );
+        }
+    }
+    votes.retain_voters(|player| lobby.players.contains_key(&player));
+
+    let (kind, passed) = match votes.update(time.delta(), &settings.votes) {
+        Some(result) => result,
+        None => return,
+    };
+    let question = kind.question(|id| lobby.names.get(&id).cloned().unwrap_or_default());
+    info!(
+        "Vote {} {}",
+        question,
+        if passed { "passed." } else { "failed." }
+    );
+    let message = bincode::serialize(&ServerMessages::VoteEnded { kind, passed }).unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    if !passed {
+        return;
+    }
+    match kind {
+        // the regular disconnect handling cleans up after the player
+        VoteKind::Kick { player } => server.disconnect(player),
+    }
+}
+
+/// replicate the control points: to joining players, on ownership changes and regularly while
+/// their progress moves
+fn broadcast_control_points_system(
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    mut sync: Local<ControlPointSync>,
+    mut control_point_events: EventReader<ControlPointEvent>,
+    control_points: Query<(&ControlPoint, &Transform)>,
+    joined: Query<&Player, (Added<Player>, Without<Bot>)>,
+) {
+    for event in control_point_events.iter() {
+        info!(
+            "The {} team took the control point at {}.",
+            event.team.label(),
+            event.position
+        );
+    }
+    if control_points.is_empty() {
+        return;
+    }
+    sync.since += time.delta_seconds();
+    let points: Vec<(Vec3, ControlPointState)> = control_points
+        .iter()
+        .map(|(control_point, transform)| (transform.translation, control_point.state))
+        .collect();
+    let phase_changed = points.len() != sync.sent.len()
+        || points
+            .iter()
+            .zip(&sync.sent)
+            .any(|((_, state), (_, sent))| !state.same_phase(sent));
+    let progressed = points != sync.sent && sync.since >= control::CONTROL_POINT_SYNC_INTERVAL;
+    let message = bincode::serialize(&ServerMessages::ControlPoints {
+        points: points.clone(),
+    })
+    .unwrap();
+    if phase_changed || progressed {
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+        sync.sent = points;
+        sync.since = 0.0;
+    } else {
+        for player in joined.iter() {
+            server.send_message(
+                player.id,
+                ServerChannel::ServerMessages.id(),
+                message.clone(),
+            );
+        }
+    }
+}
+
+/// replicate the flags: to joining players and whenever something happened to them
+fn broadcast_flags_system(
+    mut server: ResMut<RenetServer>,
+    mut flag_events: EventReader<FlagEvent>,
+    mut match_events: EventReader<MatchEvent>,
+    flags: Query<&Flag>,
+    players: Query<&Player>,
+    joined: Query<&Player, (Added<Player>, Without<Bot>)>,
+) {
+    let mut changed = match_events.iter().count() > 0;
+    for event in flag_events.iter() {
+        changed = true;
+        let player = event
+            .player
+            .and_then(|player| players.get(player).ok())
+            .map(|player| player.id);
+        let action = match event.kind {
+            FlagEventKind::Taken => "took",
+            FlagEventKind::Dropped => "dropped",
+            FlagEventKind::Returned => "returned",
+            FlagEventKind::Captured => "captured",
+        };
+        match player {
+            Some(player) => info!(
+                "Player {} {} the {} flag.",
+                player,
+                action,
+                event.team.label()
+            ),
+            None => info!("The {} flag {}.", event.team.label(), action),
+        }
+    }
+    if flags.is_empty() {
+        return;
+    }
+    let message = bincode::serialize(&ServerMessages::Flags {
+        flags: flags.iter().map(|flag| (flag.team, flag.state)).collect(),
+    })
+    .unwrap();
+    if changed {
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    } else {
+        for player in joined.iter() {
+            server.send_message(
+                player.id,
+                ServerChannel::ServerMessages.id(),
+                message.clone(),
+            );
+        }
+    }
+}
+
+/// confirm damage dealt to the attacking player, for hitmarkers and damage numbers
+fn hit_confirm_system(
+    mut server: ResMut<RenetServer>,
+    mut dealt_events: EventReader<DamageDealtEvent>,
+    players: Query<&Player>,
+    transforms: Query<&Transform>,
+) {
+    for event in dealt_events.iter() {
+        let attacker = match players.get(event.attacker) {
+            Ok(attacker) if !bot::is_bot(attacker.id) => attacker,
+            _ => continue,
+        };
+        let position = match transforms.get(event.target) {
+            Ok(transform) => transform.translation,
+            Err(_) => continue,
+        };
+        let message = bincode::serialize(&ServerMessages::HitConfirm {
+            amount: event.amount,
+            headshot: event.headshot,
+            kill: event.kill,
+            position,
+        })
+        .unwrap();
+        server.send_message(attacker.id, ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+/// tell players where the damage they took came from
+fn damage_taken_system(
+    mut server: ResMut<RenetServer>,
+    mut taken_events: EventReader<DamageTakenEvent>,
+    players: Query<&Player>,
+) {
+    for event in taken_events.iter() {
+        let victim = match players.get(event.target) {
+            Ok(victim) if !bot::is_bot(victim.id) => victim,
+            _ => continue,
+        };
+        let message = bincode::serialize(&ServerMessages::DamageTaken {
+            amount: event.amount,
+            source: event.source_position,
+        })
+        .unwrap();
+        server.send_message(victim.id, ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+/// forward jump pad, teleporter and usable activations to all clients
+fn broadcast_level_events_system(
+    mut server: ResMut<RenetServer>,
+    mut jump_pad_events: EventReader<JumpPadEvent>,
+    mut teleport_events: EventReader<TeleportEvent>,
+    mut usable_events: EventReader<UsableChangedEvent>,
+    pads: Query<&Transform>,
+    players: Query<&Player>,
+) {
+    let mut level_events = Vec::new();
+    for event in jump_pad_events.iter() {
+        let position = pads
+            .get(event.pad)
+            .map_or(event.position, |transform| transform.translation);
+        level_events.push((LevelEvent::JumpPad { position }, Some(event.player)));
+    }
+    for event in teleport_events.iter() {
+        let from = event.from;
+        let to = event.to;
+        level_events.push((LevelEvent::Teleport { from, to }, Some(event.entity)));
+    }
+    for event in usable_events.iter() {
+        let position = event.position;
+        let active = event.active;
+        level_events.push((LevelEvent::Use { position, active }, event.player));
+    }
+    for (event, entity) in level_events {
+        let player = entity
+            .and_then(|entity| players.get(entity).ok())
+            .map(|player| player.id);
+        let message = bincode::serialize(&ServerMessages::LevelEvent { event, player }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+/// tell all clients who took which pickup and which pickups are back
+fn broadcast_pickup_events_system(
+    mut server: ResMut<RenetServer>,
+    mut taken_events: EventReader<PickupTakenEvent>,
+    mut respawn_events: EventReader<PickupRespawnEvent>,
+    players: Query<&Player>,
+) {
+    for event in taken_events.iter() {
+        let player = players.get(event.player).ok().map(|player| player.id);
+        if let Some(player) = player {
+            info!("Player {} took {}.", player, event.kind.label());
+        }
+        let message = bincode::serialize(&ServerMessages::PickupTaken {
+            position: event.position,
+            kind: event.kind,
+            player,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+    for event in respawn_events.iter() {
+        let message = bincode::serialize(&ServerMessages::PickupRespawned {
+            position: event.position,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+/// forward player movement events (footsteps, jumps, ...) to all clients in one message
+fn broadcast_movement_events_system(
+    mut server: ResMut<RenetServer>,
+    mut movement_events: EventReader<MovementEvent>,
+    players: Query<&Player>,
+) {
+    let events: Vec<_> = movement_events
+        .iter()
+        .filter_map(|event| {
+            let player = players.get(event.entity).ok()?;
+            Some((player.id, event.kind, event.position))
+        })
+        .collect();
+    if events.is_empty() {
+        return;
+    }
+    let message = bincode::serialize(&ServerMessages::MovementEvents { events }).unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+}
+
+fn projectile_on_removal_system(
+    mut server: ResMut<RenetServer>,
+    removed_projectiles: RemovedComponents<Projectile>,
+) {
+    for entity in removed_projectiles.iter() {
+        let message = ServerMessages::DespawnProjectile { entity };
+        debug!(?message, "projectile removed");
+
+        let message = bincode::serialize(&message).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+struct AddCubeTimer(Timer);
+#[derive(Component)]
+struct CubeMarker;
+
+const CRATE_HEALTH: f32 = 40.0;
+
+/// seconds until a destroyed crate is back at its place
+const CRATE_RESPAWN_TIME: f32 = 15.0;
+
+/// destructible physics prop, comes back at `home` after it was destroyed
+#[derive(Component)]
+struct Crate {
+    home: Vec3,
+}
+
+/// destroyed crates waiting to come back, with their home positions
+#[derive(Default)]
+struct CrateRespawns(Vec<(Timer, Vec3)>);
+
+fn spawn_crate(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    prefabs: &PrefabRegistry,
+    home: Vec3,
+) -> Entity {
+    let prefab = prefabs.get(prefab::CRATE);
+    let mut entity = commands.spawn_bundle(prefab.pbr_bundle(
+        meshes,
+        materials,
+        Transform::from_translation(home),
+    ));
+    prefab.insert_physics(&mut entity, Vec3::ZERO);
+    entity
+        .insert(CubeMarker)
+        .insert(Crate { home })
+        .insert(Health::new(CRATE_HEALTH))
+        .id()
+}
+
+/// a small stack of crates next to the spawn point, inside the default map
+fn crate_homes() -> Vec<Vec3> {
+    let size = CRATE_HALF_SIZE * 2.0;
+    let base = PLAYER_SPAWN_POINT + Vec3::new(4.2, CRATE_HALF_SIZE, -0.4);
+    [
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, size + 0.05),
+        Vec3::new(0.0, size, size * 0.5),
+    ]
+    .into_iter()
+    .map(|offset| base + offset)
+    .collect()
+}
+
+fn setup_crates_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    prefabs: Res<PrefabRegistry>,
+) {
+    for home in crate_homes() {
+        spawn_crate(&mut commands, &mut meshes, &mut materials, &prefabs, home);
+    }
+}
+
+/// a new match starts in a clean level: projectiles and dropped weapons are gone, all crates
+/// come back at their homes and taken pickups are back
+#[allow(clippy::type_complexity)]
+fn reset_world_system(
+    mut commands: Commands,
+    mut server: ResMut<RenetServer>,
+    mut match_events: EventReader<MatchEvent>,
+    projectiles: Query<Entity, With<Projectile>>,
+    props: Query<Entity, Or<(With<Crate>, With<DroppedWeapon>)>>,
+    mut crate_respawns: ResMut<CrateRespawns>,
+    mut pickups: Query<&mut Pickup>,
+) {
+    let restart = match_events
+        .iter()
+        .filter(|event| matches!(event, MatchEvent::Started))
+        .count()
+        > 0;
+    if !restart {
+        return;
+    }
+    // projectile_on_removal_system tells the clients
+    for entity in projectiles.iter() {
+        commands.entity(entity).despawn();
+    }
+    for entity in props.iter() {
+        commands.entity(entity).despawn();
+        let message = bincode::serialize(&ServerMessages::DespawnProjectile { entity }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+    crate_respawns.0 = crate_homes()
+        .into_iter()
+        .map(|home| (Timer::from_seconds(0.0, false), home))
+        .collect();
+    for mut pickup in &mut pickups {
+        if !pickup.is_available() {
+            pickup.respawn_now();
+        }
+    }
+}
+
+/// remove crates whose health ran out and tell the clients to break them apart
+fn destroy_crates_system(
+    mut commands: Commands,
+    mut server: ResMut<RenetServer>,
+    mut death_events: EventReader<DeathEvent>,
+    crates: Query<(&Transform, &Crate)>,
+    mut respawns: ResMut<CrateRespawns>,
+) {
+    for event in death_events.iter() {
+        let (transform, crate_) = match crates.get(event.entity) {
+            Ok(crate_) => crate_,
+            Err(_) => continue,
+        };
+        commands.entity(event.entity).despawn();
+        respawns
+            .0
+            .push((Timer::from_seconds(CRATE_RESPAWN_TIME, false), crate_.home));
+        let message = bincode::serialize(&ServerMessages::PropDestroyed {
+            entity: event.entity,
+            position: transform.translation,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+/// bring destroyed crates back at their home position
+fn crate_respawn_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut server: ResMut<RenetServer>,
+    tick: Res<NetworkTick>,
+    prefabs: Res<PrefabRegistry>,
+    mut respawns: ResMut<CrateRespawns>,
+) {
+    for (timer, _) in &mut respawns.0 {
+        timer.tick(time.delta());
+    }
+    let (due, waiting) = std::mem::take(&mut respawns.0)
+        .into_iter()
+        .partition(|(timer, _)| timer.finished());
+    respawns.0 = waiting;
+    let due: Vec<(Timer, Vec3)> = due;
+    for (_, home) in due {
+        let entity = spawn_crate(&mut commands, &mut meshes, &mut materials, &prefabs, home);
+        let message = bincode::serialize(&ServerMessages::SpawnProjectile {
+            entity,
+            translation: home,
+            prefab: prefab::CRATE.to_string(),
+            velocity: Vec3::ZERO,
+            tick: tick.0,
+            owner: None,
+        })
+        .unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+}
+
+fn _add_cube_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut timer: ResMut<AddCubeTimer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut server: ResMut<RenetServer>,
+    tick: Res<NetworkTick>,
+    prefabs: Res<PrefabRegistry>,
+) {
+    timer.0.tick(time.delta());
+
+    if timer.0.just_finished() {
+        let prefab = prefabs.get(prefab::BOX);
+        let translation = Vec3::new(0.0, 3.0, 0.0);
+        let mut cube_entity = commands.spawn_bundle(prefab.pbr_bundle(
+            &mut meshes,
+            &mut materials,
+            Transform::from_translation(translation),
+        ));
+        prefab.insert_physics(&mut cube_entity, Vec3::ZERO);
+        let cube_entity = cube_entity.insert(CubeMarker).id();
+
+        let message = ServerMessages::SpawnProjectile {
+            entity: cube_entity,
+            translation,
+            prefab: prefab::BOX.to_string(),
+            owner: None,
+            velocity: Vec3::ZERO,
+            tick: tick.0,
+        };
+        let message = bincode::serialize(&message).unwrap();
+        // info!("spawn projectile: {}", message.len());
+        server.broadcast_message(ServerChannel::ServerMessages.id(), message);
+    }
+}
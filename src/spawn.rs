@@ -0,0 +1,135 @@
+//! spawn points: maps place SpawnPoint entities, optionally reserved for a team. Players
+//! (re)spawn at the safest one: not on top of another player, far from the closest enemy and
+//! out of sight of enemies.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    targets::{self, PLAYER_CENTER_OFFSET},
+    team::Team,
+    PLAYER_SPAWN_POINT,
+};
+
+/// spawn points of the red team on maps built around PLAYER_SPAWN_POINT, relative to it. The
+/// default map is only 10 by 10, keep them on it.
+const RED_SPAWN_POINTS: [Vec3; 3] = [
+    Vec3::new(-2.0, 0.0, 0.5),
+    Vec3::new(-2.0, 0.0, -1.5),
+    Vec3::new(-1.0, 0.0, -0.5),
+];
+
+/// spawn points of the blue team, relative to PLAYER_SPAWN_POINT
+const BLUE_SPAWN_POINTS: [Vec3; 3] = [
+    Vec3::new(2.0, 0.0, 0.5),
+    Vec3::new(2.0, 0.0, -1.5),
+    Vec3::new(1.0, 0.0, -0.5),
+];
+
+/// spawn points with another player closer than this are taken
+const SPAWN_CLEARANCE: f32 = 1.2;
+
+/// enemies further away than this don't make a spawn point any safer
+const SAFE_DISTANCE: f32 = 20.0;
+
+/// score taken off a spawn point for every enemy seeing it, in meters of enemy distance
+const VISIBLE_PENALTY: f32 = 10.0;
+
+/// score of a taken spawn point, still better than nothing if all are taken
+const TAKEN_PENALTY: f32 = 1000.0;
+
+/// players spawn here, `team` reserves the point for the players of a team in team modes.
+/// Players without a team use all spawn points.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct SpawnPoint {
+    pub team: Option<Team>,
+}
+
+pub fn spawn_spawn_point(commands: &mut Commands, translation: Vec3, spawn_point: SpawnPoint) {
+    commands
+        .spawn_bundle(TransformBundle::from_transform(
+            Transform::from_translation(translation),
+        ))
+        .insert(spawn_point);
+}
+
+/// the team spawn points next to PLAYER_SPAWN_POINT, for maps with room around it
+pub fn spawn_default_spawn_points(commands: &mut Commands) {
+    for (team, offsets) in [
+        (Team::Red, RED_SPAWN_POINTS),
+        (Team::Blue, BLUE_SPAWN_POINTS),
+    ] {
+        for offset in offsets {
+            spawn_spawn_point(
+                commands,
+                PLAYER_SPAWN_POINT + offset,
+                SpawnPoint { team: Some(team) },
+            );
+        }
+    }
+}
+
+/// `visible` of select_spawn_point: a player standing at `point` sees `target` standing at
+/// `position`, see `targets::line_of_sight`
+pub fn visible(
+    physics_context: &RapierContext,
+    point: Vec3,
+    target: Entity,
+    position: Vec3,
+) -> bool {
+    targets::line_of_sight(
+        physics_context,
+        point + PLAYER_CENTER_OFFSET,
+        target,
+        position + PLAYER_CENTER_OFFSET,
+        None,
+    )
+}
+
+/// pick the safest spawn point for a player of `team`: the team's spawn points in team modes
+/// (all of them if the map has none for the team), all of them without a team. `players` are
+/// all other players with their teams and positions, everyone is an enemy of players without a
+/// team. `visible` tells if a player (entity and position) can be seen from a spawn point.
+/// Falls back to PLAYER_SPAWN_POINT on maps without spawn points.
+pub fn select_spawn_point<'a>(
+    team: Option<Team>,
+    spawn_points: impl Iterator<Item = (Vec3, &'a SpawnPoint)>,
+    players: impl Iterator<Item = (Entity, Option<Team>, Vec3)>,
+    visible: impl Fn(Vec3, Entity, Vec3) -> bool,
+) -> Vec3 {
+    let spawn_points: Vec<(Vec3, &SpawnPoint)> = spawn_points.collect();
+    let own: Vec<Vec3> = spawn_points
+        .iter()
+        .filter(|(_, spawn_point)| team.is_some() && spawn_point.team == team)
+        .map(|(position, _)| *position)
+        .collect();
+    let candidates = if own.is_empty() {
+        spawn_points.iter().map(|(position, _)| *position).collect()
+    } else {
+        own
+    };
+    let players: Vec<(Entity, Option<Team>, Vec3)> = players.collect();
+    let score = |point: Vec3| {
+        let mut closest_enemy = SAFE_DISTANCE;
+        let mut penalty = 0.0;
+        for (entity, other, position) in &players {
+            let distance = position.distance(point);
+            if distance < SPAWN_CLEARANCE {
+                penalty += TAKEN_PENALTY;
+            }
+            if team.is_some() && *other == team {
+                continue;
+            }
+            closest_enemy = closest_enemy.min(distance);
+            if distance < SAFE_DISTANCE * 2.0 && visible(point, *entity, *position) {
+                penalty += VISIBLE_PENALTY;
+            }
+        }
+        closest_enemy - penalty
+    };
+    candidates
+        .into_iter()
+        .map(|point| (point, score(point)))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(PLAYER_SPAWN_POINT, |(point, _)| point)
+}
@@ -0,0 +1,157 @@
+//! spectating: a noclip free camera, or following other players in first person. The view of
+//! a followed player is reconstructed from its replicated position, yaw and pitch.
+//!
+//! The server marks spectating players with `Spectator`. They are left out of the player
+//! replication of network frames, their inputs are dropped and they can not be hit.
+
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{ecs::schedule::ShouldRun, input::mouse::MouseMotion, prelude::*};
+
+use crate::{
+    controller::{look_quat, ANGLE_EPSILON},
+    input::{Action, ActionInput},
+};
+
+/// free camera speed (m/s)
+pub const FREE_CAM_SPEED: f32 = 8.0;
+
+/// free camera speed factor while sprinting
+pub const FREE_CAM_SPRINT_FACTOR: f32 = 3.0;
+
+/// server: the player is spectating
+#[derive(Component)]
+pub struct Spectator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectatorView {
+    FreeCam,
+    /// first person view of the player with this client id
+    Follow(u64),
+}
+
+#[derive(Debug, Default)]
+pub struct FreeCam {
+    pub position: Vec3,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+impl FreeCam {
+    /// continue from a camera transform, e.g. the player view when starting to spectate
+    pub fn set_from(&mut self, transform: &GlobalTransform) {
+        let (yaw, pitch, _) = transform
+            .to_scale_rotation_translation()
+            .1
+            .to_euler(EulerRot::YXZ);
+        self.position = transform.translation();
+        self.pitch = pitch;
+        self.yaw = yaw;
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.position).with_rotation(look_quat(self.pitch, self.yaw))
+    }
+}
+
+/// client resource, `view` is Some while spectating
+#[derive(Debug, Default)]
+pub struct Spectating {
+    pub view: Option<SpectatorView>,
+    pub free_cam: FreeCam,
+}
+
+impl Spectating {
+    /// start spectating as observer, `--spectate`
+    pub fn from_args() -> Self {
+        Self {
+            view: std::env::args()
+                .skip(1)
+                .any(|arg| arg == "--spectate")
+                .then_some(SpectatorView::FreeCam),
+            ..default()
+        }
+    }
+
+    pub fn is_spectating(&self) -> bool {
+        self.view.is_some()
+    }
+
+    /// client id of the followed player
+    pub fn followed(&self) -> Option<u64> {
+        match self.view {
+            Some(SpectatorView::Follow(id)) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// follow the next (or previous) of the sorted client ids in `players`. Falls back to the
+    /// free camera if there is nobody to follow.
+    pub fn cycle(&mut self, players: &[u64], forward: bool) {
+        if players.is_empty() {
+            self.view = Some(SpectatorView::FreeCam);
+            return;
+        }
+        let len = players.len();
+        let current = self
+            .followed()
+            .and_then(|followed| players.iter().position(|id| *id == followed));
+        let index = match (current, forward) {
+            (Some(index), true) => (index + 1) % len,
+            (Some(index), false) => (index + len - 1) % len,
+            (None, true) => 0,
+            (None, false) => len - 1,
+        };
+        self.view = Some(SpectatorView::Follow(players[index]));
+    }
+}
+
+/// run criteria for the player controls, which are replaced by the spectator controls
+pub fn run_if_not_spectating(spectating: Res<Spectating>) -> ShouldRun {
+    if spectating.is_spectating() {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
+
+/// fly the free camera with the movement actions, mouse look while the cursor is locked
+pub fn free_cam_system(
+    time: Res<Time>,
+    actions: ActionInput,
+    windows: Res<Windows>,
+    mut mouse_events: EventReader<MouseMotion>,
+    mut spectating: ResMut<Spectating>,
+) {
+    let mouse = &actions.map.mouse;
+    let counts: Vec2 = mouse_events
+        .iter()
+        .map(|mouse_event| mouse.curve(mouse_event.delta))
+        .sum();
+    if spectating.view != Some(SpectatorView::FreeCam) || !actions.map.enable_input {
+        return;
+    }
+    let free_cam = &mut spectating.free_cam;
+    let look = windows.get_primary().map_or(false, |window| {
+        window.is_focused() && window.cursor_locked()
+    });
+    if look {
+        let delta = mouse.scale(counts, false);
+        free_cam.pitch =
+            (free_cam.pitch - delta.y).clamp(-FRAC_PI_2 + ANGLE_EPSILON, FRAC_PI_2 - ANGLE_EPSILON);
+        free_cam.yaw -= delta.x;
+    }
+
+    // horizontal movement along the view direction, up and down along the world axis
+    let stick = actions.left_stick();
+    let right = (actions.axis(Action::MoveRight, Action::MoveLeft) + stick.x).clamp(-1.0, 1.0);
+    let forward = (actions.axis(Action::MoveForward, Action::MoveBack) + stick.y).clamp(-1.0, 1.0);
+    let up = actions.axis(Action::MoveUp, Action::MoveDown);
+    let direction =
+        look_quat(free_cam.pitch, free_cam.yaw) * Vec3::new(right, 0.0, -forward) + Vec3::Y * up;
+    let mut speed = FREE_CAM_SPEED;
+    if actions.pressed(Action::Sprint) {
+        speed *= FREE_CAM_SPRINT_FACTOR;
+    }
+    free_cam.position += direction.clamp_length_max(1.0) * speed * time.delta_seconds();
+}
@@ -0,0 +1,298 @@
+//! persistent player statistics of the server, kept in a sqlite file set with `stats_db` in
+//! server.toml. Players are identified by their `PlayerIdentity`, bots and clients without
+//! one are not recorded. The game mode records kills, deaths, hits and playtime, the server
+//! fired shots. Changes are collected in memory and written at the end of every match and when
+//! the server exits.
+//!
+//!     stats_db = "stats.sqlite"
+//!
+//! A shot counts once it is fired, a hit once per shot that damages a player: an explosion
+//! hitting two players is one hit, hazard zones left behind are none.
+//!
+//! With `game.carry_loadout` in co-op the store also keeps the weapons and ammo of the players
+//! from one map to the next.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{app::AppExit, prelude::*};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    identity::PlayerIdentity,
+    weapon::{Ammo, Inventory},
+};
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS player_stats (
+    identity TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    kills INTEGER NOT NULL,
+    deaths INTEGER NOT NULL,
+    shots INTEGER NOT NULL,
+    hits INTEGER NOT NULL,
+    playtime REAL NOT NULL,
+    last_seen INTEGER NOT NULL
+)";
+
+/// weapons and ammo at the last map change, bincode encoded
+const CREATE_LOADOUT_TABLE: &str = "CREATE TABLE IF NOT EXISTS carried_loadouts (
+    identity TEXT PRIMARY KEY,
+    loadout BLOB NOT NULL
+)";
+
+/// adds the new counts to the stored ones. The name is empty for players that only
+/// spectated since the last flush.
+const UPSERT: &str = "INSERT INTO player_stats
+    (identity, name, kills, deaths, shots, hits, playtime, last_seen)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+    ON CONFLICT (identity) DO UPDATE SET
+        name = COALESCE(NULLIF(excluded.name, ''), name),
+        kills = kills + excluded.kills,
+        deaths = deaths + excluded.deaths,
+        shots = shots + excluded.shots,
+        hits = hits + excluded.hits,
+        playtime = playtime + excluded.playtime,
+        last_seen = excluded.last_seen";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PlayerStats {
+    pub name: String,
+    pub kills: u32,
+    pub deaths: u32,
+    pub shots: u32,
+    pub hits: u32,
+    /// seconds in the game, spectating does not count
+    pub playtime: f64,
+}
+
+impl PlayerStats {
+    /// hits per shot, None before the first shot. Projectiles fired during the warmup can
+    /// still hit once the match runs, the accuracy is capped at 100%.
+    pub fn accuracy(&self) -> Option<f32> {
+        (self.shots > 0).then_some((self.hits as f32 / self.shots as f32).min(1.0))
+    }
+}
+
+pub struct StatsStore {
+    // the connection is not Sync
+    connection: Mutex<Connection>,
+    /// identities of the connected clients
+    identities: HashMap<u64, PlayerIdentity>,
+    /// changes since the last flush
+    pending: HashMap<PlayerIdentity, PlayerStats>,
+}
+
+impl StatsStore {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(CREATE_TABLE)?;
+        connection.execute_batch(CREATE_LOADOUT_TABLE)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+            identities: HashMap::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    pub fn connected(&mut self, client_id: u64, identity: PlayerIdentity) {
+        self.identities.insert(client_id, identity);
+    }
+
+    /// the pending stats of the client stay until the next flush
+    pub fn disconnected(&mut self, client_id: u64) {
+        self.identities.remove(&client_id);
+    }
+
+    /// pending stats of a client with an identity
+    fn stats(&mut self, client_id: u64) -> Option<&mut PlayerStats> {
+        let identity = self.identities.get(&client_id)?;
+        Some(self.pending.entry(*identity).or_default())
+    }
+
+    pub fn kill(&mut self, client_id: u64) {
+        if let Some(stats) = self.stats(client_id) {
+            stats.kills += 1;
+        }
+    }
+
+    pub fn death(&mut self, client_id: u64) {
+        if let Some(stats) = self.stats(client_id) {
+            stats.deaths += 1;
+        }
+    }
+
+    pub fn shot(&mut self, client_id: u64) {
+        if let Some(stats) = self.stats(client_id) {
+            stats.shots += 1;
+        }
+    }
+
+    pub fn hit(&mut self, client_id: u64) {
+        if let Some(stats) = self.stats(client_id) {
+            stats.hits += 1;
+        }
+    }
+
+    /// `seconds` more in the game, under the current `name`
+    pub fn play(&mut self, client_id: u64, name: &str, seconds: f64) {
+        if let Some(stats) = self.stats(client_id) {
+            stats.playtime += seconds;
+            if stats.name != name {
+                stats.name = name.to_string();
+            }
+        }
+    }
+
+    /// write the pending stats in one transaction
+    pub fn flush(&mut self) -> rusqlite::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs() as i64);
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        for (identity, stats) in &self.pending {
+            transaction.execute(
+                UPSERT,
+                params![
+                    identity.to_string(),
+                    stats.name,
+                    stats.kills,
+                    stats.deaths,
+                    stats.shots,
+                    stats.hits,
+                    stats.playtime,
+                    now
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        info!("wrote the stats of {} players", self.pending.len());
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// the stored stats of a player, without the pending changes
+    pub fn load(&self, identity: PlayerIdentity) -> rusqlite::Result<Option<PlayerStats>> {
+        self.connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT name, kills, deaths, shots, hits, playtime FROM player_stats
+                    WHERE identity = ?1",
+                params![identity.to_string()],
+                |row| {
+                    Ok(PlayerStats {
+                        name: row.get(0)?,
+                        kills: row.get(1)?,
+                        deaths: row.get(2)?,
+                        shots: row.get(3)?,
+                        hits: row.get(4)?,
+                        playtime: row.get(5)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    /// replace the carried loadouts with `loadouts`
+    pub fn carry_loadouts(
+        &self,
+        loadouts: &[(PlayerIdentity, (Inventory, Ammo))],
+    ) -> rusqlite::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM carried_loadouts", [])?;
+        for (identity, loadout) in loadouts {
+            let loadout = bincode::serialize(loadout)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e))?;
+            transaction.execute(
+                "INSERT INTO carried_loadouts (identity, loadout) VALUES (?1, ?2)",
+                params![identity.to_string(), loadout],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    /// the carried loadout of a player, it is only handed out once
+    pub fn take_loadout(
+        &self,
+        identity: PlayerIdentity,
+    ) -> rusqlite::Result<Option<(Inventory, Ammo)>> {
+        let connection = self.connection.lock().unwrap();
+        let loadout: Option<Vec<u8>> = connection
+            .query_row(
+                "SELECT loadout FROM carried_loadouts WHERE identity = ?1",
+                params![identity.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        connection.execute(
+            "DELETE FROM carried_loadouts WHERE identity = ?1",
+            params![identity.to_string()],
+        )?;
+        loadout
+            .map(|loadout| {
+                bincode::deserialize(&loadout).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Blob, e)
+                })
+            })
+            .transpose()
+    }
+
+    /// drop the loadouts nobody took
+    pub fn clear_loadouts(&self) -> rusqlite::Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM carried_loadouts", [])
+            .map(|_| ())
+    }
+}
+
+pub fn flush_on_exit_system(
+    mut app_exit_events: EventReader<AppExit>,
+    mut stats: ResMut<StatsStore>,
+) {
+    if app_exit_events.iter().next().is_none() {
+        return;
+    }
+    if let Err(e) = stats.flush() {
+        warn!("failed to write the stats: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weapon::WeaponKind;
+
+    #[test]
+    fn carried_loadouts_are_taken_once() {
+        let stats = StatsStore::open(":memory:").unwrap();
+        let (kept, dropped) = (PlayerIdentity::random(), PlayerIdentity::random());
+        let inventory = Inventory {
+            weapons: vec![WeaponKind::Hitscan],
+            active: WeaponKind::Hitscan,
+        };
+        let ammo = Ammo {
+            grenades: 1,
+            rockets: 7,
+        };
+        stats
+            .carry_loadouts(&[
+                (kept, (inventory.clone(), ammo)),
+                (dropped, Default::default()),
+            ])
+            .unwrap();
+        assert_eq!(stats.take_loadout(kept).unwrap(), Some((inventory, ammo)));
+        assert_eq!(stats.take_loadout(kept).unwrap(), None);
+        stats.clear_loadouts().unwrap();
+        assert_eq!(stats.take_loadout(dropped).unwrap(), None);
+    }
+}
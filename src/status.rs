@@ -0,0 +1,262 @@
+//! status effects: timed buffs and debuffs on players (speed boost, slow, burn, shield). The
+//! server applies, stacks and expires them and feeds them into movement and damage. Clients
+//! only get the effects of their own player, for the HUD and to predict the changed speed.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controller::FpsController,
+    health::{DamageCause, DamageEvent, DamageType, Health},
+    spectator::Spectator,
+};
+
+/// seconds between two burn damage pulses
+const BURN_PULSE: f32 = 0.5;
+
+pub struct StatusPlugin;
+
+impl Plugin for StatusPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ApplyStatusEvent>()
+            .add_system(apply_status_system)
+            .add_system(status_tick_system.after(apply_status_system));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StatusKind {
+    SpeedBoost,
+    Slow,
+    /// damage over time, credited to whoever set the player on fire
+    Burn,
+    /// reduces all incoming damage
+    Shield,
+    /// carrying the enemy flag slows down, refreshed while the flag is carried
+    FlagCarrier,
+}
+
+impl StatusKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            StatusKind::SpeedBoost => "speed",
+            StatusKind::Slow => "slowed",
+            StatusKind::Burn => "burning",
+            StatusKind::Shield => "shield",
+            StatusKind::FlagCarrier => "flag",
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            StatusKind::SpeedBoost => Color::rgb(0.3, 0.9, 1.0),
+            StatusKind::Slow => Color::rgb(0.3, 0.9, 0.1),
+            StatusKind::Burn => Color::rgb(1.0, 0.4, 0.0),
+            StatusKind::Shield => Color::rgb(0.7, 0.5, 1.0),
+            StatusKind::FlagCarrier => Color::rgb(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// default duration (seconds)
+    pub fn duration(&self) -> f32 {
+        match self {
+            StatusKind::SpeedBoost => 10.0,
+            StatusKind::Slow => 2.0,
+            StatusKind::Burn => 3.0,
+            StatusKind::Shield => 15.0,
+            StatusKind::FlagCarrier => 0.25,
+        }
+    }
+
+    /// the remaining time is worth showing, false for effects that last as long as a condition
+    pub fn timed(&self) -> bool {
+        !matches!(self, StatusKind::FlagCarrier)
+    }
+
+    /// re-applying an active effect refreshes its duration and adds a stack up to this limit
+    pub fn max_stacks(&self) -> u32 {
+        match self {
+            StatusKind::SpeedBoost | StatusKind::Shield | StatusKind::FlagCarrier => 1,
+            StatusKind::Slow | StatusKind::Burn => 3,
+        }
+    }
+
+    /// movement speed multiplier per stack
+    pub fn speed_factor(&self) -> f32 {
+        match self {
+            StatusKind::SpeedBoost => 1.3,
+            StatusKind::Slow => 0.8,
+            StatusKind::FlagCarrier => 0.85,
+            StatusKind::Burn | StatusKind::Shield => 1.0,
+        }
+    }
+
+    /// incoming damage multiplier per stack
+    pub fn damage_taken_factor(&self) -> f32 {
+        match self {
+            StatusKind::Shield => 0.5,
+            _ => 1.0,
+        }
+    }
+
+    /// damage per burn pulse and stack
+    pub fn damage_per_pulse(&self) -> f32 {
+        match self {
+            StatusKind::Burn => 2.0,
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    /// seconds left
+    pub remaining: f32,
+    pub stacks: u32,
+    /// entity credited with damage the effect deals, server only
+    #[serde(skip)]
+    pub source: Option<Entity>,
+}
+
+/// movement speed multiplier of a set of effects
+pub fn speed_factor(effects: &[StatusEffect]) -> f32 {
+    effects
+        .iter()
+        .map(|effect| effect.kind.speed_factor().powi(effect.stacks as i32))
+        .product()
+}
+
+/// active status effects of a player
+#[derive(Debug, Component)]
+pub struct StatusEffects {
+    pub effects: Vec<StatusEffect>,
+    burn_pulse: Timer,
+}
+
+impl Default for StatusEffects {
+    fn default() -> Self {
+        Self {
+            effects: Vec::new(),
+            burn_pulse: Timer::from_seconds(BURN_PULSE, true),
+        }
+    }
+}
+
+impl StatusEffects {
+    pub fn apply(&mut self, kind: StatusKind, duration: f32, source: Option<Entity>) {
+        match self.effects.iter_mut().find(|effect| effect.kind == kind) {
+            Some(effect) => {
+                effect.remaining = effect.remaining.max(duration);
+                effect.stacks = (effect.stacks + 1).min(kind.max_stacks());
+                if source.is_some() {
+                    effect.source = source;
+                }
+            }
+            None => self.effects.push(StatusEffect {
+                kind,
+                remaining: duration,
+                stacks: 1,
+                source,
+            }),
+        }
+    }
+
+    pub fn has(&self, kind: StatusKind) -> bool {
+        self.effects.iter().any(|effect| effect.kind == kind)
+    }
+
+    pub fn speed_factor(&self) -> f32 {
+        speed_factor(&self.effects)
+    }
+
+    pub fn damage_taken_factor(&self) -> f32 {
+        self.effects
+            .iter()
+            .map(|effect| effect.kind.damage_taken_factor().powi(effect.stacks as i32))
+            .product()
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+}
+
+/// server: give `target` a status effect
+#[derive(Debug, Clone)]
+pub struct ApplyStatusEvent {
+    pub target: Entity,
+    pub kind: StatusKind,
+    pub duration: f32,
+    /// player responsible for the effect, credited with burn kills
+    pub source: Option<Entity>,
+}
+
+impl ApplyStatusEvent {
+    /// the effect with its default duration
+    pub fn new(target: Entity, kind: StatusKind, source: Option<Entity>) -> Self {
+        Self {
+            target,
+            kind,
+            duration: kind.duration(),
+            source,
+        }
+    }
+}
+
+/// server: add effects to living, non-spectating players
+pub fn apply_status_system(
+    mut status_events: EventReader<ApplyStatusEvent>,
+    mut players: Query<(&mut StatusEffects, Option<&Health>), Without<Spectator>>,
+) {
+    for event in status_events.iter() {
+        match players.get_mut(event.target) {
+            Ok((mut status, health)) if !health.map_or(false, Health::is_dead) => {
+                status.apply(event.kind, event.duration, event.source);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// server: run down effect durations, deal burn damage and hand the speed modifiers to the
+/// controller
+pub fn status_tick_system(
+    time: Res<Time>,
+    mut players: Query<(Entity, &mut StatusEffects, Option<&mut FpsController>)>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    let dt = time.delta_seconds();
+    for (entity, mut status, controller) in &mut players {
+        status.burn_pulse.tick(time.delta());
+        if status.burn_pulse.just_finished() {
+            for effect in &status.effects {
+                let amount = effect.kind.damage_per_pulse() * effect.stacks as f32;
+                if amount <= 0.0 {
+                    continue;
+                }
+                damage_events.send(DamageEvent {
+                    target: entity,
+                    amount,
+                    attacker: effect.source,
+                    source_position: None,
+                    cause: DamageCause::Hazard,
+                    damage_type: DamageType::Environmental,
+                    weapon: None,
+                    headshot: false,
+                    projectile: None,
+                });
+            }
+        }
+        for effect in &mut status.effects {
+            effect.remaining -= dt;
+        }
+        status.effects.retain(|effect| effect.remaining > 0.0);
+        if let Some(mut controller) = controller {
+            let factor = status.speed_factor();
+            if controller.status_speed_factor != factor {
+                controller.status_speed_factor = factor;
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! shared gameplay queries about players: who is an enemy of whom, who is in reach of an area
+//! effect and who can be seen from a point. Area damage, weapons and later AI, turrets and
+//! interest management use these instead of their own loops, so that spectators and team
+//! mates are filtered the same way everywhere.
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_rapier3d::prelude::*;
+
+use crate::{spectator::Spectator, team::Team, Player};
+
+/// player origin (feet) to the middle of the capsule
+pub const PLAYER_CENTER_OFFSET: Vec3 = Vec3::Y;
+
+/// players of different teams are enemies, players without a team are enemies of everyone
+pub fn is_enemy(a: Option<Team>, b: Option<Team>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a != b,
+        _ => true,
+    }
+}
+
+/// players taking part in the game (no spectators), with their center positions
+#[derive(SystemParam)]
+pub struct PlayerTargets<'w, 's> {
+    players: Query<
+        'w,
+        's,
+        (Entity, &'static Transform, Option<&'static Team>),
+        (With<Player>, Without<Spectator>),
+    >,
+    physics_context: Res<'w, RapierContext>,
+}
+
+impl<'w, 's> PlayerTargets<'w, 's> {
+    /// all players with their center positions
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+        self.players
+            .iter()
+            .map(|(entity, transform, _)| (entity, transform.translation + PLAYER_CENTER_OFFSET))
+    }
+
+    /// center position of `entity`, None if it is no player (any more) or a spectator
+    pub fn position(&self, entity: Entity) -> Option<Vec3> {
+        self.players
+            .get(entity)
+            .ok()
+            .map(|(_, transform, _)| transform.translation + PLAYER_CENTER_OFFSET)
+    }
+
+    pub fn team_of(&self, entity: Entity) -> Option<Team> {
+        self.players
+            .get(entity)
+            .ok()
+            .and_then(|(_, _, team)| team.copied())
+    }
+
+    /// players that are enemies of `team`
+    pub fn enemies_of(&self, team: Option<Team>) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+        self.players
+            .iter()
+            .filter(move |(_, _, other)| is_enemy(team, other.copied()))
+            .map(|(entity, transform, _)| (entity, transform.translation + PLAYER_CENTER_OFFSET))
+    }
+
+    /// players with their center within `radius` of `center`
+    pub fn players_within(
+        &self,
+        center: Vec3,
+        radius: f32,
+    ) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+        self.iter()
+            .filter(move |(_, position)| position.distance_squared(center) <= radius * radius)
+    }
+
+    /// nothing blocks the line from `eye` to the center of `target`. `exclude` is the looking
+    /// entity, so that its own collider does not block the view.
+    pub fn visible_from(&self, eye: Vec3, target: Entity, exclude: Option<Entity>) -> bool {
+        self.position(target).map_or(false, |center| {
+            line_of_sight(&self.physics_context, eye, target, center, exclude)
+        })
+    }
+}
+
+/// `visible_from` for callers that can't take PlayerTargets next to their own player queries:
+/// nothing but `target` is hit on the line from `eye` to `center`. Targets whose collider is not
+/// in the physics world yet (spawned this frame) are visible.
+pub fn line_of_sight(
+    physics_context: &RapierContext,
+    eye: Vec3,
+    target: Entity,
+    center: Vec3,
+    exclude: Option<Entity>,
+) -> bool {
+    let to_target = center - eye;
+    let distance = to_target.length();
+    if distance <= f32::EPSILON {
+        return true;
+    }
+    let mut filter = QueryFilter::default().exclude_sensors();
+    if let Some(exclude) = exclude {
+        filter = filter.exclude_rigid_body(exclude);
+    }
+    match physics_context.cast_ray(eye, to_target / distance, distance, true, filter) {
+        Some((hit, _)) => hit == target,
+        None => true,
+    }
+}
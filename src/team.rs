@@ -0,0 +1,74 @@
+//! teams: in team based game modes players are put into the smaller team on connect, spawn at
+//! their team's spawn points (see `spawn`) and, unless friendly fire is enabled, can't hurt
+//! their teammates. Players without a team (free-for-all) spawn anywhere.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TeamSettings {
+    /// players can damage their teammates
+    pub friendly_fire: bool,
+}
+
+impl Default for TeamSettings {
+    fn default() -> Self {
+        Self {
+            friendly_fire: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component, Serialize, Deserialize)]
+pub enum Team {
+    Red,
+    Blue,
+}
+
+impl Team {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Team::Red => "red",
+            Team::Blue => "blue",
+        }
+    }
+
+    pub fn opponent(&self) -> Team {
+        match self {
+            Team::Red => Team::Blue,
+            Team::Blue => Team::Red,
+        }
+    }
+
+    /// player model color
+    pub fn color(&self) -> Color {
+        match self {
+            Team::Red => Color::rgb(0.8, 0.25, 0.2),
+            Team::Blue => Color::rgb(0.2, 0.4, 0.85),
+        }
+    }
+
+    /// the team with fewer players, red on a tie
+    pub fn balanced(teams: impl Iterator<Item = Team>) -> Team {
+        let (red, blue) = teams.fold((0, 0), |(red, blue), team| match team {
+            Team::Red => (red + 1, blue),
+            Team::Blue => (red, blue + 1),
+        });
+        if blue < red {
+            Team::Blue
+        } else {
+            Team::Red
+        }
+    }
+}
+
+/// true if damage from `attacker` to `target` is blocked as friendly fire. Self damage is
+/// never blocked.
+pub fn is_friendly_fire(
+    settings: &TeamSettings,
+    attacker: Option<Team>,
+    target: Option<Team>,
+) -> bool {
+    !settings.friendly_fire && attacker.is_some() && attacker == target
+}
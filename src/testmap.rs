@@ -0,0 +1,368 @@
+//! procedural movement test map (`--map=movement_test`).
+//!
+//! A standardized environment for movement tuning and automated movement tests:
+//! - ramps of increasing angle (10° to 60°, the steep ones are surfable)
+//! - stair flights with increasing step heights (0.1 to 0.5)
+//! - a long walled corridor for bunny hopping
+//! - a surf ramp with a start platform reached by an approach ramp
+//! - a closed room with a sliding door, opened by a button outside and a lever inside
+//! - an elevator up to a landing and a revolving door
+//! - penetration test panels of each surface material
+//! - a row of pickups
+//!
+//! The player spawn area around the origin is kept clear.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    level::SurfaceMaterial,
+    mover::{spawn_mover, Mover, MoverBundle, MoverMode},
+    pickup::{spawn_pickup, PickupKind},
+    spawn::spawn_default_spawn_points,
+    usable::{spawn_usable, Usable, UsableKind},
+};
+
+/// half size of the floor plane
+const FLOOR_HALF_SIZE: f32 = 40.0;
+/// height of the floor surface
+const FLOOR_Y: f32 = -0.5;
+
+fn spawn_block(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    material: &Handle<StandardMaterial>,
+    transform: Transform,
+    half_extents: Vec3,
+) -> Entity {
+    let size = half_extents * 2.0;
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(size.x, size.y, size.z))),
+            material: material.clone(),
+            transform,
+            ..default()
+        })
+        .insert(Collider::cuboid(
+            half_extents.x,
+            half_extents.y,
+            half_extents.z,
+        ))
+        .insert(SurfaceMaterial::Concrete)
+        .id()
+}
+
+/// ramp of `length` rising in direction -z (angle in radians) with its low edge at `low_edge`
+fn ramp_transform(low_edge: Vec3, length: f32, angle: f32) -> Transform {
+    let up_slope = Vec3::new(0.0, angle.sin(), -angle.cos());
+    Transform::from_translation(low_edge + up_slope * length * 0.5)
+        .with_rotation(Quat::from_rotation_x(angle))
+}
+
+pub fn setup_movement_test_level(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let floor_material = materials.add(Color::rgb(0.3, 0.5, 0.3).into());
+    let ramp_material = materials.add(Color::rgb(0.6, 0.5, 0.3).into());
+    let stair_material = materials.add(Color::rgb(0.5, 0.5, 0.5).into());
+    let wall_material = materials.add(Color::rgb(0.4, 0.3, 0.3).into());
+    let surf_material = materials.add(Color::rgb(0.4, 0.4, 0.6).into());
+
+    // floor, same surface height as the default level so the spawn point fits
+    spawn_block(
+        &mut commands,
+        &mut meshes,
+        &floor_material,
+        Transform::from_xyz(0.0, FLOOR_Y - 0.5, 0.0),
+        Vec3::new(FLOOR_HALF_SIZE, 0.5, FLOOR_HALF_SIZE),
+    );
+
+    // ramps, 10° to 60° in 10° steps
+    for i in 0..6 {
+        let angle = ((i + 1) as f32 * 10.0).to_radians();
+        spawn_block(
+            &mut commands,
+            &mut meshes,
+            &ramp_material,
+            ramp_transform(Vec3::new(-15.0 + i as f32 * 6.0, FLOOR_Y, -8.0), 8.0, angle),
+            Vec3::new(1.5, 0.1, 4.0),
+        );
+    }
+
+    // stairs, step heights 0.1 to 0.5, 8 steps each
+    const STEP_DEPTH: f32 = 0.6;
+    for i in 0..5 {
+        let step_height = (i + 1) as f32 * 0.1;
+        let x = -12.0 + i as f32 * 5.0;
+        for step in 0..8 {
+            let height = step_height * (step + 1) as f32;
+            spawn_block(
+                &mut commands,
+                &mut meshes,
+                &stair_material,
+                Transform::from_xyz(
+                    x,
+                    FLOOR_Y + height * 0.5,
+                    8.0 + (step as f32 + 0.5) * STEP_DEPTH,
+                ),
+                Vec3::new(1.5, height * 0.5, STEP_DEPTH * 0.5),
+            );
+        }
+    }
+
+    // bhop corridor, 6 wide and 70 long
+    for x in [24.0, 30.0] {
+        spawn_block(
+            &mut commands,
+            &mut meshes,
+            &wall_material,
+            Transform::from_xyz(x, FLOOR_Y + 1.5, 0.0),
+            Vec3::new(0.25, 1.5, 35.0),
+        );
+    }
+
+    // room with a doorway facing the spawn
+    let room_center = Vec3::new(14.0, FLOOR_Y + 1.5, 25.0);
+    let room_half_size = 5.0;
+    let door_half_width = 1.0;
+    for (offset, half_extents) in [
+        (
+            Vec3::new(0.0, 0.0, room_half_size),
+            Vec3::new(room_half_size, 1.5, 0.25),
+        ),
+        (
+            Vec3::new(-room_half_size, 0.0, 0.0),
+            Vec3::new(0.25, 1.5, room_half_size),
+        ),
+        (
+            Vec3::new(room_half_size, 0.0, 0.0),
+            Vec3::new(0.25, 1.5, room_half_size),
+        ),
+    ] {
+        spawn_block(
+            &mut commands,
+            &mut meshes,
+            &wall_material,
+            Transform::from_translation(room_center + offset),
+            half_extents,
+        );
+    }
+    let door_wall_half_width = (room_half_size - door_half_width) * 0.5;
+    for side in [-1.0, 1.0] {
+        spawn_block(
+            &mut commands,
+            &mut meshes,
+            &wall_material,
+            Transform::from_translation(
+                room_center
+                    + Vec3::new(
+                        side * (door_half_width + door_wall_half_width),
+                        0.0,
+                        -room_half_size,
+                    ),
+            ),
+            Vec3::new(door_wall_half_width, 1.5, 0.25),
+        );
+    }
+    spawn_block(
+        &mut commands,
+        &mut meshes,
+        &wall_material,
+        Transform::from_translation(room_center + Vec3::new(0.0, 1.75, 0.0)),
+        Vec3::new(room_half_size, 0.25, room_half_size),
+    );
+    // the door slides into the wall next to the doorway
+    let door_closed = Transform::from_translation(room_center - Vec3::Z * room_half_size);
+    let door_open =
+        Transform::from_translation(door_closed.translation + Vec3::X * door_half_width * 2.0);
+    let door = spawn_usable(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        door_closed,
+        Vec3::new(door_half_width, 1.5, 0.1),
+        Usable::new(UsableKind::Door),
+    );
+    commands
+        .entity(door)
+        .insert_bundle(MoverBundle::new(Mover::new(
+            vec![door_closed, door_open],
+            MoverMode::Usable,
+        )));
+    // button on the outside of the wall, lever on the inside, both facing away from it
+    for (x, z, kind, rotation) in [
+        (1.0, -0.35, UsableKind::Button, std::f32::consts::PI),
+        (-1.0, 0.35, UsableKind::Lever, 0.0),
+    ] {
+        let transform = Transform::from_translation(
+            room_center + Vec3::new(x * (door_half_width + 1.0), 0.0, z - room_half_size),
+        )
+        .with_rotation(Quat::from_rotation_y(rotation));
+        spawn_usable(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            transform,
+            Vec3::new(0.1, 0.15, 0.1),
+            Usable::new(kind).with_targets(vec![door]),
+        );
+    }
+
+    // elevator going up and down next to a landing 5 above the floor
+    let elevator_bottom = Transform::from_xyz(-20.0, FLOOR_Y + 0.1, 20.0);
+    let elevator_top = Transform::from_translation(elevator_bottom.translation + Vec3::Y * 5.0);
+    spawn_mover(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        Vec3::new(1.5, 0.1, 1.5),
+        Color::rgb(0.5, 0.5, 0.55),
+        Mover::new(
+            vec![elevator_bottom, elevator_top],
+            MoverMode::PingPong { wait: 2.0 },
+        ),
+    );
+    spawn_block(
+        &mut commands,
+        &mut meshes,
+        &wall_material,
+        Transform::from_xyz(-20.0, FLOOR_Y + 2.55, 23.5),
+        Vec3::new(1.5, 2.55, 2.0),
+    );
+
+    // revolving door turning a quarter around its center when used
+    let revolving_closed = Transform::from_xyz(-14.0, FLOOR_Y + 1.5, 20.0);
+    let revolving_open =
+        revolving_closed.with_rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2));
+    let revolving = spawn_usable(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        revolving_closed,
+        Vec3::new(1.0, 1.5, 0.1),
+        Usable::new(UsableKind::Door),
+    );
+    commands
+        .entity(revolving)
+        .insert_bundle(MoverBundle::new(Mover::new(
+            vec![revolving_closed, revolving_open],
+            MoverMode::Usable,
+        )));
+
+    // surf ramp: two 60° slopes forming a V along z, starting at z = -15
+    let surf_x = -30.0;
+    let surf_angle = 60f32.to_radians();
+    let surf_width = 6.0;
+    let surf_base_y = 1.0;
+    for side in [-1.0, 1.0] {
+        let slope = Vec3::new(side * surf_angle.cos(), surf_angle.sin(), 0.0);
+        let low_edge = Vec3::new(surf_x + side * 0.25, surf_base_y, 5.0);
+        spawn_block(
+            &mut commands,
+            &mut meshes,
+            &surf_material,
+            Transform::from_translation(low_edge + slope * surf_width * 0.5)
+                .with_rotation(Quat::from_rotation_z(side * surf_angle)),
+            Vec3::new(surf_width * 0.5, 0.1, 20.0),
+        );
+    }
+    // start platform above the upper surf edge, reached by a 20° approach ramp from -z
+    let platform_top = surf_base_y + surf_width * surf_angle.sin() + 0.3;
+    let platform_front = -15.0;
+    let platform_depth = 4.0;
+    spawn_block(
+        &mut commands,
+        &mut meshes,
+        &surf_material,
+        Transform::from_xyz(
+            surf_x,
+            (platform_top + FLOOR_Y) * 0.5,
+            platform_front - platform_depth * 0.5,
+        ),
+        Vec3::new(4.0, (platform_top - FLOOR_Y) * 0.5, platform_depth * 0.5),
+    );
+    let approach_angle = 20f32.to_radians();
+    let approach_length = (platform_top - FLOOR_Y) / approach_angle.sin();
+    let up_slope = Vec3::new(0.0, approach_angle.sin(), approach_angle.cos());
+    let low_edge = Vec3::new(
+        surf_x,
+        FLOOR_Y,
+        platform_front - platform_depth - approach_length * approach_angle.cos(),
+    );
+    spawn_block(
+        &mut commands,
+        &mut meshes,
+        &ramp_material,
+        Transform::from_translation(low_edge + up_slope * approach_length * 0.5)
+            .with_rotation(Quat::from_rotation_x(-approach_angle)),
+        Vec3::new(2.0, 0.1, approach_length * 0.5),
+    );
+
+    // penetration panels behind the ramps, thin enough to shoot through except concrete
+    for (i, (material, thickness, color)) in [
+        (SurfaceMaterial::Concrete, 0.5, Color::rgb(0.5, 0.5, 0.5)),
+        (SurfaceMaterial::Metal, 0.04, Color::rgb(0.6, 0.6, 0.7)),
+        (SurfaceMaterial::Wood, 0.2, Color::rgb(0.6, 0.4, 0.2)),
+        (
+            SurfaceMaterial::Glass,
+            0.05,
+            Color::rgba(0.8, 0.9, 1.0, 0.4),
+        ),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let panel_material = materials.add(StandardMaterial {
+            base_color: color,
+            alpha_mode: if color.a() < 1.0 {
+                AlphaMode::Blend
+            } else {
+                AlphaMode::Opaque
+            },
+            ..default()
+        });
+        let panel = spawn_block(
+            &mut commands,
+            &mut meshes,
+            &panel_material,
+            Transform::from_xyz(-4.5 + i as f32 * 3.0, FLOOR_Y + 1.5, -22.0),
+            Vec3::new(1.0, 1.5, thickness * 0.5),
+        );
+        commands.entity(panel).insert(material);
+    }
+
+    // pickups next to the spawn
+    for (i, kind) in [
+        PickupKind::Health,
+        PickupKind::Armor,
+        PickupKind::Ammo,
+        PickupKind::SpeedBoost,
+        PickupKind::Shield,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        spawn_pickup(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            Vec3::new(-6.0 + i as f32 * 3.0, FLOOR_Y + 0.5, -4.0),
+            kind,
+        );
+    }
+
+    spawn_default_spawn_points(&mut commands);
+
+    // light
+    commands.spawn_bundle(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            illuminance: 20000.0,
+            ..default()
+        },
+        transform: Transform::from_xyz(0.0, 10.0, 0.0)
+            .looking_at(Vec3::new(0.3, 0.0, 0.5), Vec3::Y),
+        ..default()
+    });
+}
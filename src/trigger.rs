@@ -0,0 +1,166 @@
+//! trigger volumes: invisible box, sphere or convex volumes placed by maps. The server finds the
+//! bodies inside them with Rapier shape intersections, reports bodies entering and leaving as
+//! TriggerEvents and carries out the TriggerAction of the volume. Unlike jump pads and
+//! teleporters, triggers are not predicted by clients.
+
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// shape of a trigger volume, relative to its transform
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerShape {
+    Box {
+        half_extents: Vec3,
+    },
+    Sphere {
+        radius: f32,
+    },
+    /// convex hull of the points
+    Convex {
+        points: Vec<Vec3>,
+    },
+}
+
+impl TriggerShape {
+    /// None for degenerate convex hulls
+    pub fn collider(&self) -> Option<Collider> {
+        match self {
+            TriggerShape::Box { half_extents } => Some(Collider::cuboid(
+                half_extents.x,
+                half_extents.y,
+                half_extents.z,
+            )),
+            TriggerShape::Sphere { radius } => Some(Collider::ball(*radius)),
+            TriggerShape::Convex { points } => Collider::convex_hull(points),
+        }
+    }
+}
+
+/// what a trigger volume does to players (and props) entering it. In map files the action is
+/// given by name, e.g. `{"action": "damage", "per_second": 10}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TriggerAction {
+    /// move players to `translation`, stopping them
+    Teleport { translation: Vec3 },
+    /// hurt players while they are inside
+    Damage { per_second: f32 },
+    /// kill players and remove props, like falling below the kill-Z
+    Kill,
+    /// launch players with a fixed velocity
+    JumpPad { launch_velocity: Vec3 },
+    /// area of a game mode objective, game modes look at the occupants of the volume
+    Objective { name: String },
+}
+
+impl TriggerAction {
+    pub fn label(&self) -> &'static str {
+        match self {
+            TriggerAction::Teleport { .. } => "teleport",
+            TriggerAction::Damage { .. } => "damage",
+            TriggerAction::Kill => "kill",
+            TriggerAction::JumpPad { .. } => "jump pad",
+            TriggerAction::Objective { .. } => "objective",
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct TriggerVolume {
+    pub action: TriggerAction,
+    collider: Collider,
+    /// bodies inside since the last update of trigger_volume_system
+    inside: HashSet<Entity>,
+}
+
+impl TriggerVolume {
+    /// None if the shape has no volume
+    pub fn new(shape: &TriggerShape, action: TriggerAction) -> Option<Self> {
+        Some(Self {
+            action,
+            collider: shape.collider()?,
+            inside: HashSet::new(),
+        })
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.inside.contains(&entity)
+    }
+
+    pub fn occupants(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.inside.iter().copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerEventKind {
+    Enter,
+    Exit,
+}
+
+/// a body entered or left a trigger volume
+#[derive(Debug, Clone, Copy)]
+pub struct TriggerEvent {
+    pub trigger: Entity,
+    pub entity: Entity,
+    pub kind: TriggerEventKind,
+}
+
+/// spawn a trigger volume, None if the shape has no volume
+pub fn spawn_trigger_volume(
+    commands: &mut Commands,
+    transform: Transform,
+    shape: &TriggerShape,
+    action: TriggerAction,
+) -> Option<Entity> {
+    let volume = TriggerVolume::new(shape, action)?;
+    Some(
+        commands
+            .spawn_bundle(TransformBundle::from_transform(transform))
+            .insert(volume)
+            .id(),
+    )
+}
+
+/// server: update the bodies inside every trigger volume and send events for the changes.
+/// Level geometry and sensors are ignored.
+pub fn trigger_volume_system(
+    physics_context: Res<RapierContext>,
+    mut triggers: Query<(Entity, &GlobalTransform, &mut TriggerVolume)>,
+    mut trigger_events: EventWriter<TriggerEvent>,
+) {
+    let filter = QueryFilter::exclude_fixed().exclude_sensors();
+    for (trigger, transform, mut volume) in &mut triggers {
+        let (_, rotation, translation) = transform.to_scale_rotation_translation();
+        let mut inside = HashSet::new();
+        physics_context.intersections_with_shape(
+            translation,
+            rotation,
+            &volume.collider,
+            filter,
+            |entity| {
+                inside.insert(entity);
+                true
+            },
+        );
+        for &entity in inside.difference(&volume.inside) {
+            trigger_events.send(TriggerEvent {
+                trigger,
+                entity,
+                kind: TriggerEventKind::Enter,
+            });
+        }
+        for &entity in volume.inside.difference(&inside) {
+            trigger_events.send(TriggerEvent {
+                trigger,
+                entity,
+                kind: TriggerEventKind::Exit,
+            });
+        }
+        volume.inside = inside;
+    }
+}
@@ -0,0 +1,246 @@
+//! usable level entities: buttons, levers and doors players trigger with the use key. The
+//! server traces the use command against the level and switches the usable (and the usables
+//! it is wired to), clients follow the replicated state. Like other level devices, usables
+//! are identified by their (rest) position across the network.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::mover::Mover;
+
+/// maximum distance from the view origin to a usable (m)
+pub const USE_RANGE: f32 = 2.5;
+
+/// replicated positions closer than this belong to the same usable
+const USABLE_MATCH_DISTANCE: f32 = 0.1;
+
+/// seconds until a pressed button pops back
+const BUTTON_RESET_TIME: f32 = 1.0;
+
+/// how far a pressed button sinks in (m)
+const BUTTON_PRESS_DEPTH: f32 = 0.05;
+
+/// lever angle to either side of the rest pose (radians)
+const LEVER_ANGLE: f32 = 0.6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UsableKind {
+    /// switches its targets and pops back after a moment
+    Button,
+    /// flips between two positions, its targets follow it
+    Lever,
+    /// opens and closes, moved by the Mover on the same entity
+    Door,
+}
+
+impl UsableKind {
+    pub fn color(&self) -> Color {
+        match self {
+            UsableKind::Button => Color::rgb(0.9, 0.2, 0.1),
+            UsableKind::Lever => Color::rgb(0.9, 0.7, 0.1),
+            UsableKind::Door => Color::rgb(0.45, 0.35, 0.25),
+        }
+    }
+}
+
+#[derive(Debug, Component)]
+pub struct Usable {
+    pub kind: UsableKind,
+    /// pressed / flipped / open
+    pub active: bool,
+    /// pose while inactive, usables face along their +Z axis
+    pub rest: Transform,
+    /// other usables switched along, e.g. the door a button opens
+    pub targets: Vec<Entity>,
+    /// runs while a button is pressed, server only
+    reset: Option<Timer>,
+}
+
+impl Usable {
+    pub fn new(kind: UsableKind) -> Self {
+        Self {
+            kind,
+            active: false,
+            rest: Transform::identity(),
+            targets: Vec::new(),
+            reset: None,
+        }
+    }
+
+    pub fn with_targets(mut self, targets: Vec<Entity>) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    /// true if the replicated `position` refers to this usable
+    pub fn matches(&self, position: Vec3) -> bool {
+        self.rest.translation.distance(position) < USABLE_MATCH_DISTANCE
+    }
+
+    /// switch the usable itself. Returns the state its targets should take: toggled for
+    /// buttons (None), the new state for levers and doors.
+    fn trigger(&mut self) -> Option<bool> {
+        match self.kind {
+            UsableKind::Button => {
+                self.active = true;
+                self.reset = Some(Timer::from_seconds(BUTTON_RESET_TIME, false));
+                None
+            }
+            UsableKind::Lever | UsableKind::Door => {
+                self.active = !self.active;
+                Some(self.active)
+            }
+        }
+    }
+
+    /// pose for the current state, doors are left to their mover
+    pub fn pose(&self) -> Transform {
+        match (self.kind, self.active) {
+            (_, false) | (UsableKind::Door, true) => self.rest,
+            (UsableKind::Button, true) => {
+                let mut pose = self.rest;
+                pose.translation -= self.rest.rotation * Vec3::Z * BUTTON_PRESS_DEPTH;
+                pose
+            }
+            (UsableKind::Lever, true) => {
+                self.rest * Transform::from_rotation(Quat::from_rotation_x(-2.0 * LEVER_ANGLE))
+            }
+        }
+    }
+}
+
+/// spawn a usable box of `half_extents` at `rest`. Levers start tilted to the inactive side.
+pub fn spawn_usable(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    rest: Transform,
+    half_extents: Vec3,
+    usable: Usable,
+) -> Entity {
+    let rest = match usable.kind {
+        UsableKind::Lever => rest * Transform::from_rotation(Quat::from_rotation_x(LEVER_ANGLE)),
+        _ => rest,
+    };
+    let size = half_extents * 2.0;
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(size.x, size.y, size.z))),
+            material: materials.add(usable.kind.color().into()),
+            transform: rest,
+            ..default()
+        })
+        .insert(Collider::cuboid(
+            half_extents.x,
+            half_extents.y,
+            half_extents.z,
+        ))
+        .insert(Usable { rest, ..usable })
+        .id()
+}
+
+/// server: validated use command of a player
+#[derive(Debug, Clone)]
+pub struct UseEvent {
+    pub player: Entity,
+    /// view origin, already checked against the player position
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+/// server: a usable changed its state
+#[derive(Debug, Clone)]
+pub struct UsableChangedEvent {
+    pub position: Vec3,
+    pub active: bool,
+    /// player that caused the change, None for buttons popping back
+    pub player: Option<Entity>,
+}
+
+/// server: trace use commands and switch the usable in front of the player and its targets
+pub fn use_system(
+    physics_context: Res<RapierContext>,
+    mut use_events: EventReader<UseEvent>,
+    mut usables: Query<&mut Usable>,
+    mut changed_events: EventWriter<UsableChangedEvent>,
+) {
+    for event in use_events.iter() {
+        let filter = QueryFilter::default().exclude_rigid_body(event.player);
+        let hit = physics_context.cast_ray(
+            event.origin,
+            event.direction.normalize_or_zero(),
+            USE_RANGE,
+            true,
+            filter,
+        );
+        let usable = hit.and_then(|(entity, _)| usables.get_mut(entity).ok());
+        let (targets, target_state) = match usable {
+            Some(mut usable) => {
+                let target_state = usable.trigger();
+                changed_events.send(UsableChangedEvent {
+                    position: usable.rest.translation,
+                    active: usable.active,
+                    player: Some(event.player),
+                });
+                (usable.targets.clone(), target_state)
+            }
+            None => continue,
+        };
+        for target in targets {
+            if let Ok(mut usable) = usables.get_mut(target) {
+                usable.active = target_state.unwrap_or(!usable.active);
+                changed_events.send(UsableChangedEvent {
+                    position: usable.rest.translation,
+                    active: usable.active,
+                    player: Some(event.player),
+                });
+            }
+        }
+    }
+}
+
+/// server: pop pressed buttons back
+pub fn button_reset_system(
+    time: Res<Time>,
+    mut usables: Query<&mut Usable>,
+    mut changed_events: EventWriter<UsableChangedEvent>,
+) {
+    for mut usable in &mut usables {
+        // only touch pressed buttons, so the others don't get marked as changed
+        if usable.reset.is_none() {
+            continue;
+        }
+        let finished = match &mut usable.reset {
+            Some(reset) => reset.tick(time.delta()).finished(),
+            None => continue,
+        };
+        if finished {
+            usable.reset = None;
+            usable.active = false;
+            changed_events.send(UsableChangedEvent {
+                position: usable.rest.translation,
+                active: false,
+                player: None,
+            });
+        }
+    }
+}
+
+/// client and server: move usables into the pose of their state
+#[allow(clippy::type_complexity)]
+pub fn usable_pose_system(
+    mut usables: Query<(&Usable, &mut Transform), (Changed<Usable>, Without<Mover>)>,
+) {
+    for (usable, mut transform) in &mut usables {
+        *transform = usable.pose();
+    }
+}
+
+/// client: set the state of the usable at `position`, as replicated by the server
+pub fn set_usable_active(usables: &mut Query<&mut Usable>, position: Vec3, active: bool) {
+    for mut usable in usables.iter_mut() {
+        if usable.matches(position) {
+            usable.active = active;
+        }
+    }
+}
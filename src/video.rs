@@ -0,0 +1,141 @@
+//! window settings of the client: resolution, window mode and vsync.
+//!
+//! settings in video.toml. `window_mode` is windowed, borderless or fullscreen:
+//!     width = 1280
+//!     height = 720
+//!     window_mode = "windowed"
+//!     vsync = true
+
+use std::{fmt, str::FromStr};
+
+use bevy::{
+    prelude::*,
+    window::{PresentMode, WindowMode},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub const VIDEO_CONFIG_FILE: &str = "video.toml";
+
+/// resolutions offered by the settings menu
+pub const RESOLUTIONS: [(f32, f32); 5] = [
+    (1280.0, 720.0),
+    (1600.0, 900.0),
+    (1920.0, 1080.0),
+    (2560.0, 1440.0),
+    (3840.0, 2160.0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum DisplayMode {
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+impl DisplayMode {
+    pub const ALL: [DisplayMode; 3] = [
+        DisplayMode::Windowed,
+        DisplayMode::Borderless,
+        DisplayMode::Fullscreen,
+    ];
+
+    fn window_mode(self) -> WindowMode {
+        match self {
+            DisplayMode::Windowed => WindowMode::Windowed,
+            DisplayMode::Borderless => WindowMode::BorderlessFullscreen,
+            DisplayMode::Fullscreen => WindowMode::SizedFullscreen,
+        }
+    }
+}
+
+impl fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DisplayMode::Windowed => "windowed",
+            DisplayMode::Borderless => "borderless",
+            DisplayMode::Fullscreen => "fullscreen",
+        })
+    }
+}
+
+impl FromStr for DisplayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DisplayMode::ALL
+            .into_iter()
+            .find(|mode| mode.to_string() == s)
+            .ok_or_else(|| format!("unknown window mode {}", s))
+    }
+}
+
+impl TryFrom<String> for DisplayMode {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl From<DisplayMode> for String {
+    fn from(mode: DisplayMode) -> Self {
+        mode.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoSettings {
+    pub width: f32,
+    pub height: f32,
+    #[serde(rename = "window_mode")]
+    pub mode: DisplayMode,
+    pub vsync: bool,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        Self {
+            width: 1280.0,
+            height: 720.0,
+            mode: DisplayMode::Windowed,
+            vsync: true,
+        }
+    }
+}
+
+impl VideoSettings {
+    fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        }
+    }
+
+    /// the window to open at startup
+    pub fn window_descriptor(&self) -> WindowDescriptor {
+        WindowDescriptor {
+            width: self.width,
+            height: self.height,
+            mode: self.mode.window_mode(),
+            present_mode: self.present_mode(),
+            ..default()
+        }
+    }
+
+    /// change the open window
+    pub fn apply(&self, window: &mut Window) {
+        if window.requested_width() != self.width || window.requested_height() != self.height {
+            window.set_resolution(self.width, self.height);
+        }
+        if window.mode() != self.mode.window_mode() {
+            window.set_mode(self.mode.window_mode());
+        }
+        if window.present_mode() != self.present_mode() {
+            window.set_present_mode(self.present_mode());
+        }
+    }
+}
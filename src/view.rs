@@ -0,0 +1,306 @@
+//! view effects on the first person render camera: head bob while walking, roll when
+//! strafing and a FOV kick at high speed. Only the RenderPlayer camera is changed, the logical
+//! controller and the inputs sent to the server are not affected.
+//!
+//! Camera shake is trauma based: explosions, damage and hard landings add trauma (0..1), which
+//! decays over time. The shake strength is trauma squared, so small hits stay subtle.
+//!
+//! settings in view.toml. Setting an amount to 0 disables the effect:
+//!     view_effects = true
+//!     bob_amount = 0.04
+//!     roll_angle = 1.5
+//!     fov = 45
+//!     view_model_fov = 50
+//!     fov_kick = 12
+//!     ads_zoom = 0.7
+//!     shake_amount = 1
+//!     damage_numbers = true
+//!     view_model = true
+//!     view_model_sway = 1
+//!     view_model_bob = 1
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::{CameraMode, FovModifiers, VIEW_MODEL_FOV},
+    controller::{FpsController, LandedEvent, LogicalPlayer, RenderPlayer},
+    spectator::Spectating,
+};
+
+pub const VIEW_CONFIG_FILE: &str = "view.toml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewEffects {
+    /// master switch for all effects
+    #[serde(rename = "view_effects")]
+    pub enabled: bool,
+    /// vertical head bob amplitude (m)
+    pub bob_amount: f32,
+    /// head bob cycles per meter walked
+    pub bob_frequency: f32,
+    /// lateral speed for the full bob amplitude
+    pub bob_speed: f32,
+    /// roll at full strafe speed (degrees)
+    pub roll_angle: f32,
+    /// strafe speed for the full roll
+    pub roll_speed: f32,
+    /// vertical field of view without kick (degrees), clamped to the camera limits
+    pub fov: f32,
+    /// vertical field of view of the arms and weapon (degrees)
+    pub view_model_fov: f32,
+    /// FOV added at `fov_kick_full_speed` (degrees)
+    pub fov_kick: f32,
+    /// lateral speed where the FOV starts widening, a bit above walking
+    pub fov_kick_start_speed: f32,
+    pub fov_kick_full_speed: f32,
+    /// FOV multiplier while aiming down sights, 1 disables the zoom
+    pub ads_zoom: f32,
+    /// how fast roll and FOV follow their targets (1/s)
+    #[serde(rename = "view_response")]
+    pub response: f32,
+    /// scales all camera shake, 0 disables it
+    pub shake_amount: f32,
+    /// rotation at full trauma (degrees)
+    pub shake_max_angle: f32,
+    /// offset at full trauma (m)
+    pub shake_max_offset: f32,
+    /// shake oscillations per second
+    pub shake_frequency: f32,
+    /// trauma lost per second
+    pub shake_decay: f32,
+    /// show the damage of confirmed hits above the victims
+    pub damage_numbers: bool,
+    /// draw the arms and weapon in first person
+    pub view_model: bool,
+    /// scales the view model sway against the look motion, 0 disables it
+    pub view_model_sway: f32,
+    /// scales the view model bob while walking, 0 disables it
+    pub view_model_bob: f32,
+}
+
+impl Default for ViewEffects {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bob_amount: 0.04,
+            bob_frequency: 0.6,
+            bob_speed: 10.0,
+            roll_angle: 1.5,
+            roll_speed: 10.0,
+            fov: 45.0,
+            view_model_fov: VIEW_MODEL_FOV,
+            fov_kick: 12.0,
+            fov_kick_start_speed: 12.0,
+            fov_kick_full_speed: 30.0,
+            ads_zoom: 0.7,
+            response: 8.0,
+            shake_amount: 1.0,
+            shake_max_angle: 4.0,
+            shake_max_offset: 0.1,
+            shake_frequency: 15.0,
+            shake_decay: 1.5,
+            damage_numbers: true,
+            view_model: true,
+            view_model_sway: 1.0,
+            view_model_bob: 1.0,
+        }
+    }
+}
+
+/// trauma added by an explosion at the player's position
+pub const EXPLOSION_TRAUMA: f32 = 0.8;
+
+/// explosions further away than this do not shake the camera
+pub const EXPLOSION_SHAKE_RADIUS: f32 = 8.0;
+
+/// trauma per fraction of max health lost
+pub const DAMAGE_TRAUMA: f32 = 1.5;
+
+/// landings slower than this (m/s) do not shake the camera
+pub const LANDING_SHAKE_MIN_SPEED: f32 = 8.0;
+
+/// trauma per m/s landing speed above LANDING_SHAKE_MIN_SPEED
+pub const LANDING_TRAUMA: f32 = 0.04;
+
+/// camera shake state, gameplay events add trauma
+#[derive(Debug, Default)]
+pub struct CameraShake {
+    trauma: f32,
+    /// time base of the shake oscillation (seconds)
+    time: f32,
+}
+
+impl CameraShake {
+    pub fn trauma(&self) -> f32 {
+        self.trauma
+    }
+
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount.max(0.0)).min(1.0);
+    }
+
+    /// trauma of an explosion at `distance` from the player
+    pub fn add_explosion(&mut self, distance: f32) {
+        self.add_trauma(EXPLOSION_TRAUMA * (1.0 - distance / EXPLOSION_SHAKE_RADIUS));
+    }
+
+    /// trauma of a landing with the given downward speed
+    pub fn add_landing(&mut self, impact_speed: f32) {
+        self.add_trauma((impact_speed - LANDING_SHAKE_MIN_SPEED) * LANDING_TRAUMA);
+    }
+}
+
+/// smooth pseudo noise in -1..1, different for each `seed`
+fn shake_noise(time: f32, seed: f32) -> f32 {
+    ((time + seed).sin() * 0.5
+        + (time * 2.3 + seed * 1.7).sin() * 0.3
+        + (time * 4.1 + seed * 2.9).sin() * 0.2)
+        .clamp(-1.0, 1.0)
+}
+
+/// add trauma for hard landings of the locally simulated player
+pub fn landing_shake_system(
+    mut landed_events: EventReader<LandedEvent>,
+    logical_query: Query<Entity, With<LogicalPlayer>>,
+    mut shake: ResMut<CameraShake>,
+) {
+    for event in landed_events.iter() {
+        if logical_query.get(event.entity).is_ok() {
+            shake.add_landing(event.impact_speed);
+        }
+    }
+}
+
+/// decay the trauma and shake the player view camera on top of its transform. Runs after all
+/// systems positioning the camera, the logical controller is not affected.
+pub fn camera_shake_system(
+    time: Res<Time>,
+    effects: Res<ViewEffects>,
+    mode: Res<CameraMode>,
+    spectating: Res<Spectating>,
+    mut shake: ResMut<CameraShake>,
+    mut camera_query: Query<&mut Transform, (With<RenderPlayer>, With<Camera>)>,
+) {
+    let dt = time.delta_seconds();
+    shake.trauma = (shake.trauma - effects.shake_decay * dt).max(0.0);
+    shake.time += dt * effects.shake_frequency * std::f32::consts::TAU;
+    if shake.trauma <= 0.0
+        || effects.shake_amount <= 0.0
+        || !mode.is_player_view()
+        || spectating.is_spectating()
+    {
+        return;
+    }
+    let strength = shake.trauma * shake.trauma * effects.shake_amount;
+    let t = shake.time;
+    let angle = effects.shake_max_angle.to_radians() * strength;
+    let rotation = Quat::from_euler(
+        EulerRot::YXZ,
+        angle * shake_noise(t, 0.0),
+        angle * shake_noise(t, 10.0),
+        angle * shake_noise(t, 20.0),
+    );
+    let offset = Vec3::new(
+        shake_noise(t, 30.0),
+        shake_noise(t, 40.0),
+        shake_noise(t, 50.0),
+    ) * effects.shake_max_offset
+        * strength;
+    for mut transform in &mut camera_query {
+        transform.translation += transform.rotation * offset;
+        transform.rotation *= rotation;
+    }
+}
+
+/// smoothed effect state of the render camera
+#[derive(Default)]
+pub struct ViewEffectState {
+    /// head bob cycle position (radians)
+    bob_phase: f32,
+    /// current bob amplitude, fades out when stopping or leaving the ground
+    bob_weight: f32,
+    /// current roll (radians)
+    roll: f32,
+    /// current FOV kick (degrees)
+    fov_kick: f32,
+    /// current aim down sights blend (0..1)
+    ads: f32,
+}
+
+/// apply the view effects on top of the first person camera transform. Runs after
+/// fps_controller_render, which resets the transform every frame. The FOV changes go to
+/// FovModifiers, the camera module applies them.
+#[allow(clippy::type_complexity)]
+pub fn view_effects_system(
+    time: Res<Time>,
+    effects: Res<ViewEffects>,
+    mode: Res<CameraMode>,
+    spectating: Res<Spectating>,
+    logical_query: Query<&FpsController, With<LogicalPlayer>>,
+    mut camera_query: Query<&mut Transform, (With<RenderPlayer>, With<Camera>)>,
+    mut fov_modifiers: ResMut<FovModifiers>,
+    mut state: Local<ViewEffectState>,
+) {
+    let dt = time.delta_seconds();
+    let active = effects.enabled && *mode == CameraMode::FirstPerson && !spectating.is_spectating();
+    let controller = logical_query.get_single().ok().filter(|_| active);
+
+    // targets, all effects fade out when inactive
+    let (bob_target, roll_target, fov_target, ads_target) = match controller {
+        Some(controller) => {
+            let velocity = controller.velocity;
+            let lateral_speed = velocity.xz().length();
+            let grounded = controller.ground_tick >= 1;
+            let bob = if grounded {
+                (lateral_speed / effects.bob_speed.max(0.01)).min(1.0)
+            } else {
+                0.0
+            };
+            state.bob_phase = (state.bob_phase
+                + lateral_speed * effects.bob_frequency * std::f32::consts::TAU * dt)
+                % std::f32::consts::TAU;
+            let right = Quat::from_rotation_y(controller.yaw) * Vec3::X;
+            let strafe = (velocity.dot(right) / effects.roll_speed.max(0.01)).clamp(-1.0, 1.0);
+            let kick_range = (effects.fov_kick_full_speed - effects.fov_kick_start_speed).max(0.01);
+            let kick =
+                ((lateral_speed - effects.fov_kick_start_speed) / kick_range).clamp(0.0, 1.0);
+            (
+                bob,
+                -strafe * effects.roll_angle.to_radians(),
+                kick * effects.fov_kick,
+                if controller.aiming { 1.0 } else { 0.0 },
+            )
+        }
+        None => (0.0, 0.0, 0.0, 0.0),
+    };
+    let follow = 1.0 - (-effects.response * dt).exp();
+    state.bob_weight += (bob_target - state.bob_weight) * follow;
+    state.roll += (roll_target - state.roll) * follow;
+    state.fov_kick += (fov_target - state.fov_kick) * follow;
+    state.ads += (ads_target - state.ads) * follow;
+
+    let modifiers = FovModifiers {
+        kick: state.fov_kick,
+        zoom: 1.0 + (effects.ads_zoom - 1.0) * state.ads,
+    };
+    if *fov_modifiers != modifiers {
+        *fov_modifiers = modifiers;
+    }
+
+    if controller.is_none() {
+        return;
+    }
+    for mut transform in &mut camera_query {
+        // the view dips twice per cycle (each step) and sways once
+        let bob = state.bob_weight * effects.bob_amount;
+        let offset = Vec3::new(
+            state.bob_phase.cos() * bob * 0.5,
+            -(state.bob_phase * 2.0).sin().abs() * bob,
+            0.0,
+        );
+        transform.translation += transform.rotation * offset;
+        transform.rotation *= Quat::from_rotation_z(state.roll);
+    }
+}
@@ -0,0 +1,323 @@
+//! first person view model: arms holding the active weapon, drawn by the ViewModelCamera on
+//! its own render layer. The model sways against the look motion, bobs while walking, kicks
+//! back when firing, dips away when the weapon changes and tilts for a reload when ammo is
+//! refilled. Purely cosmetic and local, like the view effects.
+
+use bevy::{math::Vec3Swizzles, prelude::*, render::view::RenderLayers};
+
+use crate::{
+    camera::{CameraMode, ViewModelCamera, VIEW_MODEL_LAYER},
+    controller::{FpsController, LogicalPlayer},
+    frame::OwnPlayerState,
+    killcam::KillCam,
+    spectator::Spectating,
+    view::ViewEffects,
+    weapon::{Ammo, Inventory, WeaponKind},
+};
+
+/// position of the view model in front of the camera, at the hip and while aiming down sights
+const HIP_OFFSET: Vec3 = Vec3::new(0.22, -0.2, -0.45);
+const ADS_OFFSET: Vec3 = Vec3::new(0.0, -0.12, -0.35);
+
+/// seconds to lower the old weapon and raise the new one
+const SWITCH_TIME: f32 = 0.35;
+
+/// seconds of the reload motion
+const RELOAD_TIME: f32 = 0.8;
+
+/// recoil lost per second
+const KICK_DECAY: f32 = 8.0;
+
+/// largest sway offset (m)
+const MAX_SWAY: f32 = 0.05;
+
+/// gameplay events animating the view model, sent by the client
+#[derive(Debug, Clone, Copy)]
+pub enum ViewModelEvent {
+    Fire(WeaponKind),
+    Reload,
+}
+
+/// animation state of the view model, on its root entity
+#[derive(Debug, Component)]
+pub struct ViewModel {
+    /// weapon in the hands
+    weapon: WeaponKind,
+    /// weapon taken out by the running switch
+    next_weapon: Option<WeaponKind>,
+    /// progress of the weapon switch, 0..1
+    switch: f32,
+    /// remaining reload time
+    reload: f32,
+    /// recoil, 0..1
+    kick: f32,
+    /// smoothed look rate (yaw, pitch per second)
+    look_rate: Vec2,
+    last_look: Option<Vec2>,
+    bob_phase: f32,
+    bob_weight: f32,
+    /// aim down sights blend, 0..1
+    ads: f32,
+}
+
+impl Default for ViewModel {
+    fn default() -> Self {
+        Self {
+            weapon: WeaponKind::Hitscan,
+            next_weapon: None,
+            switch: 0.0,
+            reload: 0.0,
+            kick: 0.0,
+            look_rate: Vec2::ZERO,
+            last_look: None,
+            bob_phase: 0.0,
+            bob_weight: 0.0,
+            ads: 0.0,
+        }
+    }
+}
+
+impl ViewModel {
+    /// start taking out `weapon`, unless it is in the hands or on the way already
+    fn switch_to(&mut self, weapon: WeaponKind) {
+        if self.next_weapon.unwrap_or(self.weapon) != weapon {
+            self.next_weapon = Some(weapon);
+            self.switch = 0.0;
+            self.reload = 0.0;
+        }
+    }
+
+    /// how far the weapon is lowered by the switch, 0..1
+    fn lowered(&self) -> f32 {
+        match self.next_weapon {
+            Some(_) => (self.switch * std::f32::consts::PI).sin(),
+            None => 0.0,
+        }
+    }
+}
+
+/// the weapon mesh shown for `0`
+#[derive(Component)]
+pub struct ViewModelWeapon(pub WeaponKind);
+
+/// spawn the view model below the ViewModelCamera. Runs after the startup stage, which spawns
+/// the camera.
+pub fn setup_view_model(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    camera_query: Query<Entity, With<ViewModelCamera>>,
+) {
+    let camera = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let layer = RenderLayers::layer(VIEW_MODEL_LAYER);
+    let skin = materials.add(Color::rgb(0.85, 0.65, 0.5).into());
+    let sleeve = materials.add(Color::rgb(0.25, 0.3, 0.25).into());
+    let mut part =
+        |mesh: Mesh, material: Handle<StandardMaterial>, transform: Transform| PbrBundle {
+            mesh: meshes.add(mesh),
+            material,
+            transform,
+            ..default()
+        };
+    let forearm = Mesh::from(shape::Box::new(0.07, 0.07, 0.3));
+    let hand = Mesh::from(shape::Box::new(0.08, 0.05, 0.08));
+    let arms = [
+        part(
+            forearm.clone(),
+            sleeve.clone(),
+            Transform::from_xyz(0.06, -0.08, 0.12).with_rotation(Quat::from_rotation_y(0.3)),
+        ),
+        part(
+            hand.clone(),
+            skin.clone(),
+            Transform::from_xyz(0.0, -0.05, 0.0),
+        ),
+        part(
+            forearm,
+            sleeve,
+            Transform::from_xyz(-0.14, -0.1, -0.05).with_rotation(Quat::from_rotation_y(-0.5)),
+        ),
+        part(hand, skin, Transform::from_xyz(-0.05, -0.04, -0.2)),
+    ];
+    let rifle = part(
+        Mesh::from(shape::Box::new(0.05, 0.08, 0.55)),
+        materials.add(Color::rgb(0.2, 0.2, 0.2).into()),
+        Transform::from_xyz(0.0, 0.0, -0.15),
+    );
+    let mut launcher = part(
+        Mesh::from(shape::Box::new(0.12, 0.12, 0.7)),
+        materials.add(Color::rgb(0.3, 0.4, 0.2).into()),
+        Transform::from_xyz(0.0, 0.03, -0.15),
+    );
+    launcher.visibility.is_visible = false;
+    commands.entity(camera).with_children(|parent| {
+        parent
+            .spawn_bundle(SpatialBundle {
+                transform: Transform::from_translation(HIP_OFFSET),
+                ..default()
+            })
+            .insert(ViewModel::default())
+            .with_children(|parent| {
+                for arm in arms {
+                    parent.spawn_bundle(arm).insert(layer);
+                }
+                parent
+                    .spawn_bundle(rifle)
+                    .insert(layer)
+                    .insert(ViewModelWeapon(WeaponKind::Hitscan));
+                parent
+                    .spawn_bundle(launcher)
+                    .insert(layer)
+                    .insert(ViewModelWeapon(WeaponKind::Rocket));
+            });
+    });
+}
+
+/// reload when the ammo of the own player is refilled
+pub fn view_model_reload_system(
+    own_state: Res<OwnPlayerState>,
+    mut last_ammo: Local<Option<Ammo>>,
+    mut events: EventWriter<ViewModelEvent>,
+) {
+    if !own_state.is_changed() {
+        return;
+    }
+    if let (Some(last), Some(ammo)) = (*last_ammo, own_state.ammo) {
+        if ammo.rockets > last.rockets || ammo.grenades > last.grenades {
+            events.send(ViewModelEvent::Reload);
+        }
+    }
+    *last_ammo = own_state.ammo;
+}
+
+/// show the view model camera only while looking through the own eyes
+pub fn view_model_camera_system(
+    effects: Res<ViewEffects>,
+    mode: Res<CameraMode>,
+    spectating: Res<Spectating>,
+    kill_cam: Res<KillCam>,
+    mut camera_query: Query<&mut Camera, With<ViewModelCamera>>,
+) {
+    let active = effects.view_model
+        && *mode == CameraMode::FirstPerson
+        && !spectating.is_spectating()
+        && !kill_cam.is_active();
+    for mut camera in &mut camera_query {
+        if camera.is_active != active {
+            camera.is_active = active;
+        }
+    }
+}
+
+/// animate the view model from the weapon events and the motion of the local player
+#[allow(clippy::type_complexity)]
+pub fn view_model_system(
+    time: Res<Time>,
+    effects: Res<ViewEffects>,
+    inventory: Res<Inventory>,
+    mut events: EventReader<ViewModelEvent>,
+    logical_query: Query<&FpsController, With<LogicalPlayer>>,
+    mut view_model_query: Query<(&mut ViewModel, &mut Transform)>,
+    mut weapon_query: Query<(&ViewModelWeapon, &mut Visibility)>,
+) {
+    let (mut view_model, mut transform) = match view_model_query.get_single_mut() {
+        Ok(view_model) => view_model,
+        Err(_) => return,
+    };
+    let dt = time.delta_seconds();
+    for event in events.iter() {
+        match *event {
+            ViewModelEvent::Fire(weapon) => {
+                view_model.switch_to(weapon);
+                view_model.kick = 1.0;
+            }
+            ViewModelEvent::Reload => {
+                if view_model.next_weapon.is_none() {
+                    view_model.reload = RELOAD_TIME;
+                }
+            }
+        }
+    }
+    // a dropped weapon can't stay in the hands
+    if !inventory.has(view_model.weapon) {
+        view_model.switch_to(WeaponKind::Hitscan);
+    }
+
+    if let Some(next_weapon) = view_model.next_weapon {
+        let before = view_model.switch;
+        view_model.switch = (view_model.switch + dt / SWITCH_TIME).min(1.0);
+        // swap the meshes at the lowest point
+        if before < 0.5 && view_model.switch >= 0.5 {
+            view_model.weapon = next_weapon;
+        }
+        if view_model.switch >= 1.0 {
+            view_model.next_weapon = None;
+        }
+    }
+    view_model.reload = (view_model.reload - dt).max(0.0);
+    view_model.kick = (view_model.kick - KICK_DECAY * dt).max(0.0);
+
+    let follow = 1.0 - (-effects.response * dt).exp();
+    let (bob_target, ads_target) = match logical_query.get_single() {
+        Ok(controller) => {
+            let look = Vec2::new(controller.yaw, controller.pitch);
+            if let Some(last_look) = view_model.last_look {
+                if dt > 0.0 {
+                    // yaw takes the short way around
+                    let mut delta = look - last_look;
+                    delta.x = (delta.x + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU)
+                        - std::f32::consts::PI;
+                    let rate = delta / dt;
+                    view_model.look_rate += (rate - view_model.look_rate) * follow;
+                }
+            }
+            view_model.last_look = Some(look);
+            let lateral_speed = controller.velocity.xz().length();
+            view_model.bob_phase = (view_model.bob_phase
+                + lateral_speed * effects.bob_frequency * std::f32::consts::TAU * dt)
+                % std::f32::consts::TAU;
+            let bob = if controller.ground_tick >= 1 {
+                (lateral_speed / effects.bob_speed.max(0.01)).min(1.0)
+            } else {
+                0.0
+            };
+            (bob, if controller.aiming { 1.0 } else { 0.0 })
+        }
+        Err(_) => (0.0, 0.0),
+    };
+    view_model.bob_weight += (bob_target - view_model.bob_weight) * follow;
+    view_model.ads += (ads_target - view_model.ads) * follow;
+
+    // sway lags behind the view: turning left (yaw up) pushes the model right, looking up
+    // pushes it down. Bob and sway are reduced while aiming down sights.
+    let steady = 1.0 - view_model.ads * 0.8;
+    let sway =
+        (view_model.look_rate * 0.02 * effects.view_model_sway).clamp_length_max(MAX_SWAY) * steady;
+    let bob = view_model.bob_weight * effects.view_model_bob * 0.015 * steady;
+    let phase = view_model.bob_phase;
+    let reload = (view_model.reload / RELOAD_TIME * std::f32::consts::PI).sin();
+    let lowered = view_model.lowered();
+    let offset = HIP_OFFSET.lerp(ADS_OFFSET, view_model.ads)
+        + Vec3::new(
+            sway.x + phase.cos() * bob,
+            -sway.y - (phase * 2.0).sin().abs() * bob - lowered * 0.3 - reload * 0.05,
+            view_model.kick * 0.06,
+        );
+    transform.translation = offset;
+    transform.rotation = Quat::from_euler(
+        EulerRot::YXZ,
+        -sway.x * 2.0,
+        view_model.kick * 0.12 - lowered * 0.6 - reload * 0.3,
+        reload * 0.5,
+    );
+
+    for (weapon, mut visibility) in &mut weapon_query {
+        let visible = weapon.0 == view_model.weapon;
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+    }
+}
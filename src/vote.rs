@@ -0,0 +1,307 @@
+//! votes: a player calls a vote, the players connected at that moment answer it with yes or
+//! no. It passes once enough of them said yes, and fails when that can't happen anymore or
+//! time runs out. One vote runs at a time, callers have to wait a cooldown before they can
+//! call the next one. The server runs the votes and replicates the running one, clients show
+//! the prompt and send the answers. Vote-kick is the first kind.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VoteKind {
+    /// disconnect the player with this client id
+    Kick { player: u64 },
+}
+
+impl VoteKind {
+    /// the question put to the voters, `name` resolves client ids to player names
+    pub fn question(&self, name: impl Fn(u64) -> String) -> String {
+        match self {
+            VoteKind::Kick { player } => format!("kick {}?", name(*player)),
+        }
+    }
+
+    /// the subject of the vote has no say in it
+    fn can_vote(&self, player: u64) -> bool {
+        match self {
+            VoteKind::Kick { player: subject } => *subject != player,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoteSettings {
+    /// seconds a vote is open
+    pub duration: f32,
+    /// fraction of the voters needed to pass a vote
+    pub pass_fraction: f32,
+    /// connected players needed to call a vote
+    pub min_players: usize,
+    /// seconds before a player can call the next vote
+    pub cooldown: f32,
+}
+
+impl Default for VoteSettings {
+    fn default() -> Self {
+        Self {
+            duration: 30.0,
+            pass_fraction: 0.6,
+            min_players: 3,
+            cooldown: 60.0,
+        }
+    }
+}
+
+/// replicated state of the running vote, players by client id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteState {
+    pub kind: VoteKind,
+    pub initiator: u64,
+    pub yes: u32,
+    pub no: u32,
+    /// yes votes needed to pass
+    pub needed: u32,
+    pub voters: u32,
+    pub time_left: f32,
+}
+
+#[derive(Debug)]
+pub struct Vote {
+    pub kind: VoteKind,
+    pub initiator: u64,
+    voters: HashSet<u64>,
+    ballots: HashMap<u64, bool>,
+    timer: Timer,
+}
+
+impl Vote {
+    fn count(&self, yes: bool) -> u32 {
+        self.ballots
+            .values()
+            .filter(|ballot| **ballot == yes)
+            .count() as u32
+    }
+
+    fn needed(&self, settings: &VoteSettings) -> u32 {
+        ((self.voters.len() as f32 * settings.pass_fraction).ceil() as u32).max(1)
+    }
+
+    pub fn state(&self, settings: &VoteSettings) -> VoteState {
+        VoteState {
+            kind: self.kind,
+            initiator: self.initiator,
+            yes: self.count(true),
+            no: self.count(false),
+            needed: self.needed(settings),
+            voters: self.voters.len() as u32,
+            time_left: self.timer.duration().as_secs_f32() - self.timer.elapsed_secs(),
+        }
+    }
+
+    /// Some(passed) once the vote is decided
+    fn result(&self, settings: &VoteSettings) -> Option<bool> {
+        let needed = self.needed(settings);
+        let yes = self.count(true);
+        let cast = self
+            .ballots
+            .keys()
+            .filter(|player| self.voters.contains(player))
+            .count();
+        let open = self.voters.len().saturating_sub(cast) as u32;
+        if yes >= needed {
+            Some(true)
+        } else if yes + open < needed || self.timer.finished() {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// server: the running vote and the cooldowns of the players who called votes
+#[derive(Debug, Default)]
+pub struct Votes {
+    pub current: Option<Vote>,
+    cooldowns: HashMap<u64, Timer>,
+}
+
+impl Votes {
+    /// `initiator` calls a vote, `players` are the connected players. The initiator votes yes.
+    /// Err with the reason if the vote can't be called.
+    pub fn call(
+        &mut self,
+        settings: &VoteSettings,
+        kind: VoteKind,
+        initiator: u64,
+        players: impl Iterator<Item = u64>,
+    ) -> Result<(), String> {
+        if self.current.is_some() {
+            return Err("a vote is already running".to_string());
+        }
+        if let Some(cooldown) = self.cooldowns.get(&initiator) {
+            let left = cooldown.duration().as_secs_f32() - cooldown.elapsed_secs();
+            return Err(format!("wait {:.0} seconds before calling a vote", left));
+        }
+        let players: HashSet<u64> = players.collect();
+        if !players.contains(&initiator) {
+            return Err("only players can call votes".to_string());
+        }
+        if players.len() < settings.min_players {
+            return Err(format!("a vote needs {} players", settings.min_players));
+        }
+        match kind {
+            VoteKind::Kick { player } if player == initiator => {
+                return Err("you can't kick yourself".to_string());
+            }
+            VoteKind::Kick { player } if !players.contains(&player) => {
+                return Err("no such player".to_string());
+            }
+            VoteKind::Kick { .. } => (),
+        }
+        let voters = players
+            .into_iter()
+            .filter(|player| kind.can_vote(*player))
+            .collect();
+        self.current = Some(Vote {
+            kind,
+            initiator,
+            voters,
+            ballots: HashMap::from([(initiator, true)]),
+            timer: Timer::from_seconds(settings.duration, false),
+        });
+        self.cooldowns
+            .insert(initiator, Timer::from_seconds(settings.cooldown, false));
+        Ok(())
+    }
+
+    /// `player` answers the running vote, a new answer replaces the old one. False if there is
+    /// no vote or the player can't vote on it.
+    pub fn cast(&mut self, player: u64, yes: bool) -> bool {
+        match &mut self.current {
+            Some(vote) if vote.voters.contains(&player) => {
+                vote.ballots.insert(player, yes);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// players who left don't count anymore
+    pub fn retain_voters(&mut self, connected: impl Fn(u64) -> bool) {
+        if let Some(vote) = &mut self.current {
+            vote.voters.retain(|player| connected(*player));
+            vote.ballots.retain(|player, _| connected(*player));
+        }
+    }
+
+    /// advance the cooldowns and the running vote, the vote once it is decided
+    pub fn update(&mut self, delta: Duration, settings: &VoteSettings) -> Option<(VoteKind, bool)> {
+        self.cooldowns
+            .retain(|_, cooldown| !cooldown.tick(delta).finished());
+        let vote = self.current.as_mut()?;
+        vote.timer.tick(delta);
+        let passed = vote.result(settings)?;
+        let kind = vote.kind;
+        self.current = None;
+        Some((kind, passed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kick(votes: &mut Votes, initiator: u64, player: u64, players: u64) -> Result<(), String> {
+        votes.call(
+            &VoteSettings::default(),
+            VoteKind::Kick { player },
+            initiator,
+            1..=players,
+        )
+    }
+
+    fn update(votes: &mut Votes, seconds: f32) -> Option<(VoteKind, bool)> {
+        votes.update(Duration::from_secs_f32(seconds), &VoteSettings::default())
+    }
+
+    #[test]
+    fn call_checks() {
+        let mut votes = Votes::default();
+        assert!(kick(&mut votes, 1, 2, 2).is_err());
+        assert!(kick(&mut votes, 1, 1, 3).is_err());
+        assert!(kick(&mut votes, 1, 4, 3).is_err());
+        // the ballot of an initiator who is not a voter used to underflow the open count
+        assert!(kick(&mut votes, 4, 1, 3).is_err());
+        assert!(kick(&mut votes, 1, 3, 3).is_ok());
+        assert!(kick(&mut votes, 2, 3, 3).is_err());
+    }
+
+    #[test]
+    fn passes_with_enough_yes() {
+        let mut votes = Votes::default();
+        kick(&mut votes, 1, 3, 3).unwrap();
+        // the subject has no say
+        assert!(!votes.cast(3, false));
+        assert_eq!(update(&mut votes, 0.1), None);
+        assert!(votes.cast(2, true));
+        assert_eq!(
+            update(&mut votes, 0.1),
+            Some((VoteKind::Kick { player: 3 }, true))
+        );
+        assert!(votes.current.is_none());
+    }
+
+    #[test]
+    fn fails_when_it_cant_pass() {
+        let mut votes = Votes::default();
+        kick(&mut votes, 1, 3, 3).unwrap();
+        votes.cast(2, false);
+        assert_eq!(
+            update(&mut votes, 0.1),
+            Some((VoteKind::Kick { player: 3 }, false))
+        );
+    }
+
+    #[test]
+    fn fails_on_timeout() {
+        let mut votes = Votes::default();
+        kick(&mut votes, 1, 5, 5).unwrap();
+        assert_eq!(update(&mut votes, 1.0), None);
+        let duration = VoteSettings::default().duration;
+        assert_eq!(
+            update(&mut votes, duration),
+            Some((VoteKind::Kick { player: 5 }, false))
+        );
+    }
+
+    #[test]
+    fn voters_leaving() {
+        let mut votes = Votes::default();
+        kick(&mut votes, 1, 5, 5).unwrap();
+        votes.cast(2, true);
+        votes.cast(3, false);
+        // the ballots of players who left are dropped with them
+        votes.retain_voters(|player| player == 1);
+        assert_eq!(
+            update(&mut votes, 0.1),
+            Some((VoteKind::Kick { player: 5 }, true))
+        );
+    }
+
+    #[test]
+    fn cooldown() {
+        let mut votes = Votes::default();
+        kick(&mut votes, 1, 3, 3).unwrap();
+        votes.cast(2, false);
+        update(&mut votes, 0.1);
+        assert!(kick(&mut votes, 1, 3, 3).is_err());
+        assert!(kick(&mut votes, 2, 3, 3).is_ok());
+        votes.current = None;
+        update(&mut votes, VoteSettings::default().cooldown);
+        assert!(kick(&mut votes, 1, 3, 3).is_ok());
+    }
+}
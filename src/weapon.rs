@@ -0,0 +1,604 @@
+//! weapons. Hitscan: the client sends its view origin and direction, the server traces the
+//! shot against the physics world, applies the damage and broadcasts the tracer to all
+//! clients. Hitscan shots pass through thin layers of soft surface materials with reduced
+//! damage, clients get the entry and exit points for decals. Grenades are thrown from the
+//! view and explode when their fuse runs out, rockets explode on impact. Melee swings sweep a
+//! short sphere forward from the eyes.
+//!
+//! Every weapon has a fire interval. Clients don't fire faster, the server drops shots that
+//! arrive before the interval of the shooter's last shot has passed.
+//!
+//! Hitscan shots and rockets deviate inside a spread cone that grows while firing (bloom) and
+//! kick the view (recoil). Both follow a pattern seeded by the client id and the shot number,
+//! so the server and the shooting client agree on it.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controller::EYE_HEIGHT, level::SurfaceMaterial, FIREBALL_EXPLOSION_RADIUS,
+    FIREBALL_EXPLOSION_STRENGTH,
+};
+
+/// hitscan shots end after this distance
+pub const HITSCAN_RANGE: f32 = 100.0;
+
+pub const HITSCAN_DAMAGE: f32 = 20.0;
+
+/// hitscan hits at least this high above a player's feet are headshots
+pub const HEADSHOT_HEIGHT: f32 = 1.5;
+
+/// damage multiplier of headshots
+pub const HEADSHOT_MULTIPLIER: f32 = 2.0;
+
+/// origins further than this from the shooter's eyes on the server are not trusted, the shot
+/// starts at the eyes instead
+pub const MAX_HITSCAN_ORIGIN_OFFSET: f32 = 5.0;
+
+/// how long tracers stay visible (seconds)
+pub const TRACER_LIFETIME: f32 = 0.1;
+
+/// layers a hitscan shot passes through at most
+pub const MAX_PENETRATIONS: usize = 3;
+
+/// the trace continues this far behind an exit point, so it doesn't hit the same surface again
+const PENETRATION_SKIN: f32 = 0.01;
+
+/// how long bullet hole decals stay (seconds)
+pub const DECAL_LIFETIME: f32 = 10.0;
+
+/// bullet hole decals kept at once, the oldest are removed first
+pub const MAX_DECALS: usize = 128;
+
+/// bloom (0..1) recovered per second
+pub const BLOOM_RECOVERY: f32 = 1.5;
+
+/// seconds between two hitscan shots
+pub const HITSCAN_FIRE_INTERVAL: f64 = 0.1;
+
+/// seconds between two rockets
+pub const ROCKET_FIRE_INTERVAL: f64 = 0.8;
+
+/// seconds between two fireballs (basic attack)
+pub const FIREBALL_FIRE_INTERVAL: f64 = 0.5;
+
+/// the server accepts shots this much earlier than the fire interval, the network can deliver
+/// commands closer together than they were sent
+pub const FIRE_INTERVAL_SLACK: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SpreadSettings {
+    /// cone half angle without bloom (radians)
+    pub base: f32,
+    /// cone half angle added at full bloom (radians)
+    pub max_bloom: f32,
+    /// bloom (0..1) added per shot
+    pub bloom_per_shot: f32,
+    /// view kick per shot: pitch up and the largest sideways yaw of the pattern (radians)
+    pub recoil: Vec2,
+}
+
+pub const HITSCAN_SPREAD: SpreadSettings = SpreadSettings {
+    base: 0.002,
+    max_bloom: 0.05,
+    bloom_per_shot: 0.2,
+    recoil: Vec2::new(0.015, 0.01),
+};
+
+pub const ROCKET_SPREAD: SpreadSettings = SpreadSettings {
+    base: 0.0,
+    max_bloom: 0.02,
+    bloom_per_shot: 0.5,
+    recoil: Vec2::new(0.05, 0.0),
+};
+
+/// spread cone multiplier while aiming down sights, applies to bloom and movement spread
+pub const ADS_SPREAD_FACTOR: f32 = 0.4;
+
+/// scale a spread cone for the aiming state of the shooter
+pub fn aimed_cone(cone: f32, aiming: bool) -> f32 {
+    if aiming {
+        cone * ADS_SPREAD_FACTOR
+    } else {
+        cone
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WeaponKind {
+    Hitscan,
+    Rocket,
+}
+
+impl WeaponKind {
+    pub fn spread(self) -> &'static SpreadSettings {
+        match self {
+            WeaponKind::Hitscan => &HITSCAN_SPREAD,
+            WeaponKind::Rocket => &ROCKET_SPREAD,
+        }
+    }
+
+    /// seconds between two shots
+    pub fn fire_interval(self) -> f64 {
+        match self {
+            WeaponKind::Hitscan => HITSCAN_FIRE_INTERVAL,
+            WeaponKind::Rocket => ROCKET_FIRE_INTERVAL,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            WeaponKind::Hitscan => "rifle",
+            WeaponKind::Rocket => "rocket launcher",
+        }
+    }
+
+    /// dropped on death. Everyone always carries the rifle.
+    pub fn droppable(self) -> bool {
+        !matches!(self, WeaponKind::Hitscan)
+    }
+}
+
+/// seconds a dropped weapon stays in the world
+pub const DROPPED_WEAPON_LIFETIME: f32 = 30.0;
+
+/// players whose center is closer than this to a dropped weapon can pick it up
+pub const WEAPON_PICKUP_RADIUS: f32 = 1.2;
+
+/// weapons a player carries. The server keeps one per player, the client one for its own
+/// player (HUD and input).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct Inventory {
+    pub weapons: Vec<WeaponKind>,
+    /// weapon fired last, dropped on death
+    pub active: WeaponKind,
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Self {
+            weapons: vec![WeaponKind::Hitscan, WeaponKind::Rocket],
+            active: WeaponKind::Hitscan,
+        }
+    }
+}
+
+impl Inventory {
+    pub fn has(&self, weapon: WeaponKind) -> bool {
+        self.weapons.contains(&weapon)
+    }
+
+    /// false if the weapon was carried already
+    pub fn add(&mut self, weapon: WeaponKind) -> bool {
+        if self.has(weapon) {
+            return false;
+        }
+        self.weapons.push(weapon);
+        true
+    }
+
+    /// take the active weapon out of the inventory if it can be dropped
+    pub fn drop_active(&mut self) -> Option<WeaponKind> {
+        let weapon = self.active;
+        if !weapon.droppable() || !self.has(weapon) {
+            return None;
+        }
+        self.weapons.retain(|carried| *carried != weapon);
+        self.active = WeaponKind::Hitscan;
+        Some(weapon)
+    }
+}
+
+/// bloom, shot numbers and fire intervals of a player's weapons. The server keeps one per
+/// player, the client one for its own player (crosshair, recoil and fire rate).
+#[derive(Debug, Default, Clone, Component)]
+pub struct WeaponSpread {
+    /// bloom (0..1) right after the last shot
+    bloom: f32,
+    /// time of the last shot (seconds since startup)
+    last_fire: f64,
+    last_shot: Option<u32>,
+    /// time of the last shot of each weapon
+    last_fire_of: HashMap<WeaponKind, f64>,
+    /// time of the last fireball
+    last_fireball: Option<f64>,
+}
+
+impl WeaponSpread {
+    /// bloom (0..1) at `now`
+    pub fn bloom(&self, now: f64) -> f32 {
+        (self.bloom - (now - self.last_fire) as f32 * BLOOM_RECOVERY).max(0.0)
+    }
+
+    /// spread cone half angle of `weapon` at `now`
+    pub fn cone(&self, weapon: WeaponKind, now: f64) -> f32 {
+        let spread = weapon.spread();
+        spread.base + self.bloom(now) * spread.max_bloom
+    }
+
+    /// whether `weapon` can fire at `now`, `slack` shortens its fire interval
+    pub fn ready(&self, weapon: WeaponKind, now: f64, slack: f64) -> bool {
+        self.last_fire_of
+            .get(&weapon)
+            .map_or(true, |last| now - last >= weapon.fire_interval() - slack)
+    }
+
+    /// fire `weapon` at `now`: returns the cone of this shot and grows the bloom
+    pub fn fire(&mut self, weapon: WeaponKind, now: f64) -> f32 {
+        let cone = self.cone(weapon, now);
+        self.bloom = (self.bloom(now) + weapon.spread().bloom_per_shot).min(1.0);
+        self.last_fire = now;
+        self.last_fire_of.insert(weapon, now);
+        cone
+    }
+
+    /// cast a fireball at `now`, false within FIREBALL_FIRE_INTERVAL (less `slack`) of the
+    /// last one
+    pub fn try_fireball(&mut self, now: f64, slack: f64) -> bool {
+        if let Some(last) = self.last_fireball {
+            if now - last < FIREBALL_FIRE_INTERVAL - slack {
+                return false;
+            }
+        }
+        self.last_fireball = Some(now);
+        true
+    }
+
+    /// client: number of the next shot
+    pub fn next_shot(&mut self) -> u32 {
+        let shot = self.last_shot.map_or(0, |last| last.wrapping_add(1));
+        self.last_shot = Some(shot);
+        shot
+    }
+
+    /// server: take the shot number sent by a client. Numbers have to increase, so clients
+    /// can't repeat a favorable entry of the pattern.
+    pub fn accept_shot(&mut self, shot: u32) -> u32 {
+        let shot = match self.last_shot {
+            Some(last) if shot <= last => last.wrapping_add(1),
+            _ => shot,
+        };
+        self.last_shot = Some(shot);
+        shot
+    }
+}
+
+/// deterministic value in [0, 1) for `shot` of the player `seed` (splitmix64)
+fn shot_random(seed: u64, shot: u32, salt: u64) -> f32 {
+    let mut x = seed ^ ((shot as u64) << 32) ^ salt.wrapping_mul(0xD1B5_4A32_D192_ED03);
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+    (x >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// `direction` deviated inside a cone with half angle `cone`, following the pattern of the
+/// player `seed`
+pub fn spread_direction(direction: Vec3, cone: f32, seed: u64, shot: u32) -> Vec3 {
+    let direction = direction.normalize_or_zero();
+    if cone <= 0.0 || direction == Vec3::ZERO {
+        return direction;
+    }
+    // uniform over the disk of the cone
+    let radius = cone * shot_random(seed, shot, 0).sqrt();
+    let angle = std::f32::consts::TAU * shot_random(seed, shot, 1);
+    let mut right = direction.cross(Vec3::Y).normalize_or_zero();
+    if right == Vec3::ZERO {
+        right = Vec3::X;
+    }
+    let up = right.cross(direction);
+    (direction + (right * angle.cos() + up * angle.sin()) * radius.tan()).normalize()
+}
+
+/// view kick (pitch, yaw) of `shot`: always up, sideways following the pattern of `seed`
+pub fn recoil_kick(weapon: WeaponKind, seed: u64, shot: u32) -> Vec2 {
+    let recoil = weapon.spread().recoil;
+    Vec2::new(
+        recoil.x,
+        recoil.y * (shot_random(seed, shot, 2) * 2.0 - 1.0),
+    )
+}
+
+/// reach of a melee swing from the eyes
+pub const MELEE_RANGE: f32 = 1.5;
+
+/// radius of the sphere swept by a melee swing
+pub const MELEE_RADIUS: f32 = 0.35;
+
+pub const MELEE_DAMAGE: f32 = 50.0;
+
+/// velocity change of players hit by a melee swing
+pub const MELEE_KNOCKBACK: f32 = 8.0;
+
+/// seconds between melee swings
+pub const MELEE_COOLDOWN: f64 = 0.6;
+
+/// how long the swing effect is shown (seconds)
+pub const MELEE_SWING_TIME: f32 = 0.15;
+
+/// grenades are thrown this much above the view direction (fraction of the throw speed)
+pub const GRENADE_THROW_LIFT: f32 = 0.25;
+
+/// grenades and rockets start this far in front of the view origin
+pub const LAUNCH_OFFSET: f32 = 0.5;
+
+/// radial knockback and damage, both fall off linearly to zero at `radius`
+#[derive(Debug, Clone, Copy)]
+pub struct Explosion {
+    pub radius: f32,
+    /// knockback speed at the center
+    pub strength: f32,
+    /// damage at the center
+    pub damage: f32,
+    /// fraction of the damage the owner takes
+    pub self_damage: f32,
+}
+
+impl Explosion {
+    pub fn damage_at(&self, distance: f32) -> f32 {
+        self.damage * (1.0 - distance / self.radius).clamp(0.0, 1.0)
+    }
+}
+
+/// fireballs only knock back, the fire pool they leave does the damage
+pub const FIREBALL_EXPLOSION: Explosion = Explosion {
+    radius: FIREBALL_EXPLOSION_RADIUS,
+    strength: FIREBALL_EXPLOSION_STRENGTH,
+    damage: 0.0,
+    self_damage: 0.0,
+};
+
+pub const GRENADE_EXPLOSION: Explosion = Explosion {
+    radius: 4.0,
+    strength: 14.0,
+    damage: 80.0,
+    self_damage: 1.0,
+};
+
+/// strong enough to rocket jump, at the price of some health
+pub const ROCKET_EXPLOSION: Explosion = Explosion {
+    radius: 3.0,
+    strength: 16.0,
+    damage: 100.0,
+    self_damage: 0.5,
+};
+
+/// grenades and rockets a player spawns with
+pub const SPAWN_GRENADES: u32 = 2;
+pub const SPAWN_ROCKETS: u32 = 5;
+
+pub const MAX_GRENADES: u32 = 5;
+pub const MAX_ROCKETS: u32 = 10;
+
+/// grenades and rockets left. Hitscan and melee need no ammo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Component)]
+pub struct Ammo {
+    pub grenades: u32,
+    pub rockets: u32,
+}
+
+impl Default for Ammo {
+    fn default() -> Self {
+        Self {
+            grenades: SPAWN_GRENADES,
+            rockets: SPAWN_ROCKETS,
+        }
+    }
+}
+
+impl Ammo {
+    pub fn take_grenade(&mut self) -> bool {
+        let available = self.grenades > 0;
+        self.grenades = self.grenades.saturating_sub(1);
+        available
+    }
+
+    pub fn take_rocket(&mut self) -> bool {
+        let available = self.rockets > 0;
+        self.rockets = self.rockets.saturating_sub(1);
+        available
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.grenades >= MAX_GRENADES && self.rockets >= MAX_ROCKETS
+    }
+
+    /// add to both, up to the maximum
+    pub fn refill(&mut self, grenades: u32, rockets: u32) {
+        self.grenades = (self.grenades + grenades).min(MAX_GRENADES);
+        self.rockets = (self.rockets + rockets).min(MAX_ROCKETS);
+    }
+}
+
+/// time of the last melee swing of a player
+#[derive(Debug, Default, Component)]
+pub struct MeleeCooldown {
+    last_swing: Option<f64>,
+}
+
+impl MeleeCooldown {
+    /// start a swing at `now` (seconds since startup), false while still cooling down
+    pub fn try_swing(&mut self, now: f64) -> bool {
+        if matches!(self.last_swing, Some(last) if now - last < MELEE_COOLDOWN) {
+            return false;
+        }
+        self.last_swing = Some(now);
+        true
+    }
+}
+
+/// first entity touched by a melee swing from `origin` along `direction`, with the point of
+/// contact
+pub fn trace_melee(
+    physics_context: &RapierContext,
+    origin: Vec3,
+    direction: Vec3,
+    attacker: Entity,
+) -> Option<(Entity, Vec3)> {
+    let direction = direction.normalize_or_zero();
+    let filter = QueryFilter::default()
+        .exclude_rigid_body(attacker)
+        .exclude_sensors();
+    physics_context
+        .cast_shape(
+            origin,
+            Quat::IDENTITY,
+            direction,
+            &Collider::ball(MELEE_RADIUS),
+            MELEE_RANGE,
+            filter,
+        )
+        .map(|(entity, toi)| (entity, origin + direction * toi.toi))
+}
+
+/// knockback of a melee hit along `direction`, lifted a bit so the target leaves the ground
+pub fn melee_knockback(direction: Vec3) -> Vec3 {
+    (direction.normalize_or_zero() + Vec3::Y * 0.3).normalize_or_zero() * MELEE_KNOCKBACK
+}
+
+/// launch velocity of a grenade thrown along the view `direction`
+pub fn grenade_velocity(direction: Vec3, speed: f32) -> Vec3 {
+    (direction.normalize_or_zero() + Vec3::Y * GRENADE_THROW_LIFT).normalize_or_zero() * speed
+}
+
+/// a hitscan shot entering or leaving a surface with a material, clients put decals there
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SurfaceImpact {
+    pub position: Vec3,
+    /// surface normal, pointing out of the hit collider
+    pub normal: Vec3,
+    pub material: SurfaceMaterial,
+    /// the shot left the surface here, on the back of a penetrated layer
+    pub exit: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct HitscanHit {
+    /// end of the tracer: the impact point, or the end of the range
+    pub end: Vec3,
+    /// entity that stopped the shot, None if the shot went into the void
+    pub entity: Option<Entity>,
+    /// fraction of the damage left after the penetrated layers
+    pub damage_factor: f32,
+    /// entry and exit points along the shot, in order
+    pub impacts: Vec<SurfaceImpact>,
+}
+
+/// start of a shot: the client's view origin if plausible, otherwise the shooter's eyes
+pub fn hitscan_origin(shooter_position: Vec3, origin: Vec3) -> Vec3 {
+    let eye = shooter_position + Vec3::Y * EYE_HEIGHT;
+    if origin.distance(eye) <= MAX_HITSCAN_ORIGIN_OFFSET {
+        origin
+    } else {
+        eye
+    }
+}
+
+/// exit point and normal of a shot entering `entity` at `entry`, None if the layer is
+/// thicker than `max_thickness`
+fn penetration_exit(
+    physics_context: &RapierContext,
+    entity: Entity,
+    entry: Vec3,
+    direction: Vec3,
+    max_thickness: f32,
+) -> Option<(Vec3, Vec3)> {
+    if max_thickness <= 0.0 {
+        return None;
+    }
+    // trace back towards the entry from behind the thickest passable layer. Starting inside
+    // the collider (toi 0) means the layer is too thick.
+    let only_entity = |other| other == entity;
+    let filter = QueryFilter::default().predicate(&only_entity);
+    let behind = entry + direction * max_thickness;
+    let (_, hit) =
+        physics_context.cast_ray_and_get_normal(behind, -direction, max_thickness, true, filter)?;
+    if hit.toi <= 0.0 {
+        return None;
+    }
+    Some((hit.point, hit.normal))
+}
+
+/// trace a shot from `origin` along `direction`, ignoring the shooter's own body. The shot
+/// passes through thin layers of penetrable materials (`surfaces` gives the material of a
+/// collider) and stops at the first other collider.
+pub fn trace_hitscan(
+    physics_context: &RapierContext,
+    origin: Vec3,
+    direction: Vec3,
+    shooter: Entity,
+    surfaces: impl Fn(Entity) -> Option<SurfaceMaterial>,
+) -> HitscanHit {
+    let direction = direction.normalize_or_zero();
+    let filter = QueryFilter::default().exclude_rigid_body(shooter);
+    let mut start = origin;
+    let mut remaining = HITSCAN_RANGE;
+    let mut damage_factor = 1.0;
+    let mut impacts = Vec::new();
+    for layer in 0..=MAX_PENETRATIONS {
+        let (entity, hit) = match physics_context
+            .cast_ray_and_get_normal(start, direction, remaining, true, filter)
+        {
+            Some(hit) => hit,
+            None => break,
+        };
+        let material = surfaces(entity);
+        if let Some(material) = material {
+            impacts.push(SurfaceImpact {
+                position: hit.point,
+                normal: hit.normal,
+                material,
+                exit: false,
+            });
+        }
+        let exit = match material {
+            Some(material) if layer < MAX_PENETRATIONS => penetration_exit(
+                physics_context,
+                entity,
+                hit.point,
+                direction,
+                material.max_penetration(),
+            )
+            .map(|exit| (material, exit)),
+            _ => None,
+        };
+        let (material, (exit, normal)) = match exit {
+            Some(exit) => exit,
+            None => {
+                return HitscanHit {
+                    end: hit.point,
+                    entity: Some(entity),
+                    damage_factor,
+                    impacts,
+                }
+            }
+        };
+        impacts.push(SurfaceImpact {
+            position: exit,
+            normal,
+            material,
+            exit: true,
+        });
+        damage_factor *= material.damage_kept();
+        remaining -= start.distance(exit) + PENETRATION_SKIN;
+        start = exit + direction * PENETRATION_SKIN;
+        if remaining <= 0.0 {
+            return HitscanHit {
+                end: start,
+                entity: None,
+                damage_factor,
+                impacts,
+            };
+        }
+    }
+    HitscanHit {
+        end: start + direction * remaining,
+        entity: None,
+        damage_factor,
+        impacts,
+    }
+}